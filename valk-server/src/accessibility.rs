@@ -0,0 +1,488 @@
+//! The accessibility tree of the desktop, so agents can target buttons and fields by role and
+//! name instead of by pixels. Read from AT-SPI, which needs applications to have
+//! accessibility enabled (GTK and Qt apps do under a desktop session, browsers and Electron
+//! apps may need `--force-renderer-accessibility`).
+
+use axum::{extract::Query, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+
+use crate::action_types::{ActionError, ClickElementInput, DesktopPoint};
+
+/// Levels below the desktop returned when the request doesn't say
+const DEFAULT_MAX_DEPTH: u32 = 8;
+pub const MAX_DEPTH: u32 = 64;
+/// Trees of big documents have hundreds of thousands of nodes, stop walking after this many
+pub const MAX_NODES: usize = 5_000;
+#[cfg(target_os = "linux")]
+const TREE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+/// A click_element lookup runs in the queue with the input driver held, so it walks a much
+/// smaller part of the tree and gives up sooner
+#[cfg(target_os = "linux")]
+const FIND_MAX_NODES: usize = 1_000;
+#[cfg(target_os = "linux")]
+const FIND_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+/// `/v1/observe/text` is meant to be asked every step, so it reads as little of the tree
+#[cfg(target_os = "linux")]
+const TEXT_MAX_NODES: usize = 1_000;
+#[cfg(target_os = "linux")]
+const TEXT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Bounds {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Bounds {
+    /// Negative for elements on monitors left of or above the primary one
+    pub fn center(&self) -> DesktopPoint {
+        DesktopPoint {
+            x: self.x + (self.width / 2) as i32,
+            y: self.y + (self.height / 2) as i32,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct AccessibleNode {
+    /// The application's bus name followed by the node's object path, e.g.
+    /// ":1.42/org/a11y/atspi/accessible/17". Valid until the application exits
+    pub id: String,
+    /// e.g. "push button", "text", "frame" or "application"
+    pub role: String,
+    pub name: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub description: String,
+    /// Position on the desktop, for nodes that are shown somewhere
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bounds: Option<Bounds>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<AccessibleNode>,
+}
+
+impl AccessibleNode {
+    /// The first node shown on screen with this role and name, both compared ignoring case,
+    /// searching parents before their children
+    pub fn find(&self, role: &str, name: &str) -> Option<&AccessibleNode> {
+        let matches = self.bounds.is_some()
+            && self.role.eq_ignore_ascii_case(role.trim())
+            && self.name.trim().to_lowercase() == name.trim().to_lowercase();
+        if matches {
+            return Some(self);
+        }
+        self.children
+            .iter()
+            .find_map(|child| child.find(role, name))
+    }
+
+    /// The nodes shown on screen that have a name, parents before their children
+    pub fn shown_text(&self) -> Vec<AccessibleElement> {
+        let mut shown = Vec::new();
+        self.collect_shown_text(&mut shown);
+        shown
+    }
+
+    fn collect_shown_text(&self, shown: &mut Vec<AccessibleElement>) {
+        if let Some(bounds) = self.bounds.filter(|_| !self.name.trim().is_empty()) {
+            shown.push(AccessibleElement {
+                id: self.id.clone(),
+                role: self.role.clone(),
+                name: self.name.trim().to_string(),
+                bounds,
+            });
+        }
+        for child in &self.children {
+            child.collect_shown_text(shown);
+        }
+    }
+}
+
+/// An element a click_element action resolved, with where it is now
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AccessibleElement {
+    pub id: String,
+    pub role: String,
+    pub name: String,
+    pub bounds: Bounds,
+}
+
+// Split an id into the bus name and object path it was made of
+#[cfg(target_os = "linux")]
+fn split_id(id: &str) -> Option<(&str, &str)> {
+    let (bus_name, _) = id.split_once('/')?;
+    Some((bus_name, &id[bus_name.len()..])).filter(|(bus_name, _)| !bus_name.is_empty())
+}
+
+#[derive(Debug, Serialize)]
+pub struct AccessibilityTree {
+    /// Set when `max_depth` or the node limit cut the tree short
+    pub truncated: bool,
+    pub root: AccessibleNode,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct TreeQuery {
+    pub max_depth: Option<u32>,
+}
+
+// Extents of hidden nodes are empty or negative, those have no bounds
+fn bounds((x, y, width, height): (i32, i32, i32, i32)) -> Option<Bounds> {
+    (width > 0 && height > 0).then_some(Bounds {
+        x,
+        y,
+        width: width as u32,
+        height: height as u32,
+    })
+}
+
+#[cfg(target_os = "linux")]
+mod atspi_tree {
+    use super::*;
+    use atspi::proxy::accessible::{AccessibleProxy, ObjectRefExt};
+    use atspi::proxy::bus::BusProxy;
+    use atspi::proxy::component::ComponentProxy;
+    use atspi::{CoordType, Interface};
+    use std::future::Future;
+    use std::pin::Pin;
+    use zbus::proxy::CacheProperties;
+
+    pub struct Walk {
+        pub connection: zbus::Connection,
+        pub nodes_left: usize,
+        pub truncated: bool,
+    }
+
+    impl Walk {
+        /// Connect to the accessibility bus, whose address the session bus hands out, to
+        /// read at most `max_nodes` nodes
+        pub async fn connect(max_nodes: usize) -> zbus::Result<Self> {
+            let session = zbus::Connection::session().await?;
+            let address = BusProxy::new(&session).await?.get_address().await?;
+            let connection = zbus::connection::Builder::address(address.as_str())?
+                .build()
+                .await?;
+            Ok(Self {
+                connection,
+                nodes_left: max_nodes,
+                truncated: false,
+            })
+        }
+
+        pub async fn desktop(&self) -> zbus::Result<AccessibleProxy<'static>> {
+            AccessibleProxy::builder(&self.connection)
+                .destination("org.a11y.atspi.Registry")?
+                .path("/org/a11y/atspi/accessible/root")?
+                .cache_properties(CacheProperties::No)
+                .build()
+                .await
+        }
+
+        pub async fn by_id(&self, id: &str) -> zbus::Result<AccessibleProxy<'static>> {
+            let (bus_name, path) = split_id(id).ok_or_else(|| {
+                zbus::Error::Failure(format!("{} is not an accessibility element id", id))
+            })?;
+            AccessibleProxy::builder(&self.connection)
+                .destination(bus_name.to_string())?
+                .path(path.to_string())?
+                .cache_properties(CacheProperties::No)
+                .build()
+                .await
+        }
+
+        pub async fn bounds(&self, node: &AccessibleProxy<'_>) -> Option<Bounds> {
+            let interfaces = node.get_interfaces().await.ok()?;
+            if !interfaces.contains(Interface::Component) {
+                return None;
+            }
+            let component = ComponentProxy::builder(&self.connection)
+                .destination(node.inner().destination().to_owned())
+                .ok()?
+                .path(node.inner().path().to_owned())
+                .ok()?
+                .cache_properties(CacheProperties::No)
+                .build()
+                .await
+                .ok()?;
+            bounds(component.get_extents(CoordType::Screen).await.ok()?)
+        }
+
+        // Boxed since it recurses. Children that went away while walking are left out
+        pub fn node<'a>(
+            &'a mut self,
+            node: &'a AccessibleProxy<'a>,
+            depth: u32,
+        ) -> Pin<Box<dyn Future<Output = zbus::Result<AccessibleNode>> + Send + 'a>> {
+            Box::pin(async move {
+                self.nodes_left = self.nodes_left.saturating_sub(1);
+                let mut result = AccessibleNode {
+                    id: format!("{}{}", node.inner().destination(), node.inner().path()),
+                    role: node.get_role().await?.name().to_string(),
+                    name: node.name().await.unwrap_or_default(),
+                    description: node.description().await.unwrap_or_default(),
+                    bounds: self.bounds(node).await,
+                    children: Vec::new(),
+                };
+                let children = node.get_children().await.unwrap_or_default();
+                if depth == 0 && !children.is_empty() {
+                    self.truncated = true;
+                    return Ok(result);
+                }
+                for child in children {
+                    if self.nodes_left == 0 {
+                        self.truncated = true;
+                        break;
+                    }
+                    let Ok(child) = child.as_accessible_proxy(&self.connection).await else {
+                        continue;
+                    };
+                    if let Ok(child) = self.node(&child, depth - 1).await {
+                        result.children.push(child);
+                    }
+                }
+                Ok(result)
+            })
+        }
+    }
+}
+
+/// The tree from the desktop down to `max_depth` levels below it, with the running
+/// applications on the first level
+#[cfg(target_os = "linux")]
+pub async fn tree(max_depth: u32) -> Result<AccessibilityTree, (StatusCode, String)> {
+    walk_tree(max_depth, MAX_NODES, TREE_TIMEOUT).await
+}
+
+#[cfg(target_os = "linux")]
+async fn walk_tree(
+    max_depth: u32,
+    max_nodes: usize,
+    timeout: std::time::Duration,
+) -> Result<AccessibilityTree, (StatusCode, String)> {
+    let unavailable = |e: zbus::Error| {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("Accessibility bus not available: {}", e),
+        )
+    };
+    let walk = async {
+        let mut walk = atspi_tree::Walk::connect(max_nodes)
+            .await
+            .map_err(unavailable)?;
+        let desktop = walk.desktop().await.map_err(unavailable)?;
+        let root = walk.node(&desktop, max_depth).await.map_err(unavailable)?;
+        Ok(AccessibilityTree {
+            truncated: walk.truncated,
+            root,
+        })
+    };
+    tokio::time::timeout(timeout, walk)
+        .await
+        .unwrap_or_else(|_| {
+            Err((
+                StatusCode::GATEWAY_TIMEOUT,
+                "Reading the accessibility tree timed out, an application may be hanging"
+                    .to_string(),
+            ))
+        })
+}
+
+/// The named elements shown on screen, for reading what is on it without recognizing text
+#[cfg(target_os = "linux")]
+pub async fn shown_text() -> Result<Vec<AccessibleElement>, (StatusCode, String)> {
+    let tree = walk_tree(MAX_DEPTH, TEXT_MAX_NODES, TEXT_TIMEOUT).await?;
+    Ok(tree.root.shown_text())
+}
+
+/// The element a click_element action targets, by its id or as the first shown element
+/// with its role and name
+#[cfg(target_os = "linux")]
+pub async fn find_element(input: &ClickElementInput) -> Result<AccessibleElement, ActionError> {
+    let (Some(role), Some(name)) = (&input.role, &input.name) else {
+        let id = input.element_id.as_deref().unwrap_or_default();
+        return tokio::time::timeout(FIND_TIMEOUT, element_by_id(id))
+            .await
+            .unwrap_or(Err(ActionError::Timeout));
+    };
+    let tree = walk_tree(MAX_DEPTH, FIND_MAX_NODES, FIND_TIMEOUT)
+        .await
+        .map_err(|(status, message)| match status {
+            StatusCode::SERVICE_UNAVAILABLE => ActionError::ServiceUnavailable(message),
+            StatusCode::GATEWAY_TIMEOUT => ActionError::Timeout,
+            _ => ActionError::ExecutionFailed(message),
+        })?;
+    let node = tree.root.find(role, name).ok_or_else(|| {
+        let searched = if tree.truncated {
+            format!(" among the first {} elements", FIND_MAX_NODES)
+        } else {
+            String::new()
+        };
+        ActionError::NotFound(format!(
+            "No {} named \"{}\" on screen{}, look it up with GET /v1/accessibility/tree and \
+             pass its element_id",
+            role, name, searched
+        ))
+    })?;
+    Ok(AccessibleElement {
+        id: node.id.clone(),
+        role: node.role.clone(),
+        name: node.name.clone(),
+        bounds: node.bounds.expect("found nodes are shown"),
+    })
+}
+
+#[cfg(target_os = "linux")]
+async fn element_by_id(id: &str) -> Result<AccessibleElement, ActionError> {
+    let walk = atspi_tree::Walk::connect(1).await.map_err(|e| {
+        ActionError::ServiceUnavailable(format!("Accessibility bus not available: {}", e))
+    })?;
+    let not_found = || ActionError::NotFound(format!("No accessibility element {}", id));
+    let node = walk.by_id(id).await.map_err(|_| not_found())?;
+    // Asking an element that went away fails, e.g. once its app exited
+    let role = node.get_role().await.map_err(|_| not_found())?;
+    let bounds = walk.bounds(&node).await.ok_or_else(|| {
+        ActionError::NotFound(format!("Accessibility element {} isn't on screen", id))
+    })?;
+    Ok(AccessibleElement {
+        id: id.to_string(),
+        role: role.name().to_string(),
+        name: node.name().await.unwrap_or_default(),
+        bounds,
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn find_element(_input: &ClickElementInput) -> Result<AccessibleElement, ActionError> {
+    Err(ActionError::Unsupported(
+        "Accessibility elements are only supported on Linux".to_string(),
+    ))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn shown_text() -> Result<Vec<AccessibleElement>, (StatusCode, String)> {
+    Err((
+        StatusCode::NOT_IMPLEMENTED,
+        "The accessibility tree is only supported on Linux".to_string(),
+    ))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn tree(_max_depth: u32) -> Result<AccessibilityTree, (StatusCode, String)> {
+    Err((
+        StatusCode::NOT_IMPLEMENTED,
+        "The accessibility tree is only supported on Linux".to_string(),
+    ))
+}
+
+/// `GET /v1/accessibility/tree`, optionally `?max_depth=4`
+pub async fn accessibility_tree(
+    Query(query): Query<TreeQuery>,
+) -> Result<Json<AccessibilityTree>, (StatusCode, String)> {
+    let max_depth = query.max_depth.unwrap_or(DEFAULT_MAX_DEPTH);
+    if max_depth > MAX_DEPTH {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("max_depth must be at most {}, got {}", MAX_DEPTH, max_depth),
+        ));
+    }
+    tree(max_depth).await.map(Json)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hidden_nodes_have_no_bounds() {
+        assert_eq!(
+            bounds((10, 20, 300, 40)),
+            Some(Bounds {
+                x: 10,
+                y: 20,
+                width: 300,
+                height: 40
+            })
+        );
+        assert_eq!(bounds((0, 0, 0, 0)), None);
+        assert_eq!(bounds((-1, -1, -1, -1)), None);
+    }
+
+    #[test]
+    fn test_center_left_of_the_primary_monitor() {
+        let bounds = Bounds {
+            x: -1920,
+            y: -40,
+            width: 200,
+            height: 30,
+        };
+        assert_eq!(bounds.center(), DesktopPoint { x: -1820, y: -25 });
+    }
+
+    #[test]
+    fn test_find_element_by_role_and_name() {
+        let node = |id: &str, role: &str, name: &str, shown: bool, children| AccessibleNode {
+            id: id.to_string(),
+            role: role.to_string(),
+            name: name.to_string(),
+            description: String::new(),
+            bounds: shown.then_some(Bounds {
+                x: 100,
+                y: 50,
+                width: 80,
+                height: 30,
+            }),
+            children,
+        };
+        let hidden_save = node("hidden", "push button", "Save", false, Vec::new());
+        let save = node("save", "push button", "Save", true, Vec::new());
+        let save_as = node("save_as", "push button", "Save As…", true, Vec::new());
+        let dialog = node(
+            "dialog",
+            "dialog",
+            "Save",
+            true,
+            vec![hidden_save, save_as, save],
+        );
+        let desktop = node("desktop", "desktop frame", "main", false, vec![dialog]);
+
+        let found = desktop.find("Push Button", " save ").unwrap();
+        assert_eq!(found.id, "save");
+        assert_eq!(
+            found.bounds.unwrap().center(),
+            DesktopPoint { x: 140, y: 65 }
+        );
+        assert!(desktop.find("push button", "Open").is_none());
+
+        // What /v1/observe/text lists without OCR, hidden nodes left out
+        let shown: Vec<_> = desktop
+            .shown_text()
+            .into_iter()
+            .map(|element| element.id)
+            .collect();
+        assert_eq!(shown, ["dialog", "save_as", "save"]);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_element_ids_split_into_bus_name_and_path() {
+        assert_eq!(
+            split_id(":1.42/org/a11y/atspi/accessible/17"),
+            Some((":1.42", "/org/a11y/atspi/accessible/17"))
+        );
+        assert_eq!(split_id("/org/a11y"), None);
+        assert_eq!(split_id(":1.42"), None);
+    }
+
+    #[tokio::test]
+    async fn test_max_depth_is_limited() {
+        let query = TreeQuery {
+            max_depth: Some(MAX_DEPTH + 1),
+        };
+        let Err((status, _)) = accessibility_tree(Query(query)).await else {
+            panic!("max_depth above the limit is refused");
+        };
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
@@ -1,144 +1,967 @@
-use crate::key_press::KeyPress;
-use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use crate::key_press::{parse_modifier, KeyPress};
 use chrono::Utc;
-use enigo::InputError;
 use enigo::{
-    Button,
+    Axis, Button, Coordinate,
     Coordinate::{Abs, Rel},
+    Direction,
     Direction::{Press, Release},
-    Enigo, Keyboard, Mouse, Settings,
+    Enigo, InputError, InputResult, Key, Keyboard, Mouse, Settings,
 };
-use image::ImageFormat;
-use std::env;
-use std::io::Cursor;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use tokio::sync::{broadcast, oneshot, Mutex};
-use tokio::time::{sleep, timeout, Duration};
+use tokio::sync::{broadcast, mpsc, oneshot, watch, Mutex, MutexGuard};
+use tokio::time::{sleep, sleep_until, timeout, timeout_at, Duration, Instant};
+use tracing::{error, info, warn};
 use uuid::Uuid;
-use xcap::Monitor;
 
+use crate::accessibility;
+use crate::agent::{self, AgentRegistry};
+use crate::annotate::{draw_pointer, draw_text_boxes};
+use crate::approvals::{ApprovalDecision, ApprovalRegistry};
+use crate::authorization::authorize;
+use crate::bandwidth::{degrade_frame, BandwidthBudget, Degradation};
+use crate::capture::{
+    crop, encode_image, encode_png, fit_within, image_hash, scale_by, select_display,
+    window_region, ImageCapture, ScreenCapture, XcapCapture,
+};
+use crate::clipboard::{Clipboard, MemoryClipboard, SystemClipboard};
+use crate::config::{Config, DragPathMode, InputBackend, MonitorOverflow, MouseMotion, PacingMode};
+use crate::cursor::CursorSnapshot;
+use crate::display::{reconnect_display, DisplayWatch};
+use crate::fair_queue::FairQueue;
+use crate::heatmap::{Interaction, InteractionKind};
+use crate::locate;
+use crate::metrics::Metrics;
 use crate::monitor::{MonitorConfig, MonitorEvent, MonitorEventPayload};
+use crate::observation::{ObservationState, ObservationTracker};
+use crate::observe::WindowCommand;
+use crate::ocr::{self, platform_recognizer, TextRecognizer};
+use crate::pacing::Pacer;
+use crate::policy::{PolicyWatch, RedactedCapture};
+use crate::relay::RelayRegistry;
+use crate::runs::RunRegistry;
+use crate::simulation::{SimulatedInput, VirtualScreen};
+use crate::touch::{platform_touch_driver, TouchDriver, TouchPhase, TouchTool};
+#[cfg(target_os = "linux")]
+use crate::uinput::UinputDriver;
 
 use crate::action_types::*;
 
 const ACTION_DELAY: Duration = Duration::from_millis(500);
-const ACTION_TIMEOUT: Duration = Duration::from_secs(10);
+pub const ACTION_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often wait_for looks at the screen again
+const WAIT_FOR_INTERVAL: Duration = Duration::from_millis(250);
 const SCREENSHOT_DELAY: Duration = Duration::from_secs(2);
+const DISPLAY_PROBE_INTERVAL: Duration = Duration::from_secs(2);
+const POLICY_POLL_INTERVAL: Duration = Duration::from_secs(2);
 const DOUBLE_CLICK_DELAY: Duration = Duration::from_millis(100);
+/// Width failure screenshots are reduced to, enough to see what went wrong
+const FAILURE_SCREENSHOT_WIDTH: u32 = 640;
+/// Consecutive input driver failures before the driver is torn down and recreated
+const DRIVER_FAILURE_THRESHOLD: u32 = 3;
+/// Error responses kept for monitor clients that fall behind, far more than the broadcast buffer
+const RECENT_MONITOR_ERRORS: usize = 1000;
+/// Why actions are refused once the queue is paused for a restart
+const RESTARTING: &str = "The server is restarting, send the action again once it is back";
+const TAP_HOLD: Duration = Duration::from_millis(50);
+const TOUCH_STEP_INTERVAL: Duration = Duration::from_millis(10);
+const SWIPE_DURATION_MS: u64 = 300;
+pub const SMOOTH_SCROLL_DURATION_MS: u64 = 300;
+pub const HOVER_DURATION_MS: u64 = 1_000;
+/// How far each channel of a target pixel may be from the expected color, for antialiasing
+const PIXEL_TOLERANCE: u8 = 24;
+/// Half the size of the crop returned with a stale target
+const STALE_CROP_RADIUS: u32 = 50;
+/// Area around a target read to check its expected text, wide enough for a label
+const TEXT_CHECK_SIZE: (u32, u32) = (400, 80);
+/// Most actions one batch may hold
+pub const MAX_BATCH_ACTIONS: usize = 64;
+const SCROLL_STEP_INTERVAL: Duration = Duration::from_millis(10);
+/// How fast a flick slows down, its speed drops to about a third after this many seconds
+const KINETIC_SCROLL_TIME_CONSTANT: f64 = 0.325;
+/// A flick stops once it is slower than this, in pixels per second
+const KINETIC_SCROLL_MIN_VELOCITY: f64 = 20.0;
 
-// Helper function to just get the screen size without taking a screenshot
-async fn get_screen_size() -> Result<(u32, u32), ActionError> {
-    Monitor::all()
-        .map_err(|_| ActionError::ExecutionFailed("Failed to get monitors".to_string()))
-        .and_then(|monitors| {
-            monitors
-                .first()
-                .cloned()
-                .ok_or_else(|| ActionError::ExecutionFailed("No monitor found".to_string()))
-        })
-        .map(|monitor| {
-            let width = monitor.width();
-            let height = monitor.height();
-            (width, height)
-        })
+pub trait InputDriver: Mouse + Keyboard + Send + 'static {}
+impl<T: Mouse + Keyboard + Send + 'static> InputDriver for T {}
+
+// Forwarding impls so the backend can be chosen at runtime
+impl Mouse for Box<dyn InputDriver> {
+    fn button(&mut self, button: Button, direction: Direction) -> InputResult<()> {
+        (**self).button(button, direction)
+    }
+
+    fn move_mouse(&mut self, x: i32, y: i32, coordinate: Coordinate) -> InputResult<()> {
+        (**self).move_mouse(x, y, coordinate)
+    }
+
+    fn scroll(&mut self, length: i32, axis: Axis) -> InputResult<()> {
+        (**self).scroll(length, axis)
+    }
+
+    fn main_display(&self) -> InputResult<(i32, i32)> {
+        (**self).main_display()
+    }
+
+    fn location(&self) -> InputResult<(i32, i32)> {
+        (**self).location()
+    }
 }
 
-// Helper function for taking screenshots - can be used by both instance and static methods
-async fn take_screenshot() -> Result<String, ActionError> {
-    // Screenshot delay is slightly longer
-    sleep(SCREENSHOT_DELAY).await;
-
-    // Capture the image
-    Monitor::all()
-        .map_err(|_| ActionError::ExecutionFailed("Failed to get monitors".to_string()))
-        .and_then(|monitors| {
-            monitors
-                .first()
-                .cloned()
-                .ok_or_else(|| ActionError::ExecutionFailed("No monitor found".to_string()))
-        })
-        .and_then(|monitor| {
-            monitor
-                .capture_image()
-                .map_err(|_| ActionError::ExecutionFailed("Failed to capture image".to_string()))
-        })
-        .and_then(|image| {
-            let mut cursor = Cursor::new(Vec::new());
-            image
-                .write_to(&mut cursor, ImageFormat::Png)
-                .map_err(|_| ActionError::ExecutionFailed("Failed to encode image".to_string()))?;
-            let bytes = cursor.into_inner();
-            let base64_image = BASE64.encode(bytes);
-            Ok(base64_image)
-        })
+impl Keyboard for Box<dyn InputDriver> {
+    fn fast_text(&mut self, text: &str) -> InputResult<Option<()>> {
+        (**self).fast_text(text)
+    }
+
+    fn text(&mut self, text: &str) -> InputResult<()> {
+        (**self).text(text)
+    }
+
+    fn key(&mut self, key: Key, direction: Direction) -> InputResult<()> {
+        (**self).key(key, direction)
+    }
+
+    fn raw(&mut self, keycode: u16, direction: Direction) -> InputResult<()> {
+        (**self).raw(keycode, direction)
+    }
 }
 
-pub trait InputDriver: Mouse + Keyboard + Send + 'static {}
-impl<T: Mouse + Keyboard + Send + 'static> InputDriver for T {}
+/// A button or key the input driver holds down
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Held {
+    Button(Button),
+    Key(Key),
+    Raw(u16),
+}
+
+/// An input driver that remembers what it holds down, so an action cut off midway releases
+/// what it pressed and nothing a client holds on purpose. It also tells the driver's own
+/// failures apart from an action failing for other reasons, like a screen capture
+pub struct TrackedInput<T> {
+    driver: T,
+    held: Vec<Held>,
+    // Since the current step began, whether an event went out and whether a call failed
+    sent: bool,
+    failed: std::cell::Cell<bool>,
+}
+
+impl<T> TrackedInput<T> {
+    fn new(driver: T) -> Self {
+        Self {
+            driver,
+            held: Vec::new(),
+            sent: false,
+            failed: std::cell::Cell::new(false),
+        }
+    }
+
+    /// Buttons and keys down right now, in the order they were pressed
+    pub fn held(&self) -> Vec<Held> {
+        self.held.clone()
+    }
+
+    // Start watching a new step
+    fn begin_step(&mut self) {
+        self.sent = false;
+        self.failed.set(false);
+    }
+
+    // Whether an input event went out during the step
+    fn sent(&self) -> bool {
+        self.sent
+    }
+
+    // Whether the driver itself failed during the step
+    fn failed(&self) -> bool {
+        self.failed.get()
+    }
+
+    fn check<R>(&self, result: InputResult<R>) -> InputResult<R> {
+        if result.is_err() {
+            self.failed.set(true);
+        }
+        result
+    }
+
+    fn check_sent<R>(&mut self, result: InputResult<R>) -> InputResult<R> {
+        let result = self.check(result)?;
+        self.sent = true;
+        Ok(result)
+    }
+
+    fn track(&mut self, input: Held, direction: Direction) {
+        match direction {
+            Press if !self.held.contains(&input) => self.held.push(input),
+            Release => self.held.retain(|held| *held != input),
+            _ => {}
+        }
+    }
+}
+
+impl<T: InputDriver> TrackedInput<T> {
+    // Release what was pressed since `before`, last pressed first
+    fn release_since(&mut self, before: &[Held]) {
+        let pressed: Vec<Held> = self
+            .held
+            .iter()
+            .filter(|held| !before.contains(held))
+            .copied()
+            .collect();
+        for held in pressed.into_iter().rev() {
+            let _ = match held {
+                Held::Button(button) => self.button(button, Release),
+                Held::Key(key) => self.key(key, Release),
+                Held::Raw(keycode) => self.raw(keycode, Release),
+            };
+        }
+    }
+}
+
+impl<T> std::ops::Deref for TrackedInput<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.driver
+    }
+}
+
+impl<T> std::ops::DerefMut for TrackedInput<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.driver
+    }
+}
+
+impl<T: Mouse> Mouse for TrackedInput<T> {
+    fn button(&mut self, button: Button, direction: Direction) -> InputResult<()> {
+        let result = self.driver.button(button, direction);
+        self.check_sent(result)?;
+        self.track(Held::Button(button), direction);
+        Ok(())
+    }
+
+    fn move_mouse(&mut self, x: i32, y: i32, coordinate: Coordinate) -> InputResult<()> {
+        let result = self.driver.move_mouse(x, y, coordinate);
+        self.check_sent(result)
+    }
+
+    fn scroll(&mut self, length: i32, axis: Axis) -> InputResult<()> {
+        let result = self.driver.scroll(length, axis);
+        self.check_sent(result)
+    }
+
+    fn main_display(&self) -> InputResult<(i32, i32)> {
+        self.check(self.driver.main_display())
+    }
+
+    fn location(&self) -> InputResult<(i32, i32)> {
+        self.check(self.driver.location())
+    }
+}
+
+impl<T: Keyboard> Keyboard for TrackedInput<T> {
+    fn fast_text(&mut self, text: &str) -> InputResult<Option<()>> {
+        let result = self.driver.fast_text(text);
+        self.check_sent(result)
+    }
+
+    fn text(&mut self, text: &str) -> InputResult<()> {
+        let result = self.driver.text(text);
+        self.check_sent(result)
+    }
+
+    fn key(&mut self, key: Key, direction: Direction) -> InputResult<()> {
+        let result = self.driver.key(key, direction);
+        self.check_sent(result)?;
+        self.track(Held::Key(key), direction);
+        Ok(())
+    }
+
+    fn raw(&mut self, keycode: u16, direction: Direction) -> InputResult<()> {
+        let result = self.driver.raw(keycode, direction);
+        self.check_sent(result)?;
+        self.track(Held::Raw(keycode), direction);
+        Ok(())
+    }
+}
+
+/// Builds a fresh input driver, used to recover when the current one stops working
+pub type DriverFactory<T> = Arc<dyn Fn() -> Result<T, String> + Send + Sync>;
 
-#[derive(Clone)]
 pub struct ActionQueue<T: InputDriver> {
-    queue: Arc<Mutex<Vec<QueueItem>>>,
-    input_driver: Arc<Mutex<T>>,
+    /// Submitting only sends here, the pending actions are owned by the dispatcher task
+    submit_tx: mpsc::UnboundedSender<Submission>,
+    submit_rx: Arc<std::sync::Mutex<Option<mpsc::UnboundedReceiver<Submission>>>>,
+    input_driver: Arc<Mutex<TrackedInput<T>>>,
+    driver_factory: Option<DriverFactory<T>>,
+    touch_driver: Arc<Mutex<Option<Box<dyn TouchDriver>>>>,
+    /// The touch or pen contact that is down, so a gesture cut off midway can be lifted
+    open_contact: Arc<std::sync::Mutex<Option<(TouchTool, i32, i32)>>>,
+    capture: Arc<dyn ScreenCapture>,
+    clipboard: Arc<dyn Clipboard>,
+    recognizer: Arc<dyn TextRecognizer>,
+    approvals: Arc<ApprovalRegistry>,
+    runs: Arc<RunRegistry>,
+    relay: Arc<RelayRegistry>,
+    agents: Arc<AgentRegistry>,
+    display: Arc<DisplayWatch>,
+    saved_cursor: Arc<std::sync::Mutex<Option<(i32, i32)>>>,
+    last_cursor: Arc<std::sync::Mutex<Option<CursorSnapshot>>>,
+    /// On its own channel rather than the monitor broadcast, so screenshots filling the
+    /// buffer can't push state changes out of it
+    observation: Arc<watch::Sender<ObservationTracker>>,
+    /// Connected takeover sessions, agent actions are held while there are any
+    takeover_sessions: Arc<watch::Sender<u32>>,
+    /// Only read-only actions are admitted while disarmed, see VALK_ARM_TOKEN
+    armed: Arc<AtomicBool>,
+    /// Set once the server is restarting, actions are left to the next process from then on
+    paused: Arc<watch::Sender<bool>>,
     monitor_tx: broadcast::Sender<MonitorEvent>,
+    /// Recent error responses sent to the monitors, for clients that lagged and missed them
+    monitor_errors: Arc<std::sync::Mutex<MonitorErrors>>,
     monitor_config: MonitorConfig,
+    bandwidth: Arc<BandwidthBudget>,
+    pacer: Arc<Pacer>,
+    policy: Arc<PolicyWatch>,
+    metrics: Arc<Metrics>,
+    config: Arc<Config>,
+}
+
+// Manual impl so cloning only clones the shared handles and doesn't require T: Clone
+impl<T: InputDriver> Clone for ActionQueue<T> {
+    fn clone(&self) -> Self {
+        ActionQueue {
+            submit_tx: self.submit_tx.clone(),
+            submit_rx: self.submit_rx.clone(),
+            input_driver: self.input_driver.clone(),
+            driver_factory: self.driver_factory.clone(),
+            touch_driver: self.touch_driver.clone(),
+            open_contact: self.open_contact.clone(),
+            capture: self.capture.clone(),
+            clipboard: self.clipboard.clone(),
+            recognizer: self.recognizer.clone(),
+            approvals: self.approvals.clone(),
+            runs: self.runs.clone(),
+            relay: self.relay.clone(),
+            agents: self.agents.clone(),
+            display: self.display.clone(),
+            saved_cursor: self.saved_cursor.clone(),
+            last_cursor: self.last_cursor.clone(),
+            observation: self.observation.clone(),
+            takeover_sessions: self.takeover_sessions.clone(),
+            armed: self.armed.clone(),
+            paused: self.paused.clone(),
+            monitor_tx: self.monitor_tx.clone(),
+            monitor_errors: self.monitor_errors.clone(),
+            monitor_config: self.monitor_config.clone(),
+            bandwidth: self.bandwidth.clone(),
+            pacer: self.pacer.clone(),
+            policy: self.policy.clone(),
+            metrics: self.metrics.clone(),
+            config: self.config.clone(),
+        }
+    }
 }
 
-pub type SharedQueue = Arc<ActionQueue<Enigo>>;
+pub type SharedQueue = Arc<ActionQueue<Box<dyn InputDriver>>>;
 
-pub async fn create_action_queue() -> SharedQueue {
-    let settings = Settings {
-        x11_display: Some(env::var("DISPLAY").unwrap()),
-        ..Settings::default()
+fn create_input_driver(
+    backend: InputBackend,
+    display: &DisplayWatch,
+    screen_size: (u32, u32),
+) -> Result<Box<dyn InputDriver>, String> {
+    match backend {
+        InputBackend::Enigo => {
+            // Asked on every call so a recreated driver follows the watch to a new display
+            let display = display
+                .current()
+                .ok_or_else(|| "DISPLAY is not set".to_string())?;
+            let settings = Settings {
+                x11_display: Some(display),
+                ..Settings::default()
+            };
+            let enigo = Enigo::new(&settings).map_err(|e| e.to_string())?;
+            Ok(Box::new(enigo))
+        }
+        #[cfg(target_os = "linux")]
+        InputBackend::Uinput => Ok(Box::new(UinputDriver::new(screen_size)?)),
+        #[cfg(not(target_os = "linux"))]
+        InputBackend::Uinput => {
+            let _ = screen_size;
+            Err("The uinput backend is only available on Linux".to_string())
+        }
+        // Simulated input is built together with its virtual screen and never needs recreating
+        InputBackend::Simulated => {
+            Err("The simulated backend has no input driver of its own".to_string())
+        }
+    }
+}
+
+/// The queue the server drives the desktop with, or why the configuration can't give one
+pub async fn create_action_queue(config: &Config) -> Result<SharedQueue, String> {
+    if config.input_backend == InputBackend::Simulated {
+        return create_simulated_queue(config).await;
+    }
+    let display = Arc::new(DisplayWatch::default());
+    let capture: Arc<dyn ScreenCapture> = match &config.capture_image {
+        Some(path) => Arc::new(ImageCapture::open(path)?),
+        None => Arc::new(XcapCapture::new(display.clone())),
     };
-    let enigo = Enigo::new(&settings).unwrap();
-    let queue = Arc::new(ActionQueue::new(enigo));
+    let screen_size = capture.screen_size().unwrap_or((1920, 1080));
+    let input_driver = create_input_driver(config.input_backend, &display, screen_size)
+        .map_err(|e| format!("Failed to create the input driver: {}", e))?;
+    let backend = config.input_backend;
+    let factory_display = display.clone();
+    let mut queue = ActionQueue::new(input_driver, config.clone())
+        .with_driver_factory(Arc::new(move || {
+            create_input_driver(backend, &factory_display, screen_size)
+        }))
+        .with_display(display)
+        .with_capture(capture);
+    if let Some(touch_driver) = platform_touch_driver(screen_size) {
+        queue = queue.with_touch_driver(touch_driver);
+    }
+    let queue = Arc::new(queue);
+    queue.start_processing().await;
+    queue.start_display_watch();
+    queue.start_policy_watch();
+    Ok(queue)
+}
+
+// A queue driving a virtual screen, there is no display to watch or touch devices to create
+async fn create_simulated_queue(config: &Config) -> Result<SharedQueue, String> {
+    let mut screen = VirtualScreen::parse(&config.simulated_monitors)
+        .map_err(|e| format!("Invalid VALK_SIMULATED_MONITORS: {}", e))?;
+    if let Some(path) = &config.capture_image {
+        let image = image::open(path).map_err(|e| format!("Failed to open {}: {}", path, e))?;
+        screen = screen.with_image(image.to_rgba8());
+    }
+    let input_driver: Box<dyn InputDriver> = Box::new(SimulatedInput::new(&screen));
+    let queue = Arc::new(
+        ActionQueue::new(input_driver, config.clone())
+            .with_capture(Arc::new(screen))
+            .with_clipboard(Arc::new(MemoryClipboard::default())),
+    );
     queue.start_processing().await;
-    queue
+    queue.start_policy_watch();
+    Ok(queue)
 }
 
 // Define type aliases for the complex parts
-type ActionSender = oneshot::Sender<Result<ActionOutput, ActionError>>;
-type QueueItem = (Action, ActionSender);
+type ActionResult = (
+    Result<ActionOutput, ActionError>,
+    ActionTiming,
+    Option<ForegroundApp>,
+    Option<Box<FailureScreenshot>>,
+    Option<(i32, i32)>,
+);
+// Errors are for actions that never made it into the queue
+type ActionSender = oneshot::Sender<Result<ActionResult, ActionError>>;
+// The request id goes along for progress events
+type QueueItem = (Action, Instant, ActionSender, Origin, String, StepTimeout);
+// How long the worker lets an action run, and what its batch does when it runs out
+type StepTimeout = (Duration, OnTimeout);
+// Items the worker runs back to back, one action or a whole batch
+type QueueUnit = Vec<QueueItem>;
+// A queue unit and the run whose lane it goes in
+type Submission = (Option<String>, QueueUnit);
+
+// Already validated, so this only fails for requests that skipped validation
+fn parse_modifiers(modifiers: &[String]) -> Result<Vec<Key>, ActionError> {
+    modifiers
+        .iter()
+        .map(|modifier| parse_modifier(modifier))
+        .collect::<Result<Vec<Key>, String>>()
+        .map_err(ActionError::InvalidInput)
+}
+
+/// Wheel clicks due in each 10ms step of a flick of `velocity` pixels per second. The
+/// speed decays exponentially, as touch screen lists do after a flick, so the clicks come
+/// close together at first and spread out towards the end
+pub fn kinetic_scroll_clicks(velocity: u32, pixels_per_click: u32) -> Vec<i32> {
+    let velocity = velocity as f64;
+    let step = SCROLL_STEP_INTERVAL.as_secs_f64();
+    let duration =
+        KINETIC_SCROLL_TIME_CONSTANT * (velocity / KINETIC_SCROLL_MIN_VELOCITY).ln().max(0.0);
+    let steps = (duration / step).ceil() as u32;
+    let mut scrolled = 0;
+    let mut clicks: Vec<i32> = (1..=steps)
+        .map(|n| {
+            // Distance covered by the end of this step, any remainder carries to the next
+            let t = n as f64 * step;
+            let distance = velocity
+                * KINETIC_SCROLL_TIME_CONSTANT
+                * (1.0 - (-t / KINETIC_SCROLL_TIME_CONSTANT).exp());
+            let due = (distance / pixels_per_click.max(1) as f64).round() as i32;
+            let now = due - scrolled;
+            scrolled = due;
+            now
+        })
+        .collect();
+    // No point waiting out the tail once the last click is done
+    while clicks.last() == Some(&0) {
+        clicks.pop();
+    }
+    clicks
+}
+
+fn enigo_button(button: MouseButton) -> Button {
+    match button {
+        MouseButton::Left => Button::Left,
+        MouseButton::Right => Button::Right,
+        MouseButton::Middle => Button::Middle,
+    }
+}
+
+// Error responses broadcast to the monitors so far, and the most recent of them
+#[derive(Default)]
+struct MonitorErrors {
+    sent: u64,
+    recent: VecDeque<MonitorEvent>,
+}
+
+// The share of its run's budget an admitted request holds until its response is recorded. A
+// request dropped before then, because its client went away while it waited for approval or
+// in the queue, gives it back
+struct Reservation<'a> {
+    runs: &'a RunRegistry,
+    request: Option<&'a ActionRequest>,
+}
+
+impl Reservation<'_> {
+    // Recording the response settled the reservation
+    fn recorded(mut self) {
+        self.request = None;
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        if let Some(request) = self.request {
+            self.runs.release(request);
+        }
+    }
+}
+
+// An approval a request waits for, resolved as it drops. Dropped before a decision, because the
+// client went away, it is withdrawn so supervisors aren't asked about a request nobody waits for
+struct AwaitedApproval<'a, T: InputDriver> {
+    queue: &'a ActionQueue<T>,
+    approval_id: String,
+    approved: bool,
+}
+
+impl<T: InputDriver> Drop for AwaitedApproval<'_, T> {
+    fn drop(&mut self) {
+        // Already gone once decided
+        self.queue.approvals.cancel(&self.approval_id);
+        self.queue
+            .send_monitor_event(MonitorEventPayload::ApprovalResolved {
+                approval_id: std::mem::take(&mut self.approval_id),
+                approved: self.approved,
+            });
+    }
+}
+
+/// Who submitted an action, input from a human who has taken over goes ahead of every agent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Origin {
+    Agent,
+    Takeover,
+}
 
 // Implementation stays on the generic type
 impl<T: InputDriver> ActionQueue<T> {
-    pub fn new(input_driver: T) -> Self {
-        let (monitor_tx, _) = broadcast::channel(100);
+    pub fn new(input_driver: T, config: Config) -> Self {
+        let display = Arc::new(DisplayWatch::default());
+        let (monitor_tx, _) = broadcast::channel(config.monitor_buffer);
+        let (submit_tx, submit_rx) = mpsc::unbounded_channel();
+        let policy = Arc::new(PolicyWatch::new(
+            config.policy_dir.as_ref().map(PathBuf::from),
+        ));
         ActionQueue {
-            queue: Arc::new(Mutex::new(Vec::new())),
-            input_driver: Arc::new(Mutex::new(input_driver)),
+            submit_tx,
+            submit_rx: Arc::new(std::sync::Mutex::new(Some(submit_rx))),
+            input_driver: Arc::new(Mutex::new(TrackedInput::new(input_driver))),
+            driver_factory: None,
+            touch_driver: Arc::new(Mutex::new(None)),
+            open_contact: Arc::new(std::sync::Mutex::new(None)),
+            capture: Arc::new(RedactedCapture::new(
+                Arc::new(XcapCapture::new(display.clone())),
+                policy.clone(),
+            )),
+            clipboard: Arc::new(SystemClipboard::default()),
+            recognizer: Arc::from(platform_recognizer()),
+            approvals: Arc::new(ApprovalRegistry::default()),
+            runs: Arc::new(match &config.state_dir {
+                Some(dir) => RunRegistry::with_recordings(Path::new(dir).join("recordings")),
+                None => RunRegistry::default(),
+            }),
+            relay: Arc::new(RelayRegistry::default()),
+            agents: Arc::new(AgentRegistry::new(agent::configured_backend(&config))),
+            display,
+            saved_cursor: Arc::new(std::sync::Mutex::new(None)),
+            last_cursor: Arc::new(std::sync::Mutex::new(None)),
+            observation: Arc::new(watch::channel(ObservationTracker::default()).0),
+            takeover_sessions: Arc::new(watch::channel(0).0),
+            armed: Arc::new(AtomicBool::new(true)),
+            paused: Arc::new(watch::channel(false).0),
             monitor_config: MonitorConfig::default(), // TODO: Make this configurable
+            bandwidth: Arc::new(BandwidthBudget::new(config.monitor_bandwidth)),
+            pacer: Arc::new(Pacer::new(config.pacing_rules.clone())),
+            policy,
+            metrics: Arc::new(Metrics::default()),
             monitor_tx,
+            monitor_errors: Arc::default(),
+            config: Arc::new(config),
+        }
+    }
+
+    /// Lets the worker recreate the input driver after repeated failures
+    pub fn with_driver_factory(mut self, factory: DriverFactory<T>) -> Self {
+        self.driver_factory = Some(factory);
+        self
+    }
+
+    /// Enables touch and pen actions using the given driver
+    pub fn with_touch_driver(mut self, touch_driver: Box<dyn TouchDriver>) -> Self {
+        self.touch_driver = Arc::new(Mutex::new(Some(touch_driver)));
+        self
+    }
+
+    /// Shares the display the input driver factory and capture were given, so the watch moves
+    /// them all together
+    pub fn with_display(mut self, display: Arc<DisplayWatch>) -> Self {
+        self.display = display;
+        self
+    }
+
+    /// Takes screenshots from the given backend instead of the display server
+    pub fn with_capture(mut self, capture: Arc<dyn ScreenCapture>) -> Self {
+        self.capture = Arc::new(RedactedCapture::new(capture, self.policy.clone()));
+        self
+    }
+
+    /// Pastes through the given clipboard instead of the display server's
+    pub fn with_clipboard(mut self, clipboard: Arc<dyn Clipboard>) -> Self {
+        self.clipboard = clipboard;
+        self
+    }
+
+    /// Reads text with the given recognizer instead of the one the server was built with
+    #[cfg(test)]
+    pub fn with_recognizer(mut self, recognizer: Arc<dyn TextRecognizer>) -> Self {
+        self.recognizer = recognizer;
+        self
+    }
+
+    /// Drives agent runs with the given model instead of the configured one
+    #[cfg(test)]
+    pub fn with_model_backend(mut self, backend: Arc<dyn agent::ModelBackend>) -> Self {
+        self.agents = Arc::new(AgentRegistry::new(Some(backend)));
+        self
+    }
+
+    pub fn capture(&self) -> &dyn ScreenCapture {
+        self.capture.as_ref()
+    }
+
+    pub fn policy(&self) -> &PolicyWatch {
+        &self.policy
+    }
+
+    pub fn approvals(&self) -> &ApprovalRegistry {
+        &self.approvals
+    }
+
+    pub fn runs(&self) -> &RunRegistry {
+        &self.runs
+    }
+
+    pub fn relay(&self) -> &RelayRegistry {
+        &self.relay
+    }
+
+    pub fn agents(&self) -> &AgentRegistry {
+        &self.agents
+    }
+
+    pub fn display(&self) -> &Arc<DisplayWatch> {
+        &self.display
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn bandwidth(&self) -> &BandwidthBudget {
+        &self.bandwidth
+    }
+
+    /// Count bytes written to a monitor client against the bandwidth budget
+    pub fn record_monitor_bytes(&self, bytes: usize) {
+        self.bandwidth.record(bytes);
+        Metrics::increment(&self.metrics.monitor_bytes_sent, bytes as u64);
+    }
+
+    /// Position stored by the last SaveCursor action
+    pub fn saved_cursor(&self) -> Option<(i32, i32)> {
+        *self.saved_cursor.lock().unwrap()
+    }
+
+    // Hold the request until a human approves it, rejects it, or the approval times out
+    async fn await_approval(&self, request: &ActionRequest) -> Result<(), ActionError> {
+        let (approval, rx) = self.approvals.request(request);
+        let approval_id = approval.approval_id.clone();
+        self.send_monitor_event(MonitorEventPayload::ApprovalRequired(approval));
+        let mut awaited = AwaitedApproval {
+            queue: self,
+            approval_id,
+            approved: false,
+        };
+
+        let approval_timeout = Duration::from_secs(self.config.approval_timeout_secs);
+        let result = match timeout(approval_timeout, rx).await {
+            Ok(Ok(ApprovalDecision::Approved)) => Ok(()),
+            Ok(Ok(ApprovalDecision::Rejected(reason))) => {
+                Err(ActionError::PermissionDenied(match reason {
+                    Some(reason) => format!("Action rejected by approver: {}", reason),
+                    None => "Action rejected by approver".to_string(),
+                }))
+            }
+            Ok(Err(e)) => Err(ActionError::ChannelError(e.to_string())),
+            Err(_) => Err(ActionError::PermissionDenied(
+                "Timed out waiting for approval".to_string(),
+            )),
+        };
+
+        awaited.approved = result.is_ok();
+        result
+    }
+
+    /// Release the mouse buttons the input driver still holds. Actions release theirs before
+    /// they end, so any still down were left by one cut off midway
+    pub async fn release_buttons(&self) -> Vec<String> {
+        let mut input_driver = self.lock_input_driver().await;
+        let buttons: Vec<Button> = input_driver
+            .held()
+            .into_iter()
+            .filter_map(|held| match held {
+                Held::Button(button) => Some(button),
+                _ => None,
+            })
+            .collect();
+        for button in buttons.iter().rev() {
+            let _ = input_driver.button(*button, Release);
+        }
+        buttons
+            .iter()
+            .map(|button| format!("{:?}", button).to_lowercase())
+            .collect()
+    }
+
+    /// Current pointer location as reported by the input driver
+    pub async fn cursor_location(&self) -> Option<(i32, i32)> {
+        self.lock_input_driver().await.location().ok()
+    }
+
+    // Lock the input driver, recording how long it took in the lock wait metrics
+    async fn lock_input_driver(&self) -> MutexGuard<'_, TrackedInput<T>> {
+        let started = Instant::now();
+        let input_driver = self.input_driver.lock().await;
+        self.metrics
+            .input_driver_lock_wait
+            .observe(started.elapsed());
+        input_driver
+    }
+
+    /// Cursor position without waiting for the queue, falling back to the position
+    /// recorded after the last action while the worker holds the driver
+    pub fn cursor_snapshot(&self) -> Option<CursorSnapshot> {
+        match self.input_driver.try_lock() {
+            Ok(input_driver) => self.record_cursor(&input_driver),
+            Err(_) => self
+                .last_cursor
+                .lock()
+                .unwrap()
+                .map(|snapshot| CursorSnapshot {
+                    cached: true,
+                    ..snapshot
+                }),
         }
     }
 
-    pub fn subscribe_monitor(&self) -> broadcast::Receiver<MonitorEvent> {
-        self.monitor_tx.subscribe()
+    fn record_cursor(&self, input_driver: &T) -> Option<CursorSnapshot> {
+        let (x, y) = input_driver.location().ok()?;
+        let snapshot = CursorSnapshot {
+            x,
+            y,
+            cached: false,
+            timestamp: Utc::now(),
+        };
+        *self.last_cursor.lock().unwrap() = Some(snapshot);
+        Some(snapshot)
+    }
+
+    /// Change the observation state, waking monitor clients if anything changed
+    pub fn update_observation(&self, change: impl FnOnce(&mut ObservationState)) {
+        self.observation
+            .send_if_modified(|observation| observation.update(change).is_some());
+    }
+
+    #[cfg(test)]
+    pub fn observation_snapshot(&self) -> (u64, serde_json::Value) {
+        self.observation.borrow().snapshot()
+    }
+
+    /// Changes whenever the observation state does, monitor clients send the difference
+    /// from what they sent last
+    pub fn subscribe_observation(&self) -> watch::Receiver<ObservationTracker> {
+        self.observation.subscribe()
+    }
+
+    /// Subscribe along with the number of error responses sent so far, see
+    /// missed_monitor_errors
+    pub fn subscribe_monitor(&self) -> (broadcast::Receiver<MonitorEvent>, u64) {
+        let errors = self.monitor_errors.lock().unwrap();
+        (self.monitor_tx.subscribe(), errors.sent)
+    }
+
+    /// The error responses numbered after `after` up to `through`, for a monitor client that
+    /// fell behind the broadcast buffer and missed them
+    pub fn missed_monitor_errors(&self, after: u64, through: u64) -> Vec<MonitorEvent> {
+        let errors = self.monitor_errors.lock().unwrap();
+        let missed: Vec<MonitorEvent> = errors
+            .recent
+            .iter()
+            .filter(|event| event.errors_sent > after && event.errors_sent <= through)
+            .cloned()
+            .collect();
+        let resent = missed.len() as u64;
+        Metrics::increment(&self.metrics.monitor_errors_resent, resent);
+        Metrics::increment(
+            &self.metrics.monitor_errors_lost,
+            through.saturating_sub(after).saturating_sub(resent),
+        );
+        missed
     }
 
     // Send an event to the monitors
     pub fn send_monitor_event(&self, payload: MonitorEventPayload) {
-        let event = MonitorEvent {
-            event_id: Uuid::new_v4().to_string(),
-            payload,
+        if self.config.monitor_overflow == MonitorOverflow::DropNewScreenshots
+            && matches!(payload, MonitorEventPayload::ScreenUpdate { .. })
+            && self.monitor_tx.len() >= self.config.monitor_buffer / 2
+        {
+            Metrics::increment(&self.metrics.monitor_screenshots_dropped, 1);
+            return;
+        }
+        let payload = match payload {
+            MonitorEventPayload::ScreenUpdate {
+                action_id,
+                image,
+                image_format,
+                screen_size,
+                timestamp,
+            } => {
+                let Some(level) = self.bandwidth.admit_frame() else {
+                    Metrics::increment(&self.metrics.monitor_screenshots_throttled, 1);
+                    return;
+                };
+                if level >= Degradation::Scale {
+                    // Re-encoding takes a while, so it runs on the blocking pool and the
+                    // frame is sent once it is done
+                    let queue = self.clone();
+                    tokio::spawn(async move {
+                        let degraded = tokio::task::spawn_blocking(move || {
+                            degrade_frame(image, image_format, level)
+                        })
+                        .await;
+                        match degraded {
+                            Ok(Ok((image, image_format))) => {
+                                queue.broadcast(MonitorEventPayload::ScreenUpdate {
+                                    action_id,
+                                    image,
+                                    image_format,
+                                    screen_size,
+                                    timestamp,
+                                })
+                            }
+                            _ => Metrics::increment(
+                                &queue.metrics.monitor_screenshots_undecodable,
+                                1,
+                            ),
+                        }
+                    });
+                    return;
+                }
+                MonitorEventPayload::ScreenUpdate {
+                    action_id,
+                    image,
+                    image_format,
+                    screen_size,
+                    timestamp,
+                }
+            }
+            payload => payload,
         };
-        let _ = self.monitor_tx.send(event);
+        self.broadcast(payload);
+    }
+
+    // Hand an event to every monitor client
+    fn broadcast(&self, payload: MonitorEventPayload) {
+        if let MonitorEventPayload::ScreenUpdate { action_id, .. } = &payload {
+            let action_id = action_id.clone();
+            self.update_observation(|state| state.last_screenshot_id = Some(action_id));
+        }
+        // Numbered and sent under the lock, so the numbers follow the order of the broadcast
+        let mut errors = self.monitor_errors.lock().unwrap();
+        let mut event = MonitorEvent::new(payload);
+        if event.payload.is_error_response() {
+            errors.sent += 1;
+            event.errors_sent = errors.sent;
+            if errors.recent.len() >= RECENT_MONITOR_ERRORS {
+                errors.recent.pop_front();
+            }
+            errors.recent.push_back(event.clone());
+        }
+        event.errors_sent = errors.sent;
+        if self.monitor_tx.send(event).is_ok() {
+            Metrics::increment(&self.metrics.monitor_events_sent, 1);
+        }
     }
 
     pub async fn send_screen_update(&self, action_id: String) {
         if self.monitor_config.always_send_screen_updates {
-            // First get a screenshot
-            if let Ok(image_data) = take_screenshot().await {
-                // Then get the screen size separately
-                let screen_size = get_screen_size().await.unwrap_or((1920, 1080));
-
+            if let Ok(ActionOutput::Screenshot {
+                image,
+                format,
+                width,
+                height,
+                captured_at,
+                ..
+            }) = self
+                .take_screenshot(
+                    &ScreenshotInput {
+                        show_cursor: self.config.monitor_show_cursor,
+                        ..Default::default()
+                    },
+                    // Recorded right after the action that this update follows
+                    self.cursor_snapshot().map(|cursor| (cursor.x, cursor.y)),
+                )
+                .await
+            {
                 self.send_monitor_event(MonitorEventPayload::ScreenUpdate {
                     action_id,
-                    image: image_data,
-                    screen_size,
-                    timestamp: Utc::now(),
+                    image,
+                    image_format: format,
+                    screen_size: (width, height),
+                    timestamp: captured_at,
                 });
             }
         }
@@ -146,10 +969,10 @@ impl<T: InputDriver> ActionQueue<T> {
 
     pub async fn send_cursor_update(&self, action_id: String) {
         if self.monitor_config.always_send_cursor_updates {
-            // Get the current cursor position
-            let (x, y) = match self.input_driver.lock().await.location() {
-                Ok((x, y)) => (x as u32, y as u32),
-                Err(_) => (0, 0), // Default to 0,0 if we can't get the position
+            // Don't wait for the worker, it may already be running the next action
+            let (x, y) = match self.cursor_snapshot() {
+                Some(snapshot) => (snapshot.x, snapshot.y),
+                None => (0, 0), // Default to 0,0 if we can't get the position
             };
 
             self.send_monitor_event(MonitorEventPayload::CursorUpdate {
@@ -161,66 +984,270 @@ impl<T: InputDriver> ActionQueue<T> {
         }
     }
 
-    // Add an action to the queue
-    async fn queue_action(
+    // Hand an action to the dispatcher, never waiting on the running action
+    fn queue_unit(
+        &self,
+        requests: &[ActionRequest],
+        origin: Origin,
+    ) -> Result<Vec<oneshot::Receiver<Result<ActionResult, ActionError>>>, ActionError> {
+        let queued_at = Instant::now();
+        let actions = requests
+            .iter()
+            .map(|request| self.in_screen_space(request))
+            .collect::<Result<Vec<_>, _>>()?;
+        let (unit, receivers) = requests
+            .iter()
+            .zip(actions)
+            .map(|(request, action)| {
+                let (tx, rx) = oneshot::channel();
+                let step_timeout = (step_limit(request), request.on_timeout);
+                let item = (
+                    action,
+                    queued_at,
+                    tx,
+                    origin,
+                    request.id.clone(),
+                    step_timeout,
+                );
+                (item, rx)
+            })
+            .unzip();
+        // A batch goes in the lane of its first action's run
+        let lane = requests.first().and_then(|request| request.run_id.clone());
+        self.submit_tx
+            .send((lane, unit))
+            .map_err(|_| ActionError::ChannelError("Action queue is not running".to_string()))?;
+        Ok(receivers)
+    }
+
+    // Model pixels per screen pixel for a request of a run working in model space, from the
+    // size of the monitor the action targets
+    fn model_scale(&self, request: &ActionRequest) -> Result<Option<f64>, ActionError> {
+        let Some(model) = request
+            .run_id
+            .as_ref()
+            .and_then(|run_id| self.runs.model_resolution(run_id))
+        else {
+            return Ok(None);
+        };
+        let displays = self.capture.displays()?;
+        let selector = match &request.action {
+            Action::Screenshot { input } => input.monitor,
+            action => action.monitor(),
+        };
+        let display = match selector {
+            Some(selector) => select_display(displays, selector)?,
+            None => displays
+                .into_iter()
+                .find(|display| display.is_primary)
+                .ok_or_else(|| ActionError::ExecutionFailed("No primary monitor".to_string()))?,
+        };
+        Ok(Some(model.scale_from(display.width, display.height)))
+    }
+
+    // The action as the worker runs it. Runs working in model space send coordinates in the
+    // pixels of screenshots scaled to their model resolution, which are converted here
+    fn in_screen_space(&self, request: &ActionRequest) -> Result<Action, ActionError> {
+        match self.model_scale(request)? {
+            Some(scale) => {
+                let action = request.action.in_screen_space(scale);
+                // Coordinates that were fine in model pixels can be out of range once scaled up
+                action.validate()?;
+                Ok(action)
+            }
+            None => Ok(request.action.clone()),
+        }
+    }
+
+    // The response as the client sees it, with positions it returns in model space for runs
+    // working in one
+    fn in_model_space(
         &self,
-        action: Action,
-    ) -> oneshot::Receiver<Result<ActionOutput, ActionError>> {
-        let (tx, rx) = oneshot::channel();
-        let mut queue = self.queue.lock().await;
-        queue.push((action, tx));
-        rx
+        request: &ActionRequest,
+        mut response: ActionResponse,
+    ) -> ActionResponse {
+        if let Ok(Some(scale)) = self.model_scale(request) {
+            response.data = response.data.map(|data| data.in_model_space(scale));
+        }
+        response
     }
 
     pub async fn execute_action(&self, request: ActionRequest) -> ActionResponse {
+        self.execute(request, true).await
+    }
+
+    /// Run an action the operator configured, such as a startup action. It is checked like
+    /// any other, but not held for approval, the operator vouched for it by writing it down
+    /// and there may be nobody connected to approve it yet
+    pub async fn execute_configured(&self, request: ActionRequest) -> ActionResponse {
+        self.execute(request, false).await
+    }
+
+    async fn execute(&self, request: ActionRequest, approval: bool) -> ActionResponse {
         // Send request event
         self.send_monitor_event(MonitorEventPayload::ActionRequest(request.clone()));
 
-        // Process the action
-        let rx = self.queue_action(request.action.clone()).await;
-        let response = match timeout(ACTION_TIMEOUT, rx).await {
-            Ok(result) => match result {
-                Ok(Ok(output)) => {
-                    ActionResponse::success(request.id.clone(), request.action.clone(), output)
+        // Reject invalid input before it reaches the queue, then wait for approval if required
+        let reservation = match self.admit(&request, approval).await {
+            Ok(reservation) => reservation,
+            Err(error) => return self.refuse(&request, error),
+        };
+        let response = self.run_queued(&request, Origin::Agent).await;
+        let response = self.finish(&request, response).await;
+        reservation.recorded();
+        response
+    }
+
+    /// Run requests in order with no other client's actions in between. Every request is
+    /// admitted before any of them runs, and once one fails the rest are skipped
+    pub async fn execute_batch(
+        &self,
+        requests: Vec<ActionRequest>,
+    ) -> Result<Vec<ActionResponse>, ActionError> {
+        let mut responses = Vec::with_capacity(requests.len());
+        self.execute_batch_each(requests, |response| {
+            responses.push(response);
+            true
+        })
+        .await?;
+        Ok(responses)
+    }
+
+    /// Like execute_batch, handing over each response as soon as its action is done. Once
+    /// `each` returns false, e.g. because the client went away, the actions that haven't
+    /// started yet are skipped
+    pub async fn execute_batch_each(
+        &self,
+        requests: Vec<ActionRequest>,
+        mut each: impl FnMut(ActionResponse) -> bool,
+    ) -> Result<(), ActionError> {
+        if !(1..=MAX_BATCH_ACTIONS).contains(&requests.len()) {
+            return Err(ActionError::InvalidInput(format!(
+                "A batch needs between 1 and {} actions, got {}",
+                MAX_BATCH_ACTIONS,
+                requests.len()
+            )));
+        }
+        for request in &requests {
+            self.send_monitor_event(MonitorEventPayload::ActionRequest(request.clone()));
+        }
+
+        // Every action is checked before anyone is asked to approve one, so an approver never
+        // decides on a batch that was going to be refused anyway
+        let mut refused = None;
+        let mut reservations = Vec::with_capacity(requests.len());
+        for (index, request) in requests.iter().enumerate() {
+            match self.check(request).await {
+                Ok(reservation) => reservations.push(reservation),
+                Err(error) => {
+                    refused = Some((index, error));
+                    break;
                 }
-                Ok(Err(error)) => {
-                    ActionResponse::error(request.id.clone(), request.action.clone(), error)
+            }
+        }
+        if refused.is_none() {
+            for (index, request) in requests.iter().enumerate() {
+                if let Err(error) = self.approve(request).await {
+                    refused = Some((index, error));
+                    break;
                 }
-                Err(e) => ActionResponse::error(
-                    request.id.clone(),
-                    request.action.clone(),
-                    ActionError::ChannelError(e.to_string()),
-                ),
-            },
-            Err(_) => {
-                // Timeout occurred - remove action from queue if it's still there
-                let mut queue = self.queue.lock().await;
-                queue.retain(|(a, _)| {
-                    !std::mem::discriminant(a).eq(&std::mem::discriminant(&request.action))
-                });
-                ActionResponse::error(
-                    request.id.clone(),
-                    request.action.clone(),
-                    ActionError::Timeout,
-                )
             }
-        };
+        }
+        if let Some((index, error)) = refused {
+            // None of the batch runs, so nothing it admitted counts against its run
+            drop(reservations);
+            let not_run = ActionError::NotRun(format!(
+                "Not run, action {} of the batch was refused",
+                requests[index].id
+            ));
+            for (i, request) in requests.iter().enumerate() {
+                let error = if i == index { &error } else { &not_run };
+                if !each(self.refuse(request, error.clone())) {
+                    break;
+                }
+            }
+            return Ok(());
+        }
+
+        match self.queue_unit(&requests, Origin::Agent) {
+            Ok(receivers) => {
+                // Dropping the receivers of the rest makes the worker skip them, and dropping
+                // their reservations gives their share of the run's budget back
+                for ((request, rx), reservation) in requests.iter().zip(receivers).zip(reservations)
+                {
+                    let response = self.await_queued(request, rx).await;
+                    let response = self.finish(request, response).await;
+                    reservation.recorded();
+                    if !each(response) {
+                        break;
+                    }
+                }
+            }
+            Err(error) => {
+                for (request, reservation) in requests.iter().zip(reservations) {
+                    let response = ActionResponse::error(
+                        request.id.clone(),
+                        request.action.clone(),
+                        error.clone(),
+                    );
+                    let response = self.finish(request, response).await;
+                    reservation.recorded();
+                    if !each(response) {
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Answer a request that never made it into the queue
+    fn refuse(&self, request: &ActionRequest, error: ActionError) -> ActionResponse {
+        let response = ActionResponse::error(request.id.clone(), request.action.clone(), error)
+            .with_metadata(request.metadata.clone());
+        self.runs.record(request, &response);
+        self.send_monitor_event(MonitorEventPayload::ActionResponse(response.clone()));
+        response
+    }
+
+    // Record the response to a queued request, and show the monitor what the action changed
+    async fn finish(&self, request: &ActionRequest, response: ActionResponse) -> ActionResponse {
+        let response = self
+            .in_model_space(request, response)
+            .with_metadata(request.metadata.clone());
+        self.runs.record(request, &response);
+        self.record_interaction(request, &response);
 
         // Step 1: Send the base response (without data) to the monitor
         self.send_monitor_event(MonitorEventPayload::ActionResponse(response.without_data()));
+        // Pings leave the screen alone, so there is nothing new to capture
+        if let Action::Ping = request.action {
+            return response;
+        }
 
         // Step 2: Handle screenshots/cursor updates for monitoring
         match response.extract_data() {
-            ActionOutput::Screenshot { image } => {
-                // Get screen size for the update
-                let screen_size = get_screen_size().await.unwrap_or((1920, 1080));
-
+            // Observers already have this frame
+            ActionOutput::Screenshot {
+                unchanged: true, ..
+            } => {
+                self.send_cursor_update(request.id.clone()).await;
+            }
+            ActionOutput::Screenshot {
+                image,
+                format,
+                width,
+                height,
+                captured_at,
+                ..
+            } => {
                 // Send screenshot event
                 self.send_monitor_event(MonitorEventPayload::ScreenUpdate {
                     action_id: request.id.clone(),
                     image,
-                    screen_size,
-                    timestamp: Utc::now(),
+                    image_format: format,
+                    screen_size: (width, height),
+                    timestamp: captured_at,
                 });
                 self.send_cursor_update(request.id.clone()).await;
             }
@@ -233,7 +1260,14 @@ impl<T: InputDriver> ActionQueue<T> {
                 });
                 self.send_screen_update(request.id.clone()).await;
             }
-            ActionOutput::NoData => {
+            // Burst frames may be cropped, so the monitor gets a full screen update
+            ActionOutput::Burst { .. }
+            | ActionOutput::Text { .. }
+            | ActionOutput::TextMatch { .. }
+            | ActionOutput::ImageMatches { .. }
+            | ActionOutput::Windows { .. }
+            | ActionOutput::Element { .. }
+            | ActionOutput::NoData => {
                 self.send_screen_update(request.id.clone()).await;
                 self.send_cursor_update(request.id.clone()).await;
             }
@@ -242,14 +1276,825 @@ impl<T: InputDriver> ActionQueue<T> {
         response
     }
 
+    // Where a run's click or typing went, for its heatmap. Clicks without coordinates and
+    // typing happen at the cursor, which the worker read right after the action, as later
+    // actions may have moved it by now
+    fn record_interaction(&self, request: &ActionRequest, response: &ActionResponse) {
+        let (Some(run_id), Some(kind)) = (&request.run_id, InteractionKind::of(&request.action))
+        else {
+            return;
+        };
+        // Heatmaps are drawn over the screen, not the model's scaled screenshots
+        let Ok(action) = self.in_screen_space(request) else {
+            return;
+        };
+        let position = match &action {
+            Action::Click { input } if input.monitor.is_none() => {
+                Some((input.x as i32, input.y as i32))
+            }
+            Action::Tap { input } => Some((input.x as i32, input.y as i32)),
+            _ => response.cursor,
+        };
+        if let Some((x, y)) = position {
+            self.runs.record_interaction(
+                run_id,
+                Interaction {
+                    x,
+                    y,
+                    kind,
+                    action_type: request.action.action_type().to_string(),
+                    succeeded: matches!(response.status, ActionResponseStatus::Success),
+                    timestamp: response.timestamp,
+                },
+            );
+        }
+    }
+
+    // Checks that must pass before an action is queued. The request holds its share of its
+    // run's budget from here until its response is recorded
+    async fn admit<'a>(
+        &'a self,
+        request: &'a ActionRequest,
+        approval: bool,
+    ) -> Result<Reservation<'a>, ActionError> {
+        let reservation = self.check(request).await?;
+        if approval {
+            self.approve(request).await?;
+        }
+        Ok(reservation)
+    }
+
+    // Every check but approval, which comes last since it waits on a human
+    async fn check<'a>(
+        &'a self,
+        request: &'a ActionRequest,
+    ) -> Result<Reservation<'a>, ActionError> {
+        request.action.validate()?;
+        let allowed_disarmed =
+            request.action.is_read_only() || matches!(request.action, Action::Ping);
+        if !self.is_armed() && !allowed_disarmed {
+            return Err(ActionError::PermissionDenied(
+                "The server is disarmed and only runs read-only actions until armed with POST /v1/arm"
+                    .to_string(),
+            ));
+        }
+        request.check_metadata()?;
+        request.check_timeout()?;
+        self.display.check()?;
+        self.runs.check(request)?;
+        // Refused from here on, the reservation is given back as it drops
+        let reservation = Reservation {
+            runs: &self.runs,
+            request: Some(request),
+        };
+        self.policy.check(request.action.action_type())?;
+        authorize(&self.config, request).await?;
+        Ok(reservation)
+    }
+
+    // Wait for an approver if the action's type needs one
+    async fn approve(&self, request: &ActionRequest) -> Result<(), ActionError> {
+        let requires_approval = self
+            .config
+            .approval_actions
+            .iter()
+            .any(|action_type| action_type == request.action.action_type())
+            || self.policy.requires_approval(request.action.action_type());
+        if requires_approval {
+            self.await_approval(request).await?;
+        }
+        Ok(())
+    }
+
+    pub fn is_armed(&self) -> bool {
+        self.armed.load(Ordering::SeqCst)
+    }
+
+    pub fn set_armed(&self, armed: bool) {
+        self.armed.store(armed, Ordering::SeqCst);
+    }
+
+    /// Stop running actions for a restart. The unit the worker is on finishes, every other
+    /// action is refused with a `restarting` error so its client sends it to the next process
+    pub fn pause(&self) {
+        self.paused.send_replace(true);
+    }
+
+    /// Resolves once the worker is done with the unit it was on when the queue paused
+    pub async fn until_idle(&self) {
+        // The worker holds the driver for the whole unit
+        drop(self.lock_input_driver().await);
+    }
+
+    /// Run input from a human who has taken over, ahead of every queued agent action.
+    /// Approvals and run limits are for agents, so only the input itself is checked
+    pub async fn execute_takeover(&self, request: ActionRequest) -> ActionResponse {
+        let admitted = request.action.validate().and_then(|_| self.display.check());
+        match admitted {
+            Ok(()) => self.run_queued(&request, Origin::Takeover).await,
+            Err(error) => ActionResponse::error(request.id, request.action, error),
+        }
+    }
+
+    /// A human has taken over, agent actions are held until every takeover session ends
+    pub fn start_takeover(&self) {
+        self.takeover_sessions
+            .send_modify(|sessions| *sessions += 1);
+        self.update_observation(|state| state.queue.takeover = true);
+    }
+
+    pub fn end_takeover(&self) {
+        let mut active = false;
+        self.takeover_sessions.send_modify(|sessions| {
+            *sessions = sessions.saturating_sub(1);
+            active = *sessions > 0;
+        });
+        self.update_observation(|state| state.queue.takeover = active);
+    }
+
+    // Queue the action and wait for the worker to finish it
+    async fn run_queued(&self, request: &ActionRequest, origin: Origin) -> ActionResponse {
+        match self.queue_unit(std::slice::from_ref(request), origin) {
+            Ok(mut receivers) => self.await_queued(request, receivers.remove(0)).await,
+            Err(error) => ActionResponse::error(request.id.clone(), request.action.clone(), error),
+        }
+    }
+
+    async fn await_queued(
+        &self,
+        request: &ActionRequest,
+        mut rx: oneshot::Receiver<Result<ActionResult, ActionError>>,
+    ) -> ActionResponse {
+        // The worker cuts the action off at its step limit, this only catches a stuck queue and
+        // leaves room for the actions queued ahead. Time they were held back for pacing holds
+        // up this one too, so it doesn't count
+        let mut deadline = Instant::now() + step_limit(request) + ACTION_TIMEOUT;
+        let mut held = self.pacer.held();
+        let result = loop {
+            match timeout_at(deadline, &mut rx).await {
+                Err(_) if self.pacer.held() > held => {
+                    deadline += self.pacer.held() - held;
+                    held = self.pacer.held();
+                }
+                result => break result,
+            }
+        };
+        match result {
+            Ok(result) => match result {
+                Ok(Ok((result, timing, foreground, failure_screenshot, cursor))) => {
+                    let mut response = match result {
+                        Ok(output) => ActionResponse::success(
+                            request.id.clone(),
+                            request.action.clone(),
+                            output,
+                        ),
+                        Err(error) => {
+                            ActionResponse::error(request.id.clone(), request.action.clone(), error)
+                        }
+                    };
+                    response.timing = Some(timing);
+                    response.foreground = foreground;
+                    response.failure_screenshot = failure_screenshot;
+                    response.cursor = cursor;
+                    response
+                }
+                Ok(Err(error)) => {
+                    ActionResponse::error(request.id.clone(), request.action.clone(), error)
+                }
+                Err(e) => ActionResponse::error(
+                    request.id.clone(),
+                    request.action.clone(),
+                    ActionError::ChannelError(e.to_string()),
+                ),
+            },
+            Err(_) => {
+                // Timeout occurred - dropping rx makes the dispatcher skip the action if it's
+                // still queued
+                ActionResponse::error(
+                    request.id.clone(),
+                    request.action.clone(),
+                    ActionError::Timeout,
+                )
+            }
+        }
+    }
+
+    // Offset from an action's monitor coordinates to input coordinates, checking they are on it
+    pub fn monitor_offset(&self, action: &Action) -> Result<(i32, i32), ActionError> {
+        let Some(selector) = action.monitor() else {
+            return Ok((0, 0));
+        };
+        let display = select_display(self.capture.displays()?, selector)?;
+        let outside = action
+            .pointer_targets()
+            .into_iter()
+            .find(|point| point.x >= display.width || point.y >= display.height);
+        if let Some(point) = outside {
+            return Err(ActionError::InvalidInput(format!(
+                "({}, {}) is outside monitor {}, which is {}x{}",
+                point.x, point.y, display.id, display.width, display.height
+            )));
+        }
+        Ok((display.x, display.y))
+    }
+
+    // Make sure the target still looks the way the client saw it, so a UI that shifted since
+    // isn't clicked blindly
+    fn check_target(&self, x: i32, y: i32, color: &str) -> Result<(), ActionError> {
+        let expected = parse_hex_color(color).map_err(ActionError::InvalidInput)?;
+        let (image, px, py) = self.capture_target(x, y, "pixel")?;
+        let actual = image.get_pixel(px, py);
+        if (0..3).all(|c| actual[c].abs_diff(expected[c]) <= PIXEL_TOLERANCE) {
+            return Ok(());
+        }
+        let message = format!(
+            "Expected {} at ({}, {}) but found #{:02x}{:02x}{:02x}",
+            color, x, y, actual[0], actual[1], actual[2]
+        );
+        stale_target(&image, px, py, message)
+    }
+
+    // Same for the text the target should show, read from the area around it. The text has
+    // to cover the target, text that moved away from it doesn't count
+    async fn check_target_text(&self, x: i32, y: i32, text: &str) -> Result<(), ActionError> {
+        let (image, px, py) = self.capture_target(x, y, "text")?;
+        let (left, top) = (
+            px.saturating_sub(TEXT_CHECK_SIZE.0 / 2),
+            py.saturating_sub(TEXT_CHECK_SIZE.1 / 2),
+        );
+        let area = image::imageops::crop_imm(
+            &image,
+            left,
+            top,
+            (px + TEXT_CHECK_SIZE.0 / 2).min(image.width()) - left,
+            (py + TEXT_CHECK_SIZE.1 / 2).min(image.height()) - top,
+        )
+        .to_image();
+        let recognizer = self.recognizer.clone();
+        let lines = tokio::task::spawn_blocking(move || recognizer.recognize(&area))
+            .await
+            .map_err(|e| {
+                ActionError::ExecutionFailed(format!("Text recognition failed: {}", e))
+            })??;
+
+        let (tx, ty) = ((px - left) as i32, (py - top) as i32);
+        let covers = |span: &TextSpan| {
+            (span.x..span.x + span.width as i32).contains(&tx)
+                && (span.y..span.y + span.height as i32).contains(&ty)
+        };
+        if ocr::best_match(&lines, text, MatchMode::Fuzzy).is_some_and(|(span, _)| covers(&span)) {
+            return Ok(());
+        }
+        let found = ocr::spans(lines, TextLevel::Line)
+            .into_iter()
+            .find(|line| covers(line))
+            .map_or("no text".to_string(), |line| format!("\"{}\"", line.text));
+        let message = format!(
+            "Expected \"{}\" at ({}, {}) but found {}",
+            text, x, y, found
+        );
+        stale_target(&image, px, py, message)
+    }
+
+    // The primary monitor, and where the target is on it
+    fn capture_target(
+        &self,
+        x: i32,
+        y: i32,
+        checked: &str,
+    ) -> Result<(image::RgbaImage, u32, u32), ActionError> {
+        let image = self.capture.capture()?;
+        let (left, top) = self
+            .capture
+            .displays()
+            .ok()
+            .and_then(|displays| displays.into_iter().find(|display| display.is_primary))
+            .map_or((0, 0), |display| (display.x, display.y));
+        let (px, py) = (x as i64 - left as i64, y as i64 - top as i64);
+        if px < 0 || py < 0 || px >= image.width() as i64 || py >= image.height() as i64 {
+            return Err(ActionError::InvalidInput(format!(
+                "Target ({}, {}) is not on the primary monitor, its {} can't be checked",
+                x, y, checked
+            )));
+        }
+        Ok((image, px as u32, py as u32))
+    }
+
+    // Frames are only encoded once all are taken, so encoding can't stretch the interval
+    async fn capture_burst(&self, input: &BurstInput) -> Result<ActionOutput, ActionError> {
+        let started = Instant::now();
+        let interval = Duration::from_millis(input.interval_ms);
+        let mut captured = Vec::with_capacity(input.count as usize);
+        for i in 0..input.count {
+            sleep_until(started + interval * i).await;
+            let image = crop(self.capture.capture()?, input.region)?;
+            let image = match input.scale {
+                Some(scale) => scale_by(image, scale),
+                None => image,
+            };
+            captured.push((image, Utc::now()));
+        }
+
+        let frames = captured
+            .into_iter()
+            .map(|(image, captured_at)| {
+                Ok(BurstFrame {
+                    image: encode_png(&image)?,
+                    width: image.width(),
+                    height: image.height(),
+                    captured_at,
+                })
+            })
+            .collect::<Result<Vec<_>, ActionError>>()?;
+        Ok(ActionOutput::Burst { frames })
+    }
+
+    // Taken as soon as the action fails, before anything else can change the screen. Capture
+    // failing is often why the action failed, that just leaves the response without one
+    fn failure_screenshot(&self) -> Option<Box<FailureScreenshot>> {
+        let image = fit_within(
+            self.capture.capture().ok()?,
+            Some(FAILURE_SCREENSHOT_WIDTH),
+            None,
+        );
+        Some(Box::new(FailureScreenshot {
+            image: encode_png(&image).ok()?,
+            width: image.width(),
+            height: image.height(),
+            captured_at: Utc::now(),
+        }))
+    }
+
+    // Top left corner of a region of the primary monitor in input coordinates, what positions in
+    // an image of the region are offset by
+    fn region_origin(&self, region: Option<Region>) -> (i32, i32) {
+        let primary = self
+            .capture
+            .displays()
+            .ok()
+            .and_then(|displays| displays.into_iter().find(|display| display.is_primary));
+        (
+            primary.as_ref().map_or(0, |display| display.x)
+                + region.map_or(0, |region| region.x as i32),
+            primary.as_ref().map_or(0, |display| display.y)
+                + region.map_or(0, |region| region.y as i32),
+        )
+    }
+
+    // Lines of words on the primary monitor in input coordinates, along with the image they
+    // were read from and where it is. Recognizing is slow, so it runs off the async workers
+    async fn recognize_lines(
+        &self,
+        region: Option<Region>,
+    ) -> Result<(Vec<Vec<TextSpan>>, image::RgbaImage, (i32, i32)), ActionError> {
+        let image = crop(self.capture.capture()?, region)?;
+        let origin = self.region_origin(region);
+        let recognizer = self.recognizer.clone();
+        let (lines, image) = tokio::task::spawn_blocking(move || {
+            recognizer.recognize(&image).map(|lines| (lines, image))
+        })
+        .await
+        .map_err(|e| ActionError::ExecutionFailed(format!("Text recognition failed: {}", e)))??;
+        let lines: Vec<Vec<TextSpan>> = lines
+            .into_iter()
+            .map(|words| {
+                words
+                    .into_iter()
+                    .map(|word| TextSpan {
+                        x: word.x + origin.0,
+                        y: word.y + origin.1,
+                        ..word
+                    })
+                    .collect()
+            })
+            .collect();
+        Ok((lines, image, origin))
+    }
+
+    /// Lines of text on the primary monitor in input coordinates, read outside the queue
+    pub async fn screen_text(&self) -> Result<Vec<TextSpan>, ActionError> {
+        let (lines, _, _) = self.recognize_lines(None).await?;
+        Ok(ocr::spans(lines, TextLevel::Line))
+    }
+
+    async fn recognize_text(&self, input: &OcrInput) -> Result<ActionOutput, ActionError> {
+        let (lines, image, origin) = self.recognize_lines(input.region).await?;
+        let spans = ocr::spans(lines, input.level);
+        let debug_image = match input.debug {
+            true => Some(text_debug_image(image, origin, spans.clone(), None).await?),
+            false => None,
+        };
+        Ok(ActionOutput::Text { spans, debug_image })
+    }
+
+    async fn find_text(&self, input: &FindTextInput) -> Result<ActionOutput, ActionError> {
+        let (lines, image, origin) = self.recognize_lines(input.region).await?;
+        let Some((span, score)) = ocr::best_match(&lines, &input.query, input.match_mode) else {
+            return Err(ActionError::NotFound(format!(
+                "No text on screen matches \"{}\" ({:?})",
+                input.query, input.match_mode
+            )));
+        };
+        let center = Point {
+            x: (span.x + span.width as i32 / 2).max(0) as u32,
+            y: (span.y + span.height as i32 / 2).max(0) as u32,
+        };
+        let debug_image = match input.debug {
+            true => {
+                let words = ocr::spans(lines, TextLevel::Word);
+                Some(text_debug_image(image, origin, words, Some(span.clone())).await?)
+            }
+            false => None,
+        };
+        Ok(ActionOutput::TextMatch {
+            span,
+            center,
+            score,
+            debug_image,
+        })
+    }
+
+    // Matching is slow on large screens, so it runs off the async workers
+    async fn locate_image(&self, input: &LocateImageInput) -> Result<ActionOutput, ActionError> {
+        let template = locate::decode_template(&input.template)?;
+        let screen = crop(self.capture.capture()?, input.region)?;
+        let origin = self.region_origin(input.region);
+        let (width, height) = template.dimensions();
+        let (confidence, limit) = (input.confidence, input.limit as usize);
+        let found = tokio::task::spawn_blocking(move || {
+            let screen = image::imageops::grayscale(&screen);
+            locate::locate(&screen, &template, confidence, limit)
+        })
+        .await
+        .map_err(|e| ActionError::ExecutionFailed(format!("Image matching failed: {}", e)))??;
+        if found.is_empty() {
+            return Err(ActionError::NotFound(format!(
+                "Template matches nothing on screen with confidence {}",
+                input.confidence
+            )));
+        }
+        let matches = found
+            .into_iter()
+            .map(|found| {
+                let (x, y) = (found.x as i32 + origin.0, found.y as i32 + origin.1);
+                ImageMatch {
+                    x,
+                    y,
+                    width,
+                    height,
+                    center: Point {
+                        x: (x + width as i32 / 2).max(0) as u32,
+                        y: (y + height as i32 / 2).max(0) as u32,
+                    },
+                    confidence: found.confidence,
+                }
+            })
+            .collect();
+        Ok(ActionOutput::ImageMatches { matches })
+    }
+
+    // Looks again every WAIT_FOR_INTERVAL until the target shows up or time runs out
+    async fn wait_for(&self, input: &WaitForInput) -> Result<ActionOutput, ActionError> {
+        let deadline = Instant::now() + Duration::from_millis(input.timeout_ms);
+        loop {
+            let result = match (&input.text, &input.image_template) {
+                (Some(text), _) => {
+                    self.find_text(&FindTextInput {
+                        query: text.clone(),
+                        match_mode: input.match_mode,
+                        region: input.region,
+                        debug: false,
+                    })
+                    .await
+                }
+                (None, template) => {
+                    self.locate_image(&LocateImageInput {
+                        template: template.clone().unwrap_or_default(),
+                        confidence: input.confidence,
+                        limit: 1,
+                        region: input.region,
+                    })
+                    .await
+                }
+            };
+            match result {
+                Err(ActionError::NotFound(_)) if Instant::now() + WAIT_FOR_INTERVAL < deadline => {
+                    sleep(WAIT_FOR_INTERVAL).await;
+                }
+                Err(ActionError::NotFound(message)) => {
+                    return Err(ActionError::NotFound(format!(
+                        "{} after waiting {}ms",
+                        message, input.timeout_ms
+                    )));
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn take_screenshot(
+        &self,
+        input: &ScreenshotInput,
+        cursor: Option<(i32, i32)>,
+    ) -> Result<ActionOutput, ActionError> {
+        let mut region = input.region;
+        // Screenshot delay is slightly longer
+        sleep(SCREENSHOT_DELAY).await;
+
+        let (image, display) = match input.monitor {
+            // The monitor showing most of the window, cropped to the window below
+            _ if input.target == ScreenshotTarget::FocusedWindow => {
+                let window = self.capture.focused_window().ok_or_else(|| {
+                    ActionError::ExecutionFailed("No window has input focus".to_string())
+                })?;
+                let Some((display, window_region)) = self
+                    .capture
+                    .displays()?
+                    .into_iter()
+                    .filter_map(|display| {
+                        window_region(&window, &display).map(|region| (display, region))
+                    })
+                    .max_by_key(|(_, region)| region.width as u64 * region.height as u64)
+                else {
+                    return Err(ActionError::ExecutionFailed(format!(
+                        "The focused window \"{}\" isn't on any monitor",
+                        window.title
+                    )));
+                };
+                region = Some(window_region);
+                (self.capture.capture_display(display.id)?, Some(display))
+            }
+            // Captures are of the primary monitor
+            None => (
+                self.capture.capture()?,
+                self.capture
+                    .displays()
+                    .ok()
+                    .and_then(|displays| displays.into_iter().find(|display| display.is_primary)),
+            ),
+            Some(selector) => {
+                let display = select_display(self.capture.displays()?, selector)?;
+                (self.capture.capture_display(display.id)?, Some(display))
+            }
+        };
+        let mut image = crop(image, region)?;
+        let captured_at = Utc::now();
+        // Image position = input position - top left corner of the captured area
+        let origin = (
+            display.as_ref().map_or(0, |display| display.x)
+                + region.map_or(0, |region| region.x as i32),
+            display.as_ref().map_or(0, |display| display.y)
+                + region.map_or(0, |region| region.y as i32),
+        );
+        if let Some((x, y)) = cursor.filter(|_| input.show_cursor) {
+            draw_pointer(&mut image, (x - origin.0, y - origin.1));
+        }
+        let full_width = image.width();
+        let image = match input.scale {
+            Some(scale) => scale_by(image, scale),
+            None => fit_within(image, input.max_width, input.max_height),
+        };
+        let hash = image_hash(&image);
+        let unchanged = input.previous_hash.as_ref() == Some(&hash);
+        Ok(ActionOutput::Screenshot {
+            image: if unchanged {
+                String::new()
+            } else {
+                encode_image(&image, input.format, input.quality)?
+            },
+            format: input.format,
+            width: image.width(),
+            height: image.height(),
+            monitor_id: display.as_ref().map_or(0, |display| display.id),
+            // A region's pixels map to input the same way, from its own corner
+            x: origin.0,
+            y: origin.1,
+            scale_factor: display.as_ref().map_or(1.0, |display| display.scale_factor),
+            image_scale: image.width() as f64 / full_width as f64,
+            captured_at,
+            hash,
+            unchanged,
+        })
+    }
+
     async fn action_delay() {
         sleep(ACTION_DELAY).await;
     }
 
+    // Press modifiers in order, returning how many are held even if one of them failed
+    async fn press_modifiers(
+        input_driver: &mut TrackedInput<T>,
+        modifiers: &[Key],
+    ) -> (usize, Result<(), ActionError>) {
+        for (held, modifier) in modifiers.iter().enumerate() {
+            if let Err(e) = input_driver.key(*modifier, Press) {
+                return (held, Err(ActionError::ExecutionFailed(e.to_string())));
+            }
+            Self::action_delay().await;
+        }
+        (modifiers.len(), Ok(()))
+    }
+
+    // Release held modifiers even if the action failed, a stuck ctrl breaks every later action
+    fn release_modifiers(
+        input_driver: &mut TrackedInput<T>,
+        held: &[Key],
+        result: Result<ActionOutput, ActionError>,
+    ) -> Result<ActionOutput, ActionError> {
+        for modifier in held.iter().rev() {
+            let released = input_driver.key(*modifier, Release);
+            if result.is_ok() {
+                released.map_err(|e| ActionError::ExecutionFailed(e.to_string()))?;
+            }
+        }
+        result
+    }
+
+    // An action cut off by its timeout stops at whatever await it was on, possibly with a
+    // button, key or touch contact still down. What it pressed is released, keys held since
+    // before it started, like those of a key_down, stay down
+    async fn release_input(&self, input_driver: &mut TrackedInput<T>, before: &[Held]) {
+        input_driver.release_since(before);
+        let contact = self.open_contact.lock().unwrap().take();
+        if let Some((tool, x, y)) = contact {
+            if let Some(touch_driver) = self.touch_driver.lock().await.as_mut() {
+                let _ = touch_driver.contact(tool, TouchPhase::Up, x, y, 0.0);
+            }
+        }
+    }
+
+    // Run an action, cutting it off at `deadline`
+    async fn run_step(
+        &self,
+        input_driver: &mut TrackedInput<T>,
+        action: &Action,
+        id: &str,
+        deadline: Instant,
+    ) -> Result<ActionOutput, ActionError> {
+        let held = input_driver.held();
+        match timeout_at(deadline, self.handle_action(input_driver, action, id)).await {
+            Ok(result) => result,
+            // Nothing may stay held for the next unit
+            Err(_) => {
+                self.release_input(input_driver, &held).await;
+                Err(ActionError::Timeout)
+            }
+        }
+    }
+
+    // How long an agent unit has to wait in the dispatcher before its first action may start.
+    // Refusing is left to the worker, so the refusal is answered like any other failure
+    fn pacing_wait(&self, unit: &QueueUnit) -> Option<Duration> {
+        let (action, _, _, origin, ..) = unit.first()?;
+        if *origin != Origin::Agent || self.config.pacing_mode != PacingMode::Delay {
+            return None;
+        }
+        let (wait, _) = self.pacer.wait(action.action_type())?;
+        self.pacer.hold(wait);
+        Some(wait)
+    }
+
+    // Hold or refuse an agent action its pacing rules don't allow to start yet
+    async fn pace(&self, action: &Action) -> Result<(), ActionError> {
+        let action_type = action.action_type();
+        if let Some((wait, rule)) = self.pacer.wait(action_type) {
+            match self.config.pacing_mode {
+                PacingMode::Delay => {
+                    self.pacer.hold(wait);
+                    sleep(wait).await
+                }
+                PacingMode::Reject => {
+                    return Err(ActionError::RateLimited(format!(
+                        "At most {} are allowed, try again in {}ms",
+                        rule,
+                        wait.as_millis()
+                    )))
+                }
+            }
+        }
+        self.pacer.record(action_type);
+        Ok(())
+    }
+
+    // Run a sequence of contacts for one touch/pen gesture, lifting the contact if any step fails
+    async fn perform_touch(
+        &self,
+        tool: TouchTool,
+        contacts: &[(TouchPhase, i32, i32, f32, Duration)],
+    ) -> Result<ActionOutput, ActionError> {
+        let mut touch_driver = self.touch_driver.lock().await;
+        let touch_driver = touch_driver.as_mut().ok_or_else(|| {
+            ActionError::ExecutionFailed(
+                "Touch and pen input is not supported on this platform".to_string(),
+            )
+        })?;
+
+        for (phase, x, y, pressure, delay) in contacts {
+            if let Err(e) = touch_driver.contact(tool, *phase, *x, *y, *pressure) {
+                if *phase != TouchPhase::Up {
+                    let _ = touch_driver.contact(tool, TouchPhase::Up, *x, *y, 0.0);
+                }
+                *self.open_contact.lock().unwrap() = None;
+                return Err(ActionError::ExecutionFailed(e));
+            }
+            *self.open_contact.lock().unwrap() = match phase {
+                TouchPhase::Up => None,
+                _ => Some((tool, *x, *y)),
+            };
+            sleep(*delay).await;
+        }
+
+        Ok(ActionOutput::NoData)
+    }
+
+    // Move from `current_pos` to `target_pos` in steps of about 10px, ending exactly on the target
+    async fn interpolate_move(
+        input_driver: &mut TrackedInput<T>,
+        current_pos: (i32, i32),
+        target_pos: (i32, i32),
+        path_mode: DragPathMode,
+    ) -> InputResult<()> {
+        let distance =
+            ((target_pos.0 - current_pos.0) as f64).hypot((target_pos.1 - current_pos.1) as f64);
+        let steps = (distance / 10.0).max(1.0); // One step for every 10 euclidan px traveled
+        let step_x = (target_pos.0 - current_pos.0) as f64 / steps;
+        let step_y = (target_pos.1 - current_pos.1) as f64 / steps;
+
+        // Do relative moves for all but last step
+        for i in 0..steps as u32 {
+            if i == steps as u32 - 1 {
+                // Last step - do absolute move to target
+                input_driver.move_mouse(target_pos.0, target_pos.1, Abs)?;
+            } else {
+                // Intermediate steps are relative by default, but absolute paths are
+                // immune to pointer acceleration applied to synthetic relative moves
+                match path_mode {
+                    DragPathMode::Relative => {
+                        input_driver.move_mouse(step_x as i32, step_y as i32, Rel)?
+                    }
+                    DragPathMode::Absolute => input_driver.move_mouse(
+                        current_pos.0 + (step_x * (i + 1) as f64) as i32,
+                        current_pos.1 + (step_y * (i + 1) as f64) as i32,
+                        Abs,
+                    )?,
+                };
+                sleep(Duration::from_millis(10)).await;
+            }
+        }
+        Ok(())
+    }
+
+    // Move the cursor to `target` along a straight line over `duration_ms`, one absolute
+    // move per step so every step shows up as a motion event
+    async fn glide(
+        input_driver: &mut TrackedInput<T>,
+        target: (i32, i32),
+        duration_ms: u64,
+    ) -> InputResult<()> {
+        let start = input_driver.location()?;
+        let steps = (duration_ms / SCROLL_STEP_INTERVAL.as_millis() as u64).max(1);
+        for step in 1..=steps {
+            let progress = step as f64 / steps as f64;
+            input_driver.move_mouse(
+                start.0 + ((target.0 - start.0) as f64 * progress).round() as i32,
+                start.1 + ((target.1 - start.1) as f64 * progress).round() as i32,
+                Abs,
+            )?;
+            if step < steps {
+                sleep(SCROLL_STEP_INTERVAL).await;
+            }
+        }
+        Ok(())
+    }
+
+    // Tell the monitor a step of a long action or batch is starting
+    fn report_progress(
+        &self,
+        action_id: &str,
+        batch_id: Option<&str>,
+        step: usize,
+        total_steps: usize,
+        current: &str,
+    ) {
+        self.send_monitor_event(MonitorEventPayload::ActionProgress {
+            action_id: action_id.to_string(),
+            batch_id: batch_id.map(str::to_string),
+            step: step as u32,
+            total_steps: total_steps as u32,
+            percent: ((step - 1) * 100 / total_steps.max(1)) as u8,
+            current: current.to_string(),
+            timestamp: Utc::now(),
+        });
+    }
+
     async fn handle_action(
-        input_driver: &mut T,
+        &self,
+        input_driver: &mut TrackedInput<T>,
         action: &Action,
+        id: &str,
     ) -> Result<ActionOutput, ActionError> {
+        let config = &self.config;
+        let (dx, dy) = self.monitor_offset(action)?;
         match action {
             Action::LeftClick => {
                 let press_result = input_driver.button(Button::Left, Press);
@@ -277,6 +2122,54 @@ impl<T: InputDriver> ActionQueue<T> {
                     .map(|_| ActionOutput::NoData)
                     .map_err(|e| ActionError::ExecutionFailed(e.to_string()))
             }
+            Action::Click { input } => {
+                // Same as a mouse_move followed by a click, without a second trip through the queue
+                let (x, y) = (input.x as i32 + dx, input.y as i32 + dy);
+                if let Some(color) = &input.expected_pixel {
+                    self.check_target(x, y, color)?;
+                }
+                if let Some(text) = &input.expected_text {
+                    self.check_target_text(x, y, text).await?;
+                }
+                let button = enigo_button(input.button);
+                let modifiers = parse_modifiers(&input.modifiers)?;
+
+                let (held, pressed) = Self::press_modifiers(input_driver, &modifiers).await;
+                let result = match pressed {
+                    Ok(()) => {
+                        async {
+                            input_driver
+                                .move_mouse(x, y, Abs)
+                                .and_then(|_| input_driver.button(button, Press))
+                                .map_err(|e| ActionError::ExecutionFailed(e.to_string()))?;
+                            Self::action_delay().await;
+                            input_driver
+                                .button(button, Release)
+                                .map(|_| ActionOutput::NoData)
+                                .map_err(|e| ActionError::ExecutionFailed(e.to_string()))
+                        }
+                        .await
+                    }
+                    Err(e) => Err(e),
+                };
+                Self::release_modifiers(input_driver, &modifiers[..held], result)
+            }
+            Action::ClickElement { input } => {
+                // Looked up right before clicking, so it is hit wherever its window moved. The
+                // lookup is bounded tightly, since the driver is held while it runs
+                let element = accessibility::find_element(input).await?;
+                let center = element.bounds.center();
+                let button = enigo_button(input.button);
+                input_driver
+                    .move_mouse(center.x, center.y, Abs)
+                    .and_then(|_| input_driver.button(button, Press))
+                    .map_err(|e| ActionError::ExecutionFailed(e.to_string()))?;
+                Self::action_delay().await;
+                input_driver
+                    .button(button, Release)
+                    .map_err(|e| ActionError::ExecutionFailed(e.to_string()))?;
+                Ok(ActionOutput::Element { element, center })
+            }
             Action::MiddleClick => {
                 let press_result = input_driver.button(Button::Middle, Press);
                 let release_result = if press_result.is_ok() {
@@ -321,73 +2214,136 @@ impl<T: InputDriver> ActionQueue<T> {
                     ))
                 }
             }
-            Action::MouseMove { input } => input_driver
-                .move_mouse(input.x as i32, input.y as i32, Abs)
-                .map(|_| ActionOutput::NoData)
-                .map_err(|e| ActionError::ExecutionFailed(e.to_string())),
-            Action::LeftClickDrag { input } => {
-                // First press and hold the left button
-                if let Err(e) = input_driver.button(Button::Left, Press) {
-                    return Err(ActionError::ExecutionFailed(e.to_string()))
+            Action::TripleClick => {
+                // Same timing as a double click, with one more click
+                for (i, click) in ["first", "second", "third"].iter().enumerate() {
+                    if i > 0 {
+                        sleep(DOUBLE_CLICK_DELAY).await;
+                    }
+                    if !matches!(
+                        (
+                            input_driver.button(Button::Left, Press),
+                            sleep(DOUBLE_CLICK_DELAY).await,
+                            input_driver.button(Button::Left, Release),
+                        ),
+                        (Ok(_), _, Ok(_))
+                    ) {
+                        return Err(ActionError::ExecutionFailed(format!(
+                            "Failed to execute {} click",
+                            click
+                        )));
+                    }
+                }
+                Ok(ActionOutput::NoData)
+            }
+            Action::MouseMove { input } => {
+                let target = (input.x as i32 + dx, input.y as i32 + dy);
+                let moved = match input.motion.unwrap_or(config.mouse_motion) {
+                    MouseMotion::Warp => input_driver.move_mouse(target.0, target.1, Abs),
+                    MouseMotion::Glide => {
+                        let duration = input.duration_ms.unwrap_or(config.glide_duration_ms);
+                        Self::glide(input_driver, target, duration).await
+                    }
+                };
+                moved
+                    .map(|_| ActionOutput::NoData)
+                    .map_err(|e| ActionError::ExecutionFailed(e.to_string()))
+            }
+            Action::Hover { input } => {
+                input_driver
+                    .move_mouse(input.x as i32 + dx, input.y as i32 + dy, Abs)
+                    .map_err(|e| ActionError::ExecutionFailed(e.to_string()))?;
+                sleep(Duration::from_millis(
+                    input.duration_ms.unwrap_or(HOVER_DURATION_MS),
+                ))
+                .await;
+                Ok(ActionOutput::NoData)
+            }
+            Action::LeftClickDrag { input }
+            | Action::RightClickDrag { input }
+            | Action::MiddleClickDrag { input } => {
+                let button = match action {
+                    Action::RightClickDrag { .. } => Button::Right,
+                    Action::MiddleClickDrag { .. } => Button::Middle,
+                    _ => Button::Left,
+                };
+                // First press and hold the button
+                self.report_progress(id, None, 1, 3, "press");
+                if let Err(e) = input_driver.button(button, Press) {
+                    return Err(ActionError::ExecutionFailed(e.to_string()))
                         as Result<ActionOutput, ActionError>;
                 }
 
                 sleep(DOUBLE_CLICK_DELAY).await;
 
                 // We need to use interpolation to drag the mouse
-                let current_pos = input_driver.location().unwrap();
-                let target_pos = (input.x as i32, input.y as i32);
-
-                let distance = (((current_pos.0 - target_pos.0).pow(2)
-                    + (current_pos.1 - target_pos.1).pow(2))
-                    as f64)
-                    .sqrt();
-                let steps = (distance / 10.0).max(1.0); // One step for every 10 euclidan px traveled
-                let step_x = (target_pos.0 - current_pos.0) as f64 / steps;
-                let step_y = (target_pos.1 - current_pos.1) as f64 / steps;
-
-                // Do relative moves for all but last step
-                for i in 0..steps as u32 {
-                    if i == steps as u32 - 1 {
-                        // Last step - do absolute move to target
-                        match input_driver.move_mouse(target_pos.0, target_pos.1, Abs) {
-                            Ok(_) => (),
-                            Err(e) => {
-                                // Cleanup: release button if move fails
-                                let _ = input_driver.button(Button::Left, Release);
-                                return Err(ActionError::ExecutionFailed(e.to_string()));
-                            }
-                        };
-                    } else {
-                        // Regular relative move for intermediate steps
-                        match input_driver.move_mouse(step_x as i32, step_y as i32, Rel) {
-                            Ok(_) => (),
-                            Err(e) => {
-                                // Cleanup: release button if move fails
-                                let _ = input_driver.button(Button::Left, Release);
-                                return Err(ActionError::ExecutionFailed(e.to_string()));
-                            }
-                        };
-                        sleep(Duration::from_millis(10)).await;
+                self.report_progress(id, None, 2, 3, "move");
+                let dragged = match input_driver.location() {
+                    Ok(current_pos) => {
+                        Self::interpolate_move(
+                            input_driver,
+                            current_pos,
+                            (input.x as i32 + dx, input.y as i32 + dy),
+                            config.drag_path_mode,
+                        )
+                        .await
                     }
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = dragged {
+                    // Cleanup: release button if move fails
+                    let _ = input_driver.button(button, Release);
+                    return Err(ActionError::ExecutionFailed(e.to_string()));
                 }
 
                 sleep(DOUBLE_CLICK_DELAY).await;
 
                 // Release button
-                match input_driver.button(Button::Left, Release) {
+                self.report_progress(id, None, 3, 3, "release");
+                match input_driver.button(button, Release) {
                     Ok(_) => Ok(ActionOutput::NoData),
                     Err(e) => Err(ActionError::ExecutionFailed(e.to_string())),
                 }
             }
-            Action::TypeText { input } => {
-                // First check if text is empty
-                if input.text.is_empty() {
-                    return Err(ActionError::InvalidInput(
-                        "Text cannot be empty".to_string(),
-                    ));
+            Action::DragPath { input } => {
+                let button = enigo_button(input.button);
+                let mut points = input
+                    .points
+                    .iter()
+                    .map(|p| (p.x as i32 + dx, p.y as i32 + dy));
+                let Some(mut from) = points.next() else {
+                    return Ok(ActionOutput::NoData);
+                };
+                // Pressing, every segment between waypoints, and releasing
+                let total_steps = input.points.len() + 1;
+
+                self.report_progress(id, None, 1, total_steps, "press");
+                input_driver
+                    .move_mouse(from.0, from.1, Abs)
+                    .and_then(|_| input_driver.button(button, Press))
+                    .map_err(|e| ActionError::ExecutionFailed(e.to_string()))?;
+                sleep(DOUBLE_CLICK_DELAY).await;
+
+                // Every waypoint is hit exactly, the segments between them are interpolated
+                for (segment, to) in points.enumerate() {
+                    self.report_progress(id, None, segment + 2, total_steps, "move");
+                    if let Err(e) =
+                        Self::interpolate_move(input_driver, from, to, config.drag_path_mode).await
+                    {
+                        let _ = input_driver.button(button, Release);
+                        return Err(ActionError::ExecutionFailed(e.to_string()));
+                    }
+                    from = to;
                 }
 
+                sleep(DOUBLE_CLICK_DELAY).await;
+                self.report_progress(id, None, total_steps, total_steps, "release");
+                input_driver
+                    .button(button, Release)
+                    .map(|_| ActionOutput::NoData)
+                    .map_err(|e| ActionError::ExecutionFailed(e.to_string()))
+            }
+            Action::TypeText { input } => {
                 // Attempt to type the text with detailed error handling
                 match input_driver.text(&input.text) {
                     Ok(_) => Ok(ActionOutput::NoData),
@@ -417,6 +2373,35 @@ impl<T: InputDriver> ActionQueue<T> {
                     }
                 }
             }
+            Action::PasteText { input } => {
+                self.clipboard.set_text(&input.text)?;
+                let modifier = if cfg!(target_os = "macos") {
+                    Key::Meta
+                } else {
+                    Key::Control
+                };
+                let result: Result<(), ActionError> = async {
+                    input_driver
+                        .key(modifier, Press)
+                        .map_err(|e| ActionError::ExecutionFailed(e.to_string()))?;
+                    Self::action_delay().await;
+                    input_driver
+                        .key(Key::Unicode('v'), Press)
+                        .map_err(|e| ActionError::ExecutionFailed(e.to_string()))?;
+                    Self::action_delay().await;
+                    input_driver
+                        .key(Key::Unicode('v'), Release)
+                        .map_err(|e| ActionError::ExecutionFailed(e.to_string()))?;
+                    Self::action_delay().await;
+                    Ok(())
+                }
+                .await;
+                // Don't leave the modifier held if pasting failed
+                let released = input_driver
+                    .key(modifier, Release)
+                    .map_err(|e| ActionError::ExecutionFailed(e.to_string()));
+                result.and(released).map(|_| ActionOutput::NoData)
+            }
             Action::KeyPress { input } => {
                 if let Ok(key_press) = KeyPress::from_str(&input.key) {
                     let result: Result<(), ActionError> = async {
@@ -459,58 +2444,699 @@ impl<T: InputDriver> ActionQueue<T> {
                     )))
                 }
             }
+            Action::KeyDown { input } | Action::KeyUp { input } => {
+                let key_press = KeyPress::from_str(&input.key).map_err(|_| {
+                    ActionError::InvalidInput(format!(
+                        "Invalid key format or key not found: {}",
+                        input.key
+                    ))
+                })?;
+                // Hold modifiers around the main key, like a key press split in two
+                let (keys, direction): (Vec<Key>, Direction) = match action {
+                    Action::KeyDown { .. } => (
+                        key_press
+                            .modifiers
+                            .iter()
+                            .copied()
+                            .chain([key_press.key])
+                            .collect(),
+                        Press,
+                    ),
+                    _ => (
+                        [key_press.key]
+                            .into_iter()
+                            .chain(key_press.modifiers.iter().rev().copied())
+                            .collect(),
+                        Release,
+                    ),
+                };
+                for (i, key) in keys.into_iter().enumerate() {
+                    if i > 0 {
+                        Self::action_delay().await;
+                    }
+                    input_driver
+                        .key(key, direction)
+                        .map_err(|e| ActionError::ExecutionFailed(e.to_string()))?;
+                }
+                Ok(ActionOutput::NoData)
+            }
             Action::CursorPosition => match input_driver.location() {
-                Ok((x, y)) => Ok(ActionOutput::CursorPosition {
-                    x: x as u32,
-                    y: y as u32,
-                }),
+                Ok((x, y)) => Ok(ActionOutput::CursorPosition { x, y }),
+                Err(e) => Err(ActionError::ExecutionFailed(e.to_string())),
+            },
+            Action::ListWindows => Ok(ActionOutput::Windows {
+                windows: self.capture().top_level_windows()?,
+            }),
+            Action::FocusWindow { input }
+            | Action::CloseWindow { input }
+            | Action::MinimizeWindow { input } => {
+                let command = match action {
+                    Action::CloseWindow { .. } => WindowCommand::Close,
+                    Action::MinimizeWindow { .. } => WindowCommand::Minimize,
+                    _ => WindowCommand::Activate,
+                };
+                let window = self
+                    .capture()
+                    .top_level_windows()?
+                    .into_iter()
+                    .find(|window| input.matches(window))
+                    .ok_or_else(|| {
+                        ActionError::NotFound(format!("No window {}", input.describe()))
+                    })?;
+                self.capture().command_window(window.id, command)?;
+                Ok(ActionOutput::Windows {
+                    windows: vec![window],
+                })
+            }
+            Action::SaveCursor => match input_driver.location() {
+                Ok((x, y)) => {
+                    *self.saved_cursor.lock().unwrap() = Some((x, y));
+                    Ok(ActionOutput::CursorPosition { x, y })
+                }
                 Err(e) => Err(ActionError::ExecutionFailed(e.to_string())),
             },
-            Action::Screenshot => {
+            Action::RestoreCursor => {
+                let Some((x, y)) = self.saved_cursor() else {
+                    return Err(ActionError::InvalidInput(
+                        "No cursor position has been saved".to_string(),
+                    ));
+                };
+                input_driver
+                    .move_mouse(x, y, Abs)
+                    .map(|_| ActionOutput::NoData)
+                    .map_err(|e| ActionError::ExecutionFailed(e.to_string()))
+            }
+            Action::Ping => Ok(ActionOutput::NoData),
+            Action::Wait { input } => {
+                sleep(Duration::from_millis(input.duration_ms)).await;
+                Ok(ActionOutput::NoData)
+            }
+            Action::Scroll { input } => {
+                let axis = match input.axis {
+                    ScrollAxis::Vertical => Axis::Vertical,
+                    ScrollAxis::Horizontal => Axis::Horizontal,
+                };
+                let modifiers = parse_modifiers(&input.modifiers)?;
+
+                let (held, pressed) = Self::press_modifiers(input_driver, &modifiers).await;
+                let result = pressed.and_then(|_| {
+                    input_driver
+                        .scroll(input.amount, axis)
+                        .map(|_| ActionOutput::NoData)
+                        .map_err(|e| ActionError::ExecutionFailed(e.to_string()))
+                });
+                Self::release_modifiers(input_driver, &modifiers[..held], result)
+            }
+            Action::SmoothScroll { input } => {
+                let axis = match input.axis {
+                    ScrollAxis::Vertical => Axis::Vertical,
+                    ScrollAxis::Horizontal => Axis::Horizontal,
+                };
+                // Wheel events only come in whole clicks, so the distance is rounded to clicks
+                // and the clicks are spread evenly over the duration
+                let clicks =
+                    (input.pixels as f64 / config.scroll_pixels_per_click as f64).round() as i32;
+                let duration =
+                    Duration::from_millis(input.duration_ms.unwrap_or(SMOOTH_SCROLL_DURATION_MS));
+                let steps = (duration.as_millis() / SCROLL_STEP_INTERVAL.as_millis())
+                    .clamp(1, clicks.unsigned_abs().max(1) as u128)
+                    as i32;
+                let interval = duration / steps as u32;
+                let modifiers = parse_modifiers(&input.modifiers)?;
+
+                let (held, pressed) = Self::press_modifiers(input_driver, &modifiers).await;
+                let result = match pressed {
+                    Ok(()) => {
+                        async {
+                            let mut scrolled = 0;
+                            for step in 1..=steps {
+                                // Clicks due by the end of this step, any remainder carries to the next
+                                let due = (clicks as i64 * step as i64 / steps as i64) as i32;
+                                if due != scrolled {
+                                    input_driver
+                                        .scroll(due - scrolled, axis)
+                                        .map_err(|e| ActionError::ExecutionFailed(e.to_string()))?;
+                                    scrolled = due;
+                                }
+                                if step < steps {
+                                    sleep(interval).await;
+                                }
+                            }
+                            Ok(ActionOutput::NoData)
+                        }
+                        .await
+                    }
+                    Err(e) => Err(e),
+                };
+                Self::release_modifiers(input_driver, &modifiers[..held], result)
+            }
+            Action::KineticScroll { input } => {
+                let (axis, sign) = match input.direction.axis() {
+                    (ScrollAxis::Vertical, sign) => (Axis::Vertical, sign),
+                    (ScrollAxis::Horizontal, sign) => (Axis::Horizontal, sign),
+                };
+                input_driver
+                    .move_mouse(input.x as i32, input.y as i32, Abs)
+                    .map_err(|e| ActionError::ExecutionFailed(e.to_string()))?;
+                let clicks = kinetic_scroll_clicks(input.velocity, config.scroll_pixels_per_click);
+                for (step, count) in clicks.into_iter().enumerate() {
+                    if step > 0 {
+                        sleep(SCROLL_STEP_INTERVAL).await;
+                    }
+                    if count != 0 {
+                        input_driver
+                            .scroll(count * sign, axis)
+                            .map_err(|e| ActionError::ExecutionFailed(e.to_string()))?;
+                    }
+                }
+                Ok(ActionOutput::NoData)
+            }
+            Action::Screenshot { input } => {
+                let cursor = if input.show_cursor {
+                    input_driver.location().ok()
+                } else {
+                    None
+                };
                 // Use the shared screenshot function
-                take_screenshot()
-                    .await
-                    .map(|image| ActionOutput::Screenshot { image })
+                self.take_screenshot(input, cursor).await
+            }
+            Action::Burst { input } => self.capture_burst(input).await,
+            Action::Ocr { input } => self.recognize_text(input).await,
+            Action::FindText { input } => self.find_text(input).await,
+            Action::LocateImage { input } => self.locate_image(input).await,
+            Action::WaitFor { input } => self.wait_for(input).await,
+            Action::Tap { input } => {
+                let (x, y) = (input.x as i32, input.y as i32);
+                self.perform_touch(
+                    TouchTool::Finger,
+                    &[
+                        (TouchPhase::Down, x, y, 1.0, TAP_HOLD),
+                        (TouchPhase::Up, x, y, 0.0, Duration::ZERO),
+                    ],
+                )
+                .await
+            }
+            Action::Swipe { input } => {
+                let duration =
+                    Duration::from_millis(input.duration_ms.unwrap_or(SWIPE_DURATION_MS));
+                let steps =
+                    (duration.as_millis() as u32 / TOUCH_STEP_INTERVAL.as_millis() as u32).max(1);
+                let (start_x, start_y) = (input.start.x as f64, input.start.y as f64);
+                let step_x = (input.end.x as f64 - start_x) / steps as f64;
+                let step_y = (input.end.y as f64 - start_y) / steps as f64;
+
+                let mut contacts = vec![(
+                    TouchPhase::Down,
+                    start_x as i32,
+                    start_y as i32,
+                    1.0,
+                    TOUCH_STEP_INTERVAL,
+                )];
+                for i in 1..=steps {
+                    contacts.push((
+                        TouchPhase::Move,
+                        (start_x + step_x * i as f64) as i32,
+                        (start_y + step_y * i as f64) as i32,
+                        1.0,
+                        TOUCH_STEP_INTERVAL,
+                    ));
+                }
+                contacts.push((
+                    TouchPhase::Up,
+                    input.end.x as i32,
+                    input.end.y as i32,
+                    0.0,
+                    Duration::ZERO,
+                ));
+
+                self.perform_touch(TouchTool::Finger, &contacts).await
+            }
+            Action::Pen { input } => {
+                let last = input.points.len() - 1;
+                let contacts: Vec<_> = input
+                    .points
+                    .iter()
+                    .enumerate()
+                    .map(|(i, p)| {
+                        let phase = if i == 0 {
+                            TouchPhase::Down
+                        } else {
+                            TouchPhase::Move
+                        };
+                        (
+                            phase,
+                            p.x as i32,
+                            p.y as i32,
+                            p.pressure,
+                            TOUCH_STEP_INTERVAL,
+                        )
+                    })
+                    .chain(std::iter::once((
+                        TouchPhase::Up,
+                        input.points[last].x as i32,
+                        input.points[last].y as i32,
+                        0.0,
+                        Duration::ZERO,
+                    )))
+                    .collect();
+
+                self.perform_touch(TouchTool::Pen, &contacts).await
             }
         }
     }
 
-    pub async fn start_processing(&self) {
-        let queue_clone = self.queue.clone();
-        let input_driver_clone = self.input_driver.clone();
+    // Replace a driver that keeps failing (e.g. the X connection dropped), reporting the outcome to the monitor
+    fn recover_driver(&self, input_driver: &mut TrackedInput<T>, failures: u32) -> bool {
+        let result = match &self.driver_factory {
+            Some(factory) => factory(),
+            None => Err("No driver factory configured".to_string()),
+        };
+        let error = match result {
+            Ok(driver) => {
+                *input_driver = TrackedInput::new(driver);
+                info!("Input driver recreated after {} failures", failures);
+                None
+            }
+            Err(e) => {
+                error!("Failed to recreate input driver: {}", e);
+                Some(e)
+            }
+        };
+        let recovered = error.is_none();
+        self.send_monitor_event(MonitorEventPayload::DriverRecovery {
+            failures,
+            recovered,
+            error,
+            timestamp: Utc::now(),
+        });
+        recovered
+    }
+
+    // The focused window as input is about to be sent, when responses are annotated with it
+    fn foreground_app(&self) -> Option<ForegroundApp> {
+        // The simulated screen has no windows
+        if !self.config.record_foreground || self.config.input_backend == InputBackend::Simulated {
+            return None;
+        }
+        self.capture.focused_window().map(|window| ForegroundApp {
+            app_name: window.app_name,
+            title: window.title,
+        })
+    }
+
+    /// Periodically probe the display server, reattaching to a new session after a logout/login
+    pub fn start_display_watch(&self) {
+        let watcher = self.clone();
 
         tokio::spawn(async move {
             loop {
-                let action = {
-                    let mut queue = queue_clone.lock().await;
-                    queue.pop()
-                };
+                sleep(DISPLAY_PROBE_INTERVAL).await;
 
-                if let Some((action, tx)) = action {
-                    let mut input_driver = input_driver_clone.lock().await;
-                    Self::action_delay().await;
+                let probe = reconnect_display(watcher.display.current().as_deref());
+                let reconnected = probe.is_ok();
+                let changed = watcher.display.update(probe);
+                if reconnected {
+                    let window = watcher.capture.focused_window();
+                    watcher.update_observation(|state| state.focused_window = window);
+                }
+                if let Some(status) = changed {
+                    if reconnected {
+                        // Capture connects per call, the input driver holds a stale connection
+                        let mut input_driver = watcher.lock_input_driver().await;
+                        watcher.recover_driver(&mut input_driver, 0);
+                    }
+                    info!(
+                        "Display {} ({:?})",
+                        if status.available {
+                            "available"
+                        } else {
+                            "unavailable"
+                        },
+                        status.display
+                    );
+                    watcher.send_monitor_event(MonitorEventPayload::DisplayStatus(status));
+                }
+            }
+        });
+    }
+
+    /// Periodically check the policy directory, reloading the rules when a file changes
+    pub fn start_policy_watch(&self) {
+        if !self.policy.is_watching() {
+            return;
+        }
+        let policy = self.policy.clone();
+
+        tokio::spawn(async move {
+            loop {
+                sleep(POLICY_POLL_INTERVAL).await;
+
+                match policy.refresh() {
+                    Some(Ok(files)) => info!("Reloaded policy from {} files", files),
+                    Some(Err(e)) => warn!("Keeping the previous policy: {}", e),
+                    None => {}
+                }
+            }
+        });
+    }
 
-                    let result = Self::handle_action(&mut input_driver, &action).await;
+    // Owns the pending actions, taking submissions as they arrive and handing the worker
+    // the next action in fair order whenever it asks for one
+    async fn dispatch(
+        self,
+        mut submissions: mpsc::UnboundedReceiver<Submission>,
+        mut ready: mpsc::Receiver<oneshot::Sender<QueueUnit>>,
+    ) {
+        let mut queue = FairQueue::new(self.config.max_queue_depth);
+        // Takeover input skips the fair queue, and holds back everything in it
+        let mut takeover_queue = VecDeque::new();
+        let mut takeover_sessions = self.takeover_sessions.subscribe();
+        let mut paused = self.paused.subscribe();
+        let mut waiting_worker: Option<oneshot::Sender<QueueUnit>> = None;
+        // An agent unit its pacing rules don't allow to start yet waits here rather than in
+        // the worker, which would hold the input driver all the while
+        let mut paced: Option<(QueueUnit, Instant)> = None;
+        loop {
+            let paced_until = paced.as_ref().map(|(_, until)| *until);
+            tokio::select! {
+                submission = submissions.recv() => {
+                    let Some((lane, unit)) = submission else { break };
+                    if unit.first().is_some_and(|item| item.3 == Origin::Takeover) {
+                        takeover_queue.push_back(unit);
+                    } else if let Err(unit) = queue.push(lane, unit) {
+                        for (_, _, tx, ..) in unit {
+                            let _ = tx.send(Err(ActionError::QueueFull(format!(
+                                "Too many pending actions, at most {} can be queued per run",
+                                self.config.max_queue_depth
+                            ))));
+                        }
+                    }
+                }
+                worker = ready.recv(), if waiting_worker.is_none() => {
+                    let Some(worker) = worker else { break };
+                    waiting_worker = Some(worker);
+                }
+                // A takeover started or ended
+                changed = takeover_sessions.changed() => {
+                    if changed.is_err() {
+                        break;
+                    }
+                }
+                // The server is restarting
+                changed = paused.changed() => {
+                    if changed.is_err() {
+                        break;
+                    }
+                }
+                // The paced unit may start
+                _ = sleep_until(paced_until.unwrap_or_else(Instant::now)), if paced_until.is_some() => {}
+            }
 
-                    // Notify completion with result
-                    let _ = tx.send(result);
+            // Nothing is handed out once paused, whatever is queued or still comes in is
+            // refused so its callers aren't left waiting on a process that is exiting
+            if *paused.borrow() {
+                let refused = takeover_queue
+                    .drain(..)
+                    .chain(paced.take().map(|(unit, _)| unit))
+                    .chain(std::iter::from_fn(|| queue.pop()));
+                for unit in refused.collect::<Vec<_>>() {
+                    for (_, _, tx, ..) in unit {
+                        let _ = tx.send(Err(ActionError::Restarting(RESTARTING.to_string())));
+                    }
                 }
+            }
 
-                sleep(Duration::from_millis(10)).await;
+            // Drop actions whose caller timed out and stopped waiting
+            let waited_for = |unit: &QueueUnit| unit.iter().any(|(_, _, tx, ..)| !tx.is_closed());
+            queue.retain(waited_for);
+            takeover_queue.retain(waited_for);
+            if paced.as_ref().is_some_and(|(unit, _)| !waited_for(unit)) {
+                paced = None;
+            }
+            if let Some(worker) = waiting_worker.take() {
+                let taken_over = *takeover_sessions.borrow() > 0;
+                let next = match takeover_queue.pop_front() {
+                    Some(item) => Some(item),
+                    None if taken_over => None,
+                    None => match paced.take() {
+                        Some((unit, until)) if until <= Instant::now() => Some(unit),
+                        Some(held) => {
+                            paced = Some(held);
+                            None
+                        }
+                        None => queue.pop().and_then(|unit| match self.pacing_wait(&unit) {
+                            Some(wait) => {
+                                paced = Some((unit, Instant::now() + wait));
+                                None
+                            }
+                            None => Some(unit),
+                        }),
+                    },
+                };
+                match next {
+                    Some(unit) => {
+                        let _ = worker.send(unit);
+                    }
+                    None => waiting_worker = Some(worker),
+                }
+            }
+            let pending = queue.len() + takeover_queue.len() + usize::from(paced.is_some());
+            self.update_observation(|state| state.queue.pending = pending);
+        }
+    }
+
+    pub async fn start_processing(&self) {
+        let Some(submissions) = self.submit_rx.lock().unwrap().take() else {
+            return; // Already processing
+        };
+        let (ready_tx, ready_rx) = mpsc::channel(1);
+        tokio::spawn(self.clone().dispatch(submissions, ready_rx));
+        let worker = self.clone();
+
+        tokio::spawn(async move {
+            let mut driver_failures = 0;
+            loop {
+                let (next_tx, next_rx) = oneshot::channel();
+                if ready_tx.send(next_tx).await.is_err() {
+                    break;
+                }
+                let unit = next_rx.await.ok();
+
+                if let Some(unit) = unit {
+                    // Held for the whole unit, so nothing else gets between a batch's actions
+                    let mut input_driver = worker.lock_input_driver().await;
+                    // Paused while the unit waited for the driver
+                    if *worker.paused.borrow() {
+                        for (_, _, tx, ..) in unit {
+                            let _ = tx.send(Err(ActionError::Restarting(RESTARTING.to_string())));
+                        }
+                        continue;
+                    }
+                    // A batch's progress gets an id of its own, its first action may report
+                    // progress of its own too
+                    let batch = match unit.as_slice() {
+                        [_, _, ..] => Some((Uuid::new_v4().to_string(), unit.len())),
+                        _ => None,
+                    };
+                    let mut items = unit.into_iter().enumerate();
+                    for (index, (action, queued_at, tx, origin, id, step_timeout)) in items.by_ref()
+                    {
+                        // The caller stopped waiting, which for a batch ends it
+                        if tx.is_closed() {
+                            break;
+                        }
+                        if let Some((batch_id, total)) = &batch {
+                            worker.report_progress(
+                                &id,
+                                Some(batch_id),
+                                index + 1,
+                                *total,
+                                action.action_type(),
+                            );
+                        }
+                        worker.update_observation(|state| {
+                            state.queue.busy = true;
+                            state.queue.current_action = Some(action.action_type().to_string());
+                        });
+                        // A human in control expects their input to land right away
+                        let paced = match origin {
+                            Origin::Agent => {
+                                Self::action_delay().await;
+                                worker.pace(&action).await
+                            }
+                            Origin::Takeover => Ok(()),
+                        };
+
+                        let foreground = worker.foreground_app();
+                        let started_at = Instant::now();
+                        let preserved_cursor =
+                            if worker.config.preserve_cursor && action.is_read_only() {
+                                input_driver.location().ok()
+                            } else {
+                                None
+                            };
+                        let deadline = started_at + step_timeout.0;
+                        input_driver.begin_step();
+                        let mut result = match paced {
+                            Ok(()) => {
+                                worker
+                                    .run_step(&mut input_driver, &action, &id, deadline)
+                                    .await
+                            }
+                            Err(error) => Err(error),
+                        };
+
+                        // Only the driver's own failures count, not a failed screen capture
+                        // or clipboard
+                        match &result {
+                            Err(_) if input_driver.failed() => {
+                                driver_failures += 1;
+                                if driver_failures >= DRIVER_FAILURE_THRESHOLD {
+                                    // Running it again would repeat the events it already sent
+                                    let sent = input_driver.sent();
+                                    if worker.recover_driver(&mut input_driver, driver_failures)
+                                        && !sent
+                                    {
+                                        result = worker
+                                            .run_step(&mut input_driver, &action, &id, deadline)
+                                            .await;
+                                    }
+                                    driver_failures = 0;
+                                }
+                            }
+                            Ok(_) if input_driver.sent() => driver_failures = 0,
+                            _ => {}
+                        }
+                        if let Some((x, y)) = preserved_cursor {
+                            if input_driver.location().ok() != Some((x, y)) {
+                                let _ = input_driver.move_mouse(x, y, Abs);
+                            }
+                        }
+                        let timing = ActionTiming {
+                            queued_ms: (started_at - queued_at).as_millis() as u64,
+                            execution_ms: started_at.elapsed().as_millis() as u64,
+                        };
+                        let failure_screenshot = match &result {
+                            Err(ActionError::ExecutionFailed(_))
+                                if worker.config.failure_screenshots =>
+                            {
+                                worker.failure_screenshot()
+                            }
+                            _ => None,
+                        };
+
+                        let cursor = worker
+                            .record_cursor(&input_driver)
+                            .map(|cursor| (cursor.x, cursor.y));
+                        worker.update_observation(|state| {
+                            state.cursor = cursor;
+                            state.queue.busy = false;
+                            state.queue.current_action = None;
+                        });
+
+                        // Notify completion with result
+                        let failed = match &result {
+                            Err(ActionError::Timeout) => {
+                                !matches!(step_timeout, (_, OnTimeout::Continue))
+                            }
+                            result => result.is_err(),
+                        };
+                        let _ =
+                            tx.send(Ok((result, timing, foreground, failure_screenshot, cursor)));
+                        if failed {
+                            break;
+                        }
+                    }
+                    // Later actions of a batch build on the ones before, so they don't run
+                    // once one has failed
+                    for (_, (_, _, tx, ..)) in items {
+                        let _ = tx.send(Err(ActionError::ExecutionFailed(
+                            "Skipped, an earlier action in the batch failed".to_string(),
+                        )));
+                    }
+                }
             }
         });
     }
 }
 
+// A target that no longer looks as expected, with the area around it for the client to look
+// at again
+fn stale_target(
+    image: &image::RgbaImage,
+    px: u32,
+    py: u32,
+    message: String,
+) -> Result<(), ActionError> {
+    let (crop_x, crop_y) = (
+        px.saturating_sub(STALE_CROP_RADIUS),
+        py.saturating_sub(STALE_CROP_RADIUS),
+    );
+    let crop = image::imageops::crop_imm(
+        image,
+        crop_x,
+        crop_y,
+        (px + STALE_CROP_RADIUS).min(image.width()) - crop_x,
+        (py + STALE_CROP_RADIUS).min(image.height()) - crop_y,
+    )
+    .to_image();
+    Err(ActionError::StaleTarget {
+        message,
+        crop: encode_png(&crop)?,
+    })
+}
+
+// The image text was read from with the spans, in input coordinates, drawn on it. Encoding
+// a whole screen takes a while, so it runs off the async workers
+async fn text_debug_image(
+    mut image: image::RgbaImage,
+    origin: (i32, i32),
+    spans: Vec<TextSpan>,
+    chosen: Option<TextSpan>,
+) -> Result<String, ActionError> {
+    let in_image = move |span: TextSpan| TextSpan {
+        x: span.x - origin.0,
+        y: span.y - origin.1,
+        ..span
+    };
+    tokio::task::spawn_blocking(move || {
+        let spans: Vec<TextSpan> = spans.into_iter().map(in_image).collect();
+        draw_text_boxes(&mut image, &spans, chosen.map(in_image).as_ref());
+        encode_png(&image)
+    })
+    .await
+    .map_err(|e| ActionError::ExecutionFailed(format!("Drawing text boxes failed: {}", e)))?
+}
+
+// How long the worker lets a request's action run, its own timeout_ms or by default
+// ACTION_TIMEOUT on top of however long the action is asked to wait
+fn step_limit(request: &ActionRequest) -> Duration {
+    match request.timeout_ms {
+        Some(timeout_ms) => Duration::from_millis(timeout_ms),
+        None => ACTION_TIMEOUT + Duration::from_millis(request.action.max_wait()),
+    }
+}
+
 /// Mock driver for testing
 #[cfg(test)]
 pub mod tests {
     use super::*;
-    use enigo::{Axis, Coordinate, Direction, InputResult, Key};
+    use crate::observe::{ListedWindow, WindowInfo};
+    use crate::runs::{OpenRunBody, RunLimits};
+    use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+    use std::sync::atomic::Ordering;
 
     pub struct MockEnigo {
         pub mouse_pos: (i32, i32),
         pub last_action: String,
+        pub relative_moves: u32,
+        /// Every scroll event as (length, axis)
+        pub scrolls: Vec<(i32, Axis)>,
+        pub button_presses: u32,
+        /// Every position the cursor was moved to
+        pub path: Vec<(i32, i32)>,
+        /// Simulates a lost display connection, every button event fails
+        pub disconnected: bool,
     }
 
     impl MockEnigo {
@@ -518,6 +3144,11 @@ pub mod tests {
             MockEnigo {
                 mouse_pos: (0, 0),
                 last_action: String::new(),
+                relative_moves: 0,
+                scrolls: Vec::new(),
+                button_presses: 0,
+                path: Vec::new(),
+                disconnected: false,
             }
         }
     }
@@ -546,18 +3177,32 @@ pub mod tests {
 
     impl Mouse for MockEnigo {
         fn button(&mut self, button: Button, direction: Direction) -> InputResult<()> {
+            if self.disconnected {
+                return Err(InputError::Simulate("display connection lost"));
+            }
             self.last_action = format!("button_{:?}_{:?}", button, direction);
+            if direction == Direction::Press {
+                self.button_presses += 1;
+            }
             Ok(())
         }
 
-        fn move_mouse(&mut self, x: i32, y: i32, _coordinate: Coordinate) -> InputResult<()> {
-            self.mouse_pos = (x, y);
+        fn move_mouse(&mut self, x: i32, y: i32, coordinate: Coordinate) -> InputResult<()> {
+            self.mouse_pos = match coordinate {
+                Coordinate::Abs => (x, y),
+                Coordinate::Rel => {
+                    self.relative_moves += 1;
+                    (self.mouse_pos.0 + x, self.mouse_pos.1 + y)
+                }
+            };
+            self.path.push(self.mouse_pos);
             self.last_action = format!("move_mouse_{},{}", x, y);
             Ok(())
         }
 
         fn scroll(&mut self, length: i32, axis: Axis) -> InputResult<()> {
             self.last_action = format!("scroll_{}_{:?}", length, axis);
+            self.scrolls.push((length, axis));
             Ok(())
         }
 
@@ -570,258 +3215,3053 @@ pub mod tests {
         }
     }
 
+    type RecordedContact = (TouchTool, TouchPhase, i32, i32, f32);
+
+    /// Records contacts so tests can inspect the gesture that was injected
+    pub struct MockTouch {
+        pub contacts: Arc<std::sync::Mutex<Vec<RecordedContact>>>,
+    }
+
+    impl TouchDriver for MockTouch {
+        fn contact(
+            &mut self,
+            tool: TouchTool,
+            phase: TouchPhase,
+            x: i32,
+            y: i32,
+            pressure: f32,
+        ) -> Result<(), String> {
+            self.contacts
+                .lock()
+                .unwrap()
+                .push((tool, phase, x, y, pressure));
+            Ok(())
+        }
+    }
+
+    /// A blank screen the size of the mock display
+    pub fn mock_capture() -> Arc<dyn ScreenCapture> {
+        Arc::new(ImageCapture::new(image::RgbaImage::new(1920, 1080)))
+    }
+
     // Make the helper function public
     pub async fn create_test_action_queue() -> Arc<ActionQueue<MockEnigo>> {
+        create_test_action_queue_with_config(Config::default()).await
+    }
+
+    pub async fn create_test_action_queue_with_config(
+        config: Config,
+    ) -> Arc<ActionQueue<MockEnigo>> {
         let mock_enigo = MockEnigo::new();
-        let action_queue = ActionQueue::new(mock_enigo);
+        let action_queue = ActionQueue::new(mock_enigo, config).with_capture(mock_capture());
         let action_queue = Arc::new(action_queue);
         action_queue.start_processing().await;
         action_queue
     }
 
     #[tokio::test]
-    async fn test_mouse_move() {
+    async fn test_mouse_move() {
+        let queue = create_test_action_queue().await;
+
+        let result = queue
+            .execute_action(ActionRequest::new(
+                "test_mouse_move".to_string(),
+                Action::MouseMove {
+                    input: MouseMoveInput {
+                        x: 100,
+                        y: 200,
+                        monitor: None,
+                        motion: None,
+                        duration_ms: None,
+                    },
+                },
+            ))
+            .await;
+        assert!(matches!(result.status, ActionResponseStatus::Success));
+
+        let enigo = queue.input_driver.lock().await;
+        assert_eq!(enigo.mouse_pos, (100, 200));
+        assert_eq!(enigo.last_action, "move_mouse_100,200");
+    }
+
+    #[tokio::test]
+    async fn test_mouse_move_glide() {
+        let config = Config {
+            mouse_motion: MouseMotion::Glide,
+            glide_duration_ms: 100,
+            ..Config::default()
+        };
+        let queue = create_test_action_queue_with_config(config).await;
+        let move_to = |x, motion| {
+            ActionRequest::new(
+                "test_mouse_move_glide".to_string(),
+                Action::MouseMove {
+                    input: MouseMoveInput {
+                        x,
+                        y: 0,
+                        monitor: None,
+                        motion,
+                        duration_ms: None,
+                    },
+                },
+            )
+        };
+
+        // Gliding by default passes through points on the way
+        let result = queue.execute_action(move_to(100, None)).await;
+        assert!(matches!(result.status, ActionResponseStatus::Success));
+        {
+            let mut enigo = queue.input_driver.lock().await;
+            assert_eq!(enigo.mouse_pos, (100, 0));
+            assert_eq!(enigo.path.len(), 10);
+            assert_eq!(enigo.path[4], (50, 0));
+            enigo.path.clear();
+        }
+
+        // A request can still warp
+        let result = queue
+            .execute_action(move_to(300, Some(MouseMotion::Warp)))
+            .await;
+        assert!(matches!(result.status, ActionResponseStatus::Success));
+        let enigo = queue.input_driver.lock().await;
+        assert_eq!(enigo.path, vec![(300, 0)]);
+    }
+
+    #[tokio::test]
+    async fn test_bad_config_fails_instead_of_panicking() {
+        let simulated = |monitors: &str, image: Option<&str>| Config {
+            input_backend: InputBackend::Simulated,
+            simulated_monitors: monitors.to_string(),
+            capture_image: image.map(str::to_string),
+            ..Config::default()
+        };
+        let error = |config| async move { create_action_queue(&config).await.err().unwrap() };
+
+        assert!(error(simulated("1920by1080", None))
+            .await
+            .contains("VALK_SIMULATED_MONITORS"));
+        assert!(
+            error(simulated("1920x1080", Some("/nonexistent/screen.png")))
+                .await
+                .contains("/nonexistent/screen.png")
+        );
+        let image = Config {
+            capture_image: Some("/nonexistent/screen.png".to_string()),
+            ..Config::default()
+        };
+        assert!(error(image).await.contains("/nonexistent/screen.png"));
+        assert!(create_action_queue(&simulated("1920x1080", None))
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_mouse_move_relative_to_monitor() {
+        let screen = VirtualScreen::parse("1920x1080,1280x1024").unwrap();
+        let queue = Arc::new(
+            ActionQueue::new(MockEnigo::new(), Config::default()).with_capture(Arc::new(screen)),
+        );
+        queue.start_processing().await;
+        let move_to = |x, y| {
+            ActionRequest::new(
+                "test_mouse_move_monitor".to_string(),
+                Action::MouseMove {
+                    input: MouseMoveInput {
+                        x,
+                        y,
+                        monitor: Some(MonitorSelector::Index(1)),
+                        motion: None,
+                        duration_ms: None,
+                    },
+                },
+            )
+        };
+
+        let result = queue.execute_action(move_to(10, 20)).await;
+        assert!(matches!(result.status, ActionResponseStatus::Success));
+        assert_eq!(queue.input_driver.lock().await.mouse_pos, (1930, 20));
+
+        // The second monitor is only 1280 wide
+        let result = queue.execute_action(move_to(1500, 20)).await;
+        assert!(matches!(
+            result.error,
+            Some(ActionError::InvalidInput { .. })
+        ));
+        assert_eq!(queue.input_driver.lock().await.mouse_pos, (1930, 20));
+    }
+
+    #[tokio::test]
+    async fn test_scroll() {
+        let queue = create_test_action_queue().await;
+
+        let result = queue
+            .execute_action(ActionRequest::new(
+                "test_scroll".to_string(),
+                Action::Scroll {
+                    input: ScrollInput {
+                        axis: ScrollAxis::Vertical,
+                        amount: -5,
+                        modifiers: Vec::new(),
+                    },
+                },
+            ))
+            .await;
+        assert!(matches!(result.status, ActionResponseStatus::Success));
+
+        let enigo = queue.input_driver.lock().await;
+        assert_eq!(enigo.last_action, "scroll_-5_Vertical");
+    }
+
+    #[tokio::test]
+    async fn test_scroll_with_modifiers_releases_them() {
+        let queue = create_test_action_queue().await;
+
+        let result = queue
+            .execute_action(ActionRequest::new(
+                "test_zoom".to_string(),
+                Action::Scroll {
+                    input: ScrollInput {
+                        axis: ScrollAxis::Vertical,
+                        amount: -3,
+                        modifiers: vec!["ctrl".to_string()],
+                    },
+                },
+            ))
+            .await;
+        assert!(matches!(result.status, ActionResponseStatus::Success));
+
+        let enigo = queue.input_driver.lock().await;
+        assert_eq!(enigo.scrolls, vec![(-3, Axis::Vertical)]);
+        assert_eq!(enigo.last_action, "key_Control_Release");
+    }
+
+    #[tokio::test]
+    async fn test_smooth_scroll_spreads_clicks_over_duration() {
+        let queue = create_test_action_queue().await;
+
+        let started = Instant::now();
+        let result = queue
+            .execute_action(ActionRequest::new(
+                "test_smooth_scroll".to_string(),
+                Action::SmoothScroll {
+                    input: SmoothScrollInput {
+                        axis: ScrollAxis::Horizontal,
+                        pixels: -400,
+                        duration_ms: Some(200),
+                        modifiers: Vec::new(),
+                    },
+                },
+            ))
+            .await;
+        assert!(matches!(result.status, ActionResponseStatus::Success));
+        assert!(started.elapsed() >= ACTION_DELAY + Duration::from_millis(150));
+
+        // 400px at 50px per click, one click at a time
+        let enigo = queue.input_driver.lock().await;
+        assert_eq!(enigo.scrolls, vec![(-1, Axis::Horizontal); 8]);
+    }
+
+    #[test]
+    fn test_kinetic_scroll_clicks_slow_down() {
+        let clicks = kinetic_scroll_clicks(2000, 50);
+        // About velocity times the time constant, 650px at 50px per click
+        assert_eq!(clicks.iter().sum::<i32>(), 13);
+        assert!(clicks.iter().all(|&count| count >= 0));
+        let fired: Vec<usize> = (0..clicks.len()).filter(|&i| clicks[i] > 0).collect();
+        assert!(fired[1] - fired[0] < fired[fired.len() - 1] - fired[fired.len() - 2]);
+        assert!(kinetic_scroll_clicks(10, 50).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_kinetic_scroll_flicks_at_point() {
+        let queue = create_test_action_queue().await;
+
+        let result = queue
+            .execute_action(ActionRequest::new(
+                "test_kinetic_scroll".to_string(),
+                Action::KineticScroll {
+                    input: KineticScrollInput {
+                        x: 300,
+                        y: 400,
+                        velocity: 2000,
+                        direction: ScrollDirection::Up,
+                    },
+                },
+            ))
+            .await;
+        assert!(matches!(result.status, ActionResponseStatus::Success));
+
+        let enigo = queue.input_driver.lock().await;
+        assert_eq!(enigo.mouse_pos, (300, 400));
+        assert!(enigo
+            .scrolls
+            .iter()
+            .all(|&(count, axis)| count < 0 && axis == Axis::Vertical));
+        assert_eq!(
+            enigo.scrolls.iter().map(|(count, _)| count).sum::<i32>(),
+            -13
+        );
+    }
+
+    #[tokio::test]
+    async fn test_hover_dwells_without_clicking() {
+        let queue = create_test_action_queue().await;
+
+        let started = Instant::now();
+        let result = queue
+            .execute_action(ActionRequest::new(
+                "test_hover".to_string(),
+                Action::Hover {
+                    input: HoverInput {
+                        x: 120,
+                        y: 80,
+                        duration_ms: Some(200),
+                        monitor: None,
+                    },
+                },
+            ))
+            .await;
+        assert!(matches!(result.status, ActionResponseStatus::Success));
+        assert!(result.timing.unwrap().execution_ms >= 200);
+        assert!(started.elapsed() >= ACTION_DELAY + Duration::from_millis(200));
+
+        let enigo = queue.input_driver.lock().await;
+        assert_eq!(enigo.mouse_pos, (120, 80));
+        assert_eq!(enigo.button_presses, 0);
+    }
+
+    #[tokio::test]
+    async fn test_click_at_coordinates() {
+        let queue = create_test_action_queue().await;
+
+        let response = queue
+            .execute_action(ActionRequest::new(
+                "test_click".to_string(),
+                Action::Click {
+                    input: ClickInput {
+                        x: 640,
+                        y: 360,
+                        button: MouseButton::Right,
+                        modifiers: Vec::new(),
+                        expected_pixel: None,
+                        expected_text: None,
+                        monitor: None,
+                    },
+                },
+            ))
+            .await;
+        assert!(matches!(response.status, ActionResponseStatus::Success));
+
+        let enigo = queue.input_driver.lock().await;
+        assert_eq!(enigo.mouse_pos, (640, 360));
+        assert_eq!(enigo.button_presses, 1);
+        assert_eq!(enigo.last_action, "button_Right_Release");
+    }
+
+    #[tokio::test]
+    async fn test_click_refuses_stale_target() {
+        let queue = create_test_action_queue().await;
+        let click = |color: &str| {
+            ActionRequest::new(
+                "test_stale_target".to_string(),
+                Action::Click {
+                    input: ClickInput {
+                        x: 20,
+                        y: 500,
+                        button: MouseButton::Left,
+                        modifiers: Vec::new(),
+                        expected_pixel: Some(color.to_string()),
+                        expected_text: None,
+                        monitor: None,
+                    },
+                },
+            )
+        };
+
+        // The mock screen is black
+        let response = queue.execute_action(click("#ff8800")).await;
+        let Some(ActionError::StaleTarget { message, crop }) = response.error else {
+            panic!("expected a stale target, got {:?}", response);
+        };
+        assert!(message.ends_with("found #000000"));
+        let crop = image::load_from_memory(&BASE64.decode(crop).unwrap()).unwrap();
+        // Cut off by the left edge of the screen
+        assert_eq!((crop.width(), crop.height()), (70, 100));
+        assert_eq!(queue.input_driver.lock().await.button_presses, 0);
+
+        let response = queue.execute_action(click("#0a0a0a")).await;
+        assert!(matches!(response.status, ActionResponseStatus::Success));
+        assert_eq!(queue.input_driver.lock().await.button_presses, 1);
+    }
+
+    #[tokio::test]
+    async fn test_click_refuses_target_with_other_text() {
+        // Where the words are in the 400x80 area read around the target at (300, 200)
+        let word = |text: &str, x, width| TextSpan {
+            text: text.to_string(),
+            x,
+            y: 30,
+            width,
+            height: 20,
+            confidence: 90.0,
+        };
+        let recognizer =
+            crate::ocr::FixedText(vec![vec![word("Cancel", 120, 60), word("Submit", 190, 60)]]);
+        let queue = Arc::new(
+            ActionQueue::new(MockEnigo::new(), Config::default())
+                .with_capture(mock_capture())
+                .with_recognizer(Arc::new(recognizer)),
+        );
+        queue.start_processing().await;
+        let click = |text: &str| {
+            ActionRequest::new(
+                "test_stale_text".to_string(),
+                Action::Click {
+                    input: ClickInput {
+                        x: 300,
+                        y: 200,
+                        button: MouseButton::Left,
+                        modifiers: Vec::new(),
+                        expected_pixel: None,
+                        expected_text: Some(text.to_string()),
+                        monitor: None,
+                    },
+                },
+            )
+        };
+
+        // Still on screen, but moved off the target
+        let response = queue.execute_action(click("Cancel")).await;
+        let Some(ActionError::StaleTarget { message, crop }) = response.error else {
+            panic!("expected a stale target, got {:?}", response);
+        };
+        assert_eq!(
+            message,
+            "Expected \"Cancel\" at (300, 200) but found \"Cancel Submit\""
+        );
+        let crop = image::load_from_memory(&BASE64.decode(crop).unwrap()).unwrap();
+        assert_eq!((crop.width(), crop.height()), (100, 100));
+        assert_eq!(queue.input_driver.lock().await.button_presses, 0);
+
+        // Recognition mistakes are allowed for
+        let response = queue.execute_action(click("subrnit")).await;
+        assert!(matches!(response.status, ActionResponseStatus::Success));
+        assert_eq!(queue.input_driver.lock().await.button_presses, 1);
+    }
+
+    #[tokio::test]
+    async fn test_paste_text() {
+        let clipboard = Arc::new(MemoryClipboard::default());
+        let queue = Arc::new(
+            ActionQueue::new(MockEnigo::new(), Config::default())
+                .with_capture(mock_capture())
+                .with_clipboard(clipboard.clone()),
+        );
+        queue.start_processing().await;
+
+        let text = "Grüße, 世界\n".repeat(500);
+        let response = queue
+            .execute_action(ActionRequest::new(
+                "test_paste_text".to_string(),
+                Action::PasteText {
+                    input: TypeTextInput { text: text.clone() },
+                },
+            ))
+            .await;
+        assert!(matches!(response.status, ActionResponseStatus::Success));
+        assert_eq!(clipboard.text(), Some(text));
+        // The paste shortcut was sent, and its modifier let go last
+        assert!(queue
+            .input_driver
+            .lock()
+            .await
+            .last_action
+            .ends_with("_Release"));
+    }
+
+    #[tokio::test]
+    async fn test_click_with_modifiers() {
+        let queue = create_test_action_queue().await;
+
+        let response = queue
+            .execute_action(ActionRequest::new(
+                "test_click_with_modifiers".to_string(),
+                Action::Click {
+                    input: ClickInput {
+                        x: 10,
+                        y: 20,
+                        button: MouseButton::Left,
+                        modifiers: vec!["ctrl".to_string(), "shift".to_string()],
+                        expected_pixel: None,
+                        expected_text: None,
+                        monitor: None,
+                    },
+                },
+            ))
+            .await;
+        assert!(matches!(response.status, ActionResponseStatus::Success));
+
+        let enigo = queue.input_driver.lock().await;
+        assert_eq!(enigo.button_presses, 1);
+        // Modifiers come up last, in reverse order
+        assert_eq!(enigo.last_action, "key_Control_Release");
+        drop(enigo);
+
+        let response = queue
+            .execute_action(ActionRequest::new(
+                "test_click_with_modifiers".to_string(),
+                Action::Click {
+                    input: ClickInput {
+                        x: 10,
+                        y: 20,
+                        button: MouseButton::Left,
+                        modifiers: vec!["hyper".to_string()],
+                        expected_pixel: None,
+                        expected_text: None,
+                        monitor: None,
+                    },
+                },
+            ))
+            .await;
+        assert!(matches!(response.error, Some(ActionError::InvalidInput(_))));
+    }
+
+    #[tokio::test]
+    async fn test_left_click() {
+        let queue = create_test_action_queue().await;
+
+        let result = queue
+            .execute_action(ActionRequest::new(
+                "test_left_click".to_string(),
+                Action::LeftClick,
+            ))
+            .await;
+        assert!(matches!(result.status, ActionResponseStatus::Success));
+
+        let enigo = queue.input_driver.lock().await;
+        assert!(enigo.last_action.contains("button_Left"));
+    }
+
+    #[tokio::test]
+    async fn test_type_text() {
+        let queue = create_test_action_queue().await;
+
+        let test_texts = ["Hello, World!", "1234567890", "Special chars: !@#$%^&*()"];
+
+        for text in test_texts {
+            let response = queue
+                .execute_action(ActionRequest::new(
+                    "test_type_text".to_string(),
+                    Action::TypeText {
+                        input: TypeTextInput {
+                            text: text.to_string(),
+                        },
+                    },
+                ))
+                .await;
+
+            match response.status {
+                ActionResponseStatus::Success => {
+                    let enigo = queue.input_driver.lock().await;
+                    assert_eq!(
+                        enigo.last_action,
+                        format!("text_{}", text),
+                        "Failed to verify text input for: {}",
+                        text
+                    );
+                }
+                ActionResponseStatus::Error => {
+                    panic!("Failed to type text '{}': {:?}", text, response.error);
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_type_unicode() {
+        let queue = create_test_action_queue().await;
+
+        let test_texts = [
+            "Unicode: ñáéíóú",
+            "Emojis: 😊🚀🌟",
+            "Cyrillic: Привет, мир!",
+            "Japanese: こんにちは世界",
+            "Korean: 안녕하세요 세계",
+            "Arabic: مرحبا بالعالم",
+            "Hebrew: שלום עולם",
+            "Greek: Γειά σου κόσμε",
+            "Turkish: Merhaba dünya",
+            "Vietnamese: Chào thế giới",
+            "Thai: สวัสดีโลก",
+            "Russian: Привет, мир!",
+            "Chinese: 你好，世界",
+        ];
+
+        for text in test_texts {
+            let response = queue
+                .execute_action(ActionRequest::new(
+                    "test_type_unicode".to_string(),
+                    Action::TypeText {
+                        input: TypeTextInput {
+                            text: text.to_string(),
+                        },
+                    },
+                ))
+                .await;
+
+            match response.status {
+                ActionResponseStatus::Success => {
+                    let enigo = queue.input_driver.lock().await;
+                    assert_eq!(
+                        enigo.last_action,
+                        format!("text_{}", text),
+                        "Failed to verify text input for: {}",
+                        text
+                    );
+                }
+                ActionResponseStatus::Error => {
+                    panic!("Failed to type text '{}': {:?}", text, response.error);
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_type_text_empty() {
+        let queue = create_test_action_queue().await;
+
+        let response = queue
+            .execute_action(ActionRequest::new(
+                "test_type_text_empty".to_string(),
+                Action::TypeText {
+                    input: TypeTextInput {
+                        text: "".to_string(),
+                    },
+                },
+            ))
+            .await;
+        assert!(matches!(response.status, ActionResponseStatus::Error));
+    }
+
+    #[tokio::test]
+    async fn test_key_press() {
+        let queue = create_test_action_queue().await;
+
+        let response = queue
+            .execute_action(ActionRequest::new(
+                "test_key_press".to_string(),
+                Action::KeyPress {
+                    input: KeyPressInput {
+                        key: "ctrl+c".to_string(),
+                    },
+                },
+            ))
+            .await;
+        assert!(matches!(response.status, ActionResponseStatus::Success));
+
+        let enigo = queue.input_driver.lock().await;
+        // The last action should be releasing the ctrl key
+        assert!(enigo.last_action.contains("key_Control_Release"));
+    }
+
+    #[tokio::test]
+    async fn test_cursor_position() {
+        let queue = create_test_action_queue().await;
+
+        // First move the cursor
+        let _ = queue
+            .execute_action(ActionRequest::new(
+                "test_cursor_position".to_string(),
+                Action::MouseMove {
+                    input: MouseMoveInput {
+                        x: 150,
+                        y: 250,
+                        monitor: None,
+                        motion: None,
+                        duration_ms: None,
+                    },
+                },
+            ))
+            .await;
+
+        // Then get position
+        let response = queue
+            .execute_action(ActionRequest::new(
+                "test_cursor_position".to_string(),
+                Action::CursorPosition,
+            ))
+            .await;
+        assert!(matches!(response.status, ActionResponseStatus::Success));
+
+        if let Some(ActionOutput::CursorPosition { x, y }) = response.data {
+            assert_eq!(x, 150);
+            assert_eq!(y, 250);
+        } else {
+            panic!("Expected cursor position data");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_action_timeout() {
+        let queue = create_test_action_queue().await;
+
+        // Create a very short timeout for testing
+        let short_timeout = Duration::from_millis(10);
+
+        // Attempt to execute an action with a short timeout
+        let result = timeout(
+            short_timeout,
+            queue.execute_action(ActionRequest::new(
+                "test_action_timeout".to_string(),
+                Action::LeftClick,
+            )),
+        )
+        .await;
+
+        // The timeout should occur
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_double_click() {
+        let queue = create_test_action_queue().await;
+
+        let response = queue
+            .execute_action(ActionRequest::new(
+                "test_double_click".to_string(),
+                Action::DoubleClick,
+            ))
+            .await;
+        assert!(matches!(response.status, ActionResponseStatus::Success));
+
+        let enigo = queue.input_driver.lock().await;
+        // Should end with a release of left button
+        assert!(enigo.last_action.contains("button_Left_Release"));
+    }
+
+    #[tokio::test]
+    async fn test_key_down_and_up_hold_across_actions() {
+        let queue = create_test_action_queue().await;
+        let key_action = |down: bool| {
+            let input = KeyPressInput {
+                key: "shift+a".to_string(),
+            };
+            if down {
+                Action::KeyDown { input }
+            } else {
+                Action::KeyUp { input }
+            }
+        };
+
+        let response = queue
+            .execute_action(ActionRequest::new(
+                "test_key_down".to_string(),
+                key_action(true),
+            ))
+            .await;
+        assert!(matches!(response.status, ActionResponseStatus::Success));
+        // The main key is pressed last and stays down
+        assert_eq!(
+            queue.input_driver.lock().await.last_action,
+            "key_Unicode('a')_Press"
+        );
+
+        let response = queue
+            .execute_action(ActionRequest::new(
+                "test_key_up".to_string(),
+                key_action(false),
+            ))
+            .await;
+        assert!(matches!(response.status, ActionResponseStatus::Success));
+        // Modifiers are released last
+        assert_eq!(
+            queue.input_driver.lock().await.last_action,
+            "key_Shift_Release"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_triple_click() {
+        let queue = create_test_action_queue().await;
+
+        let response = queue
+            .execute_action(ActionRequest::new(
+                "test_triple_click".to_string(),
+                Action::TripleClick,
+            ))
+            .await;
+        assert!(matches!(response.status, ActionResponseStatus::Success));
+
+        let enigo = queue.input_driver.lock().await;
+        assert_eq!(enigo.button_presses, 3);
+        assert_eq!(enigo.last_action, "button_Left_Release");
+    }
+
+    #[tokio::test]
+    async fn test_left_click_drag() {
+        let queue = create_test_action_queue().await;
+
+        let response = queue
+            .execute_action(ActionRequest::new(
+                "test_left_click_drag".to_string(),
+                Action::LeftClickDrag {
+                    input: MouseMoveInput {
+                        x: 300,
+                        y: 400,
+                        monitor: None,
+                        motion: None,
+                        duration_ms: None,
+                    },
+                },
+            ))
+            .await;
+        assert!(matches!(response.status, ActionResponseStatus::Success));
+
+        let enigo = queue.input_driver.lock().await;
+        assert_eq!(enigo.mouse_pos, (300, 400));
+        // Should end with a release
+        assert!(enigo.last_action.contains("button_Left_Release"));
+    }
+
+    #[tokio::test]
+    async fn test_drag_path_passes_through_waypoints() {
+        let queue = create_test_action_queue().await;
+        let waypoints = [(10, 10), (60, 10), (60, 90), (15, 40)];
+
+        let response = queue
+            .execute_action(ActionRequest::new(
+                "test_drag_path".to_string(),
+                Action::DragPath {
+                    input: DragPathInput {
+                        points: waypoints.iter().map(|&(x, y)| Point { x, y }).collect(),
+                        button: MouseButton::Right,
+                        monitor: None,
+                    },
+                },
+            ))
+            .await;
+        assert!(matches!(response.status, ActionResponseStatus::Success));
+
+        let enigo = queue.input_driver.lock().await;
+        assert_eq!(enigo.button_presses, 1);
+        assert_eq!(enigo.last_action, "button_Right_Release");
+        // Interpolated between waypoints, and every waypoint is hit exactly
+        assert!(enigo.path.len() > waypoints.len() * 2);
+        let mut path = enigo.path.iter();
+        for (x, y) in waypoints {
+            assert!(path.any(|&position| position == (x as i32, y as i32)));
+        }
+        assert_eq!(enigo.mouse_pos, (15, 40));
+    }
+
+    #[tokio::test]
+    async fn test_batch_and_drag_report_progress() {
+        let queue = create_test_action_queue().await;
+        let (mut monitor, _) = queue.subscribe_monitor();
+        let drag = ActionRequest::new(
+            "test_progress_drag".to_string(),
+            Action::DragPath {
+                input: DragPathInput {
+                    points: vec![
+                        Point { x: 10, y: 10 },
+                        Point { x: 60, y: 10 },
+                        Point { x: 60, y: 90 },
+                    ],
+                    button: MouseButton::Left,
+                    monitor: None,
+                },
+            },
+        );
+        let ping = ActionRequest::new("test_progress_ping".to_string(), Action::Ping);
+        let responses = queue.execute_batch(vec![drag, ping]).await.unwrap();
+        assert!(responses
+            .iter()
+            .all(|response| matches!(response.status, ActionResponseStatus::Success)));
+
+        let mut progress = Vec::new();
+        while let Ok(event) = monitor.try_recv() {
+            if let MonitorEventPayload::ActionProgress {
+                action_id,
+                batch_id,
+                step,
+                total_steps,
+                percent,
+                current,
+                ..
+            } = event.payload
+            {
+                progress.push((action_id, batch_id, step, total_steps, percent, current));
+            }
+        }
+        // The batch's own progress can be told apart from the drag's by its batch id
+        let batch_id = progress[0].1.clone().expect("batch steps have a batch id");
+        let expected = [
+            ("test_progress_drag", true, 1, 2, 0, "drag_path"),
+            ("test_progress_drag", false, 1, 4, 0, "press"),
+            ("test_progress_drag", false, 2, 4, 25, "move"),
+            ("test_progress_drag", false, 3, 4, 50, "move"),
+            ("test_progress_drag", false, 4, 4, 75, "release"),
+            ("test_progress_ping", true, 2, 2, 50, "ping"),
+        ];
+        assert_eq!(progress.len(), expected.len());
+        for (event, (id, in_batch, step, total, percent, current)) in progress.iter().zip(expected)
+        {
+            assert_eq!(
+                (
+                    event.0.as_str(),
+                    event.1.as_ref(),
+                    event.2,
+                    event.3,
+                    event.4,
+                    event.5.as_str()
+                ),
+                (
+                    id,
+                    in_batch.then_some(&batch_id),
+                    step,
+                    total,
+                    percent,
+                    current
+                )
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_left_click_drag_absolute_path() {
+        let queue = create_test_action_queue_with_config(Config {
+            drag_path_mode: DragPathMode::Absolute,
+            ..Config::default()
+        })
+        .await;
+
+        let response = queue
+            .execute_action(ActionRequest::new(
+                "test_left_click_drag_absolute_path".to_string(),
+                Action::LeftClickDrag {
+                    input: MouseMoveInput {
+                        x: 300,
+                        y: 400,
+                        monitor: None,
+                        motion: None,
+                        duration_ms: None,
+                    },
+                },
+            ))
+            .await;
+        assert!(matches!(response.status, ActionResponseStatus::Success));
+
+        let enigo = queue.input_driver.lock().await;
+        assert_eq!(enigo.mouse_pos, (300, 400));
+        // No relative moves means pointer acceleration can't affect the path
+        assert_eq!(enigo.relative_moves, 0);
+    }
+
+    #[tokio::test]
+    async fn test_middle_and_right_click_drag() {
+        let queue = create_test_action_queue().await;
+
+        for (action, button) in [
+            (
+                Action::MiddleClickDrag {
+                    input: MouseMoveInput {
+                        x: 300,
+                        y: 400,
+                        monitor: None,
+                        motion: None,
+                        duration_ms: None,
+                    },
+                },
+                "Middle",
+            ),
+            (
+                Action::RightClickDrag {
+                    input: MouseMoveInput {
+                        x: 50,
+                        y: 60,
+                        monitor: None,
+                        motion: None,
+                        duration_ms: None,
+                    },
+                },
+                "Right",
+            ),
+        ] {
+            let target = match &action {
+                Action::MiddleClickDrag { input } | Action::RightClickDrag { input } => {
+                    (input.x as i32, input.y as i32)
+                }
+                _ => unreachable!(),
+            };
+            let response = queue
+                .execute_action(ActionRequest::new("test_drag".to_string(), action))
+                .await;
+            assert!(matches!(response.status, ActionResponseStatus::Success));
+
+            let enigo = queue.input_driver.lock().await;
+            assert_eq!(enigo.mouse_pos, target);
+            assert_eq!(enigo.last_action, format!("button_{}_Release", button));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_touch_unsupported() {
+        let queue = create_test_action_queue().await;
+
+        let response = queue
+            .execute_action(ActionRequest::new(
+                "test_touch_unsupported".to_string(),
+                Action::Tap {
+                    input: Point { x: 10, y: 20 },
+                },
+            ))
+            .await;
+        assert!(matches!(
+            response.error,
+            Some(ActionError::ExecutionFailed(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_tap_and_swipe() {
+        let contacts = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let queue = Arc::new(
+            ActionQueue::new(MockEnigo::new(), Config::default()).with_touch_driver(Box::new(
+                MockTouch {
+                    contacts: contacts.clone(),
+                },
+            )),
+        );
+        queue.start_processing().await;
+
+        let response = queue
+            .execute_action(ActionRequest::new(
+                "test_tap".to_string(),
+                Action::Tap {
+                    input: Point { x: 10, y: 20 },
+                },
+            ))
+            .await;
+        assert!(matches!(response.status, ActionResponseStatus::Success));
+        {
+            let contacts = contacts.lock().unwrap();
+            assert_eq!(contacts.len(), 2);
+            assert_eq!(contacts[0].1, TouchPhase::Down);
+            assert_eq!((contacts[0].2, contacts[0].3), (10, 20));
+            assert_eq!(contacts[1].1, TouchPhase::Up);
+        }
+        contacts.lock().unwrap().clear();
+
+        let response = queue
+            .execute_action(ActionRequest::new(
+                "test_swipe".to_string(),
+                Action::Swipe {
+                    input: SwipeInput {
+                        start: Point { x: 0, y: 500 },
+                        end: Point { x: 0, y: 100 },
+                        duration_ms: Some(100),
+                    },
+                },
+            ))
+            .await;
+        assert!(matches!(response.status, ActionResponseStatus::Success));
+
+        let contacts = contacts.lock().unwrap();
+        assert_eq!(contacts.first().unwrap().1, TouchPhase::Down);
+        assert!(contacts.len() > 3);
+        let last_move = &contacts[contacts.len() - 2];
+        assert_eq!((last_move.1, last_move.3), (TouchPhase::Move, 100));
+        assert_eq!(contacts.last().unwrap().1, TouchPhase::Up);
+    }
+
+    #[tokio::test]
+    async fn test_timed_out_gesture_releases_input() {
+        let contacts = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let queue = Arc::new(
+            ActionQueue::new(MockEnigo::new(), Config::default()).with_touch_driver(Box::new(
+                MockTouch {
+                    contacts: contacts.clone(),
+                },
+            )),
+        );
+        queue.start_processing().await;
+
+        let response = queue
+            .execute_action(ActionRequest {
+                timeout_ms: Some(100),
+                ..ActionRequest::new(
+                    "slow_swipe".to_string(),
+                    Action::Swipe {
+                        input: SwipeInput {
+                            start: Point { x: 0, y: 500 },
+                            end: Point { x: 0, y: 100 },
+                            duration_ms: Some(5000),
+                        },
+                    },
+                )
+            })
+            .await;
+        assert!(matches!(response.error, Some(ActionError::Timeout)));
+        // The swipe pressed nothing on the mouse or keyboard, so nothing there is released
+        assert_eq!(queue.input_driver.lock().await.last_action, "");
+        // The contact was lifted where the swipe had got to
+        let contacts = contacts.lock().unwrap();
+        let (last_move, up) = (&contacts[contacts.len() - 2], contacts.last().unwrap());
+        assert_eq!(last_move.1, TouchPhase::Move);
+        assert_eq!(
+            (up.1, up.2, up.3),
+            (TouchPhase::Up, last_move.2, last_move.3)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pen_invalid_pressure() {
+        let queue = create_test_action_queue().await;
+
+        let response = queue
+            .execute_action(ActionRequest::new(
+                "test_pen_invalid_pressure".to_string(),
+                Action::Pen {
+                    input: PenInput {
+                        points: vec![PenPoint {
+                            x: 1,
+                            y: 1,
+                            pressure: 1.5,
+                        }],
+                    },
+                },
+            ))
+            .await;
+        assert!(matches!(response.error, Some(ActionError::InvalidInput(_))));
+    }
+
+    #[tokio::test]
+    async fn test_pen_stroke_point_limit() {
+        // The longest stroke is drawn within the gesture duration
+        assert!(
+            TOUCH_STEP_INTERVAL * MAX_PEN_POINTS as u32
+                <= Duration::from_millis(MAX_GESTURE_DURATION_MS)
+        );
+        let queue = create_test_action_queue().await;
+        let point = PenPoint {
+            x: 1,
+            y: 1,
+            pressure: 0.5,
+        };
+
+        let response = queue
+            .execute_action(ActionRequest::new(
+                "test_pen_stroke_point_limit".to_string(),
+                Action::Pen {
+                    input: PenInput {
+                        points: vec![point; MAX_PEN_POINTS + 1],
+                    },
+                },
+            ))
+            .await;
+        assert!(matches!(response.error, Some(ActionError::InvalidInput(_))));
+    }
+
+    async fn wait_for_pending_approval(
+        queue: &ActionQueue<MockEnigo>,
+    ) -> crate::approvals::PendingApproval {
+        loop {
+            if let Some(approval) = queue.approvals().list().pop() {
+                return approval;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_approval_required() {
+        let queue = create_test_action_queue_with_config(Config {
+            approval_actions: vec!["left_click".to_string()],
+            ..Config::default()
+        })
+        .await;
+
+        let pending = tokio::spawn({
+            let queue = queue.clone();
+            async move {
+                queue
+                    .execute_action(ActionRequest::new(
+                        "test_approval_required".to_string(),
+                        Action::LeftClick,
+                    ))
+                    .await
+            }
+        });
+
+        let approval = wait_for_pending_approval(&queue).await;
+        assert_eq!(approval.request.id, "test_approval_required");
+        // Nothing executes while the approval is pending
+        assert_eq!(queue.input_driver.lock().await.last_action, "");
+
+        assert!(queue
+            .approvals()
+            .decide(&approval.approval_id, ApprovalDecision::Approved));
+        let response = pending.await.unwrap();
+        assert!(matches!(response.status, ActionResponseStatus::Success));
+        assert!(queue
+            .input_driver
+            .lock()
+            .await
+            .last_action
+            .contains("button_Left"));
+    }
+
+    #[tokio::test]
+    async fn test_abandoned_request_gives_its_budget_back() {
+        let queue = create_test_action_queue_with_config(Config {
+            approval_actions: vec!["left_click".to_string()],
+            ..Config::default()
+        })
+        .await;
+        let run = queue.runs().open(OpenRunBody {
+            limits: RunLimits {
+                max_actions: Some(1),
+                ..RunLimits::default()
+            },
+            ..OpenRunBody::default()
+        });
+        let click = ActionRequest {
+            run_id: Some(run.run_id.clone()),
+            ..ActionRequest::new("abandoned_click".to_string(), Action::LeftClick)
+        };
+
+        // The client goes away while the click waits for approval
+        let pending = tokio::spawn({
+            let queue = queue.clone();
+            let click = click.clone();
+            async move { queue.execute_action(click).await }
+        });
+        wait_for_pending_approval(&queue).await;
+        pending.abort();
+        assert!(pending.await.unwrap_err().is_cancelled());
+
+        // Nobody is asked to approve it any more, and it no longer counts against the run
+        assert!(queue.approvals().list().is_empty());
+        assert!(queue.runs().check(&click).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_approval_rejected() {
+        let queue = create_test_action_queue_with_config(Config {
+            approval_actions: vec!["type_text".to_string()],
+            ..Config::default()
+        })
+        .await;
+
+        let pending = tokio::spawn({
+            let queue = queue.clone();
+            async move {
+                queue
+                    .execute_action(ActionRequest::new(
+                        "test_approval_rejected".to_string(),
+                        Action::TypeText {
+                            input: TypeTextInput {
+                                text: "hunter2".to_string(),
+                            },
+                        },
+                    ))
+                    .await
+            }
+        });
+
+        let approval = wait_for_pending_approval(&queue).await;
+        queue.approvals().decide(
+            &approval.approval_id,
+            ApprovalDecision::Rejected(Some("password field".to_string())),
+        );
+
+        let response = pending.await.unwrap();
+        match response.error {
+            Some(ActionError::PermissionDenied(message)) => {
+                assert!(message.contains("password field"))
+            }
+            other => panic!("Expected permission denied, got {:?}", other),
+        }
+        assert_eq!(queue.input_driver.lock().await.last_action, "");
+    }
+
+    #[tokio::test]
+    async fn test_policy_denies_action() {
+        let dir = std::env::temp_dir().join(format!("valk-queue-policy-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("deny.json"),
+            r#"{"denied_actions": ["type_text"]}"#,
+        )
+        .unwrap();
+        let queue = create_test_action_queue_with_config(Config {
+            policy_dir: Some(dir.to_string_lossy().to_string()),
+            ..Config::default()
+        })
+        .await;
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let response = queue
+            .execute_action(ActionRequest::new(
+                "test_policy_denies_action".to_string(),
+                Action::TypeText {
+                    input: TypeTextInput {
+                        text: "hunter2".to_string(),
+                    },
+                },
+            ))
+            .await;
+        match response.error {
+            Some(ActionError::PermissionDenied(message)) => assert!(message.contains("type_text")),
+            other => panic!("Expected permission denied, got {:?}", other),
+        }
+        assert_eq!(queue.input_driver.lock().await.last_action, "");
+    }
+
+    #[tokio::test]
+    async fn test_run_in_model_space_scales_coordinates_and_screenshots() {
+        let queue = create_test_action_queue().await;
+        let run = queue.runs().open(OpenRunBody {
+            model_resolution: serde_json::from_value(
+                serde_json::json!({"width": 1280, "height": 800}),
+            )
+            .unwrap(),
+            ..OpenRunBody::default()
+        });
+        let in_run = |id: &str, action| ActionRequest {
+            run_id: Some(run.run_id.clone()),
+            ..ActionRequest::new(id.to_string(), action)
+        };
+
+        // The 1920x1080 screen fits 1280x800 at two thirds, as 1280x720
+        let response = queue
+            .execute_action(in_run(
+                "model_click",
+                Action::Click {
+                    input: ClickInput {
+                        x: 640,
+                        y: 360,
+                        button: MouseButton::Left,
+                        modifiers: Vec::new(),
+                        expected_pixel: None,
+                        expected_text: None,
+                        monitor: None,
+                    },
+                },
+            ))
+            .await;
+        assert!(matches!(response.status, ActionResponseStatus::Success));
+        assert_eq!(queue.input_driver.lock().await.mouse_pos, (960, 540));
+        // The run records what the client sent, its heatmap where the click landed
+        assert!(matches!(&response.action, Action::Click { input } if input.x == 640));
+        let interaction = queue.runs().get(&run.run_id).unwrap().interactions[0].clone();
+        assert_eq!((interaction.x, interaction.y), (960, 540));
+
+        // Clicks at the cursor land where the worker found it after the click, wherever
+        // later actions have moved it since
+        let mut clicked = ActionResponse::success(
+            "cursor_click".to_string(),
+            Action::LeftClick,
+            ActionOutput::NoData,
+        );
+        clicked.cursor = Some((12, 34));
+        queue.record_interaction(&in_run("cursor_click", Action::LeftClick), &clicked);
+        let interaction = queue.runs().get(&run.run_id).unwrap().interactions[1].clone();
+        assert_eq!((interaction.x, interaction.y), (12, 34));
+
+        let response = queue
+            .execute_action(in_run(
+                "model_screenshot",
+                Action::Screenshot {
+                    input: Default::default(),
+                },
+            ))
+            .await;
+        let Some(ActionOutput::Screenshot { width, height, .. }) = response.data else {
+            panic!("Expected a screenshot, got {:?}", response.data);
+        };
+        assert_eq!((width, height), (1280, 720));
+
+        // What comes back is in model pixels too
+        let response = queue
+            .execute_action(in_run("model_cursor", Action::CursorPosition))
+            .await;
+        assert!(matches!(
+            response.data,
+            Some(ActionOutput::CursorPosition { x: 640, y: 360 })
+        ));
+        let response = queue
+            .execute_action(in_run(
+                "model_burst",
+                Action::Burst {
+                    input: BurstInput {
+                        count: 1,
+                        interval_ms: 0,
+                        region: None,
+                        scale: None,
+                    },
+                },
+            ))
+            .await;
+        let Some(ActionOutput::Burst { frames }) = response.data else {
+            panic!("Expected a burst, got {:?}", response.data);
+        };
+        assert_eq!((frames[0].width, frames[0].height), (1280, 720));
+    }
+
+    #[tokio::test]
+    async fn test_run_in_model_space_rejects_coordinates_scaled_out_of_range() {
+        let queue = create_test_action_queue().await;
+        let run = queue.runs().open(OpenRunBody {
+            model_resolution: serde_json::from_value(serde_json::json!({"width": 1, "height": 1}))
+                .unwrap(),
+            ..OpenRunBody::default()
+        });
+
+        // One model pixel is 1920 screen pixels
+        let response = queue
+            .execute_action(ActionRequest {
+                run_id: Some(run.run_id.clone()),
+                ..ActionRequest::new(
+                    "scaled_move".to_string(),
+                    Action::MouseMove {
+                        input: MouseMoveInput {
+                            x: 100,
+                            y: 0,
+                            monitor: None,
+                            motion: None,
+                            duration_ms: None,
+                        },
+                    },
+                )
+            })
+            .await;
+        assert!(matches!(response.error, Some(ActionError::InvalidInput(_))));
+        assert_eq!(queue.input_driver.lock().await.last_action, "");
+    }
+
+    #[tokio::test]
+    async fn test_actions_recorded_on_run() {
+        let queue = create_test_action_queue().await;
+        let run = queue.runs().open(OpenRunBody::default());
+
+        let response = queue
+            .execute_action(ActionRequest {
+                run_id: Some(run.run_id.clone()),
+                task_id: Some("open_menu".to_string()),
+                ..ActionRequest::new("test_run_click".to_string(), Action::LeftClick)
+            })
+            .await;
+        assert!(matches!(response.status, ActionResponseStatus::Success));
+
+        let recorded = queue.runs().get(&run.run_id).unwrap();
+        assert_eq!(recorded.metrics.totals.actions, 1);
+        assert!(recorded.history[0].response.timing.is_some());
+        assert_eq!(recorded.history[0].response.request_id, "test_run_click");
+
+        // Closed runs stop accepting actions
+        queue.runs().close(&run.run_id);
+        let response = queue
+            .execute_action(ActionRequest {
+                run_id: Some(run.run_id.clone()),
+                ..ActionRequest::new("test_closed_run".to_string(), Action::LeftClick)
+            })
+            .await;
+        assert!(matches!(response.error, Some(ActionError::InvalidInput(_))));
+    }
+
+    #[tokio::test]
+    async fn test_metadata_echoed_and_recorded() {
+        let queue = create_test_action_queue().await;
+        let run = queue.runs().open(OpenRunBody::default());
+        let (mut monitor, _) = queue.subscribe_monitor();
+        let metadata = serde_json::json!({"model": "planner-v2", "tool_call_id": "call_7"});
+
+        let response = queue
+            .execute_action(ActionRequest {
+                run_id: Some(run.run_id.clone()),
+                metadata: metadata.as_object().cloned(),
+                ..ActionRequest::new("test_metadata".to_string(), Action::Ping)
+            })
+            .await;
+        assert!(matches!(response.status, ActionResponseStatus::Success));
+        assert_eq!(
+            serde_json::to_value(&response).unwrap()["metadata"],
+            metadata
+        );
+
+        let recorded = queue.runs().get(&run.run_id).unwrap();
+        assert_eq!(
+            recorded.history[0].response.metadata,
+            metadata.as_object().cloned()
+        );
+        let mut echoed = 0;
+        while let Ok(event) = monitor.try_recv() {
+            match event.payload {
+                MonitorEventPayload::ActionRequest(request) => {
+                    assert_eq!(request.metadata, metadata.as_object().cloned());
+                    echoed += 1;
+                }
+                MonitorEventPayload::ActionResponse(response) => {
+                    assert_eq!(response.metadata, metadata.as_object().cloned());
+                    echoed += 1;
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(echoed, 2);
+
+        // Metadata is kept for every action, so it can't be arbitrarily large
+        let response = queue
+            .execute_action(ActionRequest {
+                metadata: serde_json::json!({"plan": "x".repeat(MAX_METADATA_BYTES)})
+                    .as_object()
+                    .cloned(),
+                ..ActionRequest::new("test_large_metadata".to_string(), Action::Ping)
+            })
+            .await;
+        assert!(matches!(response.error, Some(ActionError::InvalidInput(_))));
+        assert!(response.metadata.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_driver_recreated_after_repeated_failures() {
+        let broken = MockEnigo {
+            disconnected: true,
+            ..MockEnigo::new()
+        };
+        let queue = Arc::new(
+            ActionQueue::new(broken, Config::default())
+                .with_driver_factory(Arc::new(|| Ok(MockEnigo::new()))),
+        );
+        queue.start_processing().await;
+        let (mut monitor, _) = queue.subscribe_monitor();
+
+        for i in 1..DRIVER_FAILURE_THRESHOLD {
+            let response = queue
+                .execute_action(ActionRequest::new(
+                    format!("test_driver_failure_{}", i),
+                    Action::LeftClick,
+                ))
+                .await;
+            assert!(matches!(
+                response.error,
+                Some(ActionError::ExecutionFailed(_))
+            ));
+        }
+
+        // The failure that reaches the threshold is retried on a fresh driver
+        let response = queue
+            .execute_action(ActionRequest::new(
+                "test_driver_recovered".to_string(),
+                Action::LeftClick,
+            ))
+            .await;
+        assert!(matches!(response.status, ActionResponseStatus::Success));
+        assert!(!queue.input_driver.lock().await.disconnected);
+
+        loop {
+            let event = monitor.recv().await.unwrap();
+            if let MonitorEventPayload::DriverRecovery { recovered, .. } = event.payload {
+                assert!(recovered);
+                break;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_driver_recovery_doesnt_repeat_sent_input() {
+        let broken = MockEnigo {
+            disconnected: true,
+            ..MockEnigo::new()
+        };
+        let queue = Arc::new(
+            ActionQueue::new(broken, Config::default())
+                .with_driver_factory(Arc::new(|| Ok(MockEnigo::new()))),
+        );
+        queue.start_processing().await;
+
+        // The cursor moves before the button fails, so each click has sent input
+        for i in 1..=DRIVER_FAILURE_THRESHOLD {
+            let response = queue
+                .execute_action(ActionRequest::new(
+                    format!("test_partial_click_{}", i),
+                    Action::Click {
+                        input: ClickInput {
+                            x: 10,
+                            y: 20,
+                            button: MouseButton::Left,
+                            modifiers: Vec::new(),
+                            expected_pixel: None,
+                            expected_text: None,
+                            monitor: None,
+                        },
+                    },
+                ))
+                .await;
+            assert!(matches!(
+                response.error,
+                Some(ActionError::ExecutionFailed(_))
+            ));
+        }
+
+        // The driver was recreated, but the last click wasn't run again on it
+        let input_driver = queue.input_driver.lock().await;
+        assert!(!input_driver.disconnected);
+        assert_eq!(input_driver.button_presses, 0);
+    }
+
+    #[tokio::test]
+    async fn test_save_and_restore_cursor() {
+        let queue = create_test_action_queue().await;
+
+        let response = queue
+            .execute_action(ActionRequest::new(
+                "test_restore_without_save".to_string(),
+                Action::RestoreCursor,
+            ))
+            .await;
+        assert!(matches!(response.error, Some(ActionError::InvalidInput(_))));
+
+        // On a monitor left of the primary one
+        queue.input_driver.lock().await.mouse_pos = (-40, 50);
+        let response = queue
+            .execute_action(ActionRequest::new(
+                "test_save_cursor".to_string(),
+                Action::SaveCursor,
+            ))
+            .await;
+        assert!(matches!(
+            response.data,
+            Some(ActionOutput::CursorPosition { x: -40, y: 50 })
+        ));
+
+        queue.input_driver.lock().await.mouse_pos = (300, 400);
+        let response = queue
+            .execute_action(ActionRequest::new(
+                "test_restore_cursor".to_string(),
+                Action::RestoreCursor,
+            ))
+            .await;
+        assert!(matches!(response.status, ActionResponseStatus::Success));
+        assert_eq!(queue.input_driver.lock().await.mouse_pos, (-40, 50));
+    }
+
+    #[tokio::test]
+    async fn test_screenshot_uses_capture_backend() {
+        let image = image::RgbaImage::from_pixel(4, 3, image::Rgba([255, 0, 0, 255]));
+        let queue = Arc::new(
+            ActionQueue::new(MockEnigo::new(), Config::default())
+                .with_capture(Arc::new(ImageCapture::new(image))),
+        );
+        queue.start_processing().await;
+
+        let response = queue
+            .execute_action(ActionRequest::new(
+                "test_screenshot".to_string(),
+                Action::Screenshot {
+                    input: Default::default(),
+                },
+            ))
+            .await;
+        let Some(ActionOutput::Screenshot {
+            image,
+            width,
+            height,
+            monitor_id,
+            scale_factor,
+            ..
+        }) = response.data
+        else {
+            panic!("expected a screenshot, got {:?}", response);
+        };
+        assert_eq!((width, height, monitor_id, scale_factor), (4, 3, 0, 1.0));
+        let png = BASE64.decode(image).unwrap();
+        let decoded = image::load_from_memory(&png).unwrap().to_rgba8();
+        assert_eq!(decoded.dimensions(), (4, 3));
+        assert_eq!(decoded.get_pixel(0, 0), &image::Rgba([255, 0, 0, 255]));
+    }
+
+    #[tokio::test]
+    async fn test_screenshot_region_is_cropped() {
+        let mut image = image::RgbaImage::from_pixel(8, 6, image::Rgba([0, 0, 0, 255]));
+        image.put_pixel(3, 2, image::Rgba([0, 255, 0, 255]));
+        let queue = Arc::new(
+            ActionQueue::new(MockEnigo::new(), Config::default())
+                .with_capture(Arc::new(ImageCapture::new(image))),
+        );
+        queue.start_processing().await;
+        let screenshot = |region| {
+            ActionRequest::new(
+                "test_screenshot_region".to_string(),
+                Action::Screenshot {
+                    input: ScreenshotInput {
+                        region,
+                        ..Default::default()
+                    },
+                },
+            )
+        };
+
+        let response = queue
+            .execute_action(screenshot(Some(Region {
+                x: 3,
+                y: 2,
+                width: 4,
+                height: 2,
+            })))
+            .await;
+        let Some(ActionOutput::Screenshot {
+            image,
+            width,
+            height,
+            x,
+            y,
+            ..
+        }) = response.data
+        else {
+            panic!("expected a screenshot, got {:?}", response);
+        };
+        assert_eq!((width, height, x, y), (4, 2, 3, 2));
+        let png = BASE64.decode(image).unwrap();
+        let decoded = image::load_from_memory(&png).unwrap().to_rgba8();
+        assert_eq!(decoded.get_pixel(0, 0), &image::Rgba([0, 255, 0, 255]));
+
+        let response = queue
+            .execute_action(screenshot(Some(Region {
+                x: 6,
+                y: 0,
+                width: 4,
+                height: 2,
+            })))
+            .await;
+        assert!(matches!(response.error, Some(ActionError::InvalidInput(_))));
+    }
+
+    #[tokio::test]
+    async fn test_screenshot_downscales_to_max_size() {
+        let queue = create_test_action_queue().await;
+
+        let response = queue
+            .execute_action(ActionRequest::new(
+                "test_screenshot_downscaled".to_string(),
+                Action::Screenshot {
+                    input: ScreenshotInput {
+                        max_width: Some(1280),
+                        max_height: Some(540),
+                        ..Default::default()
+                    },
+                },
+            ))
+            .await;
+        let Some(ActionOutput::Screenshot {
+            width,
+            height,
+            image_scale,
+            ..
+        }) = response.data
+        else {
+            panic!("expected a screenshot, got {:?}", response);
+        };
+        assert_eq!((width, height, image_scale), (960, 540, 0.5));
+    }
+
+    #[tokio::test]
+    async fn test_screenshot_shows_cursor() {
+        let queue = create_test_action_queue().await;
+        queue.input_driver.lock().await.mouse_pos = (100, 50);
+        let screenshot = |show_cursor| {
+            ActionRequest::new(
+                "test_screenshot_cursor".to_string(),
+                Action::Screenshot {
+                    input: ScreenshotInput {
+                        show_cursor,
+                        ..Default::default()
+                    },
+                },
+            )
+        };
+
+        for (show_cursor, tip) in [(true, [0, 0, 0, 255]), (false, [0, 0, 0, 0])] {
+            let response = queue.execute_action(screenshot(show_cursor)).await;
+            let Some(ActionOutput::Screenshot { image, .. }) = response.data else {
+                panic!("expected a screenshot, got {:?}", response);
+            };
+            let png = BASE64.decode(image).unwrap();
+            let decoded = image::load_from_memory(&png).unwrap().to_rgba8();
+            assert_eq!(decoded.get_pixel(100, 50), &image::Rgba(tip));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_screenshot_unchanged_since_previous_hash() {
+        let queue = create_test_action_queue().await;
+        let screenshot = |previous_hash: &str| {
+            ActionRequest::new(
+                "test_screenshot_unchanged".to_string(),
+                Action::Screenshot {
+                    input: ScreenshotInput {
+                        previous_hash: Some(previous_hash.to_string()),
+                        ..Default::default()
+                    },
+                },
+            )
+        };
+
+        let response = queue.execute_action(screenshot("stale")).await;
+        let Some(ActionOutput::Screenshot {
+            image,
+            hash,
+            unchanged: false,
+            ..
+        }) = response.data
+        else {
+            panic!("expected a new screenshot, got {:?}", response);
+        };
+        assert!(!image.is_empty());
+
+        let response = queue.execute_action(screenshot(&hash)).await;
+        let Some(ActionOutput::Screenshot {
+            image,
+            hash: same_hash,
+            unchanged: true,
+            ..
+        }) = &response.data
+        else {
+            panic!("expected an unchanged screenshot, got {:?}", response);
+        };
+        assert!(image.is_empty());
+        assert_eq!(same_hash, &hash);
+        let json = serde_json::to_value(&response.data).unwrap();
+        assert!(json.get("image").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_screenshot_of_selected_monitor() {
+        // The second monitor is green, the primary one black
+        let image = image::RgbaImage::from_fn(6, 3, |x, _| {
+            image::Rgba([0, if x >= 4 { 255 } else { 0 }, 0, 255])
+        });
+        let screen = VirtualScreen::parse("4x3,2x2").unwrap().with_image(image);
+        let queue = Arc::new(
+            ActionQueue::new(SimulatedInput::new(&screen), Config::default())
+                .with_capture(Arc::new(screen)),
+        );
+        queue.start_processing().await;
+        let screenshot = |monitor| {
+            ActionRequest::new(
+                "test_screenshot_monitor".to_string(),
+                Action::Screenshot {
+                    input: ScreenshotInput {
+                        monitor: Some(monitor),
+                        ..Default::default()
+                    },
+                },
+            )
+        };
+
+        for monitor in [MonitorSelector::Index(1), MonitorSelector::Id(1)] {
+            let response = queue.execute_action(screenshot(monitor)).await;
+            let Some(ActionOutput::Screenshot {
+                image,
+                width,
+                height,
+                monitor_id,
+                x,
+                ..
+            }) = response.data
+            else {
+                panic!("expected a screenshot, got {:?}", response);
+            };
+            assert_eq!((width, height, monitor_id, x), (2, 2, 1, 4));
+            let png = BASE64.decode(image).unwrap();
+            let decoded = image::load_from_memory(&png).unwrap().to_rgba8();
+            assert_eq!(decoded.get_pixel(0, 0), &image::Rgba([0, 255, 0, 255]));
+        }
+
+        let response = queue
+            .execute_action(screenshot(MonitorSelector::Index(2)))
+            .await;
+        assert!(matches!(response.error, Some(ActionError::InvalidInput(_))));
+    }
+
+    #[tokio::test]
+    async fn test_screenshot_of_focused_window() {
+        // A virtual screen with one window, which is mostly on the green second monitor
+        struct WindowedScreen(VirtualScreen, Option<WindowInfo>);
+        impl ScreenCapture for WindowedScreen {
+            fn capture(&self) -> Result<image::RgbaImage, ActionError> {
+                self.0.capture()
+            }
+            fn capture_display(&self, id: u32) -> Result<image::RgbaImage, ActionError> {
+                self.0.capture_display(id)
+            }
+            fn screen_size(&self) -> Result<(u32, u32), ActionError> {
+                self.0.screen_size()
+            }
+            fn displays(&self) -> Result<Vec<crate::capture::DisplayInfo>, ActionError> {
+                self.0.displays()
+            }
+            fn focused_window(&self) -> Option<WindowInfo> {
+                self.1.clone()
+            }
+        }
+        let window = WindowInfo {
+            id: 7,
+            app_name: "browser".to_string(),
+            title: "Checkout".to_string(),
+            x: 3,
+            y: 0,
+            width: 3,
+            height: 2,
+        };
+        let screenshot = ActionRequest::new(
+            "test_screenshot_window".to_string(),
+            Action::Screenshot {
+                input: ScreenshotInput {
+                    target: ScreenshotTarget::FocusedWindow,
+                    ..Default::default()
+                },
+            },
+        );
+
+        for (window, expected) in [(Some(window), true), (None, false)] {
+            let image = image::RgbaImage::from_fn(6, 3, |x, _| {
+                image::Rgba([0, if x >= 4 { 255 } else { 0 }, 0, 255])
+            });
+            let screen = VirtualScreen::parse("4x3,2x2").unwrap().with_image(image);
+            let queue = Arc::new(
+                ActionQueue::new(SimulatedInput::new(&screen), Config::default())
+                    .with_capture(Arc::new(WindowedScreen(screen, window))),
+            );
+            queue.start_processing().await;
+            let response = queue.execute_action(screenshot.clone()).await;
+            if !expected {
+                assert!(matches!(
+                    response.error,
+                    Some(ActionError::ExecutionFailed(_))
+                ));
+                continue;
+            }
+            let Some(ActionOutput::Screenshot {
+                width,
+                height,
+                monitor_id,
+                x,
+                y,
+                ..
+            }) = response.data
+            else {
+                panic!("expected a screenshot, got {:?}", response);
+            };
+            assert_eq!((width, height, monitor_id, x, y), (2, 2, 1, 4, 0));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_list_and_command_windows() {
+        struct Desktop(
+            Vec<ListedWindow>,
+            std::sync::Mutex<Vec<(u32, WindowCommand)>>,
+        );
+        impl ScreenCapture for Desktop {
+            fn capture(&self) -> Result<image::RgbaImage, ActionError> {
+                Ok(image::RgbaImage::new(4, 3))
+            }
+            fn capture_display(&self, _id: u32) -> Result<image::RgbaImage, ActionError> {
+                self.capture()
+            }
+            fn screen_size(&self) -> Result<(u32, u32), ActionError> {
+                Ok((4, 3))
+            }
+            fn displays(&self) -> Result<Vec<crate::capture::DisplayInfo>, ActionError> {
+                VirtualScreen::parse("4x3").unwrap().displays()
+            }
+            fn top_level_windows(&self) -> Result<Vec<ListedWindow>, ActionError> {
+                Ok(self.0.clone())
+            }
+            fn command_window(&self, id: u32, command: WindowCommand) -> Result<(), ActionError> {
+                self.1.lock().unwrap().push((id, command));
+                Ok(())
+            }
+        }
+        let window = |id: u32, title: &str, z_order| ListedWindow {
+            id,
+            pid: 100 + id,
+            app_name: "editor".to_string(),
+            title: title.to_string(),
+            x: 0,
+            y: 0,
+            width: 4,
+            height: 3,
+            z_order,
+            is_focused: z_order == 0,
+            is_minimized: false,
+            is_maximized: true,
+        };
+        let windows = vec![window(9, "notes.txt", 0), window(4, "todo.txt", 1)];
+        let desktop = Arc::new(Desktop(windows.clone(), Default::default()));
+        let queue = Arc::new(
+            ActionQueue::new(MockEnigo::new(), Config::default()).with_capture(desktop.clone()),
+        );
+        queue.start_processing().await;
+
+        let response = queue
+            .execute_action(ActionRequest::new(
+                "test_list_windows".to_string(),
+                Action::ListWindows,
+            ))
+            .await;
+        let Some(ActionOutput::Windows { windows: listed }) = response.data else {
+            panic!("expected windows, got {:?}", response);
+        };
+        assert_eq!(listed, windows);
+
+        let focus = |title_match: &str| {
+            ActionRequest::new(
+                "test_focus_window".to_string(),
+                Action::FocusWindow {
+                    input: WindowInput {
+                        id: None,
+                        title_match: Some(title_match.to_string()),
+                    },
+                },
+            )
+        };
+        let response = queue.execute_action(focus("TODO")).await;
+        assert!(matches!(response.status, ActionResponseStatus::Success));
+        let response = queue.execute_action(focus("calendar")).await;
+        assert!(matches!(response.error, Some(ActionError::NotFound(_))));
+
+        let by_id = WindowInput {
+            id: Some(9),
+            title_match: None,
+        };
+        for action in [
+            Action::MinimizeWindow {
+                input: by_id.clone(),
+            },
+            Action::CloseWindow { input: by_id },
+        ] {
+            let response = queue
+                .execute_action(ActionRequest::new("test_window".to_string(), action))
+                .await;
+            assert!(matches!(response.status, ActionResponseStatus::Success));
+        }
+        assert_eq!(
+            *desktop.1.lock().unwrap(),
+            [
+                (4, WindowCommand::Activate),
+                (9, WindowCommand::Minimize),
+                (9, WindowCommand::Close)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_locate_image_reports_matches_in_input_coordinates() {
+        let icon = image::RgbaImage::from_fn(12, 10, |x, y| {
+            image::Rgba(if (x + 2 * y) % 5 == 0 {
+                [200, 40, 40, 255]
+            } else {
+                [240, 240, 240, 255]
+            })
+        });
+        let mut screen = image::RgbaImage::from_pixel(300, 200, image::Rgba([30, 30, 30, 255]));
+        image::imageops::replace(&mut screen, &icon, 120, 40);
+        let queue = Arc::new(
+            ActionQueue::new(MockEnigo::new(), Config::default())
+                .with_capture(Arc::new(ImageCapture::new(screen))),
+        );
+        queue.start_processing().await;
+        let locate = |template: &image::RgbaImage| {
+            ActionRequest::new(
+                "test_locate_image".to_string(),
+                Action::LocateImage {
+                    input: LocateImageInput {
+                        template: encode_png(template).unwrap(),
+                        confidence: 0.9,
+                        limit: 1,
+                        region: Some(Region {
+                            x: 100,
+                            y: 20,
+                            width: 150,
+                            height: 100,
+                        }),
+                    },
+                },
+            )
+        };
+
+        let response = queue.execute_action(locate(&icon)).await;
+        let Some(ActionOutput::ImageMatches { matches }) = response.data else {
+            panic!("expected matches, got {:?}", response);
+        };
+        assert_eq!(matches.len(), 1);
+        assert_eq!((matches[0].x, matches[0].y), (120, 40));
+        assert_eq!(matches[0].center, Point { x: 126, y: 45 });
+        assert!(matches[0].confidence > 0.99);
+
+        let flipped = image::imageops::flip_horizontal(&icon);
+        let response = queue.execute_action(locate(&flipped)).await;
+        assert!(matches!(response.error, Some(ActionError::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test_ocr_reports_spans_in_input_coordinates() {
+        let word = |text: &str, x, width, confidence| TextSpan {
+            text: text.to_string(),
+            x,
+            y: 20,
+            width,
+            height: 16,
+            confidence,
+        };
+        let recognizer = crate::ocr::FixedText(vec![vec![
+            word("Save", 10, 50, 96.0),
+            word("changes", 66, 64, 90.0),
+        ]]);
+        let queue = Arc::new(
+            ActionQueue::new(MockEnigo::new(), Config::default())
+                .with_capture(mock_capture())
+                .with_recognizer(Arc::new(recognizer)),
+        );
+        queue.start_processing().await;
+        let ocr = |level, debug| {
+            ActionRequest::new(
+                "test_ocr".to_string(),
+                Action::Ocr {
+                    input: OcrInput {
+                        region: Some(Region {
+                            x: 100,
+                            y: 50,
+                            width: 400,
+                            height: 300,
+                        }),
+                        level,
+                        debug,
+                    },
+                },
+            )
+        };
+
+        let response = queue.execute_action(ocr(TextLevel::Line, false)).await;
+        let Some(ActionOutput::Text { spans, debug_image }) = response.data else {
+            panic!("expected text, got {:?}", response);
+        };
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].text, "Save changes");
+        assert_eq!(
+            (spans[0].x, spans[0].y, spans[0].width, spans[0].height),
+            (110, 70, 120, 16)
+        );
+        assert!(debug_image.is_none());
+
+        let response = queue.execute_action(ocr(TextLevel::Word, true)).await;
+        let Some(ActionOutput::Text { spans, debug_image }) = response.data else {
+            panic!("expected text, got {:?}", response);
+        };
+        assert_eq!(spans.len(), 2);
+        assert_eq!((spans[1].text.as_str(), spans[1].x), ("changes", 166));
+        // The region that was read, with the boxes drawn where the words are in it
+        let debug_image = image::load_from_memory(&BASE64.decode(debug_image.unwrap()).unwrap())
+            .unwrap()
+            .to_rgba8();
+        assert_eq!(debug_image.dimensions(), (400, 300));
+        assert_ne!(*debug_image.get_pixel(10, 50), image::Rgba([0, 0, 0, 255]));
+
+        let find = |query: &str, debug| {
+            ActionRequest::new(
+                "test_find_text".to_string(),
+                Action::FindText {
+                    input: FindTextInput {
+                        query: query.to_string(),
+                        match_mode: MatchMode::Contains,
+                        region: None,
+                        debug,
+                    },
+                },
+            )
+        };
+        let response = queue.execute_action(find("changes", true)).await;
+        let Some(ActionOutput::TextMatch {
+            span,
+            center,
+            debug_image,
+            ..
+        }) = response.data
+        else {
+            panic!("expected a match, got {:?}", response);
+        };
+        assert_eq!(span.text, "changes");
+        assert_eq!((center.x, center.y), (98, 28));
+        let debug_image = image::load_from_memory(&BASE64.decode(debug_image.unwrap()).unwrap())
+            .unwrap()
+            .to_rgba8();
+        assert_eq!(*debug_image.get_pixel(98, 28), crate::annotate::HIGHLIGHT);
+        let response = queue.execute_action(find("Discard", false)).await;
+        assert!(matches!(response.error, Some(ActionError::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_text_polls_until_it_appears() {
+        // A page that shows its login button from the third look on
+        struct LoadingPage(std::sync::atomic::AtomicU32);
+        impl TextRecognizer for LoadingPage {
+            fn recognize(
+                &self,
+                _image: &image::RgbaImage,
+            ) -> Result<Vec<Vec<TextSpan>>, ActionError> {
+                let looks = self.0.fetch_add(1, Ordering::SeqCst);
+                Ok(match looks {
+                    0 | 1 => Vec::new(),
+                    _ => vec![vec![TextSpan {
+                        text: "Login".to_string(),
+                        x: 40,
+                        y: 60,
+                        width: 50,
+                        height: 20,
+                        confidence: 95.0,
+                    }]],
+                })
+            }
+        }
+        let page = Arc::new(LoadingPage(std::sync::atomic::AtomicU32::new(0)));
+        let queue = Arc::new(
+            ActionQueue::new(MockEnigo::new(), Config::default())
+                .with_capture(mock_capture())
+                .with_recognizer(page.clone()),
+        );
+        queue.start_processing().await;
+        let wait_for = |text: &str, timeout_ms| {
+            ActionRequest::new(
+                "test_wait_for".to_string(),
+                Action::WaitFor {
+                    input: WaitForInput {
+                        text: Some(text.to_string()),
+                        match_mode: MatchMode::Exact,
+                        image_template: None,
+                        confidence: 0.9,
+                        timeout_ms,
+                        region: None,
+                    },
+                },
+            )
+        };
+
+        let response = queue.execute_action(wait_for("Login", 5000)).await;
+        let Some(ActionOutput::TextMatch { center, .. }) = response.data else {
+            panic!("expected a match, got {:?}", response);
+        };
+        assert_eq!((center.x, center.y), (65, 70));
+        assert_eq!(page.0.load(Ordering::SeqCst), 3);
+
+        let response = queue.execute_action(wait_for("Logout", 600)).await;
+        let Some(ActionError::NotFound(message)) = response.error else {
+            panic!("expected not found, got {:?}", response);
+        };
+        assert!(message.ends_with("after waiting 600ms"));
+    }
+
+    #[tokio::test]
+    async fn test_burst_captures_frames_at_interval() {
+        let queue = create_test_action_queue().await;
+        let burst = |region| {
+            ActionRequest::new(
+                "test_burst".to_string(),
+                Action::Burst {
+                    input: BurstInput {
+                        count: 3,
+                        interval_ms: 100,
+                        region,
+                        scale: None,
+                    },
+                },
+            )
+        };
+
+        let response = queue
+            .execute_action(burst(Some(Region {
+                x: 100,
+                y: 50,
+                width: 40,
+                height: 30,
+            })))
+            .await;
+        let Some(ActionOutput::Burst { frames }) = response.data else {
+            panic!("expected a burst, got {:?}", response);
+        };
+        assert_eq!(frames.len(), 3);
+        assert!(frames
+            .iter()
+            .all(|frame| (frame.width, frame.height) == (40, 30)));
+        // Two intervals apart, give or take how long each capture took
+        let spacing = frames[2].captured_at - frames[0].captured_at;
+        assert!(spacing.num_milliseconds() >= 180);
+
+        // The mock screen is 1920x1080
+        let response = queue
+            .execute_action(burst(Some(Region {
+                x: 1900,
+                y: 0,
+                width: 40,
+                height: 30,
+            })))
+            .await;
+        assert!(matches!(response.error, Some(ActionError::InvalidInput(_))));
+    }
+
+    #[tokio::test]
+    async fn test_ping_reports_timing_without_input() {
+        let queue = create_test_action_queue().await;
+        queue.input_driver.lock().await.mouse_pos = (10, 20);
+
+        let response = queue
+            .execute_action(ActionRequest::new("test_ping".to_string(), Action::Ping))
+            .await;
+        assert!(matches!(response.status, ActionResponseStatus::Success));
+        assert!(response.data.is_none());
+        let timing = response.timing.unwrap();
+        assert!(timing.queued_ms >= ACTION_DELAY.as_millis() as u64);
+
+        let input_driver = queue.input_driver.lock().await;
+        assert_eq!(input_driver.mouse_pos, (10, 20));
+        assert!(input_driver.last_action.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_wait() {
+        let queue = create_test_action_queue().await;
+
+        let response = queue
+            .execute_action(ActionRequest::new(
+                "test_wait".to_string(),
+                Action::Wait {
+                    input: WaitInput { duration_ms: 300 },
+                },
+            ))
+            .await;
+        assert!(matches!(response.status, ActionResponseStatus::Success));
+        assert!(response.timing.unwrap().execution_ms >= 300);
+        assert!(queue.input_driver.lock().await.last_action.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_lock_waits_are_recorded() {
+        let queue = create_test_action_queue().await;
+        let busy = queue.input_driver.lock().await;
+        let pending = tokio::spawn({
+            let queue = queue.clone();
+            async move {
+                queue
+                    .execute_action(ActionRequest::new(
+                        "test_lock_wait".to_string(),
+                        Action::Ping,
+                    ))
+                    .await
+            }
+        });
+        // The worker picks up the ping and waits for the driver we are holding
+        sleep(Duration::from_millis(300)).await;
+        drop(busy);
+        assert!(matches!(
+            pending.await.unwrap().status,
+            ActionResponseStatus::Success
+        ));
+
+        let text = queue.metrics().render();
+        let waited: f64 = text
+            .lines()
+            .find_map(|line| {
+                line.strip_prefix("valk_lock_wait_seconds_sum{lock=\"input_driver\"} ")
+            })
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(waited >= 0.2, "waited {}s", waited);
+    }
+
+    #[tokio::test]
+    async fn test_takeover_holds_agent_actions() {
         let queue = create_test_action_queue().await;
+        queue.start_takeover();
+        assert_eq!(queue.observation_snapshot().1["queue"]["takeover"], true);
 
-        let result = queue
-            .execute_action(ActionRequest {
-                id: "test_mouse_move".to_string(),
-                action: Action::MouseMove {
-                    input: MouseMoveInput { x: 100, y: 200 },
+        let agent = tokio::spawn({
+            let queue = queue.clone();
+            async move {
+                queue
+                    .execute_action(ActionRequest::new(
+                        "test_agent_click".to_string(),
+                        Action::LeftClick,
+                    ))
+                    .await
+            }
+        });
+        sleep(Duration::from_millis(50)).await;
+
+        // Human input runs right away, without the action delay
+        let started = Instant::now();
+        let response = queue
+            .execute_takeover(ActionRequest::new(
+                "test_human_move".to_string(),
+                Action::MouseMove {
+                    input: MouseMoveInput {
+                        x: 42,
+                        y: 24,
+                        monitor: None,
+                        motion: None,
+                        duration_ms: None,
+                    },
                 },
-            })
+            ))
             .await;
-        assert!(matches!(result.status, ActionResponseStatus::Success));
+        assert!(matches!(response.status, ActionResponseStatus::Success));
+        assert!(started.elapsed() < ACTION_DELAY);
 
-        let enigo = queue.input_driver.lock().await;
-        assert_eq!(enigo.mouse_pos, (100, 200));
-        assert_eq!(enigo.last_action, "move_mouse_100,200");
+        // The agent's click waits until control is handed back
+        sleep(ACTION_DELAY * 2).await;
+        assert!(!agent.is_finished());
+        assert_eq!(queue.input_driver.lock().await.button_presses, 0);
+
+        queue.end_takeover();
+        assert!(matches!(
+            agent.await.unwrap().status,
+            ActionResponseStatus::Success
+        ));
+        assert_eq!(queue.input_driver.lock().await.button_presses, 1);
+        assert_eq!(queue.observation_snapshot().1["queue"]["takeover"], false);
     }
 
     #[tokio::test]
-    async fn test_left_click() {
+    async fn test_paused_queue_refuses_actions() {
         let queue = create_test_action_queue().await;
+        // Keeps the click waiting in the queue
+        queue.start_takeover();
+        let queued = tokio::spawn({
+            let queue = queue.clone();
+            async move {
+                queue
+                    .execute_action(ActionRequest::new(
+                        "test_queued_click".to_string(),
+                        Action::LeftClick,
+                    ))
+                    .await
+            }
+        });
+        sleep(Duration::from_millis(50)).await;
 
-        let result = queue
-            .execute_action(ActionRequest {
-                id: "test_left_click".to_string(),
-                action: Action::LeftClick,
-            })
-            .await;
-        assert!(matches!(result.status, ActionResponseStatus::Success));
+        queue.pause();
+        queue.until_idle().await;
+        let response = queued.await.unwrap();
+        assert!(matches!(response.error, Some(ActionError::Restarting(_))));
 
-        let enigo = queue.input_driver.lock().await;
-        assert!(enigo.last_action.contains("button_Left"));
+        queue.end_takeover();
+        let response = queue
+            .execute_action(ActionRequest::new(
+                "test_late_click".to_string(),
+                Action::LeftClick,
+            ))
+            .await;
+        assert!(matches!(response.error, Some(ActionError::Restarting(_))));
+        assert_eq!(queue.input_driver.lock().await.button_presses, 0);
     }
 
     #[tokio::test]
-    async fn test_type_text() {
+    async fn test_batch_runs_without_interleaving() {
         let queue = create_test_action_queue().await;
+        let move_to = |id: &str, x, y| {
+            ActionRequest::new(
+                id.to_string(),
+                Action::MouseMove {
+                    input: MouseMoveInput {
+                        x,
+                        y,
+                        monitor: None,
+                        motion: None,
+                        duration_ms: None,
+                    },
+                },
+            )
+        };
 
-        let test_texts = ["Hello, World!", "1234567890", "Special chars: !@#$%^&*()"];
+        let batch = tokio::spawn({
+            let queue = queue.clone();
+            let requests = vec![
+                move_to("first", 10, 10),
+                ActionRequest::new("click".to_string(), Action::LeftClick),
+                move_to("last", 20, 20),
+            ];
+            async move { queue.execute_batch(requests).await }
+        });
+        sleep(Duration::from_millis(50)).await;
+        // Another client's action, submitted while the batch runs
+        let other = queue.execute_action(move_to("other", 99, 99)).await;
+        assert!(matches!(other.status, ActionResponseStatus::Success));
 
-        for text in test_texts {
-            let response = queue
-                .execute_action(ActionRequest {
-                    id: "test_type_text".to_string(),
-                    action: Action::TypeText {
-                        input: TypeTextInput {
-                            text: text.to_string(),
-                        },
-                    },
-                })
-                .await;
+        let responses = batch.await.unwrap().unwrap();
+        let ids: Vec<&str> = responses.iter().map(|r| r.request_id.as_str()).collect();
+        assert_eq!(ids, ["first", "click", "last"]);
+        assert!(responses
+            .iter()
+            .all(|r| matches!(r.status, ActionResponseStatus::Success)));
+        assert_eq!(
+            queue.input_driver.lock().await.path,
+            [(10, 10), (20, 20), (99, 99)]
+        );
 
-            match response.status {
-                ActionResponseStatus::Success => {
-                    let enigo = queue.input_driver.lock().await;
-                    assert_eq!(
-                        enigo.last_action,
-                        format!("text_{}", text),
-                        "Failed to verify text input for: {}",
-                        text
-                    );
-                }
-                ActionResponseStatus::Error => {
-                    panic!("Failed to type text '{}': {:?}", text, response.error);
-                }
-            }
-        }
+        // Nothing runs if any action is refused
+        let responses = queue
+            .execute_batch(vec![
+                move_to("valid", 1, 1),
+                move_to("invalid", u32::MAX, 1),
+            ])
+            .await
+            .unwrap();
+        assert!(matches!(responses[0].error, Some(ActionError::NotRun(_))));
+        assert!(matches!(
+            responses[1].error,
+            Some(ActionError::InvalidInput(_))
+        ));
+        assert_eq!(queue.input_driver.lock().await.path.len(), 3);
+        assert!(queue.execute_batch(Vec::new()).await.is_err());
     }
 
     #[tokio::test]
-    async fn test_type_unicode() {
+    async fn test_batch_stops_at_first_failure() {
         let queue = create_test_action_queue().await;
+        queue.input_driver.lock().await.disconnected = true;
 
-        let test_texts = [
-            "Unicode: ñáéíóú",
-            "Emojis: 😊🚀🌟",
-            "Cyrillic: Привет, мир!",
-            "Japanese: こんにちは世界",
-            "Korean: 안녕하세요 세계",
-            "Arabic: مرحبا بالعالم",
-            "Hebrew: שלום עולם",
-            "Greek: Γειά σου κόσμε",
-            "Turkish: Merhaba dünya",
-            "Vietnamese: Chào thế giới",
-            "Thai: สวัสดีโลก",
-            "Russian: Привет, мир!",
-            "Chinese: 你好，世界",
-        ];
-
-        for text in test_texts {
-            let response = queue
-                .execute_action(ActionRequest {
-                    id: "test_type_unicode".to_string(),
-                    action: Action::TypeText {
-                        input: TypeTextInput {
-                            text: text.to_string(),
+        let responses = queue
+            .execute_batch(vec![
+                ActionRequest::new("click".to_string(), Action::LeftClick),
+                ActionRequest::new(
+                    "move".to_string(),
+                    Action::MouseMove {
+                        input: MouseMoveInput {
+                            x: 5,
+                            y: 5,
+                            monitor: None,
+                            motion: None,
+                            duration_ms: None,
                         },
                     },
-                })
-                .await;
-
-            match response.status {
-                ActionResponseStatus::Success => {
-                    let enigo = queue.input_driver.lock().await;
-                    assert_eq!(
-                        enigo.last_action,
-                        format!("text_{}", text),
-                        "Failed to verify text input for: {}",
-                        text
-                    );
-                }
-                ActionResponseStatus::Error => {
-                    panic!("Failed to type text '{}': {:?}", text, response.error);
-                }
-            }
+                ),
+            ])
+            .await
+            .unwrap();
+        assert!(matches!(
+            responses[0].error,
+            Some(ActionError::ExecutionFailed(_))
+        ));
+        match &responses[1].error {
+            Some(ActionError::ExecutionFailed(message)) => assert!(message.starts_with("Skipped")),
+            other => panic!("expected the move to be skipped, got {:?}", other),
         }
+        assert!(queue.input_driver.lock().await.path.is_empty());
     }
 
     #[tokio::test]
-    async fn test_type_text_empty() {
+    async fn test_batch_past_run_budget_is_refused() {
+        let queue = create_test_action_queue().await;
+        let run = queue.runs().open(OpenRunBody {
+            limits: RunLimits {
+                max_actions: Some(3),
+                ..RunLimits::default()
+            },
+            ..OpenRunBody::default()
+        });
+        let ping = |id: &str| ActionRequest {
+            run_id: Some(run.run_id.clone()),
+            ..ActionRequest::new(id.to_string(), Action::Ping)
+        };
+
+        let response = queue.execute_action(ping("first")).await;
+        assert!(matches!(response.status, ActionResponseStatus::Success));
+        // Two actions are left, so a batch of three doesn't run at all
+        let responses = queue
+            .execute_batch(vec![ping("a"), ping("b"), ping("c")])
+            .await
+            .unwrap();
+        assert!(matches!(
+            responses[2].error,
+            Some(ActionError::BudgetExceeded(_))
+        ));
+        assert!(responses[..2]
+            .iter()
+            .all(|r| matches!(r.error, Some(ActionError::NotRun(_)))));
+        // The refused batch gave its reservations back
+        let responses = queue
+            .execute_batch(vec![ping("d"), ping("e")])
+            .await
+            .unwrap();
+        assert!(responses
+            .iter()
+            .all(|r| matches!(r.status, ActionResponseStatus::Success)));
+        let response = queue.execute_action(ping("last")).await;
+        assert!(matches!(
+            response.error,
+            Some(ActionError::BudgetExceeded(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_batch_step_timeouts() {
         let queue = create_test_action_queue().await;
+        let slow = |id: &str, on_timeout| ActionRequest {
+            timeout_ms: Some(50),
+            on_timeout,
+            ..ActionRequest::new(
+                id.to_string(),
+                Action::Wait {
+                    input: WaitInput { duration_ms: 5000 },
+                },
+            )
+        };
+
+        let responses = queue
+            .execute_batch(vec![
+                slow("optional", OnTimeout::Continue),
+                ActionRequest::new("ping".to_string(), Action::Ping),
+                slow("required", OnTimeout::Abort),
+                ActionRequest::new("skipped".to_string(), Action::Ping),
+            ])
+            .await
+            .unwrap();
+        // Both waits were cut off long before their 5 seconds
+        for cut_off in [&responses[0], &responses[2]] {
+            assert!(matches!(cut_off.error, Some(ActionError::Timeout)));
+            assert!(cut_off.timing.as_ref().unwrap().execution_ms < 1000);
+        }
+        assert!(matches!(responses[1].status, ActionResponseStatus::Success));
+        match &responses[3].error {
+            Some(ActionError::ExecutionFailed(message)) => assert!(message.starts_with("Skipped")),
+            other => panic!("expected the ping to be skipped, got {:?}", other),
+        }
 
         let response = queue
             .execute_action(ActionRequest {
-                id: "test_type_text_empty".to_string(),
-                action: Action::TypeText {
-                    input: TypeTextInput {
-                        text: "".to_string(),
-                    },
-                },
+                timeout_ms: Some(0),
+                ..ActionRequest::new("zero".to_string(), Action::Ping)
             })
             .await;
-        assert!(matches!(response.status, ActionResponseStatus::Error));
+        assert!(matches!(response.error, Some(ActionError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn test_step_limit_defaults_to_action_timeout() {
+        let ping = ActionRequest::new("ping".to_string(), Action::Ping);
+        assert_eq!(step_limit(&ping), ACTION_TIMEOUT);
+        let wait_for = ActionRequest::new(
+            "wait_for".to_string(),
+            Action::WaitFor {
+                input: serde_json::from_value(serde_json::json!({
+                    "text": "Login",
+                    "timeout_ms": 30_000,
+                }))
+                .unwrap(),
+            },
+        );
+        assert_eq!(
+            step_limit(&wait_for),
+            ACTION_TIMEOUT + Duration::from_secs(30)
+        );
+        let own = ActionRequest {
+            timeout_ms: Some(50),
+            ..ping
+        };
+        assert_eq!(step_limit(&own), Duration::from_millis(50));
     }
 
     #[tokio::test]
-    async fn test_key_press() {
+    async fn test_timed_out_action_releases_only_what_it_pressed() {
         let queue = create_test_action_queue().await;
-
         let response = queue
-            .execute_action(ActionRequest {
-                id: "test_key_press".to_string(),
-                action: Action::KeyPress {
+            .execute_action(ActionRequest::new(
+                "hold".to_string(),
+                Action::KeyDown {
                     input: KeyPressInput {
-                        key: "ctrl+c".to_string(),
+                        key: "shift".to_string(),
                     },
                 },
-            })
+            ))
             .await;
         assert!(matches!(response.status, ActionResponseStatus::Success));
 
-        let enigo = queue.input_driver.lock().await;
-        // The last action should be releasing the ctrl key
-        assert!(enigo.last_action.contains("key_Control_Release"));
+        // Cut off while the button is down, before the drag moves
+        let response = queue
+            .execute_action(ActionRequest {
+                timeout_ms: Some(50),
+                ..ActionRequest::new(
+                    "drag".to_string(),
+                    Action::LeftClickDrag {
+                        input: MouseMoveInput {
+                            x: 500,
+                            y: 500,
+                            monitor: None,
+                            motion: None,
+                            duration_ms: None,
+                        },
+                    },
+                )
+            })
+            .await;
+        assert!(matches!(response.error, Some(ActionError::Timeout)));
+
+        let input_driver = queue.input_driver.lock().await;
+        assert_eq!(input_driver.last_action, "button_Left_Release");
+        assert_eq!(input_driver.held(), vec![Held::Key(Key::Shift)]);
     }
 
     #[tokio::test]
-    async fn test_cursor_position() {
-        let queue = create_test_action_queue().await;
+    async fn test_failed_action_attaches_screenshot() {
+        let queue = create_test_action_queue_with_config(Config {
+            failure_screenshots: true,
+            ..Config::default()
+        })
+        .await;
+        let response = queue
+            .execute_action(ActionRequest::new("test_ok".to_string(), Action::LeftClick))
+            .await;
+        assert!(response.failure_screenshot.is_none());
 
-        // First move the cursor
-        let _ = queue
-            .execute_action(ActionRequest {
-                id: "test_cursor_position".to_string(),
-                action: Action::MouseMove {
-                    input: MouseMoveInput { x: 150, y: 250 },
-                },
-            })
+        queue.input_driver.lock().await.disconnected = true;
+        let response = queue
+            .execute_action(ActionRequest::new(
+                "test_failed".to_string(),
+                Action::LeftClick,
+            ))
             .await;
+        assert!(matches!(
+            response.error,
+            Some(ActionError::ExecutionFailed(_))
+        ));
+        let screenshot = response.failure_screenshot.as_ref().unwrap();
+        assert_eq!((screenshot.width, screenshot.height), (640, 360));
+        assert!(!screenshot.image.is_empty());
+        assert!(response.without_data().failure_screenshot.is_none());
+    }
 
-        // Then get position
+    #[tokio::test]
+    async fn test_pacing_rules_delay_or_reject_actions() {
+        let rules = vec!["ping=1/2000".parse().unwrap()];
+        let ping = || ActionRequest::new("test_paced".to_string(), Action::Ping);
+
+        let queue = create_test_action_queue_with_config(Config {
+            pacing_rules: rules.clone(),
+            ..Config::default()
+        })
+        .await;
+        let started = Instant::now();
+        for _ in 0..2 {
+            let response = queue.execute_action(ping()).await;
+            assert!(matches!(response.status, ActionResponseStatus::Success));
+        }
+        assert!(started.elapsed() >= Duration::from_millis(2000));
+
+        let queue = create_test_action_queue_with_config(Config {
+            pacing_rules: rules,
+            pacing_mode: PacingMode::Reject,
+            ..Config::default()
+        })
+        .await;
+        let response = queue.execute_action(ping()).await;
+        assert!(matches!(response.status, ActionResponseStatus::Success));
+        let response = queue.execute_action(ping()).await;
+        assert!(matches!(response.error, Some(ActionError::RateLimited(_))));
+        // Other action types aren't held back
         let response = queue
-            .execute_action(ActionRequest {
-                id: "test_cursor_position".to_string(),
-                action: Action::CursorPosition,
-            })
+            .execute_action(ActionRequest::new(
+                "test_unpaced".to_string(),
+                Action::CursorPosition,
+            ))
             .await;
         assert!(matches!(response.status, ActionResponseStatus::Success));
+    }
 
-        if let Some(ActionOutput::CursorPosition { x, y }) = response.data {
-            assert_eq!(x, 150);
-            assert_eq!(y, 250);
-        } else {
-            panic!("Expected cursor position data");
-        }
+    #[tokio::test]
+    async fn test_paced_action_waits_without_holding_the_driver() {
+        let queue = create_test_action_queue_with_config(Config {
+            pacing_rules: vec!["ping=1/1000".parse().unwrap()],
+            ..Config::default()
+        })
+        .await;
+        let ping = || ActionRequest::new("test_paced".to_string(), Action::Ping);
+        let response = queue.execute_action(ping()).await;
+        assert!(matches!(response.status, ActionResponseStatus::Success));
+
+        let started = Instant::now();
+        let paced = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.execute_action(ping()).await }
+        });
+        sleep(Duration::from_millis(300)).await;
+        // The second ping waits in the dispatcher, the driver is free for anything else
+        assert!(queue.input_driver.try_lock().is_ok());
+        assert!(!paced.is_finished());
+        assert!(queue.pacer.held() > Duration::ZERO);
+
+        let response = paced.await.unwrap();
+        assert!(matches!(response.status, ActionResponseStatus::Success));
+        assert!(started.elapsed() >= Duration::from_millis(600));
     }
 
     #[tokio::test]
-    async fn test_action_timeout() {
-        let queue = create_test_action_queue().await;
+    async fn test_submitting_never_waits_for_running_action() {
+        let queue = create_test_action_queue_with_config(Config {
+            max_queue_depth: 1,
+            ..Config::default()
+        })
+        .await;
+        let busy = queue.input_driver.lock().await;
 
-        // Create a very short timeout for testing
-        let short_timeout = Duration::from_millis(10);
+        let mut queued = Vec::new();
+        for i in 0..2 {
+            queued.push(tokio::spawn({
+                let queue = queue.clone();
+                async move {
+                    queue
+                        .execute_action(ActionRequest::new(
+                            format!("test_queued_{}", i),
+                            Action::Ping,
+                        ))
+                        .await
+                }
+            }));
+            // Let the worker take the first ping, the second one waits in the queue
+            sleep(Duration::from_millis(50)).await;
+        }
 
-        // Attempt to execute an action with a short timeout
-        let result = timeout(
-            short_timeout,
-            queue.execute_action(ActionRequest {
-                id: "test_action_timeout".to_string(),
-                action: Action::LeftClick,
-            }),
+        // The queue is full, which is reported right away while the driver is still busy
+        let rejected = timeout(
+            Duration::from_millis(200),
+            queue.execute_action(ActionRequest::new(
+                "test_rejected".to_string(),
+                Action::Ping,
+            )),
         )
-        .await;
+        .await
+        .expect("submitting waited for the running action");
+        assert!(matches!(rejected.error, Some(ActionError::QueueFull(_))));
+        assert!(rejected.timing.is_none());
 
-        // The timeout should occur
-        assert!(result.is_err());
+        drop(busy);
+        for pending in queued {
+            assert!(matches!(
+                pending.await.unwrap().status,
+                ActionResponseStatus::Success
+            ));
+        }
     }
 
     #[tokio::test]
-    async fn test_double_click() {
+    async fn test_monitor_screen_updates_keep_screenshot_format() {
         let queue = create_test_action_queue().await;
-
+        let (mut monitor, _) = queue.subscribe_monitor();
         let response = queue
-            .execute_action(ActionRequest {
-                id: "test_double_click".to_string(),
-                action: Action::DoubleClick,
-            })
+            .execute_action(ActionRequest::new(
+                "webp_screenshot".to_string(),
+                Action::Screenshot {
+                    input: ScreenshotInput {
+                        format: ScreenshotFormat::Webp,
+                        ..Default::default()
+                    },
+                },
+            ))
             .await;
         assert!(matches!(response.status, ActionResponseStatus::Success));
 
-        let enigo = queue.input_driver.lock().await;
-        // Should end with a release of left button
-        assert!(enigo.last_action.contains("button_Left_Release"));
+        let mut formats = Vec::new();
+        while let Ok(event) = monitor.try_recv() {
+            if let MonitorEventPayload::ScreenUpdate { image_format, .. } = event.payload {
+                formats.push(image_format);
+            }
+        }
+        assert_eq!(formats, [ScreenshotFormat::Webp]);
     }
 
     #[tokio::test]
-    async fn test_left_click_drag() {
+    async fn test_monitor_drops_new_screenshots_when_full() {
+        let queue = create_test_action_queue_with_config(Config {
+            monitor_buffer: 4,
+            monitor_overflow: MonitorOverflow::DropNewScreenshots,
+            ..Config::default()
+        })
+        .await;
+        // A subscriber that never reads, so events pile up in the buffer
+        let (mut monitor, _) = queue.subscribe_monitor();
+
+        for _ in 0..3 {
+            queue.send_monitor_event(MonitorEventPayload::ScreenUpdate {
+                action_id: "test_overflow".to_string(),
+                image: String::new(),
+                image_format: ScreenshotFormat::Png,
+                screen_size: (1, 1),
+                timestamp: Utc::now(),
+            });
+        }
+        queue.send_monitor_event(MonitorEventPayload::ActionResponse(ActionResponse::error(
+            "test_overflow".to_string(),
+            Action::LeftClick,
+            ActionError::Timeout,
+        )));
+
+        let metrics = queue.metrics();
+        assert_eq!(
+            metrics.monitor_screenshots_dropped.load(Ordering::Relaxed),
+            1
+        );
+        assert_eq!(metrics.monitor_events_sent.load(Ordering::Relaxed), 3);
+        let mut received = Vec::new();
+        while let Ok(event) = monitor.try_recv() {
+            received.push(event.payload);
+        }
+        assert!(matches!(
+            received.last(),
+            Some(MonitorEventPayload::ActionResponse(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_monitor_errors_missed_by_lagging_clients_are_kept() {
+        let queue = create_test_action_queue_with_config(Config {
+            monitor_buffer: 2,
+            ..Config::default()
+        })
+        .await;
+        let (mut monitor, errors_seen) = queue.subscribe_monitor();
+        let error = |id: &str| {
+            MonitorEventPayload::ActionResponse(ActionResponse::error(
+                id.to_string(),
+                Action::LeftClick,
+                ActionError::Timeout,
+            ))
+        };
+        queue.send_monitor_event(error("test_first"));
+        for _ in 0..3 {
+            queue.send_monitor_event(MonitorEventPayload::ApprovalResolved {
+                approval_id: "test_lagging".to_string(),
+                approved: true,
+            });
+        }
+        queue.send_monitor_event(error("test_second"));
+
+        assert!(matches!(
+            monitor.try_recv(),
+            Err(broadcast::error::TryRecvError::Lagged(_))
+        ));
+        let next = monitor.try_recv().unwrap();
+        let missed = queue.missed_monitor_errors(errors_seen, next.errors_sent);
+        let ids: Vec<_> = missed
+            .iter()
+            .map(|event| match &event.payload {
+                MonitorEventPayload::ActionResponse(response) => response.request_id.as_str(),
+                _ => "",
+            })
+            .collect();
+        assert_eq!(ids, ["test_first"]);
+        assert_eq!(
+            queue
+                .metrics()
+                .monitor_errors_resent
+                .load(Ordering::Relaxed),
+            1
+        );
+        assert!(monitor.try_recv().unwrap().payload.is_error_response());
+    }
+
+    #[tokio::test]
+    async fn test_monitor_screen_updates_stay_within_bandwidth() {
+        let queue = create_test_action_queue_with_config(Config {
+            monitor_bandwidth: Some(1000),
+            ..Config::default()
+        })
+        .await;
+        let (mut monitor, _) = queue.subscribe_monitor();
+        let screen_update = || MonitorEventPayload::ScreenUpdate {
+            action_id: "test_bandwidth".to_string(),
+            image: String::new(),
+            image_format: ScreenshotFormat::Png,
+            screen_size: (1, 1),
+            timestamp: Utc::now(),
+        };
+
+        queue.send_monitor_event(screen_update());
+        // A client used up the budget, screen updates stop but other events still go out
+        queue.record_monitor_bytes(1000);
+        queue.send_monitor_event(screen_update());
+        queue.send_monitor_event(MonitorEventPayload::CursorUpdate {
+            action_id: "test_bandwidth".to_string(),
+            x: 0,
+            y: 0,
+            timestamp: Utc::now(),
+        });
+
+        let metrics = queue.metrics();
+        assert_eq!(
+            metrics
+                .monitor_screenshots_throttled
+                .load(Ordering::Relaxed),
+            1
+        );
+        assert_eq!(metrics.monitor_bytes_sent.load(Ordering::Relaxed), 1000);
+        let mut screen_updates = 0;
+        let mut cursor_updates = 0;
+        while let Ok(event) = monitor.try_recv() {
+            match event.payload {
+                MonitorEventPayload::ScreenUpdate { .. } => screen_updates += 1,
+                MonitorEventPayload::CursorUpdate { .. } => cursor_updates += 1,
+                _ => {}
+            }
+        }
+        assert_eq!((screen_updates, cursor_updates), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn test_cursor_snapshot_falls_back_to_cache_while_busy() {
         let queue = create_test_action_queue().await;
+        queue.input_driver.lock().await.mouse_pos = (12, 34);
 
-        let response = queue
-            .execute_action(ActionRequest {
-                id: "test_left_click_drag".to_string(),
-                action: Action::LeftClickDrag {
-                    input: MouseMoveInput { x: 300, y: 400 },
+        let snapshot = queue.cursor_snapshot().unwrap();
+        assert_eq!((snapshot.x, snapshot.y, snapshot.cached), (12, 34, false));
+
+        let mut busy = queue.input_driver.lock().await;
+        busy.mouse_pos = (56, 78);
+        let snapshot = queue.cursor_snapshot().unwrap();
+        assert_eq!((snapshot.x, snapshot.y, snapshot.cached), (12, 34, true));
+    }
+
+    #[tokio::test]
+    async fn test_observation_patches_follow_actions() {
+        let queue = create_test_action_queue().await;
+        let mut observation = queue.subscribe_observation();
+        let (_, before) = observation.borrow_and_update().snapshot();
+
+        queue
+            .execute_action(ActionRequest::new(
+                "test_observation".to_string(),
+                Action::MouseMove {
+                    input: MouseMoveInput {
+                        x: 7,
+                        y: 8,
+                        monitor: None,
+                        motion: None,
+                        duration_ms: None,
+                    },
                 },
-            })
+            ))
             .await;
-        assert!(matches!(response.status, ActionResponseStatus::Success));
 
-        let enigo = queue.input_driver.lock().await;
-        assert_eq!(enigo.mouse_pos, (300, 400));
-        // Should end with a release
-        assert!(enigo.last_action.contains("button_Left_Release"));
+        assert!(observation.has_changed().unwrap());
+        let (_, after) = observation.borrow_and_update().snapshot();
+        let mut paths = Vec::new();
+        crate::observation::diff(&before, &after, "", &mut paths);
+        assert!(paths.contains(&crate::observation::PatchOp::Replace {
+            path: "/cursor".to_string(),
+            value: serde_json::json!([7, 8]),
+        }));
+        let (version, state) = queue.observation_snapshot();
+        assert!(version > 0);
+        assert_eq!(state["queue"]["busy"], false);
     }
 }
@@ -1,50 +1,1120 @@
 use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 use uuid::Uuid;
 
+use crate::config::MouseMotion;
+use crate::key_press::{parse_modifier, KeyPress};
+
+/// X11 coordinates are signed 16 bit, so nothing past this can be on screen
+pub const MAX_COORDINATE: u32 = i16::MAX as u32;
+/// Gestures longer than this would run into the action timeout anyway
+pub const MAX_GESTURE_DURATION_MS: u64 = 5_000;
+/// Most wheel clicks a single scroll may send
+pub const MAX_SCROLL_CLICKS: u32 = 100;
+/// Most pixels a single smooth scroll may cover
+pub const MAX_SMOOTH_SCROLL_PIXELS: u32 = 20_000;
+/// Fastest flick of a kinetic scroll, in pixels per second
+pub const MAX_KINETIC_SCROLL_VELOCITY: u32 = 20_000;
+/// Most waypoints a single drag path may have
+pub const MAX_DRAG_PATH_POINTS: usize = 1000;
+/// Most points a single pen stroke may have, they are drawn 10ms apart so the longest stroke
+/// is over within the gesture duration
+pub const MAX_PEN_POINTS: usize = (MAX_GESTURE_DURATION_MS / 10) as usize;
+/// Most frames a single burst may capture, they are held in memory until all are taken
+pub const MAX_BURST_FRAMES: u32 = 10;
+/// Largest serialized request metadata, it is kept in run history for every action
+pub const MAX_METADATA_BYTES: usize = 4096;
+/// Longest timeout a request may set for its action
+pub const MAX_REQUEST_TIMEOUT_MS: u64 = 120_000;
+/// Most matches a single locate_image may return
+pub const MAX_IMAGE_MATCHES: u32 = 50;
+/// Longest side a locate_image or wait_for template may have
+pub const MAX_TEMPLATE_SIDE: u32 = 512;
+/// Longest a single wait_for may block the queue and hold the input driver, no longer than
+/// the worker gives any other action. Longer waits are a loop of wait_for in the client
+pub const MAX_WAIT_FOR_MS: u64 = 10_000;
+
+fn check_coordinates(x: u32, y: u32) -> Result<(), ActionError> {
+    if x > MAX_COORDINATE || y > MAX_COORDINATE {
+        return Err(ActionError::InvalidInput(format!(
+            "Coordinates ({}, {}) are out of range, must be at most {}",
+            x, y, MAX_COORDINATE
+        )));
+    }
+    Ok(())
+}
+
+fn check_modifiers(modifiers: &[String]) -> Result<(), ActionError> {
+    for modifier in modifiers {
+        parse_modifier(modifier).map_err(ActionError::InvalidInput)?;
+    }
+    Ok(())
+}
+
+fn check_region(region: &Region) -> Result<(), ActionError> {
+    if region.width == 0 || region.height == 0 {
+        return Err(ActionError::InvalidInput(
+            "Region must not be empty".to_string(),
+        ));
+    }
+    check_coordinates(region.x, region.y)
+}
+
 /// Represents the core set of actions that can be performed
 /// Each variant defines a specific operation that can be requested
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
 pub enum Action {
+    /// Click the left button at the current cursor position
     LeftClick,
+    /// Click the right button at the current cursor position, usually opening a context menu
     RightClick,
+    /// Click the middle button at the current cursor position
     MiddleClick,
+    /// Double-click at the current cursor position, e.g. to open a file or select a word
     DoubleClick,
-    MouseMove {
-        input: MouseMoveInput,
-    },
-    LeftClickDrag {
-        input: MouseMoveInput,
-    },
-    TypeText {
-        input: TypeTextInput,
-    },
+    /// Selects a whole line or paragraph in most text fields
+    TripleClick,
+    /// Move to a point and click there, as one queue item
+    Click { input: ClickInput },
+    /// Click the middle of an element of the accessibility tree, found by its id or by its
+    /// role and name, wherever its window is now
+    ClickElement { input: ClickElementInput },
+    /// Move the cursor to a point without clicking
+    MouseMove { input: MouseMoveInput },
+    /// Drag with the left button held from the cursor to a point, e.g. to select or move
+    LeftClickDrag { input: MouseMoveInput },
+    /// Drag with the right button held, for context gestures
+    RightClickDrag { input: MouseMoveInput },
+    /// Drag with the middle button held, which pans in CAD tools and browsers
+    MiddleClickDrag { input: MouseMoveInput },
+    /// Press at the first point, drag through every following one and release at the last
+    DragPath { input: DragPathInput },
+    /// Move to a point and stay there without clicking, to bring up tooltips and hover menus
+    Hover { input: HoverInput },
+    /// Type text into the focused element, key by key
+    TypeText { input: TypeTextInput },
+    /// Put the text on the clipboard and paste it, faster and more reliable than typing
+    /// for long or non-ASCII text
+    PasteText { input: TypeTextInput },
+    /// Press and release a key or combination, e.g. "enter" or "ctrl+s"
     #[serde(rename_all = "snake_case")]
-    KeyPress {
-        input: KeyPressInput,
+    KeyPress { input: KeyPressInput },
+    /// Press and hold the keys of a combination, modifiers first, until a matching KeyUp
+    KeyDown { input: KeyPressInput },
+    /// Release the keys of a combination, the main key first
+    KeyUp { input: KeyPressInput },
+    /// The primary monitor, or a region of it
+    Screenshot {
+        #[serde(default)]
+        input: ScreenshotInput,
+    },
+    /// Several screenshots at a fixed interval, to catch short lived UI like toasts
+    Burst { input: BurstInput },
+    /// Text on the primary monitor, or a region of it, and where it is
+    Ocr {
+        #[serde(default)]
+        input: OcrInput,
     },
-    Screenshot,
+    /// Where on the primary monitor some text is, e.g. a button's label to click
+    FindText { input: FindTextInput },
+    /// Where on the primary monitor an image appears, e.g. an icon to click
+    LocateImage { input: LocateImageInput },
+    /// Block until some text or an image appears on the primary monitor, e.g. once a page loads
+    WaitFor { input: WaitForInput },
+    /// Where the cursor is
     CursorPosition,
+    /// Every top-level window with its geometry and stacking order, to see what is open
+    ListWindows,
+    /// Raise a window and give it input focus, so the keystrokes that follow go to it
+    FocusWindow { input: WindowInput },
+    /// Close a window as its close button would
+    CloseWindow { input: WindowInput },
+    /// Minimize a window to the taskbar or dock
+    MinimizeWindow { input: WindowInput },
+    /// Touch the screen at a point and lift the finger again
+    Tap { input: Point },
+    /// Slide a finger across the touch screen from one point to another
+    Swipe { input: SwipeInput },
+    /// Draw a stroke with a pressure-sensitive pen
+    Pen { input: PenInput },
+    /// Remember the current cursor position so a later RestoreCursor can return to it
+    SaveCursor,
+    /// Move the cursor back to where SaveCursor found it
+    RestoreCursor,
+    /// Goes through the queue and worker without doing anything, to measure baseline latency
+    Ping,
+    /// Pause, e.g. for an animation or page load between the actions of a batch
+    Wait { input: WaitInput },
+    /// Turn the mouse wheel at the current cursor position
+    Scroll { input: ScrollInput },
+    /// Scroll a distance in pixels spread over a duration, instead of one jump
+    SmoothScroll { input: SmoothScrollInput },
+    /// Flick-scroll at a point, fast at first and slowing down like a flicked touch screen
+    KineticScroll { input: KineticScrollInput },
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl Action {
+    /// The serialized `type` tag of the action, used to match actions against config rules
+    pub fn action_type(&self) -> &'static str {
+        match self {
+            Action::LeftClick => "left_click",
+            Action::RightClick => "right_click",
+            Action::MiddleClick => "middle_click",
+            Action::DoubleClick => "double_click",
+            Action::TripleClick => "triple_click",
+            Action::Click { .. } => "click",
+            Action::ClickElement { .. } => "click_element",
+            Action::MouseMove { .. } => "mouse_move",
+            Action::LeftClickDrag { .. } => "left_click_drag",
+            Action::RightClickDrag { .. } => "right_click_drag",
+            Action::MiddleClickDrag { .. } => "middle_click_drag",
+            Action::DragPath { .. } => "drag_path",
+            Action::Hover { .. } => "hover",
+            Action::TypeText { .. } => "type_text",
+            Action::PasteText { .. } => "paste_text",
+            Action::KeyPress { .. } => "key_press",
+            Action::KeyDown { .. } => "key_down",
+            Action::KeyUp { .. } => "key_up",
+            Action::Screenshot { .. } => "screenshot",
+            Action::Burst { .. } => "burst",
+            Action::Ocr { .. } => "ocr",
+            Action::FindText { .. } => "find_text",
+            Action::LocateImage { .. } => "locate_image",
+            Action::WaitFor { .. } => "wait_for",
+            Action::CursorPosition => "cursor_position",
+            Action::ListWindows => "list_windows",
+            Action::FocusWindow { .. } => "focus_window",
+            Action::CloseWindow { .. } => "close_window",
+            Action::MinimizeWindow { .. } => "minimize_window",
+            Action::Tap { .. } => "tap",
+            Action::Swipe { .. } => "swipe",
+            Action::Pen { .. } => "pen",
+            Action::SaveCursor => "save_cursor",
+            Action::RestoreCursor => "restore_cursor",
+            Action::Ping => "ping",
+            Action::Wait { .. } => "wait",
+            Action::Scroll { .. } => "scroll",
+            Action::SmoothScroll { .. } => "smooth_scroll",
+            Action::KineticScroll { .. } => "kinetic_scroll",
+        }
+    }
+
+    /// Monitor the action's coordinates are relative to, if it has coordinates and a monitor
+    pub fn monitor(&self) -> Option<MonitorSelector> {
+        match self {
+            Action::MouseMove { input }
+            | Action::LeftClickDrag { input }
+            | Action::RightClickDrag { input }
+            | Action::MiddleClickDrag { input } => input.monitor,
+            Action::Click { input } => input.monitor,
+            Action::Hover { input } => input.monitor,
+            Action::DragPath { input } => input.monitor,
+            _ => None,
+        }
+    }
+
+    /// Points the pointer goes to, in the action's own coordinates
+    pub fn pointer_targets(&self) -> Vec<Point> {
+        match self {
+            Action::MouseMove { input }
+            | Action::LeftClickDrag { input }
+            | Action::RightClickDrag { input }
+            | Action::MiddleClickDrag { input } => vec![Point {
+                x: input.x,
+                y: input.y,
+            }],
+            Action::Click { input } => vec![Point {
+                x: input.x,
+                y: input.y,
+            }],
+            Action::Hover { input } => vec![Point {
+                x: input.x,
+                y: input.y,
+            }],
+            Action::DragPath { input } => input.points.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The action with coordinates and regions given in the pixels of screenshots scaled by
+    /// `scale` converted to screen pixels, and its screenshots scaled to match
+    pub fn in_screen_space(&self, scale: f64) -> Action {
+        let to_screen = |value: u32| (value as f64 / scale).round() as u32;
+        let point = |point: &Point| Point {
+            x: to_screen(point.x),
+            y: to_screen(point.y),
+        };
+        let region = |region: Option<Region>| {
+            region.map(|region| Region {
+                x: to_screen(region.x),
+                y: to_screen(region.y),
+                width: to_screen(region.width).max(1),
+                height: to_screen(region.height).max(1),
+            })
+        };
+        let mut action = self.clone();
+        match &mut action {
+            Action::MouseMove { input }
+            | Action::LeftClickDrag { input }
+            | Action::RightClickDrag { input }
+            | Action::MiddleClickDrag { input } => {
+                (input.x, input.y) = (to_screen(input.x), to_screen(input.y))
+            }
+            Action::Click { input } => {
+                (input.x, input.y) = (to_screen(input.x), to_screen(input.y))
+            }
+            Action::Hover { input } => {
+                (input.x, input.y) = (to_screen(input.x), to_screen(input.y))
+            }
+            Action::KineticScroll { input } => {
+                (input.x, input.y) = (to_screen(input.x), to_screen(input.y))
+            }
+            Action::DragPath { input } => {
+                input.points = input.points.iter().map(point).collect();
+            }
+            Action::Tap { input } => *input = point(input),
+            Action::Swipe { input } => {
+                (input.start, input.end) = (point(&input.start), point(&input.end))
+            }
+            Action::Pen { input } => {
+                for sample in &mut input.points {
+                    (sample.x, sample.y) = (to_screen(sample.x), to_screen(sample.y));
+                }
+            }
+            Action::Screenshot { input } => {
+                input.region = region(input.region);
+                input.scale = Some(scale);
+            }
+            Action::Burst { input } => {
+                input.region = region(input.region);
+                input.scale = Some(scale);
+            }
+            Action::Ocr { input } => input.region = region(input.region),
+            Action::FindText { input } => input.region = region(input.region),
+            Action::LocateImage { input } => input.region = region(input.region),
+            Action::WaitFor { input } => input.region = region(input.region),
+            _ => {}
+        }
+        action
+    }
+
+    /// Actions that only observe the screen and should never leave the cursor somewhere else
+    pub fn is_read_only(&self) -> bool {
+        matches!(
+            self,
+            Action::Screenshot { .. }
+                | Action::Burst { .. }
+                | Action::Ocr { .. }
+                | Action::FindText { .. }
+                | Action::LocateImage { .. }
+                | Action::WaitFor { .. }
+                | Action::CursorPosition
+                | Action::ListWindows
+                | Action::SaveCursor
+        )
+    }
+
+    /// How long the action may block on purpose, on top of the usual action timeout
+    pub fn max_wait(&self) -> u64 {
+        match self {
+            Action::WaitFor { input } => input.timeout_ms,
+            _ => 0,
+        }
+    }
+
+    /// Checks inputs that can be validated without touching the input driver
+    pub fn validate(&self) -> Result<(), ActionError> {
+        match self {
+            Action::MouseMove { input } => {
+                check_coordinates(input.x, input.y)?;
+                match input.duration_ms {
+                    Some(duration) if duration > MAX_GESTURE_DURATION_MS => {
+                        Err(ActionError::InvalidInput(format!(
+                            "Glide duration must be at most {}ms, got {}ms",
+                            MAX_GESTURE_DURATION_MS, duration
+                        )))
+                    }
+                    _ => Ok(()),
+                }
+            }
+            Action::LeftClickDrag { input }
+            | Action::RightClickDrag { input }
+            | Action::MiddleClickDrag { input } => check_coordinates(input.x, input.y),
+            Action::DragPath { input } => {
+                if !(2..=MAX_DRAG_PATH_POINTS).contains(&input.points.len()) {
+                    return Err(ActionError::InvalidInput(format!(
+                        "Drag path needs between 2 and {} points, got {}",
+                        MAX_DRAG_PATH_POINTS,
+                        input.points.len()
+                    )));
+                }
+                for point in &input.points {
+                    check_coordinates(point.x, point.y)?;
+                }
+                let duration = input.duration_ms();
+                if duration > MAX_GESTURE_DURATION_MS {
+                    return Err(ActionError::InvalidInput(format!(
+                        "Drag path must be over within {}ms, its {} points take about {}ms",
+                        MAX_GESTURE_DURATION_MS,
+                        input.points.len(),
+                        duration
+                    )));
+                }
+                Ok(())
+            }
+            Action::Hover { input } => {
+                check_coordinates(input.x, input.y)?;
+                match input.duration_ms {
+                    Some(duration) if duration > MAX_GESTURE_DURATION_MS => {
+                        Err(ActionError::InvalidInput(format!(
+                            "Hover duration must be at most {}ms, got {}ms",
+                            MAX_GESTURE_DURATION_MS, duration
+                        )))
+                    }
+                    _ => Ok(()),
+                }
+            }
+            Action::Tap { input } => check_coordinates(input.x, input.y),
+            Action::Wait { input } if input.duration_ms > MAX_GESTURE_DURATION_MS => {
+                Err(ActionError::InvalidInput(format!(
+                    "Wait must be at most {}ms, got {}ms",
+                    MAX_GESTURE_DURATION_MS, input.duration_ms
+                )))
+            }
+            Action::Burst { input } => {
+                if !(1..=MAX_BURST_FRAMES).contains(&input.count) {
+                    return Err(ActionError::InvalidInput(format!(
+                        "Burst needs between 1 and {} frames, got {}",
+                        MAX_BURST_FRAMES, input.count
+                    )));
+                }
+                let duration = input.interval_ms.saturating_mul(u64::from(input.count - 1));
+                if duration > MAX_GESTURE_DURATION_MS {
+                    return Err(ActionError::InvalidInput(format!(
+                        "Burst must be over within {}ms, {} frames {}ms apart take {}ms",
+                        MAX_GESTURE_DURATION_MS, input.count, input.interval_ms, duration
+                    )));
+                }
+                input.region.as_ref().map_or(Ok(()), check_region)
+            }
+            Action::Ocr { input } => input.region.as_ref().map_or(Ok(()), check_region),
+            Action::FindText { input } => {
+                if input.query.trim().is_empty() {
+                    return Err(ActionError::InvalidInput(
+                        "Query must not be empty".to_string(),
+                    ));
+                }
+                input.region.as_ref().map_or(Ok(()), check_region)
+            }
+            Action::LocateImage { input } => {
+                if input.template.trim().is_empty() {
+                    return Err(ActionError::InvalidInput(
+                        "Template must not be empty".to_string(),
+                    ));
+                }
+                crate::locate::check_template(&input.template)?;
+                if !(0.0..=1.0).contains(&input.confidence) {
+                    return Err(ActionError::InvalidInput(
+                        "Confidence must be between 0 and 1".to_string(),
+                    ));
+                }
+                if !(1..=MAX_IMAGE_MATCHES).contains(&input.limit) {
+                    return Err(ActionError::InvalidInput(format!(
+                        "Limit must be between 1 and {}, got {}",
+                        MAX_IMAGE_MATCHES, input.limit
+                    )));
+                }
+                input.region.as_ref().map_or(Ok(()), check_region)
+            }
+            Action::WaitFor { input } => {
+                match (&input.text, &input.image_template) {
+                    (Some(text), None) if !text.trim().is_empty() => {}
+                    (None, Some(template)) if !template.trim().is_empty() => {
+                        crate::locate::check_template(template)?
+                    }
+                    _ => {
+                        return Err(ActionError::InvalidInput(
+                            "Wait for either text or an image_template".to_string(),
+                        ))
+                    }
+                }
+                if !(0.0..=1.0).contains(&input.confidence) {
+                    return Err(ActionError::InvalidInput(
+                        "Confidence must be between 0 and 1".to_string(),
+                    ));
+                }
+                if input.timeout_ms > MAX_WAIT_FOR_MS {
+                    return Err(ActionError::InvalidInput(format!(
+                        "Timeout must be at most {}ms, got {}ms",
+                        MAX_WAIT_FOR_MS, input.timeout_ms
+                    )));
+                }
+                input.region.as_ref().map_or(Ok(()), check_region)
+            }
+            Action::ClickElement { input } => {
+                let by_role = match (&input.role, &input.name) {
+                    (Some(role), Some(_)) => !role.trim().is_empty(),
+                    (None, None) => false,
+                    _ => {
+                        return Err(ActionError::InvalidInput(
+                            "Give both role and name to pick an element by them".to_string(),
+                        ))
+                    }
+                };
+                match (&input.element_id, by_role) {
+                    (Some(id), false) if !id.trim().is_empty() => Ok(()),
+                    (None, true) => Ok(()),
+                    _ => Err(ActionError::InvalidInput(
+                        "Pick an element by either element_id or role and name".to_string(),
+                    )),
+                }
+            }
+            Action::FocusWindow { input }
+            | Action::CloseWindow { input }
+            | Action::MinimizeWindow { input } => match (input.id, &input.title_match) {
+                (Some(_), None) => Ok(()),
+                (None, Some(title)) if !title.trim().is_empty() => Ok(()),
+                _ => Err(ActionError::InvalidInput(
+                    "Pick a window by either id or title_match".to_string(),
+                )),
+            },
+            Action::Screenshot { input } => {
+                match input.quality {
+                    Some(quality) if !(1..=100).contains(&quality) => {
+                        return Err(ActionError::InvalidInput(
+                            "Quality must be between 1 and 100".to_string(),
+                        ))
+                    }
+                    Some(_) if input.format != ScreenshotFormat::Jpeg => {
+                        return Err(ActionError::InvalidInput(format!(
+                            "Quality only applies to jpeg, {:?} is lossless",
+                            input.format
+                        )))
+                    }
+                    _ => {}
+                }
+                if input.max_width == Some(0) || input.max_height == Some(0) {
+                    return Err(ActionError::InvalidInput(
+                        "max_width and max_height must be greater than 0".to_string(),
+                    ));
+                }
+                if input.target == ScreenshotTarget::FocusedWindow
+                    && (input.monitor.is_some() || input.region.is_some())
+                {
+                    return Err(ActionError::InvalidInput(
+                        "The focused window is found on its own, monitor and region don't apply"
+                            .to_string(),
+                    ));
+                }
+                input.region.as_ref().map_or(Ok(()), check_region)
+            }
+            Action::Click { input } => {
+                check_coordinates(input.x, input.y)?;
+                check_modifiers(&input.modifiers)?;
+                if let Some(color) = &input.expected_pixel {
+                    parse_hex_color(color).map_err(ActionError::InvalidInput)?;
+                }
+                if input
+                    .expected_text
+                    .as_ref()
+                    .is_some_and(|text| text.trim().is_empty())
+                {
+                    return Err(ActionError::InvalidInput(
+                        "expected_text must not be empty".to_string(),
+                    ));
+                }
+                Ok(())
+            }
+            Action::Swipe { input } => {
+                check_coordinates(input.start.x, input.start.y)?;
+                check_coordinates(input.end.x, input.end.y)?;
+                match input.duration_ms {
+                    Some(duration) if duration > MAX_GESTURE_DURATION_MS => {
+                        Err(ActionError::InvalidInput(format!(
+                            "Swipe duration must be at most {}ms, got {}ms",
+                            MAX_GESTURE_DURATION_MS, duration
+                        )))
+                    }
+                    _ => Ok(()),
+                }
+            }
+            Action::Scroll { input } => {
+                if input.amount.unsigned_abs() > MAX_SCROLL_CLICKS {
+                    return Err(ActionError::InvalidInput(format!(
+                        "Scroll amount must be at most {} clicks, got {}",
+                        MAX_SCROLL_CLICKS, input.amount
+                    )));
+                }
+                check_modifiers(&input.modifiers)
+            }
+            Action::SmoothScroll { input } => {
+                if input.pixels.unsigned_abs() > MAX_SMOOTH_SCROLL_PIXELS {
+                    return Err(ActionError::InvalidInput(format!(
+                        "Smooth scroll distance must be at most {} pixels, got {}",
+                        MAX_SMOOTH_SCROLL_PIXELS, input.pixels
+                    )));
+                }
+                check_modifiers(&input.modifiers)?;
+                match input.duration_ms {
+                    Some(duration) if duration > MAX_GESTURE_DURATION_MS => {
+                        Err(ActionError::InvalidInput(format!(
+                            "Smooth scroll duration must be at most {}ms, got {}ms",
+                            MAX_GESTURE_DURATION_MS, duration
+                        )))
+                    }
+                    _ => Ok(()),
+                }
+            }
+            Action::KineticScroll { input } => {
+                check_coordinates(input.x, input.y)?;
+                if !(1..=MAX_KINETIC_SCROLL_VELOCITY).contains(&input.velocity) {
+                    return Err(ActionError::InvalidInput(format!(
+                        "Kinetic scroll velocity must be between 1 and {} pixels per second, got {}",
+                        MAX_KINETIC_SCROLL_VELOCITY, input.velocity
+                    )));
+                }
+                Ok(())
+            }
+            Action::TypeText { input } | Action::PasteText { input } if input.text.is_empty() => {
+                Err(ActionError::InvalidInput(
+                    "Text cannot be empty".to_string(),
+                ))
+            }
+            Action::KeyPress { input } | Action::KeyDown { input } | Action::KeyUp { input } => {
+                KeyPress::from_str(&input.key).map(|_| ()).map_err(|_| {
+                    ActionError::InvalidInput(format!(
+                        "Invalid key format or key not found: {}",
+                        input.key
+                    ))
+                })
+            }
+            Action::Pen { input } => {
+                if !(1..=MAX_PEN_POINTS).contains(&input.points.len()) {
+                    return Err(ActionError::InvalidInput(format!(
+                        "Pen stroke needs between 1 and {} points, got {}",
+                        MAX_PEN_POINTS,
+                        input.points.len()
+                    )));
+                }
+                for point in &input.points {
+                    check_coordinates(point.x, point.y)?;
+                }
+                match input
+                    .points
+                    .iter()
+                    .find(|p| !(0.0..=1.0).contains(&p.pressure))
+                {
+                    Some(point) => Err(ActionError::InvalidInput(format!(
+                        "Pen pressure must be between 0.0 and 1.0, got {}",
+                        point.pressure
+                    ))),
+                    None => Ok(()),
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct MouseMoveInput {
     pub x: u32,
     pub y: u32,
+    /// Monitor the coordinates are relative to, input coordinates if unset
+    #[serde(default, alias = "display", skip_serializing_if = "Option::is_none")]
+    pub monitor: Option<MonitorSelector>,
+    /// How `mouse_move` gets there, `VALK_MOUSE_MOTION` if unset. Drags ignore it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub motion: Option<MouseMotion>,
+    /// How long a glide takes, `VALK_GLIDE_DURATION_MS` if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MouseButton {
+    #[default]
+    Left,
+    Right,
+    Middle,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct ScreenshotInput {
+    /// What to capture, a monitor unless set to the focused window
+    #[serde(default, skip_serializing_if = "ScreenshotTarget::is_monitor")]
+    pub target: ScreenshotTarget,
+    /// Monitor to capture, the primary monitor if unset
+    #[serde(default, alias = "display", skip_serializing_if = "Option::is_none")]
+    pub monitor: Option<MonitorSelector>,
+    /// Part of the monitor to keep, the whole monitor if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<Region>,
+    #[serde(default, skip_serializing_if = "ScreenshotFormat::is_png")]
+    pub format: ScreenshotFormat,
+    /// JPEG quality from 1 to 100
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quality: Option<u8>,
+    /// Draw the mouse pointer where it is, captures don't include it
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub show_cursor: bool,
+    /// Shrink the image to fit within these dimensions, keeping its aspect ratio
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_width: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_height: Option<u32>,
+    /// Hash of a screenshot the agent already has, no image is sent while the screen matches it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_hash: Option<String>,
+    /// Image pixels per screen pixel for runs working in model space, instead of the max size
+    #[serde(skip)]
+    pub scale: Option<f64>,
+}
+
+/// What a screenshot shows
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ScreenshotTarget {
+    /// A whole monitor, or a region of it
+    #[default]
+    Monitor,
+    /// The window with input focus, cropped from the monitor it is on
+    FocusedWindow,
+}
+
+impl ScreenshotTarget {
+    fn is_monitor(&self) -> bool {
+        *self == ScreenshotTarget::Monitor
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// How a screenshot is encoded, PNG keeps every pixel but JPEG is far smaller
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ScreenshotFormat {
+    #[default]
+    Png,
+    Jpeg,
+    /// Lossless WebP, the encoder has no lossy mode
+    Webp,
+}
+
+impl ScreenshotFormat {
+    fn is_png(&self) -> bool {
+        *self == ScreenshotFormat::Png
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ScreenshotFormat::Png => "png",
+            ScreenshotFormat::Jpeg => "jpg",
+            ScreenshotFormat::Webp => "webp",
+        }
+    }
+
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension {
+            "png" => Some(ScreenshotFormat::Png),
+            "jpg" => Some(ScreenshotFormat::Jpeg),
+            "webp" => Some(ScreenshotFormat::Webp),
+            _ => None,
+        }
+    }
+
+    pub fn media_type(self) -> &'static str {
+        match self {
+            ScreenshotFormat::Png => "image/png",
+            ScreenshotFormat::Jpeg => "image/jpeg",
+            ScreenshotFormat::Webp => "image/webp",
+        }
+    }
+}
+
+/// A monitor by its position in the display list of `/v1/observe`, or by its id
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MonitorSelector {
+    Index(usize),
+    Id(u32),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct BurstInput {
+    pub count: u32,
+    pub interval_ms: u64,
+    /// Part of the primary monitor to keep, the whole monitor if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<Region>,
+    /// Image pixels per screen pixel for runs working in model space
+    #[serde(skip)]
+    pub scale: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct OcrInput {
+    /// Part of the primary monitor to read, the whole monitor if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<Region>,
+    #[serde(default)]
+    pub level: TextLevel,
+    /// Also return the image that was read, with the text boxes and confidences drawn on it
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub debug: bool,
+}
+
+/// Whether recognized text is returned a line or a word at a time
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TextLevel {
+    #[default]
+    Line,
+    Word,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct FindTextInput {
+    pub query: String,
+    #[serde(default)]
+    pub match_mode: MatchMode,
+    /// Part of the primary monitor to search, the whole monitor if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<Region>,
+    /// Also return the image that was searched, with every word read and the match drawn on it
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub debug: bool,
+}
+
+/// How text on screen has to compare to a query, case and spacing never matter
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchMode {
+    /// The text is the query
+    Exact,
+    /// The text includes the query
+    #[default]
+    Contains,
+    /// The text is close to the query, for recognition mistakes like "rn" read for "m"
+    Fuzzy,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct LocateImageInput {
+    /// Base64 encoded image to look for, e.g. a PNG of an icon cut from a screenshot
+    pub template: String,
+    /// Least confidence of a match, from 0 to 1
+    #[serde(default = "default_locate_confidence")]
+    pub confidence: f32,
+    /// Most matches to return, the best first
+    #[serde(default = "default_locate_limit")]
+    pub limit: u32,
+    /// Part of the primary monitor to search, the whole monitor if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<Region>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct WaitForInput {
+    /// Text to wait for, matched as by find_text
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(default)]
+    pub match_mode: MatchMode,
+    /// Base64 encoded image to wait for, matched as by locate_image
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_template: Option<String>,
+    /// Least confidence of an image match, from 0 to 1
+    #[serde(default = "default_locate_confidence")]
+    pub confidence: f32,
+    /// How long to keep looking before failing with not_found, at most 10000
+    pub timeout_ms: u64,
+    /// Part of the primary monitor to watch, the whole monitor if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<Region>,
+}
+
+/// The element a click_element action clicks
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ClickElementInput {
+    /// Element id as listed by /v1/accessibility/tree
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub element_id: Option<String>,
+    /// Role of the element, e.g. "push button", compared ignoring case
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    /// Name of the element, e.g. its label, compared ignoring case
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub button: MouseButton,
+}
+
+/// The window focus_window, close_window or minimize_window is for
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct WindowInput {
+    /// Window id as listed by list_windows
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u32>,
+    /// Part of the title, ignoring case. The frontmost window with a matching title is picked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title_match: Option<String>,
+}
+
+impl WindowInput {
+    pub fn matches(&self, window: &crate::observe::ListedWindow) -> bool {
+        match (self.id, &self.title_match) {
+            (Some(id), _) => window.id == id,
+            (None, Some(title)) => window
+                .title
+                .to_lowercase()
+                .contains(&title.trim().to_lowercase()),
+            (None, None) => false,
+        }
+    }
+
+    /// The window looked for, for messages
+    pub fn describe(&self) -> String {
+        match (self.id, &self.title_match) {
+            (Some(id), _) => format!("with id {}", id),
+            (_, title) => format!(
+                "with \"{}\" in its title",
+                title.as_deref().unwrap_or_default()
+            ),
+        }
+    }
+}
+
+fn default_locate_confidence() -> f32 {
+    0.9
+}
+
+fn default_locate_limit() -> u32 {
+    1
+}
+
+/// Where a template image matched, in input coordinates
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageMatch {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    /// Where to click the match
+    pub center: Point,
+    /// How well the screen matches the template, from 0 to 1
+    pub confidence: f32,
+}
+
+/// Recognized text and its bounds in input coordinates
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TextSpan {
+    pub text: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    /// How sure the recognizer is, from 0 to 100
+    pub confidence: f32,
+}
+
+/// A rectangle in screenshot pixels
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ClickInput {
+    pub x: u32,
+    pub y: u32,
+    #[serde(default)]
+    pub button: MouseButton,
+    /// Keys held for the click, e.g. ["ctrl"] to add to a selection
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub modifiers: Vec<String>,
+    /// Color the target pixel should have, e.g. "#1a73e8". If it doesn't, the UI has moved
+    /// since the client looked and the click fails with a stale_target error instead
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected_pixel: Option<String>,
+    /// Text the target should show, e.g. "Submit", checked the same way by reading the area
+    /// around it. Recognition mistakes are allowed for, as by find_text's fuzzy mode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected_text: Option<String>,
+    /// Monitor the coordinates are relative to, input coordinates if unset
+    #[serde(default, alias = "display", skip_serializing_if = "Option::is_none")]
+    pub monitor: Option<MonitorSelector>,
+}
+
+/// An "#rrggbb" color as its red, green and blue channels
+pub fn parse_hex_color(color: &str) -> Result<[u8; 3], String> {
+    let invalid = || format!("Invalid color {}, expected #rrggbb", color);
+    let hex = color.strip_prefix('#').unwrap_or(color);
+    if hex.len() != 6 || !hex.is_ascii() {
+        return Err(invalid());
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| invalid());
+    Ok([channel(0)?, channel(2)?, channel(4)?])
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct DragPathInput {
+    pub points: Vec<Point>,
+    /// Button held down along the path
+    #[serde(default)]
+    pub button: MouseButton,
+    /// Monitor the coordinates are relative to, input coordinates if unset
+    #[serde(default, alias = "display", skip_serializing_if = "Option::is_none")]
+    pub monitor: Option<MonitorSelector>,
+}
+
+impl DragPathInput {
+    /// About how long the drag takes, its segments are interpolated 10px every 10ms
+    pub fn duration_ms(&self) -> u64 {
+        let length: f64 = self
+            .points
+            .windows(2)
+            .map(|segment| {
+                let dx = segment[1].x as f64 - segment[0].x as f64;
+                let dy = segment[1].y as f64 - segment[0].y as f64;
+                dx.hypot(dy)
+            })
+            .sum();
+        length.round() as u64
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct WaitInput {
+    pub duration_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct HoverInput {
+    pub x: u32,
+    pub y: u32,
+    /// How long the cursor stays at the point
+    #[serde(default)]
+    pub duration_ms: Option<u64>,
+    /// Monitor the coordinates are relative to, input coordinates if unset
+    #[serde(default, alias = "display", skip_serializing_if = "Option::is_none")]
+    pub monitor: Option<MonitorSelector>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct TypeTextInput {
     pub text: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct KeyPressInput {
+    /// A key or a combination joined with "+", e.g. "ctrl+shift+t", as listed by /v1/keys
     pub key: String,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Point {
+    pub x: u32,
+    pub y: u32,
+}
+
+/// A point on the desktop, negative left of or above the primary monitor
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DesktopPoint {
+    pub x: i32,
+    pub y: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SwipeInput {
+    pub start: Point,
+    pub end: Point,
+    /// How long the finger takes to travel from start to end
+    #[serde(default)]
+    pub duration_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ScrollAxis {
+    Vertical,
+    Horizontal,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ScrollInput {
+    pub axis: ScrollAxis,
+    /// Wheel clicks, positive scrolls down or right, negative up or left
+    pub amount: i32,
+    /// Keys held while scrolling, e.g. ["ctrl"] to zoom or ["shift"] to pan sideways
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub modifiers: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SmoothScrollInput {
+    pub axis: ScrollAxis,
+    /// Distance, positive scrolls down or right, negative up or left
+    pub pixels: i32,
+    /// How long the scroll takes from start to end
+    #[serde(default)]
+    pub duration_ms: Option<u64>,
+    /// Keys held while scrolling, e.g. ["ctrl"] to zoom or ["shift"] to pan sideways
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub modifiers: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ScrollDirection {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl ScrollDirection {
+    /// The axis and the sign of wheel clicks going this way
+    pub fn axis(self) -> (ScrollAxis, i32) {
+        match self {
+            ScrollDirection::Up => (ScrollAxis::Vertical, -1),
+            ScrollDirection::Down => (ScrollAxis::Vertical, 1),
+            ScrollDirection::Left => (ScrollAxis::Horizontal, -1),
+            ScrollDirection::Right => (ScrollAxis::Horizontal, 1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct KineticScrollInput {
+    /// Where to scroll, the cursor is moved there first
+    pub x: u32,
+    pub y: u32,
+    /// Speed of the flick in pixels per second, the distance is about a third of it
+    pub velocity: u32,
+    pub direction: ScrollDirection,
+}
+
+/// A single sample of a pen stroke, pressure is normalized to 0.0..=1.0
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PenPoint {
+    pub x: u32,
+    pub y: u32,
+    pub pressure: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PenInput {
+    /// The stroke, the pen touches down at the first point and lifts at the last
+    pub points: Vec<PenPoint>,
+}
+
 /// Output data produced by actions that return information
 /// Only certain actions (Screenshot, CursorPosition) produce output
 /// NoData ActionOutput is used for actions that don't produce output instead of None
@@ -52,13 +1122,180 @@ pub struct KeyPressInput {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(untagged)]
 pub enum ActionOutput {
-    Screenshot { image: String },
-    CursorPosition { x: u32, y: u32 },
+    /// The primary monitor, with the geometry needed to map its pixels back to input
+    /// coordinates: input position = monitor position + pixel position / image_scale
+    Screenshot {
+        /// Left out when the screen is unchanged
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        image: String,
+        #[serde(default)]
+        format: ScreenshotFormat,
+        width: u32,
+        height: u32,
+        monitor_id: u32,
+        /// Top left corner of the monitor on the desktop
+        x: i32,
+        y: i32,
+        scale_factor: f32,
+        /// Image pixels per screen pixel, below 1 when the image was shrunk to a max size
+        #[serde(default = "full_scale")]
+        image_scale: f64,
+        captured_at: DateTime<Utc>,
+        /// Identifies the frame, pass it back as previous_hash to skip identical frames
+        #[serde(default)]
+        hash: String,
+        /// The frame matched previous_hash, so no image was sent
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        unchanged: bool,
+    },
+    /// Where the cursor is on the desktop, negative left of or above the primary monitor
+    CursorPosition {
+        x: i32,
+        y: i32,
+    },
+    Burst {
+        frames: Vec<BurstFrame>,
+    },
+    Text {
+        spans: Vec<TextSpan>,
+        /// Base64 PNG of the image read with the spans drawn on it, when asked for with debug
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        debug_image: Option<String>,
+    },
+    /// The best match for a find_text query, `center` is where to click it
+    TextMatch {
+        span: TextSpan,
+        center: Point,
+        /// How well the text matches, from 0 to 1
+        score: f32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        debug_image: Option<String>,
+    },
+    /// Matches of a locate_image template, the best first
+    ImageMatches {
+        matches: Vec<ImageMatch>,
+    },
+    /// Top-level windows, the frontmost first
+    Windows {
+        windows: Vec<crate::observe::ListedWindow>,
+    },
+    /// The element a click_element action clicked, at `center`
+    Element {
+        element: crate::accessibility::AccessibleElement,
+        center: DesktopPoint,
+    },
     NoData, // Used for actions that don't produce output
 }
 
+impl ActionOutput {
+    /// The output with positions on the screen converted to the pixels of screenshots scaled
+    /// by `scale`, the inverse of `Action::in_screen_space`. Images are already captured at
+    /// that scale
+    pub fn in_model_space(self, scale: f64) -> ActionOutput {
+        let to_model = |value: i32| (value as f64 * scale).round() as i32;
+        let size = |value: u32| ((value as f64 * scale).round() as u32).max(1);
+        let point = |point: Point| Point {
+            x: (point.x as f64 * scale).round() as u32,
+            y: (point.y as f64 * scale).round() as u32,
+        };
+        let span = |span: TextSpan| TextSpan {
+            x: to_model(span.x),
+            y: to_model(span.y),
+            width: size(span.width),
+            height: size(span.height),
+            ..span
+        };
+        match self {
+            ActionOutput::CursorPosition { x, y } => ActionOutput::CursorPosition {
+                x: to_model(x),
+                y: to_model(y),
+            },
+            ActionOutput::Text { spans, debug_image } => ActionOutput::Text {
+                spans: spans.into_iter().map(span).collect(),
+                debug_image,
+            },
+            ActionOutput::TextMatch {
+                span: text,
+                center,
+                score,
+                debug_image,
+            } => ActionOutput::TextMatch {
+                span: span(text),
+                center: point(center),
+                score,
+                debug_image,
+            },
+            ActionOutput::ImageMatches { matches } => ActionOutput::ImageMatches {
+                matches: matches
+                    .into_iter()
+                    .map(|found| ImageMatch {
+                        x: to_model(found.x),
+                        y: to_model(found.y),
+                        width: size(found.width),
+                        height: size(found.height),
+                        center: point(found.center),
+                        ..found
+                    })
+                    .collect(),
+            },
+            ActionOutput::Element {
+                mut element,
+                center,
+            } => {
+                let bounds = &mut element.bounds;
+                (bounds.x, bounds.y) = (to_model(bounds.x), to_model(bounds.y));
+                (bounds.width, bounds.height) = (size(bounds.width), size(bounds.height));
+                ActionOutput::Element {
+                    element,
+                    center: DesktopPoint {
+                        x: to_model(center.x),
+                        y: to_model(center.y),
+                    },
+                }
+            }
+            output => output,
+        }
+    }
+
+    /// The output without debug images, which are only for the client that asked
+    pub fn without_debug_image(self) -> ActionOutput {
+        match self {
+            ActionOutput::Text { spans, .. } => ActionOutput::Text {
+                spans,
+                debug_image: None,
+            },
+            ActionOutput::TextMatch {
+                span,
+                center,
+                score,
+                ..
+            } => ActionOutput::TextMatch {
+                span,
+                center,
+                score,
+                debug_image: None,
+            },
+            output => output,
+        }
+    }
+}
+
+fn full_scale() -> f64 {
+    1.0
+}
+
+/// One frame of a burst
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BurstFrame {
+    /// Base64 encoded PNG
+    pub image: String,
+    pub width: u32,
+    pub height: u32,
+    pub captured_at: DateTime<Utc>,
+}
+
 /// Represents possible errors that can occur during action execution
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Clone)]
 pub enum ActionError {
     /// Action took too long to complete
     Timeout,
@@ -68,6 +1305,30 @@ pub enum ActionError {
     InvalidInput(String),
     /// Internal queue communication error
     ChannelError(String),
+    /// Action was refused by an approver or policy
+    PermissionDenied(String),
+    /// The run the action belongs to has used up one of its budgets
+    BudgetExceeded(String),
+    /// Too many actions are already waiting in the queue for this run
+    QueueFull(String),
+    /// A pacing rule doesn't allow another action of this type yet
+    RateLimited(String),
+    /// What the action looked for isn't on the screen
+    NotFound(String),
+    /// The display server can't be reached, e.g. while the user is logged out
+    DisplayUnavailable(String),
+    /// The screen no longer shows what the action expected at its target, so it didn't run.
+    /// `crop` is a base64 PNG of the area around the target as it looks now
+    StaleTarget { message: String, crop: String },
+    /// The server is restarting and took no more actions, send it again once it's back
+    Restarting(String),
+    /// Another action of the batch was refused, so none of it ran
+    NotRun(String),
+    /// A service the action needs besides the display, like the accessibility bus, can't be
+    /// reached
+    ServiceUnavailable(String),
+    /// The action isn't supported on this platform
+    Unsupported(String),
 }
 
 // Custom serialization implementation for ActionError
@@ -77,7 +1338,12 @@ impl serde::Serialize for ActionError {
         S: serde::Serializer,
     {
         use serde::ser::SerializeStruct;
-        let mut state = serializer.serialize_struct("ActionError", 2)?;
+        let fields = if let ActionError::StaleTarget { .. } = self {
+            3
+        } else {
+            2
+        };
+        let mut state = serializer.serialize_struct("ActionError", fields)?;
 
         // Convert the enum variant to a string for the type field
         let (error_type, message) = match self {
@@ -85,21 +1351,174 @@ impl serde::Serialize for ActionError {
             ActionError::ExecutionFailed(msg) => ("execution_failed", msg.clone()),
             ActionError::InvalidInput(msg) => ("invalid_input", msg.clone()),
             ActionError::ChannelError(msg) => ("channel_error", msg.clone()),
+            ActionError::PermissionDenied(msg) => ("permission_denied", msg.clone()),
+            ActionError::BudgetExceeded(msg) => ("budget_exceeded", msg.clone()),
+            ActionError::QueueFull(msg) => ("queue_full", msg.clone()),
+            ActionError::RateLimited(msg) => ("rate_limited", msg.clone()),
+            ActionError::NotFound(msg) => ("not_found", msg.clone()),
+            ActionError::DisplayUnavailable(msg) => ("display_unavailable", msg.clone()),
+            ActionError::StaleTarget { message, .. } => ("stale_target", message.clone()),
+            ActionError::Restarting(msg) => ("restarting", msg.clone()),
+            ActionError::NotRun(msg) => ("not_run", msg.clone()),
+            ActionError::ServiceUnavailable(msg) => ("service_unavailable", msg.clone()),
+            ActionError::Unsupported(msg) => ("unsupported", msg.clone()),
         };
 
         state.serialize_field("type", error_type)?;
         state.serialize_field("message", &message)?;
+        if let ActionError::StaleTarget { crop, .. } = self {
+            state.serialize_field("crop", crop)?;
+        }
 
         state.end()
     }
 }
 
+// Reads back the shape ActionError is serialized to, for the run history kept in the journal
+impl<'de> Deserialize<'de> for ActionError {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct Fields {
+            #[serde(rename = "type")]
+            error_type: String,
+            message: String,
+            #[serde(default)]
+            crop: String,
+        }
+
+        let Fields {
+            error_type,
+            message,
+            crop,
+        } = Fields::deserialize(deserializer)?;
+        Ok(match error_type.as_str() {
+            "timeout" => ActionError::Timeout,
+            "execution_failed" => ActionError::ExecutionFailed(message),
+            "invalid_input" => ActionError::InvalidInput(message),
+            "channel_error" => ActionError::ChannelError(message),
+            "permission_denied" => ActionError::PermissionDenied(message),
+            "budget_exceeded" => ActionError::BudgetExceeded(message),
+            "queue_full" => ActionError::QueueFull(message),
+            "rate_limited" => ActionError::RateLimited(message),
+            "not_found" => ActionError::NotFound(message),
+            "display_unavailable" => ActionError::DisplayUnavailable(message),
+            "stale_target" => ActionError::StaleTarget { message, crop },
+            "restarting" => ActionError::Restarting(message),
+            "not_run" => ActionError::NotRun(message),
+            "service_unavailable" => ActionError::ServiceUnavailable(message),
+            "unsupported" => ActionError::Unsupported(message),
+            other => {
+                return Err(serde::de::Error::unknown_variant(
+                    other,
+                    &[
+                        "timeout",
+                        "execution_failed",
+                        "invalid_input",
+                        "channel_error",
+                        "permission_denied",
+                        "budget_exceeded",
+                        "queue_full",
+                        "rate_limited",
+                        "not_found",
+                        "display_unavailable",
+                        "stale_target",
+                        "restarting",
+                        "not_run",
+                        "service_unavailable",
+                        "unsupported",
+                    ],
+                ))
+            }
+        })
+    }
+}
+
 /// Incoming message requesting an action to be performed
 /// Contains a unique ID and the requested action
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct ActionRequest {
     pub id: String,
     pub action: Action,
+    /// Run the action belongs to, the run must be open
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_id: Option<String>,
+    /// Free-form task label used to group actions within a run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub task_id: Option<String>,
+    /// Free-form details of who issued the action, e.g. model name, tool call id or plan step,
+    /// echoed in the response, monitor events and run history
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<serde_json::Map<String, serde_json::Value>>,
+    /// How long the action may run before it fails with a timeout, instead of the usual limit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+    /// Whether the rest of a batch still runs when this action times out
+    #[serde(default, skip_serializing_if = "OnTimeout::is_abort")]
+    pub on_timeout: OnTimeout,
+}
+
+/// What a batch does after one of its actions runs out of time
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OnTimeout {
+    /// Skip the rest of the batch, as for any other failure
+    #[default]
+    Abort,
+    /// Run the rest of the batch anyway, e.g. after an optional wait
+    Continue,
+}
+
+impl OnTimeout {
+    fn is_abort(&self) -> bool {
+        *self == OnTimeout::Abort
+    }
+}
+
+impl ActionRequest {
+    pub fn check_timeout(&self) -> Result<(), ActionError> {
+        match self.timeout_ms {
+            Some(timeout_ms) if !(1..=MAX_REQUEST_TIMEOUT_MS).contains(&timeout_ms) => {
+                Err(ActionError::InvalidInput(format!(
+                    "timeout_ms must be between 1 and {}, got {}",
+                    MAX_REQUEST_TIMEOUT_MS, timeout_ms
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn check_metadata(&self) -> Result<(), ActionError> {
+        let Some(metadata) = &self.metadata else {
+            return Ok(());
+        };
+        let size = serde_json::to_string(metadata).map_or(0, |json| json.len());
+        if size > MAX_METADATA_BYTES {
+            return Err(ActionError::InvalidInput(format!(
+                "Metadata must be at most {} bytes, got {} bytes",
+                MAX_METADATA_BYTES, size
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl ActionRequest {
+    /// Creates a request that is not attached to any run
+    pub fn new(id: String, action: Action) -> Self {
+        Self {
+            id,
+            action,
+            run_id: None,
+            task_id: None,
+            metadata: None,
+            timeout_ms: None,
+            on_timeout: OnTimeout::Abort,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -112,7 +1531,7 @@ pub enum ActionResponseStatus {
 /// Outgoing message containing the result of an action
 /// Includes request tracking, timing, status, and any output or error information
 // Base action response type - for websocket monitoring
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ActionResponse {
     pub id: Uuid,
     pub request_id: String,
@@ -123,6 +1542,46 @@ pub struct ActionResponse {
     pub data: Option<ActionOutput>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub error: Option<ActionError>,
+    /// Only set for actions that reached the queue
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timing: Option<ActionTiming>,
+    /// The request's metadata, echoed back
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<serde_json::Map<String, serde_json::Value>>,
+    /// Window that had focus as the action ran, only looked up with VALK_RECORD_FOREGROUND
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub foreground: Option<ForegroundApp>,
+    /// The screen right after the action failed to execute, only taken with
+    /// VALK_FAILURE_SCREENSHOTS
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failure_screenshot: Option<Box<FailureScreenshot>>,
+    /// Where the worker found the cursor right after the action, for the run's heatmap
+    #[serde(skip)]
+    pub cursor: Option<(i32, i32)>,
+}
+
+/// A scaled down screenshot of the primary monitor
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FailureScreenshot {
+    /// Base64 encoded PNG
+    pub image: String,
+    pub width: u32,
+    pub height: u32,
+    pub captured_at: DateTime<Utc>,
+}
+
+/// The application an action's input went to
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ForegroundApp {
+    pub app_name: String,
+    pub title: String,
+}
+
+/// Where an action spent its time, waiting in the queue (including the action delay) vs running
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default)]
+pub struct ActionTiming {
+    pub queued_ms: u64,
+    pub execution_ms: u64,
 }
 
 impl ActionResponse {
@@ -142,6 +1601,11 @@ impl ActionResponse {
             action,
             data,
             error: None,
+            timing: None,
+            metadata: None,
+            foreground: None,
+            failure_screenshot: None,
+            cursor: None,
         }
     }
 
@@ -155,13 +1619,28 @@ impl ActionResponse {
             action,
             error: Some(error),
             data: None,
+            timing: None,
+            metadata: None,
+            foreground: None,
+            failure_screenshot: None,
+            cursor: None,
         }
     }
 
-    /// Extracts the base response without data
+    /// Echo the metadata of the request this responds to
+    pub fn with_metadata(
+        mut self,
+        metadata: Option<serde_json::Map<String, serde_json::Value>>,
+    ) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Extracts the base response without data or images
     pub fn without_data(&self) -> ActionResponse {
         let mut self_clone = self.clone();
         self_clone.data = None;
+        self_clone.failure_screenshot = None;
         self_clone
     }
 
@@ -174,3 +1653,447 @@ impl ActionResponse {
         }
     }
 }
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    // A 1x1 PNG
+    const TEMPLATE: &str = "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mNkYPhfDwAChwGA60e6kgAAAABJRU5ErkJggg==";
+
+    #[test]
+    fn test_action_type_matches_serialized_tag() {
+        let actions = vec![
+            Action::LeftClick,
+            Action::TripleClick,
+            Action::PasteText {
+                input: TypeTextInput {
+                    text: "hello".to_string(),
+                },
+            },
+            Action::MouseMove {
+                input: MouseMoveInput {
+                    x: 1,
+                    y: 2,
+                    monitor: None,
+                    motion: None,
+                    duration_ms: None,
+                },
+            },
+            Action::Hover {
+                input: HoverInput {
+                    x: 1,
+                    y: 2,
+                    duration_ms: Some(500),
+                    monitor: None,
+                },
+            },
+            Action::ClickElement {
+                input: ClickElementInput {
+                    element_id: Some(":1.42/org/a11y/atspi/accessible/17".to_string()),
+                    role: None,
+                    name: None,
+                    button: MouseButton::Left,
+                },
+            },
+            Action::DragPath {
+                input: DragPathInput {
+                    points: vec![Point { x: 1, y: 2 }, Point { x: 3, y: 4 }],
+                    button: MouseButton::Right,
+                    monitor: None,
+                },
+            },
+            Action::MiddleClickDrag {
+                input: MouseMoveInput {
+                    x: 1,
+                    y: 2,
+                    monitor: None,
+                    motion: None,
+                    duration_ms: None,
+                },
+            },
+            Action::Click {
+                input: ClickInput {
+                    x: 1,
+                    y: 2,
+                    button: MouseButton::Left,
+                    modifiers: vec!["ctrl".to_string()],
+                    expected_pixel: Some("#1a73e8".to_string()),
+                    expected_text: Some("Submit".to_string()),
+                    monitor: None,
+                },
+            },
+            Action::Burst {
+                input: BurstInput {
+                    count: 3,
+                    interval_ms: 100,
+                    region: None,
+                    scale: None,
+                },
+            },
+            Action::Ocr {
+                input: OcrInput {
+                    region: None,
+                    level: TextLevel::Word,
+                    debug: true,
+                },
+            },
+            Action::FindText {
+                input: FindTextInput {
+                    query: "Submit".to_string(),
+                    match_mode: MatchMode::Fuzzy,
+                    region: None,
+                    debug: false,
+                },
+            },
+            Action::LocateImage {
+                input: LocateImageInput {
+                    template: TEMPLATE.to_string(),
+                    confidence: 0.8,
+                    limit: 3,
+                    region: None,
+                },
+            },
+            Action::WaitFor {
+                input: WaitForInput {
+                    text: Some("Login".to_string()),
+                    match_mode: MatchMode::Exact,
+                    image_template: None,
+                    confidence: 0.9,
+                    timeout_ms: 3000,
+                    region: None,
+                },
+            },
+            Action::KeyPress {
+                input: KeyPressInput {
+                    key: "a".to_string(),
+                },
+            },
+            Action::KeyUp {
+                input: KeyPressInput {
+                    key: "ctrl+a".to_string(),
+                },
+            },
+            Action::Screenshot {
+                input: Default::default(),
+            },
+            Action::Tap {
+                input: Point { x: 1, y: 2 },
+            },
+            Action::ListWindows,
+            Action::FocusWindow {
+                input: WindowInput {
+                    id: None,
+                    title_match: Some("Checkout".to_string()),
+                },
+            },
+            Action::CloseWindow {
+                input: WindowInput {
+                    id: Some(7),
+                    title_match: None,
+                },
+            },
+            Action::MinimizeWindow {
+                input: WindowInput {
+                    id: Some(7),
+                    title_match: None,
+                },
+            },
+            Action::SaveCursor,
+            Action::RestoreCursor,
+            Action::Ping,
+            Action::Wait {
+                input: WaitInput { duration_ms: 100 },
+            },
+            Action::Scroll {
+                input: ScrollInput {
+                    axis: ScrollAxis::Horizontal,
+                    amount: -3,
+                    modifiers: Vec::new(),
+                },
+            },
+            Action::SmoothScroll {
+                input: SmoothScrollInput {
+                    axis: ScrollAxis::Vertical,
+                    pixels: 400,
+                    duration_ms: None,
+                    modifiers: Vec::new(),
+                },
+            },
+            Action::KineticScroll {
+                input: KineticScrollInput {
+                    x: 100,
+                    y: 200,
+                    velocity: 2000,
+                    direction: ScrollDirection::Up,
+                },
+            },
+        ];
+
+        for action in actions {
+            let value = serde_json::to_value(&action).unwrap();
+            assert_eq!(value["type"], action.action_type());
+        }
+    }
+
+    #[test]
+    fn test_validate_rejects_out_of_range_input() {
+        let far = Action::MouseMove {
+            input: MouseMoveInput {
+                x: u32::MAX,
+                y: 10,
+                monitor: None,
+                motion: None,
+                duration_ms: None,
+            },
+        };
+        assert!(matches!(far.validate(), Err(ActionError::InvalidInput(_))));
+
+        let slow = Action::Swipe {
+            input: SwipeInput {
+                start: Point { x: 0, y: 0 },
+                end: Point { x: 10, y: 10 },
+                duration_ms: Some(u64::MAX),
+            },
+        };
+        assert!(matches!(slow.validate(), Err(ActionError::InvalidInput(_))));
+
+        let spin = Action::Scroll {
+            input: ScrollInput {
+                axis: ScrollAxis::Vertical,
+                amount: i32::MIN,
+                modifiers: Vec::new(),
+            },
+        };
+        assert!(matches!(spin.validate(), Err(ActionError::InvalidInput(_))));
+
+        let glide = Action::SmoothScroll {
+            input: SmoothScrollInput {
+                axis: ScrollAxis::Vertical,
+                pixels: 100,
+                duration_ms: Some(MAX_GESTURE_DURATION_MS + 1),
+                modifiers: Vec::new(),
+            },
+        };
+        assert!(matches!(
+            glide.validate(),
+            Err(ActionError::InvalidInput(_))
+        ));
+
+        let linger = Action::Hover {
+            input: HoverInput {
+                x: 10,
+                y: 10,
+                duration_ms: Some(MAX_GESTURE_DURATION_MS + 1),
+                monitor: None,
+            },
+        };
+        assert!(matches!(
+            linger.validate(),
+            Err(ActionError::InvalidInput(_))
+        ));
+
+        let dot = Action::DragPath {
+            input: DragPathInput {
+                points: vec![Point { x: 1, y: 2 }],
+                button: MouseButton::Left,
+                monitor: None,
+            },
+        };
+        assert!(matches!(dot.validate(), Err(ActionError::InvalidInput(_))));
+
+        // Few points, but back and forth across the whole coordinate range
+        let zigzag = Action::DragPath {
+            input: DragPathInput {
+                points: vec![
+                    Point { x: 0, y: 0 },
+                    Point { x: 32767, y: 0 },
+                    Point { x: 0, y: 0 },
+                ],
+                button: MouseButton::Left,
+                monitor: None,
+            },
+        };
+        assert!(matches!(
+            zigzag.validate(),
+            Err(ActionError::InvalidInput(_))
+        ));
+
+        let wait_for =
+            |text: Option<&str>, image_template: Option<&str>, timeout_ms| Action::WaitFor {
+                input: WaitForInput {
+                    text: text.map(str::to_string),
+                    match_mode: MatchMode::Contains,
+                    image_template: image_template.map(str::to_string),
+                    confidence: 0.9,
+                    timeout_ms,
+                    region: None,
+                },
+            };
+        assert!(wait_for(Some("Login"), None, 5000).validate().is_ok());
+        assert!(wait_for(None, Some(TEMPLATE), 5000).validate().is_ok());
+        assert!(wait_for(None, None, 5000).validate().is_err());
+        assert!(wait_for(Some("Login"), Some(TEMPLATE), 5000)
+            .validate()
+            .is_err());
+        assert!(wait_for(Some("Login"), None, MAX_WAIT_FOR_MS + 1)
+            .validate()
+            .is_err());
+
+        let focus = |id, title_match: Option<&str>| Action::FocusWindow {
+            input: WindowInput {
+                id,
+                title_match: title_match.map(str::to_string),
+            },
+        };
+        assert!(focus(Some(7), None).validate().is_ok());
+        assert!(focus(None, Some("Checkout")).validate().is_ok());
+        assert!(focus(None, None).validate().is_err());
+        assert!(focus(None, Some(" ")).validate().is_err());
+        assert!(focus(Some(7), Some("Checkout")).validate().is_err());
+
+        let click_element = |element_id: Option<&str>, role: Option<&str>, name: Option<&str>| {
+            Action::ClickElement {
+                input: ClickElementInput {
+                    element_id: element_id.map(str::to_string),
+                    role: role.map(str::to_string),
+                    name: name.map(str::to_string),
+                    button: MouseButton::Left,
+                },
+            }
+        };
+        let id = Some(":1.42/org/a11y/atspi/accessible/17");
+        assert!(click_element(id, None, None).validate().is_ok());
+        assert!(click_element(None, Some("push button"), Some("Save"))
+            .validate()
+            .is_ok());
+        assert!(click_element(None, None, None).validate().is_err());
+        assert!(click_element(None, Some("push button"), None)
+            .validate()
+            .is_err());
+        assert!(click_element(id, Some("push button"), Some("Save"))
+            .validate()
+            .is_err());
+
+        let screenshot = |format, quality| Action::Screenshot {
+            input: ScreenshotInput {
+                format,
+                quality,
+                ..Default::default()
+            },
+        };
+        assert!(screenshot(ScreenshotFormat::Jpeg, Some(70))
+            .validate()
+            .is_ok());
+        assert!(screenshot(ScreenshotFormat::Jpeg, Some(0))
+            .validate()
+            .is_err());
+        assert!(screenshot(ScreenshotFormat::Png, Some(70))
+            .validate()
+            .is_err());
+    }
+
+    // JSON shaped like an action, with a known or made up type and arbitrary input
+    fn action_json() -> impl Strategy<Value = serde_json::Value> {
+        let leaf = prop_oneof![
+            Just(serde_json::Value::Null),
+            any::<bool>().prop_map(serde_json::Value::from),
+            any::<i64>().prop_map(serde_json::Value::from),
+            any::<u32>().prop_map(serde_json::Value::from),
+            any::<f64>().prop_map(serde_json::Value::from),
+            ".*".prop_map(serde_json::Value::from),
+        ];
+        let value = leaf.prop_recursive(3, 24, 6, |inner| {
+            prop_oneof![
+                prop::collection::vec(inner.clone(), 0..6).prop_map(serde_json::Value::from),
+                prop::collection::btree_map(
+                    prop_oneof![
+                        Just("x".to_string()),
+                        Just("y".to_string()),
+                        Just("key".to_string()),
+                        Just("text".to_string()),
+                        Just("start".to_string()),
+                        Just("end".to_string()),
+                        Just("points".to_string()),
+                        Just("pressure".to_string()),
+                        Just("duration_ms".to_string()),
+                        Just("axis".to_string()),
+                        Just("amount".to_string()),
+                        Just("pixels".to_string()),
+                        Just("button".to_string()),
+                        Just("modifiers".to_string()),
+                        Just("expected_pixel".to_string()),
+                        Just("expected_text".to_string()),
+                        Just("count".to_string()),
+                        Just("interval_ms".to_string()),
+                        Just("region".to_string()),
+                        "[a-z_]{1,8}",
+                    ],
+                    inner,
+                    0..6,
+                )
+                .prop_map(|map| serde_json::Value::Object(map.into_iter().collect())),
+            ]
+        });
+        let action_type = prop_oneof![
+            Just("mouse_move"),
+            Just("left_click_drag"),
+            Just("right_click_drag"),
+            Just("middle_click_drag"),
+            Just("hover"),
+            Just("drag_path"),
+            Just("click"),
+            Just("click_element"),
+            Just("burst"),
+            Just("ocr"),
+            Just("find_text"),
+            Just("locate_image"),
+            Just("wait_for"),
+            Just("type_text"),
+            Just("paste_text"),
+            Just("key_press"),
+            Just("key_down"),
+            Just("key_up"),
+            Just("tap"),
+            Just("swipe"),
+            Just("pen"),
+            Just("ping"),
+            Just("list_windows"),
+            Just("focus_window"),
+            Just("close_window"),
+            Just("minimize_window"),
+            Just("wait"),
+            Just("scroll"),
+            Just("smooth_scroll"),
+            Just("kinetic_scroll"),
+            Just("no_such_action"),
+        ];
+        (action_type, value).prop_map(
+            |(action_type, input)| serde_json::json!({"type": action_type, "input": input}),
+        )
+    }
+
+    proptest! {
+        #[test]
+        fn prop_arbitrary_action_json_never_panics(json in action_json()) {
+            if let Ok(action) = serde_json::from_value::<Action>(json) {
+                if action.validate().is_ok() {
+                    // Anything that passes validation must round trip as the same action type
+                    let value = serde_json::to_value(&action).unwrap();
+                    prop_assert_eq!(&value["type"], action.action_type());
+                }
+            }
+        }
+
+        #[test]
+        fn prop_validated_coordinates_fit_on_screen(x in any::<u32>(), y in any::<u32>()) {
+            let action = Action::Tap { input: Point { x, y } };
+            prop_assert_eq!(
+                action.validate().is_ok(),
+                x <= MAX_COORDINATE && y <= MAX_COORDINATE
+            );
+        }
+    }
+}
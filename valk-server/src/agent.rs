@@ -0,0 +1,593 @@
+//! A built-in agent for simple tasks, `POST /v1/agent/run` with a goal.
+//!
+//! The loop runs on the server: take a screenshot, ask the configured model what to do next,
+//! run that action through the queue like any client's, and repeat until the model says the
+//! goal is reached or the step limit runs out. Each step is published on the monitor stream.
+//! The model answers with a JSON object holding either the next action, in the same shape as
+//! the `action` of `POST /v1/action`, or a summary when it is done, so any model behind an
+//! Anthropic or OpenAI compatible API can drive it.
+
+use axum::{
+    extract::{self, rejection::JsonRejection},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::action_queue::SharedQueue;
+use crate::action_types::{Action, ActionOutput, ActionRequest, OnTimeout, ScreenshotInput};
+use crate::config::{Config, ModelApi};
+use crate::monitor::MonitorEventPayload;
+use crate::proxy;
+use crate::tool_schema::action_tools;
+use crate::AppState;
+
+/// How long a single model call may take
+const MODEL_TIMEOUT: Duration = Duration::from_secs(120);
+const MAX_REPLY_TOKENS: u32 = 1024;
+/// Finished runs kept for `GET /v1/agent/runs/{id}`, the oldest are forgotten first
+const MAX_FINISHED_RUNS: usize = 50;
+
+const PROMPT_START: &str = "You operate a computer to reach a goal. Each turn you see the \
+current screen and the steps taken so far, and answer with a single JSON object and nothing \
+else. To act, answer {\"thought\": \"...\", \"action\": ACTION} where ACTION is \
+{\"type\": NAME, \"input\": INPUT} for one of the actions below, with INPUT matching the \
+action's schema and left out when the action takes none. Coordinates are screen pixels.";
+
+const PROMPT_END: &str = "When the goal is reached, or can't be, answer \
+{\"thought\": \"...\", \"done\": \"summary\"}.";
+
+/// Actions the agent offers the model, each with an example of it
+const AGENT_ACTIONS: &[(&str, &str)] = &[
+    (
+        "mouse_move",
+        r#"{"type": "mouse_move", "input": {"x": 640, "y": 360}}"#,
+    ),
+    ("left_click", r#"{"type": "left_click"}"#),
+    ("double_click", r#"{"type": "double_click"}"#),
+    ("right_click", r#"{"type": "right_click"}"#),
+    (
+        "type_text",
+        r#"{"type": "type_text", "input": {"text": "hello"}}"#,
+    ),
+    (
+        "key_press",
+        r#"{"type": "key_press", "input": {"key": "ctrl+s"}}"#,
+    ),
+    (
+        "scroll",
+        r#"{"type": "scroll", "input": {"axis": "vertical", "amount": 3}}"#,
+    ),
+    (
+        "wait",
+        r#"{"type": "wait", "input": {"duration_ms": 1000}}"#,
+    ),
+];
+
+// The action list is generated from the same schemas as /v1/tool_schema, so the model is
+// never told about input the actions don't take
+fn system_prompt() -> String {
+    let tools = action_tools();
+    let mut prompt = format!("{}\n\n", PROMPT_START);
+    for (name, example) in AGENT_ACTIONS {
+        let Some(tool) = tools.iter().find(|tool| tool.name == *name) else {
+            continue;
+        };
+        prompt.push_str(&format!(
+            "- {}: {}. Input schema: {}. Example: {}\n",
+            tool.name, tool.description, tool.parameters, example
+        ));
+    }
+    prompt.push('\n');
+    prompt.push_str(PROMPT_END);
+    prompt
+}
+
+/// What the model sees on each turn
+pub struct Prompt {
+    pub system: String,
+    pub text: String,
+    /// Base64 encoded PNG of the screen
+    pub screenshot: String,
+}
+
+/// A language model the agent asks for its next step
+pub trait ModelBackend: Send + Sync + 'static {
+    /// The model's reply text, blocking until it arrives
+    fn complete(&self, prompt: &Prompt) -> Result<String, String>;
+}
+
+/// The backend VALK_AGENT_API and VALK_AGENT_MODEL configure, None when no model is set
+pub fn configured_backend(config: &Config) -> Option<Arc<dyn ModelBackend>> {
+    let model = config.agent_model.clone()?;
+    let client = ModelClient {
+        url: config
+            .agent_url
+            .clone()
+            .unwrap_or_else(|| match config.agent_api {
+                ModelApi::Anthropic => "https://api.anthropic.com".to_string(),
+                ModelApi::Openai => "https://api.openai.com".to_string(),
+            }),
+        model,
+        api_key: config.agent_api_key.clone(),
+        config: config.clone(),
+    };
+    Some(match config.agent_api {
+        ModelApi::Anthropic => Arc::new(AnthropicMessages(client)),
+        ModelApi::Openai => Arc::new(OpenAiChat(client)),
+    })
+}
+
+struct ModelClient {
+    url: String,
+    model: String,
+    api_key: Option<String>,
+    config: Config,
+}
+
+impl ModelClient {
+    fn post(&self, path: &str, headers: &[(&str, String)], body: Value) -> Result<Value, String> {
+        let url = format!("{}{}", self.url.trim_end_matches('/'), path);
+        let mut request = proxy::agent(&self.config, &url)?
+            .timeout(MODEL_TIMEOUT)
+            .build()
+            .post(&url)
+            .set("Content-Type", "application/json");
+        for (name, value) in headers {
+            request = request.set(name, value);
+        }
+        let reply = request
+            .send_string(&body.to_string())
+            .map_err(|e| format!("Model request failed: {}", e))?
+            .into_string()
+            .map_err(|e| format!("Model request failed: {}", e))?;
+        serde_json::from_str(&reply).map_err(|e| format!("Invalid model reply: {}", e))
+    }
+}
+
+/// Anthropic's Messages API
+struct AnthropicMessages(ModelClient);
+
+impl ModelBackend for AnthropicMessages {
+    fn complete(&self, prompt: &Prompt) -> Result<String, String> {
+        let mut headers = vec![("anthropic-version", "2023-06-01".to_string())];
+        if let Some(key) = &self.0.api_key {
+            headers.push(("x-api-key", key.clone()));
+        }
+        let body = json!({
+            "model": self.0.model,
+            "max_tokens": MAX_REPLY_TOKENS,
+            "system": prompt.system,
+            "messages": [{
+                "role": "user",
+                "content": [
+                    {
+                        "type": "image",
+                        "source": {"type": "base64", "media_type": "image/png", "data": prompt.screenshot},
+                    },
+                    {"type": "text", "text": prompt.text},
+                ],
+            }],
+        });
+        let reply = self.0.post("/v1/messages", &headers, body)?;
+        reply["content"]
+            .as_array()
+            .and_then(|content| content.iter().find_map(|block| block["text"].as_str()))
+            .map(str::to_string)
+            .ok_or_else(|| format!("Model reply has no text: {}", reply))
+    }
+}
+
+/// OpenAI's Chat Completions API, which many other providers and local servers offer too
+struct OpenAiChat(ModelClient);
+
+impl ModelBackend for OpenAiChat {
+    fn complete(&self, prompt: &Prompt) -> Result<String, String> {
+        let headers: Vec<(&str, String)> = self
+            .0
+            .api_key
+            .iter()
+            .map(|key| ("Authorization", format!("Bearer {}", key)))
+            .collect();
+        let body = json!({
+            "model": self.0.model,
+            "max_tokens": MAX_REPLY_TOKENS,
+            "messages": [
+                {"role": "system", "content": prompt.system},
+                {
+                    "role": "user",
+                    "content": [
+                        {"type": "text", "text": prompt.text},
+                        {
+                            "type": "image_url",
+                            "image_url": {"url": format!("data:image/png;base64,{}", prompt.screenshot)},
+                        },
+                    ],
+                },
+            ],
+        });
+        let reply = self.0.post("/v1/chat/completions", &headers, body)?;
+        reply["choices"][0]["message"]["content"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| format!("Model reply has no text: {}", reply))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Decision {
+    #[serde(default)]
+    thought: String,
+    #[serde(default)]
+    action: Option<Action>,
+    #[serde(default)]
+    done: Option<String>,
+}
+
+// Models like to wrap JSON in prose or code fences, so only the outermost object is parsed
+fn parse_decision(reply: &str) -> Result<Decision, String> {
+    let start = reply.find('{');
+    let end = reply.rfind('}');
+    let (Some(start), Some(end)) = (start, end) else {
+        return Err(format!("Model reply has no JSON object: {}", reply));
+    };
+    let decision: Decision = serde_json::from_str(&reply[start..=end])
+        .map_err(|e| format!("Model reply is not a valid step: {}", e))?;
+    if decision.action.is_none() && decision.done.is_none() {
+        return Err("Model reply has neither an action nor done".to_string());
+    }
+    Ok(decision)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AgentStatus {
+    Running,
+    /// The model said it is done
+    Done,
+    /// Stopped through `POST /v1/agent/runs/{id}/stop`
+    Stopped,
+    /// Ran out of steps before the model was done
+    StepLimit,
+    /// The screen couldn't be read or the model couldn't be asked
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AgentStep {
+    pub step: u32,
+    pub thought: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub action: Option<Action>,
+    /// `success`, or the error the action failed with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AgentRun {
+    pub id: String,
+    pub goal: String,
+    pub status: AgentStatus,
+    pub steps: Vec<AgentStep>,
+    /// The model's summary, or why the run ended early
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    pub started_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<DateTime<Utc>>,
+    #[serde(skip)]
+    stop_requested: bool,
+}
+
+/// Agent runs, one at a time since they all share the screen
+#[derive(Default)]
+pub struct AgentRegistry {
+    backend: Option<Arc<dyn ModelBackend>>,
+    runs: Mutex<HashMap<String, AgentRun>>,
+}
+
+impl AgentRegistry {
+    pub fn new(backend: Option<Arc<dyn ModelBackend>>) -> Self {
+        Self {
+            backend,
+            runs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<AgentRun> {
+        self.runs.lock().unwrap().get(id).cloned()
+    }
+
+    fn start(&self, goal: String) -> Result<AgentRun, (StatusCode, String)> {
+        let mut runs = self.runs.lock().unwrap();
+        if let Some(running) = runs.values().find(|run| run.status == AgentStatus::Running) {
+            return Err((
+                StatusCode::CONFLICT,
+                format!("Agent run {} is still running", running.id),
+            ));
+        }
+        // Forget the oldest finished runs
+        while runs.len() >= MAX_FINISHED_RUNS {
+            let Some(oldest) = runs
+                .values()
+                .min_by_key(|run| run.started_at)
+                .map(|run| run.id.clone())
+            else {
+                break;
+            };
+            runs.remove(&oldest);
+        }
+        let run = AgentRun {
+            id: Uuid::new_v4().to_string(),
+            goal,
+            status: AgentStatus::Running,
+            steps: Vec::new(),
+            summary: None,
+            started_at: Utc::now(),
+            finished_at: None,
+            stop_requested: false,
+        };
+        runs.insert(run.id.clone(), run.clone());
+        Ok(run)
+    }
+
+    fn update(&self, id: &str, change: impl FnOnce(&mut AgentRun)) -> Option<AgentRun> {
+        let mut runs = self.runs.lock().unwrap();
+        let run = runs.get_mut(id)?;
+        change(run);
+        Some(run.clone())
+    }
+
+    fn stop_requested(&self, id: &str) -> bool {
+        self.get(id).is_some_and(|run| run.stop_requested)
+    }
+}
+
+fn describe(goal: &str, steps: &[AgentStep], width: u32, height: u32) -> String {
+    let mut text = format!(
+        "Goal: {}\nThe screen is {}x{} pixels.\n",
+        goal, width, height
+    );
+    if steps.is_empty() {
+        text.push_str("No steps taken yet.\n");
+    }
+    for step in steps {
+        let action = step
+            .action
+            .as_ref()
+            .and_then(|action| serde_json::to_string(action).ok())
+            .unwrap_or_default();
+        text.push_str(&format!(
+            "Step {}: {} -> {} ({})\n",
+            step.step,
+            step.thought,
+            action,
+            step.result.as_deref().unwrap_or_default()
+        ));
+    }
+    text.push_str("What is the next step?");
+    text
+}
+
+// Agent actions carry the run they belong to in their metadata, like any client's
+fn request(run_id: &str, step: String, action: Action) -> ActionRequest {
+    let mut metadata = serde_json::Map::new();
+    metadata.insert("agent_run_id".to_string(), json!(run_id));
+    ActionRequest {
+        id: format!("agent-{}-{}", run_id, step),
+        action,
+        run_id: None,
+        task_id: None,
+        metadata: Some(metadata),
+        timeout_ms: None,
+        on_timeout: OnTimeout::Abort,
+    }
+}
+
+// The loop itself, ends the run with the status it stopped at
+async fn drive(queue: SharedQueue, backend: Arc<dyn ModelBackend>, id: String, goal: String) {
+    let agents = queue.agents();
+    let max_steps = queue.config().agent_max_steps;
+    let mut steps: Vec<AgentStep> = Vec::new();
+    let system = system_prompt();
+    let (status, summary) = 'run: {
+        for step in 1..=max_steps {
+            if agents.stop_requested(&id) {
+                break 'run (AgentStatus::Stopped, None);
+            }
+            let screenshot = Action::Screenshot {
+                input: ScreenshotInput::default(),
+            };
+            let response = queue
+                .execute_action(request(&id, format!("observe-{}", step), screenshot))
+                .await;
+            let Some(ActionOutput::Screenshot {
+                image,
+                width,
+                height,
+                ..
+            }) = response.data
+            else {
+                let error = serde_json::to_string(&response.error).unwrap_or_default();
+                break 'run (
+                    AgentStatus::Failed,
+                    Some(format!("Couldn't take a screenshot: {}", error)),
+                );
+            };
+
+            let prompt = Prompt {
+                system: system.clone(),
+                text: describe(&goal, &steps, width, height),
+                screenshot: image,
+            };
+            let backend = backend.clone();
+            let reply = tokio::task::spawn_blocking(move || backend.complete(&prompt))
+                .await
+                .map_err(|e| e.to_string())
+                .and_then(|reply| reply)
+                .and_then(|reply| parse_decision(&reply));
+            let decision = match reply {
+                Ok(decision) => decision,
+                Err(e) => break 'run (AgentStatus::Failed, Some(e)),
+            };
+            if agents.stop_requested(&id) {
+                break 'run (AgentStatus::Stopped, None);
+            }
+
+            let mut agent_step = AgentStep {
+                step,
+                thought: decision.thought,
+                action: decision.action.filter(|_| decision.done.is_none()),
+                result: None,
+            };
+            if let Some(action) = agent_step.action.clone() {
+                let response = queue
+                    .execute_action(request(&id, step.to_string(), action))
+                    .await;
+                agent_step.result = Some(match response.error {
+                    Some(error) => serde_json::to_string(&error).unwrap_or_default(),
+                    None => "success".to_string(),
+                });
+            }
+            agents.update(&id, |run| run.steps.push(agent_step.clone()));
+            queue.send_monitor_event(MonitorEventPayload::AgentStep {
+                agent_run_id: id.clone(),
+                step: agent_step.clone(),
+            });
+            steps.push(agent_step);
+            if let Some(summary) = decision.done {
+                break 'run (AgentStatus::Done, Some(summary));
+            }
+        }
+        (AgentStatus::StepLimit, None)
+    };
+
+    info!("Agent run {} ended: {:?}", id, status);
+    if let Some(run) = agents.update(&id, |run| {
+        run.status = status;
+        run.summary = summary;
+        run.finished_at = Some(Utc::now());
+    }) {
+        queue.send_monitor_event(MonitorEventPayload::AgentFinished(run));
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AgentRunRequest {
+    pub goal: String,
+}
+
+/// `POST /v1/agent/run`, start working towards a goal in the background
+pub async fn run_agent(
+    extract::State(state): extract::State<Arc<AppState>>,
+    request: Result<Json<AgentRunRequest>, JsonRejection>,
+) -> Result<(StatusCode, Json<AgentRun>), (StatusCode, String)> {
+    let Json(request) = request.map_err(|rejection| (rejection.status(), rejection.body_text()))?;
+    let queue = state.action_queue.clone();
+    let backend = queue.agents().backend.clone().ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            "The agent is disabled, set VALK_AGENT_MODEL to enable it".to_string(),
+        )
+    })?;
+    let goal = request.goal.trim().to_string();
+    if goal.is_empty() {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Goal must not be empty".to_string(),
+        ));
+    }
+    let run = queue.agents().start(goal.clone())?;
+    info!("Agent run {} started: {}", run.id, goal);
+    tokio::spawn(drive(queue, backend, run.id.clone(), goal));
+    Ok((StatusCode::ACCEPTED, Json(run)))
+}
+
+/// `GET /v1/agent/runs/{id}`, a run's status and steps so far
+pub async fn get_agent_run(
+    extract::State(state): extract::State<Arc<AppState>>,
+    extract::Path(id): extract::Path<String>,
+) -> Response {
+    match state.action_queue.agents().get(&id) {
+        Some(run) => Json(run).into_response(),
+        None => (StatusCode::NOT_FOUND, format!("Agent run {} not found", id)).into_response(),
+    }
+}
+
+/// `POST /v1/agent/runs/{id}/stop`, end a run before its next step
+pub async fn stop_agent_run(
+    extract::State(state): extract::State<Arc<AppState>>,
+    extract::Path(id): extract::Path<String>,
+) -> Response {
+    let agents = state.action_queue.agents();
+    match agents.update(&id, |run| run.stop_requested = true) {
+        Some(run) => {
+            if run.status == AgentStatus::Running {
+                warn!("Stopping agent run {}", id);
+            }
+            Json(run).into_response()
+        }
+        None => (StatusCode::NOT_FOUND, format!("Agent run {} not found", id)).into_response(),
+    }
+}
+
+/// Replies with the given texts in order, for tests
+#[cfg(test)]
+pub struct ScriptedModel(pub Mutex<std::collections::VecDeque<String>>);
+
+#[cfg(test)]
+impl ModelBackend for ScriptedModel {
+    fn complete(&self, prompt: &Prompt) -> Result<String, String> {
+        assert!(!prompt.screenshot.is_empty());
+        self.0
+            .lock()
+            .unwrap()
+            .pop_front()
+            .ok_or_else(|| "Script ran out".to_string())
+    }
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_decision() {
+        let reply = "Sure!\n```json\n{\"thought\": \"open it\", \"action\": {\"type\": \"mouse_move\", \"input\": {\"x\": 5, \"y\": 6}}}\n```";
+        let decision = parse_decision(reply).unwrap();
+        assert_eq!(decision.thought, "open it");
+        assert!(matches!(decision.action, Some(Action::MouseMove { .. })));
+
+        let decision = parse_decision("{\"done\": \"Saved\"}").unwrap();
+        assert_eq!(decision.done.as_deref(), Some("Saved"));
+        assert!(parse_decision("I can't see anything").is_err());
+        assert!(parse_decision("{\"thought\": \"hmm\"}").is_err());
+        assert!(parse_decision("{\"action\": {\"type\": \"teleport\"}}").is_err());
+    }
+
+    #[test]
+    fn test_prompt_examples_are_actions() {
+        let prompt = system_prompt();
+        for (name, example) in AGENT_ACTIONS {
+            assert!(
+                prompt.contains(&format!("- {}: ", name)),
+                "{} is missing",
+                name
+            );
+            assert!(prompt.contains(example));
+            let action: Action = serde_json::from_str(example)
+                .unwrap_or_else(|e| panic!("{} is not an action: {}", example, e));
+            assert_eq!(action.action_type(), *name);
+        }
+    }
+}
@@ -0,0 +1,250 @@
+use image::{Rgba, RgbaImage};
+
+use crate::action_types::{Point, TextSpan};
+
+pub const HIGHLIGHT: Rgba<u8> = Rgba([255, 0, 64, 255]);
+pub const OUTLINE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+const MARKER_RADIUS: i32 = 12;
+
+// Arrow pointer with its tip in the top left corner, '#' is outline and '.' is fill
+const POINTER: [&str; 17] = [
+    "#",
+    "##",
+    "#.#",
+    "#..#",
+    "#...#",
+    "#....#",
+    "#.....#",
+    "#......#",
+    "#.......#",
+    "#........#",
+    "#.....#####",
+    "#..#..#",
+    "#.# #..#",
+    "##  #..#",
+    "#    #..#",
+    "     #..#",
+    "      ##",
+];
+const POINTER_OUTLINE: Rgba<u8> = Rgba([0, 0, 0, 255]);
+const LINE_THICKNESS: i32 = 3;
+
+// Text boxes by how sure the recognizer was, below 50, below 80 and from 80 up
+const UNSURE: Rgba<u8> = Rgba([230, 40, 40, 255]);
+const FAIRLY_SURE: Rgba<u8> = Rgba([240, 170, 0, 255]);
+const SURE: Rgba<u8> = Rgba([40, 190, 70, 255]);
+/// Height of the bar under a text box, as long as the box at confidence 100
+const CONFIDENCE_BAR: i32 = 4;
+
+fn put_pixel(image: &mut RgbaImage, x: i32, y: i32, color: Rgba<u8>) {
+    if x >= 0 && y >= 0 && (x as u32) < image.width() && (y as u32) < image.height() {
+        image.put_pixel(x as u32, y as u32, color);
+    }
+}
+
+// Square brush so lines stay visible on high resolution screenshots
+fn stamp(image: &mut RgbaImage, x: i32, y: i32, size: i32, color: Rgba<u8>) {
+    let half = size / 2;
+    for dy in -half..=half {
+        for dx in -half..=half {
+            put_pixel(image, x + dx, y + dy, color);
+        }
+    }
+}
+
+/// Draw a straight line using Bresenham's algorithm
+pub fn draw_line(image: &mut RgbaImage, from: Point, to: Point, color: Rgba<u8>) {
+    let (mut x, mut y) = (from.x as i32, from.y as i32);
+    let (x1, y1) = (to.x as i32, to.y as i32);
+    let dx = (x1 - x).abs();
+    let dy = -(y1 - y).abs();
+    let sx = if x < x1 { 1 } else { -1 };
+    let sy = if y < y1 { 1 } else { -1 };
+    let mut err = dx + dy;
+
+    loop {
+        stamp(image, x, y, LINE_THICKNESS, color);
+        if x == x1 && y == y1 {
+            break;
+        }
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            y += sy;
+        }
+    }
+}
+
+/// Draw a path through consecutive points
+pub fn draw_path(image: &mut RgbaImage, points: &[Point], color: Rgba<u8>) {
+    for pair in points.windows(2) {
+        draw_line(image, pair[0], pair[1], color);
+    }
+}
+
+/// Draw a crosshair inside a ring, outlined so it stands out on any background
+pub fn draw_marker(image: &mut RgbaImage, point: Point, color: Rgba<u8>) {
+    let (cx, cy) = (point.x as i32, point.y as i32);
+
+    for (radius, ring_color) in [(MARKER_RADIUS + 1, OUTLINE), (MARKER_RADIUS, color)] {
+        // Midpoint circle
+        let (mut x, mut y, mut err) = (radius, 0, 1 - radius);
+        while x >= y {
+            for (px, py) in [
+                (x, y),
+                (y, x),
+                (-y, x),
+                (-x, y),
+                (-x, -y),
+                (-y, -x),
+                (y, -x),
+                (x, -y),
+            ] {
+                stamp(image, cx + px, cy + py, 2, ring_color);
+            }
+            y += 1;
+            if err < 0 {
+                err += 2 * y + 1;
+            } else {
+                x -= 1;
+                err += 2 * (y - x) + 1;
+            }
+        }
+    }
+
+    for offset in -MARKER_RADIUS / 2..=MARKER_RADIUS / 2 {
+        put_pixel(image, cx + offset, cy, color);
+        put_pixel(image, cx, cy + offset, color);
+    }
+}
+
+fn draw_box(image: &mut RgbaImage, span: &TextSpan, color: Rgba<u8>) {
+    let (left, top) = (span.x.max(0) as u32, span.y.max(0) as u32);
+    let right = (span.x + span.width as i32).max(0) as u32;
+    let bottom = (span.y + span.height as i32).max(0) as u32;
+    let corners = [
+        Point { x: left, y: top },
+        Point { x: right, y: top },
+        Point {
+            x: right,
+            y: bottom,
+        },
+        Point { x: left, y: bottom },
+        Point { x: left, y: top },
+    ];
+    draw_path(image, &corners, color);
+}
+
+/// Outline recognized text, colored by confidence and with a bar under each box as long as
+/// the confidence, so it shows what was read and how surely. `chosen` is highlighted with a
+/// marker at its center, the span a find_text picked
+pub fn draw_text_boxes(image: &mut RgbaImage, spans: &[TextSpan], chosen: Option<&TextSpan>) {
+    for span in spans {
+        let color = match span.confidence {
+            c if c < 50.0 => UNSURE,
+            c if c < 80.0 => FAIRLY_SURE,
+            _ => SURE,
+        };
+        draw_box(image, span, color);
+        let length = (span.width as f32 * span.confidence.clamp(0.0, 100.0) / 100.0) as i32;
+        let top = span.y + span.height as i32 + LINE_THICKNESS;
+        for y in top..top + CONFIDENCE_BAR {
+            for x in span.x..span.x + length {
+                put_pixel(image, x, y, color);
+            }
+        }
+    }
+    if let Some(span) = chosen {
+        draw_box(image, span, HIGHLIGHT);
+        let center = Point {
+            x: (span.x + span.width as i32 / 2).max(0) as u32,
+            y: (span.y + span.height as i32 / 2).max(0) as u32,
+        };
+        draw_marker(image, center, HIGHLIGHT);
+    }
+}
+
+/// Draw a mouse pointer with its tip at the point, since captures don't include the real one
+pub fn draw_pointer(image: &mut RgbaImage, tip: (i32, i32)) {
+    for (dy, row) in POINTER.iter().enumerate() {
+        for (dx, cell) in row.chars().enumerate() {
+            let color = match cell {
+                '#' => POINTER_OUTLINE,
+                '.' => OUTLINE,
+                _ => continue,
+            };
+            put_pixel(image, tip.0 + dx as i32, tip.1 + dy as i32, color);
+        }
+    }
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_draw_line() {
+        let mut image = RgbaImage::new(50, 50);
+        draw_line(
+            &mut image,
+            Point { x: 5, y: 5 },
+            Point { x: 45, y: 45 },
+            HIGHLIGHT,
+        );
+        assert_eq!(*image.get_pixel(5, 5), HIGHLIGHT);
+        assert_eq!(*image.get_pixel(25, 25), HIGHLIGHT);
+        assert_eq!(*image.get_pixel(45, 45), HIGHLIGHT);
+        assert_eq!(*image.get_pixel(45, 5), Rgba([0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn test_draw_marker_near_edge() {
+        // Markers partially off-image must not panic
+        let mut image = RgbaImage::new(20, 20);
+        draw_marker(&mut image, Point { x: 0, y: 0 }, HIGHLIGHT);
+        assert_eq!(*image.get_pixel(0, 0), HIGHLIGHT);
+    }
+
+    #[test]
+    fn test_draw_text_boxes_by_confidence() {
+        let span = |x, confidence| TextSpan {
+            text: "word".to_string(),
+            x,
+            y: 10,
+            width: 20,
+            height: 10,
+            confidence,
+        };
+        let mut image = RgbaImage::new(100, 40);
+        let chosen = span(70, 95.0);
+        draw_text_boxes(
+            &mut image,
+            &[span(5, 30.0), span(40, 90.0), chosen.clone()],
+            Some(&chosen),
+        );
+        assert_eq!(*image.get_pixel(5, 10), UNSURE);
+        assert_eq!(*image.get_pixel(40, 10), SURE);
+        assert_eq!(*image.get_pixel(70, 10), HIGHLIGHT);
+        // The unsure word's bar stops at 30% of its width, the sure one's goes on to 90%
+        assert_eq!(*image.get_pixel(10, 24), UNSURE);
+        assert_eq!(*image.get_pixel(15, 24), Rgba([0, 0, 0, 0]));
+        assert_eq!(*image.get_pixel(56, 24), SURE);
+    }
+
+    #[test]
+    fn test_draw_pointer() {
+        let mut image = RgbaImage::new(20, 20);
+        draw_pointer(&mut image, (4, 2));
+        assert_eq!(*image.get_pixel(4, 2), POINTER_OUTLINE);
+        assert_eq!(*image.get_pixel(5, 5), OUTLINE);
+        assert_eq!(*image.get_pixel(3, 2), Rgba([0, 0, 0, 0]));
+        // Partially off-image must not panic
+        draw_pointer(&mut image, (15, -5));
+    }
+}
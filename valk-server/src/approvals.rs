@@ -0,0 +1,182 @@
+use axum::{
+    extract::{self, rejection::JsonRejection, Path},
+    http::{header, HeaderMap, StatusCode},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+use crate::action_types::ActionRequest;
+use crate::takeover::tokens_match;
+use crate::AppState;
+
+/// An action held until a human approves or rejects it
+#[derive(Debug, Clone, Serialize)]
+pub struct PendingApproval {
+    pub approval_id: String,
+    pub request: ActionRequest,
+    pub requested_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub enum ApprovalDecision {
+    Approved,
+    Rejected(Option<String>),
+}
+
+type PendingEntry = (PendingApproval, oneshot::Sender<ApprovalDecision>);
+
+#[derive(Default)]
+pub struct ApprovalRegistry {
+    pending: Mutex<HashMap<String, PendingEntry>>,
+}
+
+impl ApprovalRegistry {
+    /// Register a request as pending, the receiver resolves once a decision is made
+    pub fn request(
+        &self,
+        request: &ActionRequest,
+    ) -> (PendingApproval, oneshot::Receiver<ApprovalDecision>) {
+        let (tx, rx) = oneshot::channel();
+        let approval = PendingApproval {
+            approval_id: Uuid::new_v4().to_string(),
+            request: request.clone(),
+            requested_at: Utc::now(),
+        };
+        self.pending
+            .lock()
+            .unwrap()
+            .insert(approval.approval_id.clone(), (approval.clone(), tx));
+        (approval, rx)
+    }
+
+    /// Pending approvals, oldest first
+    pub fn list(&self) -> Vec<PendingApproval> {
+        let mut approvals: Vec<PendingApproval> = self
+            .pending
+            .lock()
+            .unwrap()
+            .values()
+            .map(|(approval, _)| approval.clone())
+            .collect();
+        approvals.sort_by_key(|a| a.requested_at);
+        approvals
+    }
+
+    /// Resolve a pending approval, returns false if it doesn't exist (or already timed out)
+    pub fn decide(&self, approval_id: &str, decision: ApprovalDecision) -> bool {
+        match self.pending.lock().unwrap().remove(approval_id) {
+            Some((_, tx)) => tx.send(decision).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Drop a pending approval without a decision
+    pub fn cancel(&self, approval_id: &str) {
+        self.pending.lock().unwrap().remove(approval_id);
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct RejectBody {
+    pub reason: Option<String>,
+}
+
+/// List actions waiting for approval
+pub async fn list_approvals(
+    extract::State(state): extract::State<Arc<AppState>>,
+) -> Json<Vec<PendingApproval>> {
+    Json(state.action_queue.approvals().list())
+}
+
+// Decisions take the supervisor's token, anyone who can reach the API could approve otherwise,
+// the agent whose actions are held included
+fn check_token(state: &AppState, headers: &HeaderMap) -> Result<(), (StatusCode, &'static str)> {
+    let Some(expected) = state.action_queue.config().approver_token.as_deref() else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            "Deciding approvals is disabled, set VALK_APPROVER_TOKEN to enable it",
+        ));
+    };
+    let presented = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if !presented.is_some_and(|token| tokens_match(token, expected)) {
+        return Err((StatusCode::UNAUTHORIZED, "Invalid approver token"));
+    }
+    Ok(())
+}
+
+pub async fn approve(
+    extract::State(state): extract::State<Arc<AppState>>,
+    Path(approval_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<StatusCode, (StatusCode, &'static str)> {
+    check_token(&state, &headers)?;
+    if state
+        .action_queue
+        .approvals()
+        .decide(&approval_id, ApprovalDecision::Approved)
+    {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Ok(StatusCode::NOT_FOUND)
+    }
+}
+
+pub async fn reject(
+    extract::State(state): extract::State<Arc<AppState>>,
+    Path(approval_id): Path<String>,
+    headers: HeaderMap,
+    // The body is optional, so a missing or malformed one just means no reason
+    body: Result<Json<RejectBody>, JsonRejection>,
+) -> Result<StatusCode, (StatusCode, &'static str)> {
+    check_token(&state, &headers)?;
+    let reason = body.ok().and_then(|Json(body)| body.reason);
+    if state
+        .action_queue
+        .approvals()
+        .decide(&approval_id, ApprovalDecision::Rejected(reason))
+    {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Ok(StatusCode::NOT_FOUND)
+    }
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action_types::Action;
+
+    fn request(id: &str) -> ActionRequest {
+        ActionRequest::new(id.to_string(), Action::LeftClick)
+    }
+
+    #[tokio::test]
+    async fn test_decide_resolves_receiver() {
+        let registry = ApprovalRegistry::default();
+        let (approval, rx) = registry.request(&request("first"));
+
+        assert_eq!(registry.list().len(), 1);
+        assert!(registry.decide(&approval.approval_id, ApprovalDecision::Approved));
+        assert!(matches!(rx.await, Ok(ApprovalDecision::Approved)));
+        assert!(registry.list().is_empty());
+    }
+
+    #[test]
+    fn test_decide_unknown_or_cancelled() {
+        let registry = ApprovalRegistry::default();
+        assert!(!registry.decide("missing", ApprovalDecision::Approved));
+
+        let (approval, _rx) = registry.request(&request("cancelled"));
+        registry.cancel(&approval.approval_id);
+        assert!(!registry.decide(&approval.approval_id, ApprovalDecision::Approved));
+    }
+}
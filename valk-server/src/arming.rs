@@ -0,0 +1,86 @@
+//! Safety interlock. With `VALK_ARM_TOKEN` set the server comes up disarmed and only runs
+//! read-only actions, so an agent pointed at the wrong machine can look but not drive it,
+//! until someone arms it on purpose.
+
+use axum::{
+    extract::{self, ConnectInfo, Request},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tracing::info;
+
+use crate::takeover::tokens_match;
+use crate::AppState;
+
+fn check_token(state: &AppState, request: &Request) -> Result<(), (StatusCode, &'static str)> {
+    let Some(expected) = state.action_queue.config().arm_token.as_deref() else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            "Arming is disabled, set VALK_ARM_TOKEN to enable it",
+        ));
+    };
+    let presented = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if !presented.is_some_and(|token| tokens_match(token, expected)) {
+        return Err((StatusCode::UNAUTHORIZED, "Invalid arm token"));
+    }
+    Ok(())
+}
+
+fn status(state: &AppState) -> Json<serde_json::Value> {
+    Json(json!({
+        "armed": state.action_queue.is_armed(),
+        "interlock": state.action_queue.config().arm_token.is_some(),
+    }))
+}
+
+/// `GET /v1/arm`, whether actions that change anything are accepted
+pub async fn arm_status(extract::State(state): extract::State<Arc<AppState>>) -> Response {
+    status(&state).into_response()
+}
+
+/// `POST /v1/arm`, accept every action from now on
+pub async fn arm(
+    extract::State(state): extract::State<Arc<AppState>>,
+    request: Request,
+) -> Response {
+    if let Err(refused) = check_token(&state, &request) {
+        return refused.into_response();
+    }
+    // Loopback means the request was made on the machine, not by a remote agent
+    let local = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .is_some_and(|ConnectInfo(peer)| peer.ip().is_loopback());
+    if state.action_queue.config().arm_local_only && !local {
+        return (
+            StatusCode::FORBIDDEN,
+            "Arming is only accepted from the machine itself",
+        )
+            .into_response();
+    }
+    state.action_queue.set_armed(true);
+    info!("Armed, all actions are accepted");
+    status(&state).into_response()
+}
+
+/// `POST /v1/disarm`, back to read-only actions. Disarming is always safe, so it is taken
+/// from anywhere
+pub async fn disarm(
+    extract::State(state): extract::State<Arc<AppState>>,
+    request: Request,
+) -> Response {
+    if let Err(refused) = check_token(&state, &request) {
+        return refused.into_response();
+    }
+    state.action_queue.set_armed(false);
+    info!("Disarmed, only read-only actions are accepted");
+    status(&state).into_response()
+}
@@ -0,0 +1,167 @@
+//! Optional external authorization, every agent action is POSTed to a policy service
+//! before it is queued, so policy can live in one place across deployments.
+//!
+//! The service is sent `{"action_type": ..., "request": ...}` and answers with
+//! `{"allow": bool, "reason": "..."}`. When it can't be reached in time, or answers with
+//! anything else, the action is refused unless `VALK_AUTHORIZATION_FAIL_OPEN` is set.
+
+use serde::Deserialize;
+use serde_json::json;
+use std::time::Duration;
+use tracing::warn;
+
+use crate::action_types::{ActionError, ActionRequest};
+use crate::config::Config;
+use crate::proxy;
+
+#[derive(Debug, Deserialize)]
+struct Decision {
+    allow: bool,
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+/// Ask the configured authorization service whether a request may run
+pub async fn authorize(config: &Config, request: &ActionRequest) -> Result<(), ActionError> {
+    let Some(url) = config.authorization_url.clone() else {
+        return Ok(());
+    };
+    let timeout = Duration::from_millis(config.authorization_timeout_ms);
+    let body = json!({
+        "action_type": request.action.action_type(),
+        "request": request,
+    });
+
+    // ureq blocks, and its timeout covers connecting, sending and reading the answer
+    let agent = proxy::agent(config, &url);
+    let decision =
+        tokio::task::spawn_blocking(move || ask(agent?, &url, timeout, &body.to_string()))
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|decision| decision);
+    match decision {
+        Ok(Decision { allow: true, .. }) => Ok(()),
+        Ok(Decision {
+            allow: false,
+            reason,
+        }) => Err(ActionError::PermissionDenied(match reason {
+            Some(reason) => format!("Action refused by authorization service: {}", reason),
+            None => "Action refused by authorization service".to_string(),
+        })),
+        Err(e) if config.authorization_fail_open => {
+            warn!("Authorization service failed, allowing action: {}", e);
+            Ok(())
+        }
+        Err(e) => Err(ActionError::PermissionDenied(format!(
+            "Authorization service failed: {}",
+            e
+        ))),
+    }
+}
+
+fn ask(
+    agent: ureq::AgentBuilder,
+    url: &str,
+    timeout: Duration,
+    body: &str,
+) -> Result<Decision, String> {
+    agent
+        .timeout(timeout)
+        .build()
+        .post(url)
+        .set("Content-Type", "application/json")
+        .send_string(body)
+        .map_err(|e| e.to_string())?
+        .into_string()
+        .map_err(|e| e.to_string())
+        .and_then(|body| {
+            serde_json::from_str(&body).map_err(|e| format!("Invalid decision: {}", e))
+        })
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action_types::{Action, ClickInput, MouseButton};
+    use axum::{routing::post, Json, Router};
+    use serde_json::Value;
+
+    // Allows everything but clicks, and takes its time with screenshots
+    async fn spawn_service() -> String {
+        let app = Router::new().route(
+            "/authorize",
+            post(|Json(body): Json<Value>| async move {
+                match body["action_type"].as_str() {
+                    Some("click") => Json(json!({"allow": false, "reason": "no clicking"})),
+                    Some("screenshot") => {
+                        tokio::time::sleep(Duration::from_secs(2)).await;
+                        Json(json!({"allow": true}))
+                    }
+                    _ => Json(json!({"allow": true})),
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("http://{}/authorize", addr)
+    }
+
+    fn request(action: Action) -> ActionRequest {
+        ActionRequest::new("test_authorize".to_string(), action)
+    }
+
+    #[tokio::test]
+    async fn test_authorize() {
+        let mut config = Config {
+            authorization_url: Some(spawn_service().await),
+            authorization_timeout_ms: 200,
+            ..Config::default()
+        };
+
+        assert!(authorize(&config, &request(Action::Ping)).await.is_ok());
+        let click = request(Action::Click {
+            input: ClickInput {
+                x: 1,
+                y: 2,
+                button: MouseButton::Left,
+                modifiers: Vec::new(),
+                expected_pixel: None,
+                expected_text: None,
+                monitor: None,
+            },
+        });
+        match authorize(&config, &click).await {
+            Err(ActionError::PermissionDenied(reason)) => assert!(reason.ends_with("no clicking")),
+            other => panic!("expected a refusal, got {:?}", other),
+        }
+
+        // Too slow to answer, closed by default and open when configured
+        let screenshot = request(Action::Screenshot {
+            input: Default::default(),
+        });
+        assert!(matches!(
+            authorize(&config, &screenshot).await,
+            Err(ActionError::PermissionDenied(_))
+        ));
+        config.authorization_fail_open = true;
+        assert!(authorize(&config, &screenshot).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_service() {
+        // Nothing listens on the discard port
+        let mut config = Config {
+            authorization_url: Some("http://127.0.0.1:9/authorize".to_string()),
+            ..Config::default()
+        };
+        assert!(authorize(&config, &request(Action::Ping)).await.is_err());
+        config.authorization_fail_open = true;
+        assert!(authorize(&config, &request(Action::Ping)).await.is_ok());
+
+        config.authorization_url = None;
+        config.authorization_fail_open = false;
+        assert!(authorize(&config, &request(Action::Ping)).await.is_ok());
+    }
+}
@@ -0,0 +1,274 @@
+//! Outbound bandwidth budget for the monitor stream.
+//!
+//! Screen updates are by far the largest events, so when monitor clients use more than
+//! the budget the stream degrades in steps, first sending fewer screen updates, then
+//! smaller ones, then lossy ones, and relaxes again once usage drops. Other events are
+//! small and always sent, and nothing here touches the responses of the control API.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::{self, FilterType};
+use image::DynamicImage;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::action_types::{ActionError, ScreenshotFormat};
+use crate::capture::encode_image;
+
+/// Usage is measured over windows this long
+const WINDOW: Duration = Duration::from_secs(1);
+
+/// Least time between screen updates once their rate is being limited
+const MIN_FRAME_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Screen updates are downscaled by this factor from Degradation::Scale
+const SCALE_DIVISOR: u32 = 2;
+
+const JPEG_QUALITY: u8 = 40;
+
+/// How far screen updates are cut back, each level includes the ones before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Degradation {
+    Full,
+    FrameRate,
+    Scale,
+    Quality,
+}
+
+impl Degradation {
+    fn escalate(self) -> Self {
+        match self {
+            Degradation::Full => Degradation::FrameRate,
+            Degradation::FrameRate => Degradation::Scale,
+            Degradation::Scale | Degradation::Quality => Degradation::Quality,
+        }
+    }
+
+    fn relax(self) -> Self {
+        match self {
+            Degradation::Full | Degradation::FrameRate => Degradation::Full,
+            Degradation::Scale => Degradation::FrameRate,
+            Degradation::Quality => Degradation::Scale,
+        }
+    }
+}
+
+struct Usage {
+    window_start: Instant,
+    window_bytes: u64,
+    level: Degradation,
+    last_frame: Option<Instant>,
+}
+
+/// Bytes per second shared by every monitor client
+pub struct BandwidthBudget {
+    // None is unlimited
+    limit: Option<u64>,
+    usage: Mutex<Usage>,
+}
+
+impl BandwidthBudget {
+    pub fn new(limit: Option<u64>) -> Self {
+        Self {
+            limit,
+            usage: Mutex::new(Usage {
+                window_start: Instant::now(),
+                window_bytes: 0,
+                level: Degradation::Full,
+                last_frame: None,
+            }),
+        }
+    }
+
+    /// Count bytes written to a monitor client
+    pub fn record(&self, bytes: usize) {
+        self.record_at(bytes, Instant::now());
+    }
+
+    /// Whether a screen update may be sent now, and how degraded
+    pub fn admit_frame(&self) -> Option<Degradation> {
+        self.admit_frame_at(Instant::now())
+    }
+
+    pub fn level(&self) -> Degradation {
+        self.usage.lock().unwrap().level
+    }
+
+    fn record_at(&self, bytes: usize, now: Instant) {
+        if let Some(limit) = self.limit {
+            let mut usage = self.usage.lock().unwrap();
+            Self::roll_window(&mut usage, limit, now);
+            usage.window_bytes += bytes as u64;
+        }
+    }
+
+    fn admit_frame_at(&self, now: Instant) -> Option<Degradation> {
+        let Some(limit) = self.limit else {
+            return Some(Degradation::Full);
+        };
+        let mut usage = self.usage.lock().unwrap();
+        Self::roll_window(&mut usage, limit, now);
+
+        // Hard cap, nothing more goes out until the next window
+        if usage.window_bytes >= limit {
+            return None;
+        }
+        let too_soon = usage
+            .last_frame
+            .is_some_and(|last| now.duration_since(last) < MIN_FRAME_INTERVAL);
+        if usage.level >= Degradation::FrameRate && too_soon {
+            return None;
+        }
+        usage.last_frame = Some(now);
+        Some(usage.level)
+    }
+
+    // Adjust the level from the window that just ended, one step at a time
+    fn roll_window(usage: &mut Usage, limit: u64, now: Instant) {
+        let elapsed = now.duration_since(usage.window_start);
+        if elapsed < WINDOW {
+            return;
+        }
+        usage.level = if elapsed >= WINDOW * 2 {
+            // A whole window went by without traffic
+            usage.level.relax()
+        } else if usage.window_bytes > limit {
+            usage.level.escalate()
+        } else if usage.window_bytes < limit / 2 {
+            usage.level.relax()
+        } else {
+            usage.level
+        };
+        usage.window_start = now;
+        usage.window_bytes = 0;
+    }
+}
+
+/// Re-encode a base64 screen update for the given level, returning it with its format
+pub fn degrade_frame(
+    image: String,
+    format: ScreenshotFormat,
+    level: Degradation,
+) -> Result<(String, ScreenshotFormat), ActionError> {
+    if level < Degradation::Scale {
+        return Ok((image, format));
+    }
+    let failed = || ActionError::ExecutionFailed("Failed to re-encode screen update".to_string());
+    let bytes = BASE64.decode(image).map_err(|_| failed())?;
+    let frame = image::load_from_memory(&bytes)
+        .map_err(|_| failed())?
+        .to_rgba8();
+    let frame = imageops::resize(
+        &frame,
+        (frame.width() / SCALE_DIVISOR).max(1),
+        (frame.height() / SCALE_DIVISOR).max(1),
+        FilterType::Triangle,
+    );
+    if level < Degradation::Quality {
+        return Ok((encode_image(&frame, format, None)?, format));
+    }
+
+    // JPEG has no alpha channel
+    let frame = DynamicImage::ImageRgba8(frame).to_rgb8();
+    let mut jpeg = Vec::new();
+    JpegEncoder::new_with_quality(&mut jpeg, JPEG_QUALITY)
+        .encode_image(&frame)
+        .map_err(|_| failed())?;
+    Ok((BASE64.encode(jpeg), ScreenshotFormat::Jpeg))
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::encode_png;
+    use image::{ImageFormat, RgbaImage};
+
+    #[test]
+    fn test_unlimited_budget_never_degrades() {
+        let budget = BandwidthBudget::new(None);
+        budget.record(usize::MAX / 2);
+        assert_eq!(budget.admit_frame(), Some(Degradation::Full));
+        assert_eq!(budget.admit_frame(), Some(Degradation::Full));
+    }
+
+    #[test]
+    fn test_degrades_in_steps_and_relaxes() {
+        let budget = BandwidthBudget::new(Some(1000));
+        let start = Instant::now();
+        let window = |n: u32| start + WINDOW * n + Duration::from_millis(1);
+
+        // Over budget within a window, frames stop until it ends
+        assert_eq!(budget.admit_frame_at(start), Some(Degradation::Full));
+        budget.record_at(1500, start);
+        assert_eq!(budget.admit_frame_at(start), None);
+
+        // Each window over budget cuts back further
+        let expected = [
+            Degradation::FrameRate,
+            Degradation::Scale,
+            Degradation::Quality,
+            Degradation::Quality,
+        ];
+        for (n, level) in (1..).zip(expected) {
+            assert_eq!(budget.admit_frame_at(window(n)), Some(level));
+            // Frames are rate limited from the first level on
+            assert_eq!(
+                budget.admit_frame_at(window(n) + Duration::from_millis(500)),
+                None
+            );
+            budget.record_at(1500, window(n));
+        }
+
+        // A quiet window relaxes one step
+        budget.record_at(100, window(5));
+        assert_eq!(budget.admit_frame_at(window(6)), Some(Degradation::Scale));
+        // Within half the budget but above it, the level holds
+        budget.record_at(700, window(6));
+        assert_eq!(budget.admit_frame_at(window(7)), Some(Degradation::Scale));
+        // No traffic at all for a window also relaxes
+        assert_eq!(
+            budget.admit_frame_at(window(9)),
+            Some(Degradation::FrameRate)
+        );
+        assert_eq!(budget.level(), Degradation::FrameRate);
+    }
+
+    #[test]
+    fn test_degrade_frame() {
+        let image = encode_png(&RgbaImage::from_pixel(
+            64,
+            48,
+            image::Rgba([10, 20, 30, 255]),
+        ))
+        .unwrap();
+        let decode = |image: &str, format| {
+            image::load_from_memory_with_format(&BASE64.decode(image).unwrap(), format).unwrap()
+        };
+
+        let png = ScreenshotFormat::Png;
+
+        let (full, format) = degrade_frame(image.clone(), png, Degradation::FrameRate).unwrap();
+        assert_eq!((full.as_str(), format), (image.as_str(), png));
+
+        let (scaled, format) = degrade_frame(image.clone(), png, Degradation::Scale).unwrap();
+        assert_eq!(format, png);
+        let scaled = decode(&scaled, ImageFormat::Png);
+        assert_eq!((scaled.width(), scaled.height()), (32, 24));
+
+        let (lossy, format) = degrade_frame(image, png, Degradation::Quality).unwrap();
+        assert_eq!(format, ScreenshotFormat::Jpeg);
+        let lossy = decode(&lossy, ImageFormat::Jpeg);
+        assert_eq!((lossy.width(), lossy.height()), (32, 24));
+
+        // Screenshots taken as JPEG or WebP are scaled in their own format
+        let frame = RgbaImage::from_pixel(64, 48, image::Rgba([10, 20, 30, 255]));
+        let webp = encode_image(&frame, ScreenshotFormat::Webp, None).unwrap();
+        let (scaled, format) =
+            degrade_frame(webp, ScreenshotFormat::Webp, Degradation::Scale).unwrap();
+        assert_eq!(format, ScreenshotFormat::Webp);
+        let scaled = decode(&scaled, ImageFormat::WebP);
+        assert_eq!((scaled.width(), scaled.height()), (32, 24));
+        assert!(degrade_frame("bm90IGFuIGltYWdl".to_string(), png, Degradation::Scale).is_err());
+    }
+}
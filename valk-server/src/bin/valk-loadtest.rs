@@ -0,0 +1,464 @@
+//! Hammers a valk server with a mix of actions and reports throughput, latency
+//! percentiles and how much the server's memory grew while under load.
+//!
+//! ```bash
+//! VALK_INPUT_BACKEND=simulated cargo run --release &
+//! cargo run --release --bin valk-loadtest -- --duration-secs 600 --concurrency 8 \
+//!     --mix ping=6,mouse_move=2,screenshot=1
+//! ```
+
+use std::collections::BTreeMap;
+use std::env;
+use std::io;
+use std::process;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+const USAGE: &str = "usage: valk-loadtest [--url http://127.0.0.1:8255] [--duration-secs 30] \
+[--concurrency 4] [--mix ping=8,mouse_move=1,screenshot=1] [--sample-secs 10] [--open-runs]";
+
+const ACTION_TYPES: &[&str] = &[
+    "ping",
+    "mouse_move",
+    "left_click",
+    "screenshot",
+    "cursor_position",
+    "type_text",
+    "key_press",
+];
+
+struct Options {
+    host: String,
+    duration: Duration,
+    concurrency: usize,
+    mix: Vec<(String, u32)>,
+    sample_interval: Duration,
+    /// Give every worker its own run, so the server schedules them fairly
+    open_runs: bool,
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        host: "127.0.0.1:8255".to_string(),
+        duration: Duration::from_secs(30),
+        concurrency: 4,
+        mix: parse_mix("ping=8,mouse_move=1,screenshot=1")?,
+        sample_interval: Duration::from_secs(10),
+        open_runs: false,
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--open-runs" {
+            options.open_runs = true;
+            continue;
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| format!("Missing value for {}", arg))?;
+        let number = || {
+            value
+                .parse::<u64>()
+                .map_err(|_| format!("Invalid value for {}: {}", arg, value))
+        };
+        match arg.as_str() {
+            "--url" => {
+                let host = value
+                    .strip_prefix("http://")
+                    .ok_or("Only http:// URLs are supported")?;
+                options.host = host.trim_end_matches('/').to_string();
+            }
+            "--duration-secs" => options.duration = Duration::from_secs(number()?),
+            "--concurrency" => options.concurrency = number()?.max(1) as usize,
+            "--mix" => options.mix = parse_mix(value)?,
+            "--sample-secs" => options.sample_interval = Duration::from_secs(number()?.max(1)),
+            _ => return Err(format!("Unknown option: {}", arg)),
+        }
+    }
+    Ok(options)
+}
+
+// Parse weights like "ping=8,screenshot=1"
+fn parse_mix(mix: &str) -> Result<Vec<(String, u32)>, String> {
+    let weights = mix
+        .split(',')
+        .map(|entry| {
+            let (action, weight) = entry.trim().split_once('=').unwrap_or((entry.trim(), "1"));
+            if !ACTION_TYPES.contains(&action) {
+                return Err(format!(
+                    "Unsupported action type {}, expected one of {}",
+                    action,
+                    ACTION_TYPES.join(", ")
+                ));
+            }
+            let weight = weight
+                .parse()
+                .map_err(|_| format!("Invalid weight for {}: {}", action, weight))?;
+            Ok((action.to_string(), weight))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    if weights.iter().all(|(_, weight)| *weight == 0) {
+        return Err("The action mix needs at least one non-zero weight".to_string());
+    }
+    Ok(weights)
+}
+
+/// xorshift64, plenty for picking actions and coordinates
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Rng(seed.max(1))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound.max(1)
+    }
+}
+
+fn pick_action<'a>(mix: &'a [(String, u32)], rng: &mut Rng) -> &'a str {
+    let total: u64 = mix.iter().map(|(_, weight)| *weight as u64).sum();
+    let mut roll = rng.below(total);
+    for (action, weight) in mix {
+        if roll < *weight as u64 {
+            return action;
+        }
+        roll -= *weight as u64;
+    }
+    &mix[0].0
+}
+
+fn action_json(action: &str, rng: &mut Rng) -> String {
+    match action {
+        "mouse_move" => format!(
+            r#"{{"type": "mouse_move", "input": {{"x": {}, "y": {}}}}}"#,
+            rng.below(1000),
+            rng.below(700)
+        ),
+        "type_text" => r#"{"type": "type_text", "input": {"text": "valk"}}"#.to_string(),
+        "key_press" => r#"{"type": "key_press", "input": {"key": "shift"}}"#.to_string(),
+        other => format!(r#"{{"type": "{}"}}"#, other),
+    }
+}
+
+/// A keep-alive HTTP/1.1 connection, just enough of the protocol to talk to valk
+struct Connection {
+    host: String,
+    stream: BufReader<TcpStream>,
+}
+
+impl Connection {
+    async fn open(host: &str) -> io::Result<Self> {
+        Ok(Self {
+            host: host.to_string(),
+            stream: BufReader::new(TcpStream::connect(host).await?),
+        })
+    }
+
+    async fn request(&mut self, method: &str, path: &str, body: &str) -> io::Result<(u16, String)> {
+        let request = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            self.host,
+            body.len(),
+            body
+        );
+        self.stream.get_mut().write_all(request.as_bytes()).await?;
+
+        let mut line = String::new();
+        self.stream.read_line(&mut line).await?;
+        let status = line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|status| status.parse().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Malformed status line"))?;
+
+        let mut content_length = 0;
+        let mut chunked = false;
+        loop {
+            line.clear();
+            if self.stream.read_line(&mut line).await? == 0 || line.trim().is_empty() {
+                break;
+            }
+            let (name, value) = line.split_once(':').unwrap_or((&line, ""));
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.parse().unwrap_or(0);
+            } else if name.eq_ignore_ascii_case("transfer-encoding") {
+                chunked = value.eq_ignore_ascii_case("chunked");
+            }
+        }
+
+        let body = if chunked {
+            self.read_chunked().await?
+        } else {
+            let mut body = vec![0; content_length];
+            self.stream.read_exact(&mut body).await?;
+            body
+        };
+        Ok((status, String::from_utf8_lossy(&body).into_owned()))
+    }
+
+    async fn read_chunked(&mut self) -> io::Result<Vec<u8>> {
+        let mut body = Vec::new();
+        let mut line = String::new();
+        loop {
+            line.clear();
+            self.stream.read_line(&mut line).await?;
+            let size = usize::from_str_radix(line.trim(), 16)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Malformed chunk"))?;
+            let mut chunk = vec![0; size + 2];
+            self.stream.read_exact(&mut chunk).await?;
+            if size == 0 {
+                return Ok(body);
+            }
+            body.extend_from_slice(&chunk[..size]);
+        }
+    }
+}
+
+#[derive(Default)]
+struct Stats {
+    /// Latencies of completed requests per action type, in milliseconds
+    latencies: BTreeMap<String, Vec<f64>>,
+    errors: BTreeMap<String, u64>,
+    /// Error responses by status code, 0 for transport errors
+    statuses: BTreeMap<u16, u64>,
+}
+
+impl Stats {
+    fn merge(&mut self, other: Stats) {
+        for (action, latencies) in other.latencies {
+            self.latencies.entry(action).or_default().extend(latencies);
+        }
+        for (action, errors) in other.errors {
+            *self.errors.entry(action).or_default() += errors;
+        }
+        for (status, count) in other.statuses {
+            *self.statuses.entry(status).or_default() += count;
+        }
+    }
+}
+
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = (p / 100.0 * (sorted.len() - 1) as f64).round() as usize;
+    sorted[rank.min(sorted.len() - 1)]
+}
+
+async fn worker(options: &Options, index: usize, deadline: Instant) -> io::Result<Stats> {
+    let mut stats = Stats::default();
+    let mut connection = Connection::open(&options.host).await?;
+    let seed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_nanos() as u64);
+    let mut rng = Rng::new(seed ^ (index as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+
+    let run_id = if options.open_runs {
+        let body = format!(r#"{{"agent_name": "valk-loadtest-{}"}}"#, index);
+        let (_, run) = connection.request("POST", "/v1/runs", &body).await?;
+        serde_json::from_str::<serde_json::Value>(&run)
+            .ok()
+            .and_then(|run| run["run_id"].as_str().map(str::to_string))
+    } else {
+        None
+    };
+
+    let mut sequence = 0;
+    while Instant::now() < deadline {
+        let action = pick_action(&options.mix, &mut rng);
+        sequence += 1;
+        let run = run_id.as_ref().map_or(String::new(), |run_id| {
+            format!(r#", "run_id": "{}""#, run_id)
+        });
+        let body = format!(
+            r#"{{"id": "loadtest-{}-{}"{}, "action": {}}}"#,
+            index,
+            sequence,
+            run,
+            action_json(action, &mut rng)
+        );
+
+        let started = Instant::now();
+        let result = connection.request("POST", "/v1/action", &body).await;
+        let elapsed = started.elapsed().as_secs_f64() * 1000.0;
+        match result {
+            Ok((200, _)) => stats
+                .latencies
+                .entry(action.to_string())
+                .or_default()
+                .push(elapsed),
+            Ok((status, _)) => {
+                *stats.errors.entry(action.to_string()).or_default() += 1;
+                *stats.statuses.entry(status).or_default() += 1;
+            }
+            Err(_) => {
+                *stats.errors.entry(action.to_string()).or_default() += 1;
+                *stats.statuses.entry(0).or_default() += 1;
+                connection = Connection::open(&options.host).await?;
+            }
+        }
+    }
+    Ok(stats)
+}
+
+// Resident memory of the server, from its /metrics endpoint
+async fn server_memory(host: &str) -> Option<u64> {
+    let mut connection = Connection::open(host).await.ok()?;
+    let (_, metrics) = connection.request("GET", "/metrics", "").await.ok()?;
+    metrics
+        .lines()
+        .find_map(|line| line.strip_prefix("valk_process_resident_memory_bytes "))
+        .and_then(|value| value.trim().parse().ok())
+}
+
+fn mib(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
+fn print_report(stats: &Stats, elapsed: Duration, memory: &[u64]) {
+    let completed: usize = stats.latencies.values().map(Vec::len).sum();
+    let errors: u64 = stats.errors.values().sum();
+    let total = completed as u64 + errors;
+    println!(
+        "\n{} requests in {:.1}s ({:.1}/s), {} errors",
+        total,
+        elapsed.as_secs_f64(),
+        total as f64 / elapsed.as_secs_f64().max(f64::EPSILON),
+        errors
+    );
+    println!(
+        "{:<16} {:>7} {:>7} {:>9} {:>9} {:>9} {:>9}",
+        "action", "ok", "errors", "p50 ms", "p90 ms", "p99 ms", "max ms"
+    );
+    let actions: Vec<&String> = stats.latencies.keys().chain(stats.errors.keys()).collect();
+    let mut printed = Vec::new();
+    for action in actions {
+        if printed.contains(&action) {
+            continue;
+        }
+        printed.push(action);
+        let mut latencies = stats.latencies.get(action).cloned().unwrap_or_default();
+        latencies.sort_by(f64::total_cmp);
+        println!(
+            "{:<16} {:>7} {:>7} {:>9.1} {:>9.1} {:>9.1} {:>9.1}",
+            action,
+            latencies.len(),
+            stats.errors.get(action).copied().unwrap_or(0),
+            percentile(&latencies, 50.0),
+            percentile(&latencies, 90.0),
+            percentile(&latencies, 99.0),
+            latencies.last().copied().unwrap_or(0.0)
+        );
+    }
+    for (status, count) in &stats.statuses {
+        match status {
+            0 => println!("connection errors: {}", count),
+            status => println!("status {}: {}", status, count),
+        }
+    }
+    match (memory.first(), memory.last()) {
+        (Some(first), Some(last)) => println!(
+            "server memory: {:.1} MiB -> {:.1} MiB ({:+.1} MiB)",
+            mib(*first),
+            mib(*last),
+            mib(*last) - mib(*first)
+        ),
+        _ => println!("server memory: not reported"),
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        println!("{}", USAGE);
+        return;
+    }
+    let options = match parse_options(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    let options: &'static Options = Box::leak(Box::new(options));
+
+    let mut memory: Vec<u64> = server_memory(&options.host).await.into_iter().collect();
+    let started = Instant::now();
+    let deadline = started + options.duration;
+    let workers: Vec<_> = (0..options.concurrency)
+        .map(|index| tokio::spawn(worker(options, index, deadline)))
+        .collect();
+
+    // Sample memory while the workers run, so soak tests show growth over time
+    while Instant::now() + options.sample_interval < deadline {
+        tokio::time::sleep(options.sample_interval).await;
+        if let Some(bytes) = server_memory(&options.host).await {
+            println!(
+                "[{:>5.0}s] server memory {:.1} MiB",
+                started.elapsed().as_secs_f64(),
+                mib(bytes)
+            );
+            memory.push(bytes);
+        }
+    }
+
+    let mut stats = Stats::default();
+    for worker in workers {
+        match worker.await {
+            Ok(Ok(worker_stats)) => stats.merge(worker_stats),
+            Ok(Err(e)) => {
+                eprintln!("Failed to connect to {}: {}", options.host, e);
+                process::exit(1);
+            }
+            Err(e) => eprintln!("Worker failed: {}", e),
+        }
+    }
+    let elapsed = started.elapsed();
+    memory.extend(server_memory(&options.host).await);
+    print_report(&stats, elapsed, &memory);
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mix() {
+        assert_eq!(
+            parse_mix("ping=3, screenshot").unwrap(),
+            vec![("ping".to_string(), 3), ("screenshot".to_string(), 1)]
+        );
+        assert!(parse_mix("teleport=1").is_err());
+        assert!(parse_mix("ping=0").is_err());
+    }
+
+    #[test]
+    fn test_pick_action_follows_weights() {
+        let mix = parse_mix("ping=1,screenshot=0,mouse_move=1").unwrap();
+        let mut rng = Rng::new(42);
+        let picks: Vec<&str> = (0..200).map(|_| pick_action(&mix, &mut rng)).collect();
+        assert!(!picks.contains(&"screenshot"));
+        assert!(picks.contains(&"ping") && picks.contains(&"mouse_move"));
+    }
+
+    #[test]
+    fn test_percentile() {
+        let sorted: Vec<f64> = (1..=100).map(f64::from).collect();
+        assert_eq!(percentile(&sorted, 50.0), 51.0);
+        assert_eq!(percentile(&sorted, 99.0), 99.0);
+        assert_eq!(percentile(&[], 90.0), 0.0);
+    }
+}
@@ -0,0 +1,214 @@
+//! Settings tuned for one machine and display layout, kept in the state directory.
+//!
+//! Saved settings are keyed by a fingerprint of the machine id and the monitor layout,
+//! so reinstalling or restarting the server on the same machine picks them up again,
+//! while a different layout (e.g. a docked laptop) starts from the defaults. Settings
+//! given in the environment still win, and changes apply the next time the server starts.
+
+use axum::{extract, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::capture::{fnv1a, ScreenCapture, XcapCapture};
+use crate::config::{Config, DragPathMode, InputBackend};
+use crate::display::DisplayWatch;
+use crate::state::TMP_DIR;
+use crate::AppState;
+
+const CALIBRATION_DIR: &str = "calibration";
+
+/// The settings worth keeping per machine, unset ones keep their defaults
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Calibration {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_backend: Option<InputBackend>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub drag_path_mode: Option<DragPathMode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scroll_pixels_per_click: Option<u32>,
+}
+
+impl Calibration {
+    fn validate(&self) -> Result<(), String> {
+        if self.scroll_pixels_per_click == Some(0) {
+            return Err("scroll_pixels_per_click must be greater than 0".to_string());
+        }
+        Ok(())
+    }
+
+    /// Saved settings replace the defaults, but not settings given in the environment
+    pub fn apply(&self, mut config: Config) -> Config {
+        let unset = |name: &str| env::var(name).is_err();
+        if let Some(backend) = self.input_backend.filter(|_| unset("VALK_INPUT_BACKEND")) {
+            config.input_backend = backend;
+        }
+        if let Some(mode) = self.drag_path_mode.filter(|_| unset("VALK_DRAG_PATH_MODE")) {
+            config.drag_path_mode = mode;
+        }
+        if let Some(pixels) = self
+            .scroll_pixels_per_click
+            .filter(|_| unset("VALK_SCROLL_PIXELS_PER_CLICK"))
+        {
+            config.scroll_pixels_per_click = pixels;
+        }
+        config
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CalibrationResponse {
+    pub fingerprint: String,
+    pub settings: Calibration,
+}
+
+/// Identifies this machine and the monitor layout of `display`, stable across restarts
+pub fn fingerprint(config: &Config, display: &Arc<DisplayWatch>) -> String {
+    let machine = fs::read_to_string("/etc/machine-id")
+        .or_else(|_| fs::read_to_string("/var/lib/dbus/machine-id"))
+        .unwrap_or_default();
+    let layout = match config.input_backend {
+        InputBackend::Simulated => config.simulated_monitors.clone(),
+        _ => XcapCapture::new(display.clone())
+            .displays()
+            .map(|displays| {
+                displays
+                    .iter()
+                    .map(|d| format!("{}x{}+{}+{}", d.width, d.height, d.x, d.y))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            })
+            .unwrap_or_default(),
+    };
+    format!(
+        "{:016x}",
+        fnv1a(format!("{}|{}", machine.trim(), layout).as_bytes())
+    )
+}
+
+fn calibration_path(state_dir: &Path, fingerprint: &str) -> PathBuf {
+    state_dir
+        .join(CALIBRATION_DIR)
+        .join(format!("{}.json", fingerprint))
+}
+
+/// The settings saved for this fingerprint, if any
+pub fn load(state_dir: &Path, fingerprint: &str) -> Result<Option<Calibration>, String> {
+    let path = calibration_path(state_dir, fingerprint);
+    match fs::read_to_string(&path) {
+        Ok(json) => serde_json::from_str(&json)
+            .map(Some)
+            .map_err(|e| format!("Invalid calibration {}: {}", path.display(), e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("Failed to read {}: {}", path.display(), e)),
+    }
+}
+
+// Written to the scratch directory first, so a crash never leaves half a file behind
+fn save(state_dir: &Path, fingerprint: &str, calibration: &Calibration) -> Result<(), String> {
+    let path = calibration_path(state_dir, fingerprint);
+    let tmp = state_dir
+        .join(TMP_DIR)
+        .join(format!("{}.json", fingerprint));
+    let json = serde_json::to_string_pretty(calibration).map_err(|e| e.to_string())?;
+    for dir in [tmp.parent(), path.parent()].into_iter().flatten() {
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+    fs::write(&tmp, json).map_err(|e| format!("Failed to write {}: {}", tmp.display(), e))?;
+    fs::rename(&tmp, &path).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+fn state_dir(state: &AppState) -> Result<PathBuf, (StatusCode, String)> {
+    state
+        .action_queue
+        .config()
+        .state_dir
+        .as_ref()
+        .map(PathBuf::from)
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                "No state directory to keep calibration in".to_string(),
+            )
+        })
+}
+
+/// `GET /v1/calibration`, the settings saved for this machine and display layout
+pub async fn get_calibration(
+    extract::State(state): extract::State<Arc<AppState>>,
+) -> Result<Json<CalibrationResponse>, (StatusCode, String)> {
+    let state_dir = state_dir(&state)?;
+    let fingerprint = fingerprint(state.action_queue.config(), state.action_queue.display());
+    let settings = load(&state_dir, &fingerprint)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?
+        .unwrap_or_default();
+    Ok(Json(CalibrationResponse {
+        fingerprint,
+        settings,
+    }))
+}
+
+/// `PUT /v1/calibration`, replace the saved settings, they apply from the next start
+pub async fn put_calibration(
+    extract::State(state): extract::State<Arc<AppState>>,
+    Json(settings): Json<Calibration>,
+) -> Result<Json<CalibrationResponse>, (StatusCode, String)> {
+    settings
+        .validate()
+        .map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e))?;
+    let state_dir = state_dir(&state)?;
+    let fingerprint = fingerprint(state.action_queue.config(), state.action_queue.display());
+    save(&state_dir, &fingerprint, &settings)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    Ok(Json(CalibrationResponse {
+        fingerprint,
+        settings,
+    }))
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_calibration_round_trip() {
+        let dir = std::env::temp_dir().join(format!("valk-calibration-{}", std::process::id()));
+        assert_eq!(load(&dir, "abc").unwrap(), None);
+
+        let calibration = Calibration {
+            drag_path_mode: Some(DragPathMode::Absolute),
+            scroll_pixels_per_click: Some(120),
+            ..Calibration::default()
+        };
+        save(&dir, "abc", &calibration).unwrap();
+        let loaded = load(&dir, "abc").unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(loaded, Some(calibration.clone()));
+
+        let config = calibration.apply(Config::default());
+        assert_eq!(config.drag_path_mode, DragPathMode::Absolute);
+        assert_eq!(config.scroll_pixels_per_click, 120);
+        assert_eq!(config.input_backend, InputBackend::Enigo);
+    }
+
+    #[test]
+    fn test_fingerprint_follows_layout() {
+        let simulated = |monitors: &str| Config {
+            input_backend: InputBackend::Simulated,
+            simulated_monitors: monitors.to_string(),
+            ..Config::default()
+        };
+        let display = Arc::new(DisplayWatch::default());
+        let single = fingerprint(&simulated("1920x1080"), &display);
+        assert_eq!(single.len(), 16);
+        assert_eq!(single, fingerprint(&simulated("1920x1080"), &display));
+        assert_ne!(
+            single,
+            fingerprint(&simulated("1920x1080,1280x1024"), &display)
+        );
+    }
+}
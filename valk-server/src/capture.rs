@@ -0,0 +1,504 @@
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, ImageFormat, RgbaImage};
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+use std::sync::Arc;
+use xcap::Monitor;
+
+use crate::action_types::{ActionError, MonitorSelector, Point, Region, ScreenshotFormat};
+use crate::display::DisplayWatch;
+use crate::observe::{
+    command_window, focused_window, top_level_windows, visible_windows, ListedWindow,
+    WindowCommand, WindowInfo, WindowTitle,
+};
+
+/// A monitor and where it sits on the desktop
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DisplayInfo {
+    pub id: u32,
+    pub name: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub scale_factor: f32,
+    pub is_primary: bool,
+}
+
+impl DisplayInfo {
+    pub fn contains(&self, point: Point) -> bool {
+        let (x, y) = (point.x as i64, point.y as i64);
+        x >= self.x as i64
+            && y >= self.y as i64
+            && x < self.x as i64 + self.width as i64
+            && y < self.y as i64 + self.height as i64
+    }
+}
+
+/// The display a selector picks out of the list `ScreenCapture::displays` returns
+pub fn select_display(
+    mut displays: Vec<DisplayInfo>,
+    selector: MonitorSelector,
+) -> Result<DisplayInfo, ActionError> {
+    let position = match selector {
+        MonitorSelector::Index(index) => (index < displays.len()).then_some(index),
+        MonitorSelector::Id(id) => displays.iter().position(|display| display.id == id),
+    };
+    let Some(position) = position else {
+        return Err(ActionError::InvalidInput(format!(
+            "No monitor matches {:?}, there are {} monitors",
+            selector,
+            displays.len()
+        )));
+    };
+    Ok(displays.swap_remove(position))
+}
+
+/// A rectangle on the desktop, monitors left of or above the primary one have negative positions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ScreenBounds {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// The box enclosing every display
+pub fn virtual_screen(displays: &[DisplayInfo]) -> ScreenBounds {
+    let left = displays.iter().map(|d| d.x as i64).min().unwrap_or(0);
+    let top = displays.iter().map(|d| d.y as i64).min().unwrap_or(0);
+    let right = displays
+        .iter()
+        .map(|d| d.x as i64 + d.width as i64)
+        .max()
+        .unwrap_or(0);
+    let bottom = displays
+        .iter()
+        .map(|d| d.y as i64 + d.height as i64)
+        .max()
+        .unwrap_or(0);
+    ScreenBounds {
+        x: left as i32,
+        y: top as i32,
+        width: (right - left) as u32,
+        height: (bottom - top) as u32,
+    }
+}
+
+/// Where screenshots come from, injected into the queue alongside the input driver
+pub trait ScreenCapture: Send + Sync + 'static {
+    /// Capture the primary monitor immediately
+    fn capture(&self) -> Result<RgbaImage, ActionError>;
+
+    /// Capture the monitor with this id, as listed by `displays`
+    fn capture_display(&self, id: u32) -> Result<RgbaImage, ActionError>;
+
+    /// Size of the primary monitor
+    fn screen_size(&self) -> Result<(u32, u32), ActionError>;
+
+    fn displays(&self) -> Result<Vec<DisplayInfo>, ActionError>;
+
+    /// The window with input focus, there are no windows unless a display server has them
+    fn focused_window(&self) -> Option<WindowInfo> {
+        None
+    }
+
+    /// Windows that aren't minimized and have a title
+    fn windows(&self) -> Vec<WindowTitle> {
+        Vec::new()
+    }
+
+    /// Every top-level window with its geometry, the frontmost first
+    fn top_level_windows(&self) -> Result<Vec<ListedWindow>, ActionError> {
+        Ok(Vec::new())
+    }
+
+    /// Have the window with this id, as listed by `top_level_windows`, focused, closed or
+    /// minimized
+    fn command_window(&self, _id: u32, command: WindowCommand) -> Result<(), ActionError> {
+        Err(ActionError::ExecutionFailed(format!(
+            "There are no windows to {} without a display server",
+            command.verb()
+        )))
+    }
+}
+
+/// Captures the monitors of the display server, connecting to the X display the watch holds
+/// directly and leaving everything else to xcap
+pub struct XcapCapture {
+    display: Arc<DisplayWatch>,
+}
+
+impl XcapCapture {
+    pub fn new(display: Arc<DisplayWatch>) -> Self {
+        Self { display }
+    }
+
+    fn primary_monitor(&self) -> Result<DisplayInfo, ActionError> {
+        self.displays()?
+            .into_iter()
+            .next()
+            .ok_or_else(|| ActionError::ExecutionFailed("No monitor found".to_string()))
+    }
+}
+
+impl ScreenCapture for XcapCapture {
+    fn capture(&self) -> Result<RgbaImage, ActionError> {
+        let monitor = self.primary_monitor()?;
+        self.capture_display(monitor.id)
+    }
+
+    fn capture_display(&self, id: u32) -> Result<RgbaImage, ActionError> {
+        #[cfg(target_os = "linux")]
+        if let Some(display) = self.display.x11_display() {
+            let monitor = self
+                .displays()?
+                .into_iter()
+                .find(|monitor| monitor.id == id)
+                .ok_or_else(|| {
+                    ActionError::ExecutionFailed(format!("No monitor with id {}", id))
+                })?;
+            return crate::x11::capture(&display, &monitor).map_err(|e| {
+                ActionError::ExecutionFailed(format!("Failed to capture image: {}", e))
+            });
+        }
+        let monitors = Monitor::all()
+            .map_err(|_| ActionError::ExecutionFailed("Failed to get monitors".to_string()))?;
+        let monitor = monitors
+            .iter()
+            .find(|monitor| monitor.id() == id)
+            .ok_or_else(|| ActionError::ExecutionFailed(format!("No monitor with id {}", id)))?;
+        monitor
+            .capture_image()
+            .map_err(|_| ActionError::ExecutionFailed("Failed to capture image".to_string()))
+    }
+
+    fn screen_size(&self) -> Result<(u32, u32), ActionError> {
+        self.primary_monitor()
+            .map(|monitor| (monitor.width, monitor.height))
+    }
+
+    fn displays(&self) -> Result<Vec<DisplayInfo>, ActionError> {
+        #[cfg(target_os = "linux")]
+        if let Some(display) = self.display.x11_display() {
+            return crate::x11::monitors(&display).map_err(|e| {
+                ActionError::ExecutionFailed(format!("Failed to get displays: {}", e))
+            });
+        }
+        let monitors = Monitor::all()
+            .map_err(|e| ActionError::ExecutionFailed(format!("Failed to get displays: {}", e)))?;
+        Ok(monitors
+            .iter()
+            .map(|monitor| DisplayInfo {
+                id: monitor.id(),
+                name: monitor.name().to_string(),
+                x: monitor.x(),
+                y: monitor.y(),
+                width: monitor.width(),
+                height: monitor.height(),
+                scale_factor: monitor.scale_factor(),
+                is_primary: monitor.is_primary(),
+            })
+            .collect())
+    }
+
+    fn focused_window(&self) -> Option<WindowInfo> {
+        focused_window(self.display.x11_display().as_deref())
+    }
+
+    fn windows(&self) -> Vec<WindowTitle> {
+        visible_windows(self.display.x11_display().as_deref())
+    }
+
+    fn top_level_windows(&self) -> Result<Vec<ListedWindow>, ActionError> {
+        top_level_windows(self.display.x11_display().as_deref())
+    }
+
+    fn command_window(&self, id: u32, command: WindowCommand) -> Result<(), ActionError> {
+        command_window(self.display.current().as_deref(), id, command)
+    }
+}
+
+/// Serves the same image for every capture, for tests and running without a display
+pub struct ImageCapture {
+    image: RgbaImage,
+}
+
+impl ImageCapture {
+    pub fn new(image: RgbaImage) -> Self {
+        Self { image }
+    }
+
+    pub fn open(path: &str) -> Result<Self, String> {
+        let image = image::open(path).map_err(|e| format!("Failed to open {}: {}", path, e))?;
+        Ok(Self::new(image.to_rgba8()))
+    }
+}
+
+impl ScreenCapture for ImageCapture {
+    fn capture(&self) -> Result<RgbaImage, ActionError> {
+        Ok(self.image.clone())
+    }
+
+    fn capture_display(&self, id: u32) -> Result<RgbaImage, ActionError> {
+        match id {
+            0 => self.capture(),
+            _ => Err(ActionError::ExecutionFailed(format!(
+                "No monitor with id {}",
+                id
+            ))),
+        }
+    }
+
+    fn screen_size(&self) -> Result<(u32, u32), ActionError> {
+        Ok(self.image.dimensions())
+    }
+
+    fn displays(&self) -> Result<Vec<DisplayInfo>, ActionError> {
+        let (width, height) = self.image.dimensions();
+        Ok(vec![DisplayInfo {
+            id: 0,
+            name: "image".to_string(),
+            x: 0,
+            y: 0,
+            width,
+            height,
+            scale_factor: 1.0,
+            is_primary: true,
+        }])
+    }
+}
+
+/// Cut a region out of a capture, failing if it doesn't fit inside
+pub fn crop(image: RgbaImage, region: Option<Region>) -> Result<RgbaImage, ActionError> {
+    let Some(region) = region else {
+        return Ok(image);
+    };
+    if region.x.saturating_add(region.width) > image.width()
+        || region.y.saturating_add(region.height) > image.height()
+    {
+        return Err(ActionError::InvalidInput(format!(
+            "Region {}x{} at ({}, {}) doesn't fit the {}x{} screen",
+            region.width,
+            region.height,
+            region.x,
+            region.y,
+            image.width(),
+            image.height()
+        )));
+    }
+    Ok(
+        image::imageops::crop_imm(&image, region.x, region.y, region.width, region.height)
+            .to_image(),
+    )
+}
+
+/// The part of a window that is on a display, relative to the display's corner
+pub fn window_region(window: &WindowInfo, display: &DisplayInfo) -> Option<Region> {
+    let left = window.x.max(display.x) as i64;
+    let top = window.y.max(display.y) as i64;
+    let right =
+        (window.x as i64 + window.width as i64).min(display.x as i64 + display.width as i64);
+    let bottom =
+        (window.y as i64 + window.height as i64).min(display.y as i64 + display.height as i64);
+    (right > left && bottom > top).then(|| Region {
+        x: (left - display.x as i64) as u32,
+        y: (top - display.y as i64) as u32,
+        width: (right - left) as u32,
+        height: (bottom - top) as u32,
+    })
+}
+
+const DEFAULT_JPEG_QUALITY: u8 = 80;
+
+/// Shrink an image to fit within the given size, keeping its aspect ratio. Never upscales
+pub fn fit_within(image: RgbaImage, max_width: Option<u32>, max_height: Option<u32>) -> RgbaImage {
+    let (width, height) = image.dimensions();
+    let scale = [
+        max_width.map(|max| max as f64 / width as f64),
+        max_height.map(|max| max as f64 / height as f64),
+    ]
+    .into_iter()
+    .flatten()
+    .fold(1.0, f64::min);
+    if scale >= 1.0 {
+        return image;
+    }
+    scale_by(image, scale)
+}
+
+/// Resize an image by a factor, in either direction
+pub fn scale_by(image: RgbaImage, scale: f64) -> RgbaImage {
+    if scale == 1.0 {
+        return image;
+    }
+    let (width, height) = image.dimensions();
+    let scaled = |size: u32| ((size as f64 * scale).round() as u32).max(1);
+    imageops::resize(&image, scaled(width), scaled(height), FilterType::Triangle)
+}
+
+// Encode an image as base64 in the requested format for JSON responses
+pub fn encode_image(
+    image: &RgbaImage,
+    format: ScreenshotFormat,
+    quality: Option<u8>,
+) -> Result<String, ActionError> {
+    let failed = || ActionError::ExecutionFailed("Failed to encode image".to_string());
+    let mut bytes = Vec::new();
+    match format {
+        ScreenshotFormat::Png => return encode_png(image),
+        ScreenshotFormat::Jpeg => {
+            // JPEG has no alpha channel
+            let image = DynamicImage::ImageRgba8(image.clone()).to_rgb8();
+            JpegEncoder::new_with_quality(&mut bytes, quality.unwrap_or(DEFAULT_JPEG_QUALITY))
+                .encode_image(&image)
+                .map_err(|_| failed())?;
+        }
+        ScreenshotFormat::Webp => image
+            .write_to(&mut Cursor::new(&mut bytes), ImageFormat::WebP)
+            .map_err(|_| failed())?,
+    }
+    Ok(BASE64.encode(bytes))
+}
+
+// Encode an image as base64 PNG for JSON responses
+pub fn encode_png(image: &RgbaImage) -> Result<String, ActionError> {
+    let mut cursor = Cursor::new(Vec::new());
+    image
+        .write_to(&mut cursor, ImageFormat::Png)
+        .map_err(|_| ActionError::ExecutionFailed("Failed to encode image".to_string()))?;
+    let bytes = cursor.into_inner();
+    let base64_image = BASE64.encode(bytes);
+    Ok(base64_image)
+}
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+
+// FNV-1a, unlike std's hasher its output is fixed, so hashes survive restarts and upgrades
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    fnv1a_from(FNV_OFFSET_BASIS, bytes)
+}
+
+fn fnv1a_from(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Identifies a frame by its size and pixels, to tell whether the screen changed
+pub fn image_hash(image: &RgbaImage) -> String {
+    let size = fnv1a(&[image.width().to_le_bytes(), image.height().to_le_bytes()].concat());
+    format!("{:016x}", fnv1a_from(size, image.as_raw()))
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_capture_from_file() {
+        let path = std::env::temp_dir().join(format!("valk-capture-{}.png", std::process::id()));
+        RgbaImage::from_pixel(8, 6, image::Rgba([10, 20, 30, 255]))
+            .save(&path)
+            .unwrap();
+
+        let capture = ImageCapture::open(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(capture.screen_size().unwrap(), (8, 6));
+        assert_eq!(
+            capture.capture().unwrap().get_pixel(3, 3),
+            &image::Rgba([10, 20, 30, 255])
+        );
+
+        assert!(ImageCapture::open("/nonexistent/screen.png").is_err());
+    }
+
+    #[test]
+    fn test_fit_within_keeps_aspect_ratio() {
+        let image = RgbaImage::new(1920, 1080);
+        let scaled = fit_within(image.clone(), Some(640), None);
+        assert_eq!(scaled.dimensions(), (640, 360));
+        assert_eq!(
+            fit_within(image.clone(), Some(1280), Some(360)).dimensions(),
+            (640, 360)
+        );
+
+        // Small images are never upscaled
+        let small = RgbaImage::new(320, 200);
+        assert_eq!(
+            fit_within(small, Some(640), Some(640)).dimensions(),
+            (320, 200)
+        );
+        assert_eq!(fit_within(image, None, None).dimensions(), (1920, 1080));
+    }
+
+    #[test]
+    fn test_encode_image_formats() {
+        let image = RgbaImage::from_pixel(8, 6, image::Rgba([10, 20, 30, 255]));
+        for (format, expected) in [
+            (ScreenshotFormat::Png, ImageFormat::Png),
+            (ScreenshotFormat::Jpeg, ImageFormat::Jpeg),
+            (ScreenshotFormat::Webp, ImageFormat::WebP),
+        ] {
+            let bytes = BASE64
+                .decode(encode_image(&image, format, Some(70)).unwrap())
+                .unwrap();
+            assert_eq!(image::guess_format(&bytes).unwrap(), expected);
+            let decoded = image::load_from_memory(&bytes).unwrap();
+            assert_eq!((decoded.width(), decoded.height()), (8, 6));
+        }
+    }
+
+    #[test]
+    fn test_window_region_is_clipped_to_display() {
+        let display = |x, width| DisplayInfo {
+            id: 0,
+            name: "test".to_string(),
+            x,
+            y: 0,
+            width,
+            height: 1080,
+            scale_factor: 1.0,
+            is_primary: x == 0,
+        };
+        let window = WindowInfo {
+            id: 1,
+            app_name: "editor".to_string(),
+            title: "notes.txt".to_string(),
+            x: 1800,
+            y: -20,
+            width: 400,
+            height: 300,
+        };
+
+        let region = window_region(&window, &display(0, 1920)).unwrap();
+        assert_eq!(
+            (region.x, region.y, region.width, region.height),
+            (1800, 0, 120, 280)
+        );
+        let region = window_region(&window, &display(1920, 1280)).unwrap();
+        assert_eq!(
+            (region.x, region.y, region.width, region.height),
+            (0, 0, 280, 280)
+        );
+        assert!(window_region(&window, &display(3200, 1280)).is_none());
+    }
+
+    #[test]
+    fn test_image_hash_follows_content() {
+        let image = RgbaImage::from_pixel(8, 6, image::Rgba([10, 20, 30, 255]));
+        let hash = image_hash(&image);
+        assert_eq!(hash.len(), 16);
+        assert_eq!(hash, image_hash(&image.clone()));
+
+        let mut changed = image.clone();
+        changed.put_pixel(7, 5, image::Rgba([10, 20, 31, 255]));
+        assert_ne!(hash, image_hash(&changed));
+        // Same pixels laid out differently
+        let reshaped = RgbaImage::from_pixel(6, 8, image::Rgba([10, 20, 30, 255]));
+        assert_ne!(hash, image_hash(&reshaped));
+    }
+}
@@ -0,0 +1,51 @@
+//! Clipboard access for pasting text, injected into the queue like screen capture.
+
+use std::sync::Mutex;
+
+use crate::action_types::ActionError;
+
+pub trait Clipboard: Send + Sync + 'static {
+    fn set_text(&self, text: &str) -> Result<(), ActionError>;
+}
+
+/// The display server's clipboard
+#[derive(Default)]
+pub struct SystemClipboard {
+    // On X11 the clipboard's contents are served by whoever set them, so the handle is kept
+    // for as long as the server runs rather than dropped after each paste
+    handle: Mutex<Option<arboard::Clipboard>>,
+}
+
+impl Clipboard for SystemClipboard {
+    fn set_text(&self, text: &str) -> Result<(), ActionError> {
+        let failed = |e: arboard::Error| {
+            ActionError::ExecutionFailed(format!("Failed to set the clipboard: {}", e))
+        };
+        let mut handle = self.handle.lock().unwrap();
+        let clipboard = match handle.as_mut() {
+            Some(clipboard) => clipboard,
+            None => handle.insert(arboard::Clipboard::new().map_err(failed)?),
+        };
+        clipboard.set_text(text).map_err(failed)
+    }
+}
+
+/// A clipboard only the server sees, for the simulated backend and tests
+#[derive(Default)]
+pub struct MemoryClipboard {
+    text: Mutex<Option<String>>,
+}
+
+#[cfg(test)]
+impl MemoryClipboard {
+    pub fn text(&self) -> Option<String> {
+        self.text.lock().unwrap().clone()
+    }
+}
+
+impl Clipboard for MemoryClipboard {
+    fn set_text(&self, text: &str) -> Result<(), ActionError> {
+        *self.text.lock().unwrap() = Some(text.to_string());
+        Ok(())
+    }
+}
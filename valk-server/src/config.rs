@@ -1,15 +1,334 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::env;
+use std::fmt;
+use std::str::FromStr;
+use tracing::{error, warn};
+
+use crate::proxy::ProxyUrl;
 
 // Defaults
 const DEFAULT_HOST: &str = "0.0.0.0"; // Default behavior is to listen on all interfaces, since this is expected to be accessed remotely
 const DEFAULT_PORT: u16 = 8255;
+const DEFAULT_APPROVAL_TIMEOUT_SECS: u64 = 300;
+const DEFAULT_AUTHORIZATION_TIMEOUT_MS: u64 = 2000;
+const DEFAULT_MAX_QUEUE_DEPTH: usize = 32;
+const DEFAULT_MONITOR_BUFFER: usize = 100;
+const DEFAULT_SIMULATED_MONITORS: &str = "1920x1080";
+const DEFAULT_SCROLL_PIXELS_PER_CLICK: u32 = 50;
+const DEFAULT_GLIDE_DURATION_MS: u64 = 250;
+const DEFAULT_AGENT_MAX_STEPS: u32 = 20;
+
+/// How intermediate pointer positions are emitted while dragging
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DragPathMode {
+    /// Small relative moves between interpolated points (subject to pointer acceleration)
+    Relative,
+    /// Absolute moves to every interpolated point, so acceleration can't push the path off-target
+    Absolute,
+}
+
+impl FromStr for DragPathMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "relative" => Ok(DragPathMode::Relative),
+            "absolute" => Ok(DragPathMode::Absolute),
+            _ => Err(format!("Unknown drag path mode: {}", s)),
+        }
+    }
+}
+
+/// How `mouse_move` gets the cursor to its target
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MouseMotion {
+    /// Jump straight to the target, the fastest way to get there
+    Warp,
+    /// Travel to the target over a duration, emitting the motion events hover effects rely on
+    Glide,
+}
+
+impl FromStr for MouseMotion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "warp" => Ok(MouseMotion::Warp),
+            "glide" => Ok(MouseMotion::Glide),
+            _ => Err(format!("Unknown mouse motion: {}", s)),
+        }
+    }
+}
+
+/// Which backend generates keyboard and mouse events
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InputBackend {
+    /// Synthetic events through enigo (XTest on X11)
+    Enigo,
+    /// Kernel-level virtual devices through /dev/uinput (Linux only)
+    Uinput,
+    /// A virtual screen, no display server needed
+    Simulated,
+}
+
+impl FromStr for InputBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "enigo" => Ok(InputBackend::Enigo),
+            "uinput" => Ok(InputBackend::Uinput),
+            "simulated" => Ok(InputBackend::Simulated),
+            _ => Err(format!("Unknown input backend: {}", s)),
+        }
+    }
+}
+
+/// Which API the agent's model is reached through
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModelApi {
+    /// Anthropic's Messages API
+    Anthropic,
+    /// OpenAI's Chat Completions API, offered by many other providers and local servers too
+    Openai,
+}
+
+impl FromStr for ModelApi {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "anthropic" => Ok(ModelApi::Anthropic),
+            "openai" => Ok(ModelApi::Openai),
+            _ => Err(format!("Unknown model API: {}", s)),
+        }
+    }
+}
+
+/// What to give up when monitor clients can't keep up with the event stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MonitorOverflow {
+    /// Slow clients skip the oldest buffered events
+    DropOldest,
+    /// Stop buffering new screen updates once half the buffer is used, keeping room for everything else
+    DropNewScreenshots,
+}
+
+impl FromStr for MonitorOverflow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "drop_oldest" => Ok(MonitorOverflow::DropOldest),
+            "drop_new_screenshots" => Ok(MonitorOverflow::DropNewScreenshots),
+            _ => Err(format!("Unknown monitor overflow strategy: {}", s)),
+        }
+    }
+}
+
+/// What happens to an action that a pacing rule doesn't allow to start yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PacingMode {
+    /// Hold the action until the rule allows it
+    Delay,
+    /// Fail the action with a `rate_limited` error
+    Reject,
+}
+
+impl FromStr for PacingMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "delay" => Ok(PacingMode::Delay),
+            "reject" => Ok(PacingMode::Reject),
+            _ => Err(format!("Unknown pacing mode: {}", s)),
+        }
+    }
+}
 
-#[derive(Debug, Serialize, Deserialize)]
+/// At most `max` actions of a type may start within any `per_ms` window
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PacingRule {
+    pub action_type: String,
+    pub max: u32,
+    pub per_ms: u64,
+}
+
+impl FromStr for PacingRule {
+    type Err = String;
+
+    /// `screenshot=1/500` for one screenshot per 500ms, or `click=200` for 200ms between clicks
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid pacing rule: {}", s);
+        let (action_type, limit) = s.split_once('=').ok_or_else(invalid)?;
+        let (max, per_ms) = match limit.split_once('/') {
+            Some((max, per_ms)) => (max.trim().parse(), per_ms.trim().parse()),
+            None => (Ok(1), limit.trim().parse()),
+        };
+        let (Ok(max), Ok(per_ms)) = (max, per_ms) else {
+            return Err(invalid());
+        };
+        let action_type = action_type.trim().to_lowercase();
+        if action_type.is_empty() || max == 0 || per_ms == 0 {
+            return Err(invalid());
+        }
+        Ok(PacingRule {
+            action_type,
+            max,
+            per_ms,
+        })
+    }
+}
+
+/// Proxy to use for hosts matching `pattern`, connecting directly when `proxy` is None
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProxyRule {
+    pub pattern: String,
+    pub proxy: Option<String>,
+}
+
+impl FromStr for ProxyRule {
+    type Err = String;
+
+    /// `*.corp.example=http://proxy:3128`, or `bastion.lab=direct` to skip the proxy
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (pattern, proxy) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Invalid proxy rule: {}", s))?;
+        let pattern = pattern.trim().to_lowercase();
+        if pattern.is_empty() {
+            return Err(format!("Invalid proxy rule: {}", s));
+        }
+        let proxy = match proxy.trim() {
+            "direct" => None,
+            proxy => Some(proxy.parse::<ProxyUrl>()?.to_string()),
+        };
+        Ok(ProxyRule { pattern, proxy })
+    }
+}
+
+impl fmt::Display for PacingRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} actions per {}ms",
+            self.max, self.action_type, self.per_ms
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     // Web Server settings
     pub host: String,
     pub port: u16,
+    /// Second port serving only the read-only endpoints, e.g. for auditors
+    pub mirror_port: Option<u16>,
+
+    // Input settings
+    pub input_backend: InputBackend,
+    pub drag_path_mode: DragPathMode,
+    /// How `mouse_move` travels when the request doesn't say
+    pub mouse_motion: MouseMotion,
+    /// How long a glide takes when the request doesn't say
+    pub glide_duration_ms: u64,
+    /// Put the cursor back if a read-only action (e.g. a screenshot) moved it
+    pub preserve_cursor: bool,
+    /// Note the focused window on every response, so audits can tell which app got the input
+    pub record_foreground: bool,
+    /// How far one wheel click scrolls, used to turn smooth scroll distances into clicks
+    pub scroll_pixels_per_click: u32,
+
+    // Capture settings
+    /// Image file served as every screenshot instead of capturing the display
+    pub capture_image: Option<String>,
+    /// Monitor sizes of the simulated backend's virtual screen, e.g. "1920x1080,1280x1024"
+    pub simulated_monitors: String,
+    /// Attach a scaled screenshot to responses of actions that failed to execute
+    pub failure_screenshots: bool,
+
+    // Supervision settings
+    /// Action types (e.g. "type_text") that are held until approved via /v1/approvals
+    pub approval_actions: Vec<String>,
+    pub approval_timeout_secs: u64,
+    /// Bearer token a supervisor must present to approve or reject actions, so the agent whose
+    /// actions are held can't decide on them. Approvals can't be decided when unset
+    pub approver_token: Option<String>,
+    /// External service every agent action is POSTed to for a decision before it is queued
+    pub authorization_url: Option<String>,
+    pub authorization_timeout_ms: u64,
+    /// Let actions through when the authorization service fails, instead of refusing them
+    pub authorization_fail_open: bool,
+
+    // Scheduling settings
+    /// Most actions a single run (or all requests without a run) may have waiting in the queue
+    pub max_queue_depth: usize,
+    /// Limits on how often agents may run each action type, e.g. "screenshot=1/500,click=200"
+    pub pacing_rules: Vec<PacingRule>,
+    pub pacing_mode: PacingMode,
+
+    // Monitor settings
+    /// Events buffered for /v1/monitor clients
+    pub monitor_buffer: usize,
+    pub monitor_overflow: MonitorOverflow,
+    /// Draw the mouse pointer onto screen updates
+    pub monitor_show_cursor: bool,
+    /// Bytes per second all monitor clients together may receive before screen updates degrade
+    pub monitor_bandwidth: Option<u64>,
+
+    // Takeover settings
+    /// Bearer token a human observer must present to take over input, takeover is off when unset
+    pub takeover_token: Option<String>,
+
+    // Arming settings
+    /// Bearer token for POST /v1/arm. When set the server starts disarmed and only runs
+    /// read-only actions until it is armed
+    pub arm_token: Option<String>,
+    /// Only take POST /v1/arm from the machine itself, so arming needs someone on it
+    pub arm_local_only: bool,
+
+    // Relay settings
+    /// Bearer token relay peers and relayed requests must present, relaying is off when unset
+    pub relay_token: Option<String>,
+    /// Bastion to connect out to and take relayed actions from, e.g. "wss://bastion"
+    pub relay_upstream: Option<String>,
+    /// Name this instance is reached by through the bastion, the hostname when unset
+    pub relay_name: Option<String>,
+
+    // Proxy settings
+    /// Proxy for the connections valk makes itself, e.g. "http://proxy:3128" or "socks5://gw:1080"
+    pub proxy: Option<String>,
+    /// Proxies for particular hosts instead of `proxy`, the first matching rule wins
+    pub proxy_overrides: Vec<ProxyRule>,
+
+    // Agent settings
+    /// API the agent's model is reached through
+    pub agent_api: ModelApi,
+    /// Base URL of the model API, the provider's own when unset
+    pub agent_url: Option<String>,
+    /// Model the agent asks for its next step, the agent is off when unset
+    pub agent_model: Option<String>,
+    pub agent_api_key: Option<String>,
+    /// Steps an agent run may take before it gives up
+    pub agent_max_steps: u32,
+
+    // Storage settings
+    /// Directory for recordings, journals, macros and calibration data, locked by one server at a time
+    pub state_dir: Option<String>,
+    /// Directory of policy files, reloaded whenever they change
+    pub policy_dir: Option<String>,
+    /// JSON file listing actions to run through the queue before the server starts listening
+    pub startup_actions: Option<String>,
+    /// Directories clients may browse through /v1/files, browsing is off when empty
+    pub file_roots: Vec<String>,
 }
 
 impl Default for Config {
@@ -17,6 +336,47 @@ impl Default for Config {
         Self {
             host: DEFAULT_HOST.to_string(),
             port: DEFAULT_PORT,
+            mirror_port: None,
+            input_backend: InputBackend::Enigo,
+            drag_path_mode: DragPathMode::Relative,
+            mouse_motion: MouseMotion::Warp,
+            glide_duration_ms: DEFAULT_GLIDE_DURATION_MS,
+            preserve_cursor: false,
+            record_foreground: false,
+            scroll_pixels_per_click: DEFAULT_SCROLL_PIXELS_PER_CLICK,
+            capture_image: None,
+            simulated_monitors: DEFAULT_SIMULATED_MONITORS.to_string(),
+            failure_screenshots: false,
+            approval_actions: Vec::new(),
+            approval_timeout_secs: DEFAULT_APPROVAL_TIMEOUT_SECS,
+            approver_token: None,
+            authorization_url: None,
+            authorization_timeout_ms: DEFAULT_AUTHORIZATION_TIMEOUT_MS,
+            authorization_fail_open: false,
+            max_queue_depth: DEFAULT_MAX_QUEUE_DEPTH,
+            pacing_rules: Vec::new(),
+            pacing_mode: PacingMode::Delay,
+            monitor_buffer: DEFAULT_MONITOR_BUFFER,
+            monitor_overflow: MonitorOverflow::DropOldest,
+            monitor_show_cursor: false,
+            monitor_bandwidth: None,
+            takeover_token: None,
+            arm_token: None,
+            arm_local_only: false,
+            relay_token: None,
+            relay_upstream: None,
+            relay_name: None,
+            proxy: None,
+            proxy_overrides: Vec::new(),
+            agent_api: ModelApi::Anthropic,
+            agent_url: None,
+            agent_model: None,
+            agent_api_key: None,
+            agent_max_steps: DEFAULT_AGENT_MAX_STEPS,
+            state_dir: None,
+            policy_dir: None,
+            startup_actions: None,
+            file_roots: Vec::new(),
         }
     }
 }
@@ -33,10 +393,226 @@ impl Config {
             config.port = port.parse().unwrap_or(config.port);
         }
 
+        if let Ok(port) = env::var("VALK_MIRROR_PORT") {
+            config.mirror_port = port.parse().ok().filter(|port| *port > 0);
+        }
+
+        if let Ok(backend) = env::var("VALK_INPUT_BACKEND") {
+            config.input_backend = backend.parse().unwrap_or(config.input_backend);
+        }
+
+        if let Ok(mode) = env::var("VALK_DRAG_PATH_MODE") {
+            config.drag_path_mode = mode.parse().unwrap_or(config.drag_path_mode);
+        }
+
+        if let Ok(motion) = env::var("VALK_MOUSE_MOTION") {
+            config.mouse_motion = motion.parse().unwrap_or(config.mouse_motion);
+        }
+
+        if let Ok(duration) = env::var("VALK_GLIDE_DURATION_MS") {
+            config.glide_duration_ms = duration.parse().unwrap_or(config.glide_duration_ms);
+        }
+
+        if let Ok(preserve) = env::var("VALK_PRESERVE_CURSOR") {
+            config.preserve_cursor = preserve.parse().unwrap_or(config.preserve_cursor);
+        }
+
+        if let Ok(record) = env::var("VALK_RECORD_FOREGROUND") {
+            config.record_foreground = record.parse().unwrap_or(config.record_foreground);
+        }
+
+        if let Ok(pixels) = env::var("VALK_SCROLL_PIXELS_PER_CLICK") {
+            config.scroll_pixels_per_click = pixels
+                .parse()
+                .ok()
+                .filter(|pixels| *pixels > 0)
+                .unwrap_or(config.scroll_pixels_per_click);
+        }
+
+        if let Ok(path) = env::var("VALK_CAPTURE_IMAGE") {
+            config.capture_image = Some(path).filter(|path| !path.is_empty());
+        }
+
+        if let Ok(monitors) = env::var("VALK_SIMULATED_MONITORS") {
+            config.simulated_monitors = monitors;
+        }
+
+        if let Ok(attach) = env::var("VALK_FAILURE_SCREENSHOTS") {
+            config.failure_screenshots = attach.parse().unwrap_or(config.failure_screenshots);
+        }
+
+        if let Ok(actions) = env::var("VALK_APPROVAL_ACTIONS") {
+            config.approval_actions = parse_list(&actions);
+        }
+
+        if let Ok(secs) = env::var("VALK_APPROVAL_TIMEOUT_SECS") {
+            config.approval_timeout_secs = secs.parse().unwrap_or(config.approval_timeout_secs);
+        }
+
+        if let Ok(token) = env::var("VALK_APPROVER_TOKEN") {
+            config.approver_token = Some(token).filter(|token| !token.is_empty());
+        }
+
+        if let Ok(url) = env::var("VALK_AUTHORIZATION_URL") {
+            config.authorization_url = Some(url).filter(|url| !url.is_empty());
+        }
+
+        if let Ok(ms) = env::var("VALK_AUTHORIZATION_TIMEOUT_MS") {
+            config.authorization_timeout_ms = ms
+                .parse()
+                .ok()
+                .filter(|ms| *ms > 0)
+                .unwrap_or(config.authorization_timeout_ms);
+        }
+
+        if let Ok(fail_open) = env::var("VALK_AUTHORIZATION_FAIL_OPEN") {
+            config.authorization_fail_open =
+                fail_open.parse().unwrap_or(config.authorization_fail_open);
+        }
+
+        if let Ok(depth) = env::var("VALK_MAX_QUEUE_DEPTH") {
+            config.max_queue_depth = depth.parse().unwrap_or(config.max_queue_depth);
+        }
+
+        if let Ok(rules) = env::var("VALK_PACING") {
+            config.pacing_rules = rules
+                .split(',')
+                .filter_map(|rule| {
+                    rule.parse()
+                        .map_err(|e| warn!("{} in VALK_PACING, ignoring it", e))
+                        .ok()
+                })
+                .collect();
+        }
+
+        if let Ok(mode) = env::var("VALK_PACING_MODE") {
+            config.pacing_mode = mode.parse().unwrap_or(config.pacing_mode);
+        }
+
+        if let Ok(buffer) = env::var("VALK_MONITOR_BUFFER") {
+            config.monitor_buffer = buffer
+                .parse()
+                .ok()
+                .filter(|buffer| *buffer > 0)
+                .unwrap_or(config.monitor_buffer);
+        }
+
+        if let Ok(overflow) = env::var("VALK_MONITOR_OVERFLOW") {
+            config.monitor_overflow = overflow.parse().unwrap_or(config.monitor_overflow);
+        }
+
+        if let Ok(show) = env::var("VALK_MONITOR_SHOW_CURSOR") {
+            config.monitor_show_cursor = show.parse().unwrap_or(config.monitor_show_cursor);
+        }
+
+        if let Ok(bandwidth) = env::var("VALK_MONITOR_BANDWIDTH") {
+            // 0 turns the budget off
+            config.monitor_bandwidth = bandwidth.parse().ok().filter(|bandwidth| *bandwidth > 0);
+        }
+
+        if let Ok(token) = env::var("VALK_TAKEOVER_TOKEN") {
+            config.takeover_token = Some(token).filter(|token| !token.is_empty());
+        }
+
+        if let Ok(token) = env::var("VALK_ARM_TOKEN") {
+            config.arm_token = Some(token).filter(|token| !token.is_empty());
+        }
+
+        if let Ok(local_only) = env::var("VALK_ARM_LOCAL_ONLY") {
+            config.arm_local_only = local_only.parse().unwrap_or(config.arm_local_only);
+        }
+
+        if let Ok(token) = env::var("VALK_RELAY_TOKEN") {
+            config.relay_token = Some(token).filter(|token| !token.is_empty());
+        }
+
+        if let Ok(url) = env::var("VALK_RELAY_UPSTREAM") {
+            config.relay_upstream = Some(url).filter(|url| !url.is_empty());
+        }
+
+        if let Ok(name) = env::var("VALK_RELAY_NAME") {
+            config.relay_name = Some(name).filter(|name| !name.is_empty());
+        }
+
+        if let Ok(url) = env::var("VALK_PROXY") {
+            // Kept when invalid, so outbound connections fail instead of going around the proxy
+            config.proxy = match url.parse::<ProxyUrl>() {
+                _ if url.is_empty() => None,
+                Ok(proxy) => Some(proxy.to_string()),
+                Err(e) => {
+                    error!("{} in VALK_PROXY, outbound connections will fail", e);
+                    Some(url)
+                }
+            };
+        }
+
+        if let Ok(rules) = env::var("VALK_PROXY_OVERRIDES") {
+            config.proxy_overrides = rules
+                .split(',')
+                .filter_map(|rule| {
+                    rule.parse()
+                        .map_err(|e| warn!("{} in VALK_PROXY_OVERRIDES, ignoring it", e))
+                        .ok()
+                })
+                .collect();
+        }
+
+        if let Ok(api) = env::var("VALK_AGENT_API") {
+            config.agent_api = api.parse().unwrap_or(config.agent_api);
+        }
+
+        if let Ok(url) = env::var("VALK_AGENT_URL") {
+            config.agent_url = Some(url).filter(|url| !url.is_empty());
+        }
+
+        if let Ok(model) = env::var("VALK_AGENT_MODEL") {
+            config.agent_model = Some(model).filter(|model| !model.is_empty());
+        }
+
+        if let Ok(key) = env::var("VALK_AGENT_API_KEY") {
+            config.agent_api_key = Some(key).filter(|key| !key.is_empty());
+        }
+
+        if let Ok(steps) = env::var("VALK_AGENT_MAX_STEPS") {
+            if let Ok(steps) = steps.parse::<u32>() {
+                config.agent_max_steps = steps.max(1);
+            }
+        }
+
+        if let Ok(path) = env::var("VALK_STATE_DIR") {
+            config.state_dir = Some(path).filter(|path| !path.is_empty());
+        }
+
+        if let Ok(path) = env::var("VALK_POLICY_DIR") {
+            config.policy_dir = Some(path).filter(|path| !path.is_empty());
+        }
+
+        if let Ok(path) = env::var("VALK_STARTUP_ACTIONS") {
+            config.startup_actions = Some(path).filter(|path| !path.is_empty());
+        }
+
+        if let Ok(roots) = env::var("VALK_FILE_ROOTS") {
+            // Paths are case sensitive, so not parse_list
+            config.file_roots = roots
+                .split(',')
+                .map(|root| root.trim().to_string())
+                .filter(|root| !root.is_empty())
+                .collect();
+        }
+
         config
     }
 }
 
+// Parse a comma separated env value, ignoring blanks
+fn parse_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|item| item.trim().to_lowercase())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
 // Tests
 #[cfg(test)]
 mod tests {
@@ -48,6 +624,7 @@ mod tests {
         let config = Config::default();
         assert_eq!(config.port, DEFAULT_PORT);
         assert_eq!(config.host, DEFAULT_HOST);
+        assert_eq!(config.drag_path_mode, DragPathMode::Relative);
     }
 
     #[test]
@@ -63,4 +640,59 @@ mod tests {
         env::remove_var("VALK_PORT");
         env::remove_var("VALK_HOST");
     }
+
+    #[test]
+    fn test_drag_path_mode_parse() {
+        assert_eq!("absolute".parse(), Ok(DragPathMode::Absolute));
+        assert_eq!("Relative".parse(), Ok(DragPathMode::Relative));
+        assert!("sideways".parse::<DragPathMode>().is_err());
+    }
+
+    #[test]
+    fn test_parse_list() {
+        assert_eq!(
+            parse_list("type_text, Key_Press,,"),
+            vec!["type_text", "key_press"]
+        );
+        assert!(parse_list("").is_empty());
+    }
+
+    #[test]
+    fn test_pacing_rule_parse() {
+        let rule: PacingRule = " Screenshot = 2/500".parse().unwrap();
+        assert_eq!(
+            rule,
+            PacingRule {
+                action_type: "screenshot".to_string(),
+                max: 2,
+                per_ms: 500
+            }
+        );
+        let rule: PacingRule = "click=200".parse().unwrap();
+        assert_eq!((rule.max, rule.per_ms), (1, 200));
+        assert!("click".parse::<PacingRule>().is_err());
+        assert!("click=0".parse::<PacingRule>().is_err());
+        assert!("click=0/100".parse::<PacingRule>().is_err());
+        assert!("=100".parse::<PacingRule>().is_err());
+        assert_eq!("REJECT".parse(), Ok(PacingMode::Reject));
+        assert!("wait".parse::<PacingMode>().is_err());
+    }
+
+    #[test]
+    fn test_monitor_overflow_parse() {
+        assert_eq!(
+            "drop_new_screenshots".parse(),
+            Ok(MonitorOverflow::DropNewScreenshots)
+        );
+        assert_eq!("DROP_OLDEST".parse(), Ok(MonitorOverflow::DropOldest));
+        assert!("drop_everything".parse::<MonitorOverflow>().is_err());
+    }
+
+    #[test]
+    fn test_input_backend_parse() {
+        assert_eq!("uinput".parse(), Ok(InputBackend::Uinput));
+        assert_eq!("ENIGO".parse(), Ok(InputBackend::Enigo));
+        assert_eq!("simulated".parse(), Ok(InputBackend::Simulated));
+        assert!("xdotool".parse::<InputBackend>().is_err());
+    }
 }
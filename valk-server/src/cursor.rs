@@ -0,0 +1,27 @@
+use axum::{extract, http::StatusCode, Json};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::sync::Arc;
+
+use crate::AppState;
+
+/// A pointer position read outside the action queue
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct CursorSnapshot {
+    pub x: i32,
+    pub y: i32,
+    /// True when the driver was busy with an action and the last known position was returned
+    pub cached: bool,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Read the cursor position without queueing, cheap enough to poll at 10-30Hz
+pub async fn cursor(
+    extract::State(state): extract::State<Arc<AppState>>,
+) -> Result<Json<CursorSnapshot>, StatusCode> {
+    state
+        .action_queue
+        .cursor_snapshot()
+        .map(Json)
+        .ok_or(StatusCode::SERVICE_UNAVAILABLE)
+}
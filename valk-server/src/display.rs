@@ -0,0 +1,232 @@
+use axum::{extract, Json};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::env;
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+#[cfg(unix)]
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::action_types::ActionError;
+use crate::AppState;
+
+/// Whether the display server can currently be reached, as seen by the display watch
+#[derive(Debug, Clone, Serialize)]
+pub struct DisplayStatus {
+    pub available: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    pub since: DateTime<Utc>,
+}
+
+/// The display the server drives, started from DISPLAY and moved only by the watch, capture
+/// and input backends are handed it rather than reading the environment
+pub struct DisplayWatch {
+    status: Mutex<DisplayStatus>,
+    wayland: bool,
+}
+
+impl Default for DisplayWatch {
+    fn default() -> Self {
+        let wayland = env::var("XDG_SESSION_TYPE").is_ok_and(|session| session == "wayland")
+            || env::var("WAYLAND_DISPLAY").is_ok_and(|display| display.contains("wayland"));
+        Self {
+            status: Mutex::new(DisplayStatus {
+                available: true,
+                display: env::var("DISPLAY").ok(),
+                reason: None,
+                since: Utc::now(),
+            }),
+            wayland,
+        }
+    }
+}
+
+impl DisplayWatch {
+    pub fn status(&self) -> DisplayStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// The display in use
+    pub fn current(&self) -> Option<String> {
+        self.status.lock().unwrap().display.clone()
+    }
+
+    /// The X display to capture from directly, none on Wayland sessions where only xcap can
+    /// capture the screen
+    pub fn x11_display(&self) -> Option<String> {
+        if self.wayland {
+            return None;
+        }
+        self.current()
+    }
+
+    /// Rejects actions while the display is unavailable
+    pub fn check(&self) -> Result<(), ActionError> {
+        let status = self.status.lock().unwrap();
+        if status.available {
+            Ok(())
+        } else {
+            Err(ActionError::DisplayUnavailable(
+                status
+                    .reason
+                    .clone()
+                    .unwrap_or_else(|| "Display unavailable".to_string()),
+            ))
+        }
+    }
+
+    /// Record the outcome of a probe, returns the new status if availability changed
+    pub fn update(&self, probe: Result<String, String>) -> Option<DisplayStatus> {
+        let mut status = self.status.lock().unwrap();
+        let (available, display, reason) = match probe {
+            Ok(display) => (true, Some(display), None),
+            Err(reason) => (false, status.display.clone(), Some(reason)),
+        };
+        if available == status.available && display == status.display {
+            return None;
+        }
+        *status = DisplayStatus {
+            available,
+            display,
+            reason,
+            since: Utc::now(),
+        };
+        Some(status.clone())
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn probe(display: &str) -> Result<(), String> {
+    match crate::x11::monitors(display) {
+        Ok(monitors) if !monitors.is_empty() => Ok(()),
+        Ok(_) => Err("No monitor found".to_string()),
+        Err(e) => Err(format!("Display unavailable: {}", e)),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn probe(_display: &str) -> Result<(), String> {
+    match xcap::Monitor::all() {
+        Ok(monitors) if !monitors.is_empty() => Ok(()),
+        Ok(_) => Err("No monitor found".to_string()),
+        Err(e) => Err(format!("Display unavailable: {}", e)),
+    }
+}
+
+/// Check the current display is reachable, moving to another of this user's X servers if it
+/// went away (e.g. the user logged out and back in to a new session). Returns the display to
+/// use, for the caller to keep in the watch.
+pub fn reconnect_display(current: Option<&str>) -> Result<String, String> {
+    let current = current.unwrap_or_default();
+    let error = match probe(current) {
+        Ok(()) => return Ok(current.to_string()),
+        Err(e) => e,
+    };
+    user_displays()
+        .into_iter()
+        .filter(|display| display != current)
+        .find(|display| probe(display).is_ok())
+        .ok_or(error)
+}
+
+// The X servers of the user the server runs as
+#[cfg(unix)]
+fn user_displays() -> Vec<String> {
+    local_displays(Path::new(X11_SOCKET_DIR), unsafe { libc::getuid() })
+}
+
+// Only X servers have other displays to move to
+#[cfg(not(unix))]
+fn user_displays() -> Vec<String> {
+    Vec::new()
+}
+
+#[cfg(unix)]
+const X11_SOCKET_DIR: &str = "/tmp/.X11-unix";
+
+// X servers listening in `dir` whose sockets belong to `uid`, other users' sessions are never
+// candidates even when their server would let us in
+#[cfg(unix)]
+fn local_displays(dir: &Path, uid: u32) -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut displays: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.metadata().is_ok_and(|metadata| metadata.uid() == uid))
+        .filter_map(|entry| display_from_socket_name(&entry.file_name().to_string_lossy()))
+        .collect();
+    displays.sort();
+    displays
+}
+
+#[cfg(unix)]
+fn display_from_socket_name(name: &str) -> Option<String> {
+    let number = name.strip_prefix('X')?;
+    if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    Some(format!(":{}", number))
+}
+
+/// Current display availability
+pub async fn display_status(
+    extract::State(state): extract::State<Arc<AppState>>,
+) -> Json<DisplayStatus> {
+    Json(state.action_queue.display().status())
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_update_reports_transitions_only() {
+        let watch = DisplayWatch::default();
+        watch.update(Ok(":0".to_string()));
+
+        assert!(watch.update(Ok(":0".to_string())).is_none());
+        let status = watch.update(Err("X server gone".to_string())).unwrap();
+        assert!(!status.available);
+        assert!(matches!(
+            watch.check(),
+            Err(ActionError::DisplayUnavailable(_))
+        ));
+        assert!(watch.update(Err("still gone".to_string())).is_none());
+
+        let status = watch.update(Ok(":1".to_string())).unwrap();
+        assert!(status.available);
+        assert_eq!(status.display.as_deref(), Some(":1"));
+        assert!(watch.check().is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_display_from_socket_name() {
+        assert_eq!(display_from_socket_name("X0"), Some(":0".to_string()));
+        assert_eq!(display_from_socket_name("X12"), Some(":12".to_string()));
+        assert_eq!(display_from_socket_name("X"), None);
+        assert_eq!(display_from_socket_name("X0-lock"), None);
+        assert_eq!(display_from_socket_name("wayland-0"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_local_displays_belong_to_the_user() {
+        let dir = std::env::temp_dir().join(format!("valk-displays-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["X1", "X0", "X0-lock"] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+        let uid = unsafe { libc::getuid() };
+
+        assert_eq!(local_displays(&dir, uid), vec![":0", ":1"]);
+        assert!(local_displays(&dir, uid + 1).is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,756 @@
+use serde::Serialize;
+use std::str::FromStr;
+
+use crate::action_queue::{
+    kinetic_scroll_clicks, ActionQueue, InputDriver, HOVER_DURATION_MS, SMOOTH_SCROLL_DURATION_MS,
+};
+use crate::action_types::{
+    Action, ActionError, ActionRequest, MonitorSelector, MouseButton, Point, ScreenshotTarget,
+    ScrollAxis, TextLevel,
+};
+use crate::annotate::{draw_marker, draw_path, HIGHLIGHT};
+use crate::capture::{encode_png, ScreenCapture};
+use crate::config::MouseMotion;
+use crate::key_press::{parse_modifier, KeyPress};
+
+/// Key string after parsing, in the order keys would be pressed
+#[derive(Debug, Serialize)]
+pub struct ResolvedKeyPress {
+    pub modifiers: Vec<String>,
+    pub key: String,
+}
+
+/// Everything an action would do, computed without driving any input
+#[derive(Debug, Serialize)]
+pub struct DryRunResponse {
+    pub request_id: String,
+    pub action: Action,
+    pub valid: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ActionError>,
+    /// Whether execution would send events through the input driver
+    pub uses_input_driver: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_press: Option<ResolvedKeyPress>,
+    /// Where a click or tap would land
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub click_point: Option<Point>,
+    /// Screen points the pointer (or touch contact) would visit, in order
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub pointer_path: Vec<Point>,
+    /// Human readable plan of the input events that would be sent
+    pub steps: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+    /// Base64 PNG of the current screen with the click point and pointer path drawn on it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preview_image: Option<String>,
+}
+
+// Draw the planned interaction on a fresh capture of the screen
+fn render_preview(
+    capture: &dyn ScreenCapture,
+    preview: &DryRunResponse,
+) -> Result<String, ActionError> {
+    let mut image = capture.capture()?;
+    draw_path(&mut image, &preview.pointer_path, HIGHLIGHT);
+    let markers = preview
+        .click_point
+        .iter()
+        .chain(preview.pointer_path.first())
+        .chain(preview.pointer_path.last());
+    for point in markers {
+        draw_marker(&mut image, *point, HIGHLIGHT);
+    }
+    encode_png(&image)
+}
+
+fn to_point((x, y): (i32, i32)) -> Point {
+    Point {
+        x: x.max(0) as u32,
+        y: y.max(0) as u32,
+    }
+}
+
+fn click_steps(button: &str, count: usize, at: Option<Point>) -> Vec<String> {
+    let location = match at {
+        Some(p) => format!("at ({}, {})", p.x, p.y),
+        None => "at the current cursor position".to_string(),
+    };
+    (0..count)
+        .flat_map(|_| {
+            [
+                format!("press {} button {}", button, location),
+                format!("release {} button", button),
+            ]
+        })
+        .collect()
+}
+
+// Modifiers as the keys they resolve to. Already validated, so parsing can't fail here
+fn modifier_names(modifiers: &[String]) -> Vec<String> {
+    modifiers
+        .iter()
+        .filter_map(|m| parse_modifier(m).ok())
+        .map(|m| format!("{:?}", m))
+        .collect()
+}
+
+// A step with the modifiers pressed before it and released after it
+fn held_around(modifiers: &[String], step: String) -> Vec<String> {
+    let modifiers = modifier_names(modifiers);
+    modifiers
+        .iter()
+        .map(|m| format!("press {}", m))
+        .chain([step])
+        .chain(modifiers.iter().rev().map(|m| format!("release {}", m)))
+        .collect()
+}
+
+fn scroll_direction(axis: ScrollAxis, amount: i32) -> &'static str {
+    match (axis, amount < 0) {
+        (ScrollAxis::Vertical, false) => "down",
+        (ScrollAxis::Vertical, true) => "up",
+        (ScrollAxis::Horizontal, false) => "right",
+        (ScrollAxis::Horizontal, true) => "left",
+    }
+}
+
+impl<T: InputDriver> ActionQueue<T> {
+    /// Resolve what a request would do without executing it,
+    /// optionally rendering the plan onto a screenshot for review
+    pub async fn dry_run(&self, request: &ActionRequest, render_image: bool) -> DryRunResponse {
+        let mut preview = DryRunResponse {
+            request_id: request.id.clone(),
+            action: request.action.clone(),
+            valid: true,
+            error: None,
+            uses_input_driver: true,
+            key_press: None,
+            click_point: None,
+            pointer_path: Vec::new(),
+            steps: Vec::new(),
+            warnings: Vec::new(),
+            preview_image: None,
+        };
+
+        if let Err(error) = request.action.validate() {
+            preview.valid = false;
+            preview.error = Some(error);
+            return preview;
+        }
+
+        let cursor = self.cursor_location().await.map(to_point);
+        let (dx, dy) = match self.monitor_offset(&request.action) {
+            Ok(offset) => offset,
+            Err(error) => {
+                preview.valid = false;
+                preview.error = Some(error);
+                return preview;
+            }
+        };
+        // Points relative to a monitor are previewed in input coordinates
+        let on_desktop = |x: u32, y: u32| Point {
+            x: x.saturating_add_signed(dx),
+            y: y.saturating_add_signed(dy),
+        };
+
+        match &request.action {
+            Action::LeftClick => {
+                preview.click_point = cursor;
+                preview.steps = click_steps("left", 1, cursor);
+            }
+            Action::RightClick => {
+                preview.click_point = cursor;
+                preview.steps = click_steps("right", 1, cursor);
+            }
+            Action::MiddleClick => {
+                preview.click_point = cursor;
+                preview.steps = click_steps("middle", 1, cursor);
+            }
+            Action::DoubleClick => {
+                preview.click_point = cursor;
+                preview.steps = click_steps("left", 2, cursor);
+            }
+            Action::Click { input } => {
+                let button = match input.button {
+                    MouseButton::Left => "left",
+                    MouseButton::Right => "right",
+                    MouseButton::Middle => "middle",
+                };
+                let target = on_desktop(input.x, input.y);
+                preview.pointer_path.push(target);
+                preview.click_point = Some(target);
+                let modifiers = modifier_names(&input.modifiers);
+                let pixel_check = input.expected_pixel.as_ref().map(|color| {
+                    format!(
+                        "check that ({}, {}) is {}, fail with stale_target otherwise",
+                        target.x, target.y, color
+                    )
+                });
+                let text_check = input.expected_text.as_ref().map(|text| {
+                    format!(
+                        "check that ({}, {}) shows \"{}\", fail with stale_target otherwise",
+                        target.x, target.y, text
+                    )
+                });
+                preview.steps = pixel_check
+                    .into_iter()
+                    .chain(text_check)
+                    .chain(modifiers.iter().map(|m| format!("press {}", m)))
+                    .chain([format!("move cursor to ({}, {})", target.x, target.y)])
+                    .chain(click_steps(button, 1, Some(target)))
+                    .chain(modifiers.iter().rev().map(|m| format!("release {}", m)))
+                    .collect();
+            }
+            Action::TripleClick => {
+                preview.click_point = cursor;
+                preview.steps = click_steps("left", 3, cursor);
+            }
+            Action::MouseMove { input } => {
+                let target = on_desktop(input.x, input.y);
+                preview.pointer_path.push(target);
+                let verb = match input.motion.unwrap_or(self.config().mouse_motion) {
+                    MouseMotion::Warp => "move",
+                    MouseMotion::Glide => "glide",
+                };
+                preview
+                    .steps
+                    .push(format!("{} cursor to ({}, {})", verb, target.x, target.y));
+            }
+            Action::Hover { input } => {
+                let target = on_desktop(input.x, input.y);
+                preview.pointer_path.push(target);
+                preview.steps = vec![
+                    format!("move cursor to ({}, {})", target.x, target.y),
+                    format!(
+                        "wait {}ms without clicking",
+                        input.duration_ms.unwrap_or(HOVER_DURATION_MS)
+                    ),
+                ];
+            }
+            Action::LeftClickDrag { input }
+            | Action::RightClickDrag { input }
+            | Action::MiddleClickDrag { input } => {
+                let button = match request.action {
+                    Action::RightClickDrag { .. } => "right",
+                    Action::MiddleClickDrag { .. } => "middle",
+                    _ => "left",
+                };
+                let target = on_desktop(input.x, input.y);
+                preview.pointer_path.extend(cursor);
+                preview.pointer_path.push(target);
+                preview.steps = vec![
+                    format!("press {} button at the current cursor position", button),
+                    format!("drag to ({}, {})", target.x, target.y),
+                    format!("release {} button", button),
+                ];
+            }
+            Action::DragPath { input } => {
+                let button = match input.button {
+                    MouseButton::Left => "left",
+                    MouseButton::Right => "right",
+                    MouseButton::Middle => "middle",
+                };
+                let points: Vec<Point> = input
+                    .points
+                    .iter()
+                    .map(|point| on_desktop(point.x, point.y))
+                    .collect();
+                preview.pointer_path.extend(points.iter().copied());
+                if let (Some(first), Some(last)) = (points.first(), points.last()) {
+                    preview.steps = vec![
+                        format!("press {} button at ({}, {})", button, first.x, first.y),
+                        format!(
+                            "drag through {} points to ({}, {})",
+                            input.points.len() - 1,
+                            last.x,
+                            last.y
+                        ),
+                        format!("release {} button", button),
+                    ];
+                }
+            }
+            Action::PasteText { input } => {
+                let shortcut = if cfg!(target_os = "macos") {
+                    "cmd+v"
+                } else {
+                    "ctrl+v"
+                };
+                preview.steps = vec![
+                    format!(
+                        "put {} characters on the clipboard",
+                        input.text.chars().count()
+                    ),
+                    format!("press {}", shortcut),
+                ];
+            }
+            Action::TypeText { input } => {
+                preview
+                    .steps
+                    .push(format!("type {} characters", input.text.chars().count()));
+                if !input.text.is_ascii() {
+                    preview
+                        .warnings
+                        .push("Text contains non-ASCII characters which may not be supported by your system".to_string());
+                }
+            }
+            Action::KeyPress { input } => {
+                // Already validated, so parsing can't fail here
+                if let Ok(key_press) = KeyPress::from_str(&input.key) {
+                    let modifiers: Vec<String> = key_press
+                        .modifiers
+                        .iter()
+                        .map(|m| format!("{:?}", m))
+                        .collect();
+                    let key = format!("{:?}", key_press.key);
+                    preview.steps = modifiers
+                        .iter()
+                        .map(|m| format!("press {}", m))
+                        .chain([format!("press {}", key), format!("release {}", key)])
+                        .chain(modifiers.iter().rev().map(|m| format!("release {}", m)))
+                        .collect();
+                    preview.key_press = Some(ResolvedKeyPress { modifiers, key });
+                }
+            }
+            Action::KeyDown { input } | Action::KeyUp { input } => {
+                if let Ok(key_press) = KeyPress::from_str(&input.key) {
+                    let modifiers: Vec<String> = key_press
+                        .modifiers
+                        .iter()
+                        .map(|m| format!("{:?}", m))
+                        .collect();
+                    let key = format!("{:?}", key_press.key);
+                    preview.steps = if let Action::KeyDown { .. } = request.action {
+                        modifiers
+                            .iter()
+                            .chain([&key])
+                            .map(|k| format!("press and hold {}", k))
+                            .collect()
+                    } else {
+                        [&key]
+                            .into_iter()
+                            .chain(modifiers.iter().rev())
+                            .map(|k| format!("release {}", k))
+                            .collect()
+                    };
+                    preview.key_press = Some(ResolvedKeyPress { modifiers, key });
+                }
+            }
+            Action::Screenshot { input } => {
+                preview.uses_input_driver = false;
+                let monitor = match input.monitor {
+                    Some(MonitorSelector::Index(index)) => {
+                        format!("display {} of the display list", index)
+                    }
+                    Some(MonitorSelector::Id(id)) => format!("the monitor with id {}", id),
+                    None => "the primary monitor".to_string(),
+                };
+                preview.steps.push(match input.region {
+                    _ if input.target == ScreenshotTarget::FocusedWindow => {
+                        "capture the focused window from the monitor it is on".to_string()
+                    }
+                    Some(region) => format!(
+                        "capture a {}x{} region at ({}, {}) of {}",
+                        region.width, region.height, region.x, region.y, monitor
+                    ),
+                    None => format!("capture {}", monitor),
+                });
+                if let Some(hash) = &input.previous_hash {
+                    preview.steps.push(format!(
+                        "send no image if the frame still hashes to {}",
+                        hash
+                    ));
+                }
+            }
+            Action::Burst { input } => {
+                preview.uses_input_driver = false;
+                let area = match input.region {
+                    Some(region) => format!(
+                        "a {}x{} region at ({}, {}) of the primary monitor",
+                        region.width, region.height, region.x, region.y
+                    ),
+                    None => "the primary monitor".to_string(),
+                };
+                preview.steps.push(format!(
+                    "capture {} {} times, {}ms apart",
+                    area, input.count, input.interval_ms
+                ));
+            }
+            Action::Ocr { input } => {
+                preview.uses_input_driver = false;
+                let area = match input.region {
+                    Some(region) => format!(
+                        "a {}x{} region at ({}, {}) of the primary monitor",
+                        region.width, region.height, region.x, region.y
+                    ),
+                    None => "the primary monitor".to_string(),
+                };
+                preview.steps.push(format!(
+                    "capture {} and read its text {}",
+                    area,
+                    match input.level {
+                        TextLevel::Line => "line by line",
+                        TextLevel::Word => "word by word",
+                    }
+                ));
+            }
+            Action::FindText { input } => {
+                preview.uses_input_driver = false;
+                let area = match input.region {
+                    Some(region) => format!(
+                        "a {}x{} region at ({}, {}) of the primary monitor",
+                        region.width, region.height, region.x, region.y
+                    ),
+                    None => "the primary monitor".to_string(),
+                };
+                preview.steps.push(format!(
+                    "capture {} and find the text best matching \"{}\" ({:?})",
+                    area, input.query, input.match_mode
+                ));
+            }
+            Action::LocateImage { input } => {
+                preview.uses_input_driver = false;
+                let area = match input.region {
+                    Some(region) => format!(
+                        "a {}x{} region at ({}, {}) of the primary monitor",
+                        region.width, region.height, region.x, region.y
+                    ),
+                    None => "the primary monitor".to_string(),
+                };
+                preview.steps.push(format!(
+                    "capture {} and find up to {} places matching the template with confidence {}",
+                    area, input.limit, input.confidence
+                ));
+            }
+            Action::WaitFor { input } => {
+                preview.uses_input_driver = false;
+                let area = match input.region {
+                    Some(region) => format!(
+                        "a {}x{} region at ({}, {}) of the primary monitor",
+                        region.width, region.height, region.x, region.y
+                    ),
+                    None => "the primary monitor".to_string(),
+                };
+                let target = match &input.text {
+                    Some(text) => format!("the text \"{}\" ({:?})", text, input.match_mode),
+                    None => format!("the template with confidence {}", input.confidence),
+                };
+                preview.steps.push(format!(
+                    "capture {} until {} shows up, for at most {}ms",
+                    area, target, input.timeout_ms
+                ));
+            }
+            Action::CursorPosition => {
+                preview.uses_input_driver = false;
+                preview.steps.push("read the cursor position".to_string());
+            }
+            Action::ListWindows => {
+                preview.uses_input_driver = false;
+                preview.steps.push("list the top-level windows".to_string());
+            }
+            Action::ClickElement { input } => {
+                let button = match input.button {
+                    MouseButton::Left => "left",
+                    MouseButton::Right => "right",
+                    MouseButton::Middle => "middle",
+                };
+                let element = match (&input.element_id, &input.role, &input.name) {
+                    (Some(id), ..) => format!("the accessibility element {}", id),
+                    (None, role, name) => format!(
+                        "the first {} named \"{}\" on screen",
+                        role.as_deref().unwrap_or_default(),
+                        name.as_deref().unwrap_or_default()
+                    ),
+                };
+                preview.steps = vec![
+                    format!("look up {}", element),
+                    format!("press {} button at its center", button),
+                    format!("release {} button", button),
+                ];
+            }
+            Action::FocusWindow { input } => {
+                preview.uses_input_driver = false;
+                preview
+                    .steps
+                    .push(format!("raise and focus the window {}", input.describe()));
+            }
+            Action::CloseWindow { input } => {
+                preview.uses_input_driver = false;
+                preview
+                    .steps
+                    .push(format!("close the window {}", input.describe()));
+            }
+            Action::MinimizeWindow { input } => {
+                preview.uses_input_driver = false;
+                preview
+                    .steps
+                    .push(format!("minimize the window {}", input.describe()));
+            }
+            Action::SaveCursor => {
+                preview.uses_input_driver = false;
+                preview
+                    .steps
+                    .push("remember the cursor position".to_string());
+            }
+            Action::Ping => {
+                preview.uses_input_driver = false;
+                preview
+                    .steps
+                    .push("pass through the queue without doing anything".to_string());
+            }
+            Action::Wait { input } => {
+                preview.uses_input_driver = false;
+                preview.steps.push(format!("wait {}ms", input.duration_ms));
+            }
+            Action::Scroll { input } => {
+                preview.steps = held_around(
+                    &input.modifiers,
+                    format!(
+                        "scroll {} {} clicks at the cursor",
+                        scroll_direction(input.axis, input.amount),
+                        input.amount.unsigned_abs()
+                    ),
+                );
+            }
+            Action::SmoothScroll { input } => {
+                preview.steps = held_around(
+                    &input.modifiers,
+                    format!(
+                        "scroll {} {} pixels at the cursor over {}ms",
+                        scroll_direction(input.axis, input.pixels),
+                        input.pixels.unsigned_abs(),
+                        input.duration_ms.unwrap_or(SMOOTH_SCROLL_DURATION_MS)
+                    ),
+                );
+            }
+            Action::KineticScroll { input } => {
+                let target = to_point((input.x as i32, input.y as i32));
+                let clicks =
+                    kinetic_scroll_clicks(input.velocity, self.config().scroll_pixels_per_click);
+                let (axis, sign) = input.direction.axis();
+                preview.pointer_path.push(target);
+                preview.steps = vec![
+                    format!("move cursor to ({}, {})", target.x, target.y),
+                    format!(
+                        "flick-scroll {} about {} pixels, slowing down over {}ms",
+                        scroll_direction(axis, sign),
+                        clicks.iter().sum::<i32>() as u32 * self.config().scroll_pixels_per_click,
+                        clicks.len() * 10
+                    ),
+                ];
+            }
+            Action::RestoreCursor => match self.saved_cursor().map(to_point) {
+                Some(target) => {
+                    preview.pointer_path.push(target);
+                    preview.steps.push(format!(
+                        "move cursor back to saved position ({}, {})",
+                        target.x, target.y
+                    ));
+                }
+                None => preview
+                    .warnings
+                    .push("No cursor position has been saved".to_string()),
+            },
+            Action::Tap { input } => {
+                preview.click_point = Some(*input);
+                preview.steps = vec![
+                    format!("touch down at ({}, {})", input.x, input.y),
+                    "lift touch".to_string(),
+                ];
+            }
+            Action::Swipe { input } => {
+                preview.pointer_path = vec![input.start, input.end];
+                preview.steps = vec![
+                    format!("touch down at ({}, {})", input.start.x, input.start.y),
+                    format!("swipe to ({}, {})", input.end.x, input.end.y),
+                    "lift touch".to_string(),
+                ];
+            }
+            Action::Pen { input } => {
+                preview.pointer_path = input
+                    .points
+                    .iter()
+                    .map(|p| Point { x: p.x, y: p.y })
+                    .collect();
+                preview.steps = vec![
+                    "pen down".to_string(),
+                    format!("draw through {} points", input.points.len()),
+                    "pen up".to_string(),
+                ];
+            }
+        }
+
+        if let Ok(displays) = self.capture().displays() {
+            for point in preview.click_point.iter().chain(&preview.pointer_path) {
+                if !displays.iter().any(|display| display.contains(*point)) {
+                    preview.warnings.push(format!(
+                        "Point ({}, {}) is not on any display",
+                        point.x, point.y
+                    ));
+                }
+            }
+        }
+
+        if render_image {
+            match render_preview(self.capture(), &preview) {
+                Ok(image) => preview.preview_image = Some(image),
+                Err(e) => preview
+                    .warnings
+                    .push(format!("Failed to render preview image: {:?}", e)),
+            }
+        }
+
+        preview
+    }
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action_queue::tests::create_test_action_queue;
+    use crate::action_types::{KeyPressInput, MouseMoveInput, TypeTextInput};
+    use crate::config::Config;
+    use crate::simulation::{SimulatedInput, VirtualScreen};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_dry_run_resolves_keys() {
+        let queue = create_test_action_queue().await;
+
+        let preview = queue
+            .dry_run(
+                &ActionRequest::new(
+                    "test_dry_run_resolves_keys".to_string(),
+                    Action::KeyPress {
+                        input: KeyPressInput {
+                            key: "ctrl+shift+t".to_string(),
+                        },
+                    },
+                ),
+                false,
+            )
+            .await;
+
+        assert!(preview.valid);
+        let key_press = preview.key_press.unwrap();
+        assert_eq!(key_press.modifiers, vec!["Control", "Shift"]);
+        assert_eq!(key_press.key, "Unicode('t')");
+        assert_eq!(preview.steps.first().unwrap(), "press Control");
+        assert_eq!(preview.steps.last().unwrap(), "release Control");
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_invalid_input() {
+        let queue = create_test_action_queue().await;
+
+        let preview = queue
+            .dry_run(
+                &ActionRequest::new(
+                    "test_dry_run_invalid_input".to_string(),
+                    Action::TypeText {
+                        input: TypeTextInput {
+                            text: String::new(),
+                        },
+                    },
+                ),
+                false,
+            )
+            .await;
+
+        assert!(!preview.valid);
+        assert!(matches!(preview.error, Some(ActionError::InvalidInput(_))));
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_does_not_move_cursor() {
+        let queue = create_test_action_queue().await;
+
+        let preview = queue
+            .dry_run(
+                &ActionRequest::new(
+                    "test_dry_run_does_not_move_cursor".to_string(),
+                    Action::LeftClickDrag {
+                        input: MouseMoveInput {
+                            x: 300,
+                            y: 400,
+                            monitor: None,
+                            motion: None,
+                            duration_ms: None,
+                        },
+                    },
+                ),
+                false,
+            )
+            .await;
+
+        assert!(preview.valid);
+        assert_eq!(
+            preview.pointer_path,
+            vec![Point { x: 0, y: 0 }, Point { x: 300, y: 400 }]
+        );
+        assert_eq!(queue.cursor_location().await, Some((0, 0)));
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_warns_about_points_off_every_display() {
+        let screen = VirtualScreen::parse("1920x1080,1280x1024").unwrap();
+        let queue = ActionQueue::new(SimulatedInput::new(&screen), Config::default())
+            .with_capture(Arc::new(screen));
+        let move_to = |x, y| {
+            ActionRequest::new(
+                "test_dry_run_off_display".to_string(),
+                Action::MouseMove {
+                    input: MouseMoveInput {
+                        x,
+                        y,
+                        monitor: None,
+                        motion: None,
+                        duration_ms: None,
+                    },
+                },
+            )
+        };
+
+        // On the second monitor
+        let preview = queue.dry_run(&move_to(2000, 1000), false).await;
+        assert!(preview.warnings.is_empty());
+        // Right of the primary monitor but below the shorter second one
+        let preview = queue.dry_run(&move_to(2000, 1050), false).await;
+        assert_eq!(
+            preview.warnings,
+            vec!["Point (2000, 1050) is not on any display"]
+        );
+        let preview = queue.dry_run(&move_to(4000, 10), false).await;
+        assert_eq!(preview.warnings.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_preview_image() {
+        let queue = create_test_action_queue().await;
+
+        let preview = queue
+            .dry_run(
+                &ActionRequest::new(
+                    "test_dry_run_preview_image".to_string(),
+                    Action::Tap {
+                        input: Point { x: 10, y: 10 },
+                    },
+                ),
+                true,
+            )
+            .await;
+
+        // Without a display the preview can't be captured, which must not invalidate the dry run
+        assert!(preview.valid);
+        assert_eq!(preview.click_point, Some(Point { x: 10, y: 10 }));
+        assert!(
+            preview.preview_image.is_some()
+                || preview
+                    .warnings
+                    .iter()
+                    .any(|w| w.starts_with("Failed to render preview image"))
+        );
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::VecDeque;
+
+/// Pending items grouped into one lane per run, served round-robin so a
+/// client with many queued actions can't starve the others.
+/// Items without a run share a single lane.
+pub struct FairQueue<T> {
+    lanes: VecDeque<(Option<String>, VecDeque<T>)>,
+    max_depth: usize,
+}
+
+impl<T> FairQueue<T> {
+    pub fn new(max_depth: usize) -> Self {
+        Self {
+            lanes: VecDeque::new(),
+            max_depth,
+        }
+    }
+
+    /// Add an item to the back of its lane, handing it back if the lane is full
+    pub fn push(&mut self, lane: Option<String>, item: T) -> Result<(), T> {
+        match self.lanes.iter_mut().find(|(key, _)| *key == lane) {
+            Some((_, items)) if items.len() >= self.max_depth => Err(item),
+            Some((_, items)) => {
+                items.push_back(item);
+                Ok(())
+            }
+            None => {
+                self.lanes.push_back((lane, VecDeque::from([item])));
+                Ok(())
+            }
+        }
+    }
+
+    /// Take the oldest item of the next lane, then move that lane to the back of the rotation
+    pub fn pop(&mut self) -> Option<T> {
+        let (lane, mut items) = self.lanes.pop_front()?;
+        let item = items.pop_front();
+        if !items.is_empty() {
+            self.lanes.push_back((lane, items));
+        }
+        item
+    }
+
+    /// Keep only the items matching the predicate, dropping lanes that end up empty
+    pub fn retain(&mut self, mut keep: impl FnMut(&T) -> bool) {
+        for (_, items) in self.lanes.iter_mut() {
+            items.retain(&mut keep);
+        }
+        self.lanes.retain(|(_, items)| !items.is_empty());
+    }
+
+    pub fn len(&self) -> usize {
+        self.lanes.iter().map(|(_, items)| items.len()).sum()
+    }
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lane(name: &str) -> Option<String> {
+        Some(name.to_string())
+    }
+
+    #[test]
+    fn test_round_robin_between_lanes() {
+        let mut queue = FairQueue::new(10);
+        for i in 0..3 {
+            queue.push(lane("greedy"), format!("greedy{}", i)).unwrap();
+        }
+        queue.push(lane("polite"), "polite0".to_string()).unwrap();
+        queue.push(None, "anonymous0".to_string()).unwrap();
+
+        let order: Vec<String> = std::iter::from_fn(|| queue.pop()).collect();
+        assert_eq!(
+            order,
+            vec!["greedy0", "polite0", "anonymous0", "greedy1", "greedy2"]
+        );
+    }
+
+    #[test]
+    fn test_depth_limit_is_per_lane() {
+        let mut queue = FairQueue::new(2);
+        assert!(queue.push(lane("a"), 1).is_ok());
+        assert!(queue.push(lane("a"), 2).is_ok());
+        assert_eq!(queue.push(lane("a"), 3), Err(3));
+        assert!(queue.push(lane("b"), 4).is_ok());
+        assert!(queue.push(None, 5).is_ok());
+        assert_eq!(queue.len(), 4);
+    }
+
+    #[test]
+    fn test_retain_drops_empty_lanes() {
+        let mut queue = FairQueue::new(10);
+        queue.push(lane("a"), 1).unwrap();
+        queue.push(lane("b"), 2).unwrap();
+        queue.push(lane("b"), 3).unwrap();
+        queue.retain(|item| *item != 1);
+
+        assert_eq!(queue.pop(), Some(2));
+        assert_eq!(queue.pop(), Some(3));
+        assert_eq!(queue.pop(), None);
+    }
+}
@@ -0,0 +1,146 @@
+//! Browsing the files a GUI task produced, e.g. a downloaded report or an exported image,
+//! so orchestrators can find them before fetching them. Only directories under the roots in
+//! `VALK_FILE_ROOTS` can be listed.
+
+use axum::{
+    extract::{self, Query},
+    http::StatusCode,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::AppState;
+
+/// Entries listed from one directory, a download folder can hold far more than a client
+/// needs to look through
+pub const MAX_ENTRIES: usize = 5_000;
+
+#[derive(Debug, Serialize)]
+pub struct FileEntry {
+    pub name: String,
+    /// Full path, to pass back as `path` for a directory
+    pub path: String,
+    pub is_dir: bool,
+    /// Not followed, its size and time are the link's own, wherever it points
+    pub is_symlink: bool,
+    /// Bytes, 0 for directories
+    pub size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modified: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ListQuery {
+    /// Directory to list, the roots themselves when unset
+    pub path: Option<String>,
+}
+
+// A link to something outside the roots mustn't tell anything about its target
+fn entry(path: &Path) -> Option<FileEntry> {
+    let metadata = fs::symlink_metadata(path).ok()?;
+    Some(FileEntry {
+        name: path.file_name()?.to_string_lossy().into_owned(),
+        path: path.to_string_lossy().into_owned(),
+        is_dir: metadata.is_dir(),
+        is_symlink: metadata.is_symlink(),
+        size: if metadata.is_dir() { 0 } else { metadata.len() },
+        modified: metadata.modified().ok().map(DateTime::<Utc>::from),
+    })
+}
+
+/// The entries of `path`, which has to be one of `roots` or inside one once symlinks and
+/// `..` are resolved, at most MAX_ENTRIES of them. Without a path, the roots that exist
+pub fn list(roots: &[String], path: Option<&str>) -> Result<Vec<FileEntry>, (StatusCode, String)> {
+    let roots: Vec<PathBuf> = roots
+        .iter()
+        .filter_map(|root| fs::canonicalize(root).ok())
+        .collect();
+    let Some(path) = path else {
+        return Ok(roots.iter().filter_map(|root| entry(root)).collect());
+    };
+
+    let not_found = || (StatusCode::NOT_FOUND, format!("{} not found", path));
+    let dir = fs::canonicalize(path).map_err(|_| not_found())?;
+    if !roots.iter().any(|root| dir.starts_with(root)) {
+        // Same answer as for a missing path, so nothing outside the roots can be probed
+        return Err(not_found());
+    }
+    let read = fs::read_dir(&dir).map_err(|e| {
+        (
+            StatusCode::BAD_REQUEST,
+            format!("Can't list {}: {}", dir.display(), e),
+        )
+    })?;
+    let mut entries: Vec<FileEntry> = read
+        .filter_map(|item| item.ok())
+        .take(MAX_ENTRIES)
+        .filter_map(|item| entry(&item.path()))
+        .collect();
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(entries)
+}
+
+/// `GET /v1/files/list?path=...`
+pub async fn list_files(
+    extract::State(state): extract::State<Arc<AppState>>,
+    Query(query): Query<ListQuery>,
+) -> Result<Json<Vec<FileEntry>>, (StatusCode, String)> {
+    let roots = state.action_queue.config().file_roots.clone();
+    if roots.is_empty() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            "File browsing is disabled, set VALK_FILE_ROOTS to enable it".to_string(),
+        ));
+    }
+    // Listing a directory on a slow or network disk mustn't hold up a runtime thread
+    tokio::task::spawn_blocking(move || list(&roots, query.path.as_deref()))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map(Json)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lists_only_inside_roots() {
+        let base = std::env::temp_dir().join(format!("valk-files-{}", std::process::id()));
+        let root = base.join("downloads");
+        fs::create_dir_all(root.join("exports")).unwrap();
+        fs::write(root.join("report.pdf"), b"%PDF").unwrap();
+        fs::write(base.join("secret.txt"), b"no").unwrap();
+        let roots = vec![root.to_string_lossy().into_owned()];
+
+        let listed = list(&roots, None).unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].name, "downloads");
+
+        let entries = list(&roots, Some(&listed[0].path)).unwrap();
+        let names: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, ["exports", "report.pdf"]);
+        assert!(entries[0].is_dir);
+        assert_eq!(entries[1].size, 4);
+        assert!(entries[1].modified.is_some());
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(base.join("secret.txt"), root.join("link")).unwrap();
+            let entries = list(&roots, Some(&listed[0].path)).unwrap();
+            let link = entries.iter().find(|entry| entry.name == "link").unwrap();
+            assert!(link.is_symlink);
+            // The link's own length, the length of the path it holds, not the secret's
+            assert_eq!(link.size, base.join("secret.txt").as_os_str().len() as u64);
+        }
+
+        let escape = root.join("..").to_string_lossy().into_owned();
+        let outside = list(&roots, Some(&escape)).map(|_| ()).unwrap_err();
+        fs::remove_dir_all(&base).unwrap();
+        assert_eq!(outside.0, StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,166 @@
+//! Restarting without dropping the listening socket or the open runs.
+//!
+//! Under systemd socket activation the socket belongs to systemd and is passed to each
+//! server process in turn, so connections made while the binary is being replaced wait in
+//! the socket's backlog instead of being refused. On SIGTERM the server stops accepting
+//! and pauses the queue: the action running finishes, queued ones are refused as
+//! `restarting` for their clients to send again, and the open runs are written to the
+//! journal in the state directory. The next process resumes them from there, so agents
+//! carry on with the same run ids, limits and history.
+
+use std::fs;
+use std::future::Future;
+use std::io::ErrorKind;
+use std::net::TcpListener;
+#[cfg(unix)]
+use std::os::unix::io::FromRawFd;
+use std::path::{Path, PathBuf};
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+
+use crate::runs::{JournaledRun, RunRegistry};
+use crate::state::TMP_DIR;
+
+const JOURNAL_DIR: &str = "journals";
+const RUNS_JOURNAL: &str = "runs.json";
+
+// File descriptors passed by systemd start here, after stdin, stdout and stderr
+#[cfg(unix)]
+const LISTEN_FDS_START: i32 = 3;
+
+// How many sockets were passed to this process, following sd_listen_fds(3)
+#[cfg(unix)]
+fn passed_fds(listen_pid: Option<&str>, listen_fds: Option<&str>, pid: u32) -> usize {
+    // The variables are inherited by child processes too, which must not take the sockets
+    if listen_pid.and_then(|listen_pid| listen_pid.parse::<u32>().ok()) != Some(pid) {
+        return 0;
+    }
+    listen_fds.and_then(|fds| fds.parse().ok()).unwrap_or(0)
+}
+
+/// Sockets passed by systemd socket activation, in the order of the socket unit's
+/// `ListenStream` lines. Empty when the server was started any other way
+#[cfg(unix)]
+pub fn inherited_listeners() -> Vec<TcpListener> {
+    // The variables stay set, other threads may be reading the environment. Child processes
+    // see LISTEN_PID naming another process and the sockets are closed on exec anyway
+    let count = passed_fds(
+        std::env::var("LISTEN_PID").ok().as_deref(),
+        std::env::var("LISTEN_FDS").ok().as_deref(),
+        std::process::id(),
+    );
+    (0..count as i32)
+        .map(|offset| {
+            let fd = LISTEN_FDS_START + offset;
+            // Keep them from leaking into processes the server starts
+            unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+            let listener = unsafe { TcpListener::from_raw_fd(fd) };
+            // Tokio needs non-blocking sockets
+            let _ = listener.set_nonblocking(true);
+            listener
+        })
+        .collect()
+}
+
+// Socket activation is systemd's, elsewhere the server always binds its own sockets
+#[cfg(not(unix))]
+pub fn inherited_listeners() -> Vec<TcpListener> {
+    Vec::new()
+}
+
+/// Resolves once the process gets SIGTERM, which from this call on no longer kills it outright
+#[cfg(unix)]
+pub fn terminated() -> impl Future<Output = ()> {
+    let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+    async move {
+        terminate.recv().await;
+    }
+}
+
+/// Resolves on Ctrl+C, the way a service is asked to stop where there is no SIGTERM
+#[cfg(not(unix))]
+pub fn terminated() -> impl Future<Output = ()> {
+    async {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+fn journal_path(state_dir: &Path) -> PathBuf {
+    state_dir.join(JOURNAL_DIR).join(RUNS_JOURNAL)
+}
+
+/// Write the open runs for the next process, once nothing runs that could still change them.
+/// Written to the scratch directory first, so a crash never leaves half a journal behind
+pub fn save_journal(state_dir: &Path, runs: &RunRegistry) -> Result<usize, String> {
+    let journal = runs.journal();
+    let path = journal_path(state_dir);
+    let tmp = state_dir.join(TMP_DIR).join(RUNS_JOURNAL);
+    let json = serde_json::to_string(&journal).map_err(|e| e.to_string())?;
+    for dir in [tmp.parent(), path.parent()].into_iter().flatten() {
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+    fs::write(&tmp, json).map_err(|e| format!("Failed to write {}: {}", tmp.display(), e))?;
+    fs::rename(&tmp, &path).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    Ok(journal.len())
+}
+
+/// Resume the runs the previous process journaled. The journal is removed as it is read, so
+/// a process that crashes later doesn't hand the same runs on again
+pub fn resume_journal(state_dir: &Path, runs: &RunRegistry) -> Result<usize, String> {
+    let path = journal_path(state_dir);
+    let json = match fs::read_to_string(&path) {
+        Ok(json) => json,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+    };
+    fs::remove_file(&path).map_err(|e| format!("Failed to remove {}: {}", path.display(), e))?;
+    let journal: Vec<JournaledRun> = serde_json::from_str(&json)
+        .map_err(|e| format!("Invalid journal {}: {}", path.display(), e))?;
+    let resumed = journal.len();
+    runs.resume(journal);
+    Ok(resumed)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runs::OpenRunBody;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_passed_fds_only_counts_sockets_meant_for_this_process() {
+        assert_eq!(passed_fds(Some("42"), Some("2"), 42), 2);
+        // Inherited from a parent that was socket activated
+        assert_eq!(passed_fds(Some("41"), Some("2"), 42), 0);
+        assert_eq!(passed_fds(None, Some("2"), 42), 0);
+        assert_eq!(passed_fds(Some("42"), None, 42), 0);
+        assert_eq!(passed_fds(Some("42"), Some("two"), 42), 0);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_terminated_resolves_on_sigterm() {
+        let terminated = terminated();
+        unsafe { libc::kill(libc::getpid(), libc::SIGTERM) };
+        tokio::time::timeout(std::time::Duration::from_secs(5), terminated)
+            .await
+            .expect("SIGTERM wasn't seen");
+    }
+
+    #[test]
+    fn test_journal_is_resumed_once() {
+        let dir = std::env::temp_dir().join(format!("valk-journal-{}", std::process::id()));
+        let runs = RunRegistry::default();
+        let run = runs.open(OpenRunBody::default());
+        assert_eq!(save_journal(&dir, &runs), Ok(1));
+
+        let next = RunRegistry::default();
+        assert_eq!(resume_journal(&dir, &next), Ok(1));
+        assert_eq!(next.get(&run.run_id).unwrap().run_id, run.run_id);
+        assert!(!journal_path(&dir).exists());
+        assert_eq!(resume_journal(&dir, &RunRegistry::default()), Ok(0));
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
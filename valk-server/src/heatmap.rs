@@ -0,0 +1,181 @@
+//! Heatmaps of where a run clicked and typed, to spot agents hammering the same dead spot.
+
+use chrono::{DateTime, Utc};
+use image::{imageops, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use crate::action_types::Action;
+use crate::capture::ScreenBounds;
+
+/// Heatmaps wider than this are scaled down, they only need to show where the hot spots are
+const HEATMAP_WIDTH: u32 = 960;
+/// Radius of the heat an interaction leaves, in heatmap pixels
+const SPOT_RADIUS: f32 = 14.0;
+/// Most the heat covers the screen, so what was clicked stays visible underneath
+const MAX_OPACITY: f32 = 0.75;
+const BACKDROP: Rgba<u8> = Rgba([32, 32, 32, 255]);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InteractionKind {
+    Click,
+    Type,
+}
+
+impl InteractionKind {
+    /// Clicks and text input, the actions whose position says what the agent was aiming at
+    pub fn of(action: &Action) -> Option<Self> {
+        match action {
+            Action::LeftClick
+            | Action::RightClick
+            | Action::MiddleClick
+            | Action::DoubleClick
+            | Action::TripleClick
+            | Action::Click { .. }
+            | Action::ClickElement { .. }
+            | Action::Tap { .. } => Some(InteractionKind::Click),
+            Action::TypeText { .. } | Action::PasteText { .. } => Some(InteractionKind::Type),
+            _ => None,
+        }
+    }
+}
+
+/// Where a click or typed text went, in input coordinates. Text goes wherever the cursor is
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interaction {
+    pub x: i32,
+    pub y: i32,
+    pub kind: InteractionKind,
+    pub action_type: String,
+    pub succeeded: bool,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Heat of the interactions over `bounds`, drawn on a screenshot when there is one, given
+/// with the part of the desktop it shows
+pub fn render(
+    bounds: ScreenBounds,
+    interactions: &[Interaction],
+    background: Option<(&RgbaImage, ScreenBounds)>,
+) -> RgbaImage {
+    let scale = (HEATMAP_WIDTH as f32 / bounds.width.max(1) as f32).min(1.0);
+    let width = ((bounds.width as f32 * scale).round() as u32).max(1);
+    let height = ((bounds.height as f32 * scale).round() as u32).max(1);
+
+    let mut image = RgbaImage::from_pixel(width, height, BACKDROP);
+    if let Some((screen, at)) = background {
+        let size = |length: u32| ((length as f32 * scale).round() as u32).max(1);
+        let screen = imageops::resize(
+            screen,
+            size(at.width),
+            size(at.height),
+            imageops::FilterType::Triangle,
+        );
+        let offset = |position: i32, origin: i32| ((position - origin) as f32 * scale) as i64;
+        imageops::overlay(
+            &mut image,
+            &screen,
+            offset(at.x, bounds.x),
+            offset(at.y, bounds.y),
+        );
+    }
+
+    // Each interaction leaves a cone of heat, overlapping cones add up
+    let mut heat = vec![0.0f32; (width * height) as usize];
+    let reach = SPOT_RADIUS.ceil() as i64;
+    for interaction in interactions {
+        let cx = (interaction.x - bounds.x) as f32 * scale;
+        let cy = (interaction.y - bounds.y) as f32 * scale;
+        for y in (cy as i64 - reach).max(0)..(cy as i64 + reach + 1).min(height as i64) {
+            for x in (cx as i64 - reach).max(0)..(cx as i64 + reach + 1).min(width as i64) {
+                let distance = ((x as f32 - cx).powi(2) + (y as f32 - cy).powi(2)).sqrt();
+                if distance < SPOT_RADIUS {
+                    heat[y as usize * width as usize + x as usize] += 1.0 - distance / SPOT_RADIUS;
+                }
+            }
+        }
+    }
+
+    let hottest = heat.iter().copied().fold(0.0, f32::max);
+    if hottest <= 0.0 {
+        return image;
+    }
+    for (pixel, heat) in image.pixels_mut().zip(heat) {
+        if heat <= 0.0 {
+            continue;
+        }
+        let level = heat / hottest;
+        let color = ramp(level);
+        let opacity = MAX_OPACITY * level.sqrt();
+        for (channel, color) in pixel.0.iter_mut().zip(color) {
+            *channel = (*channel as f32 * (1.0 - opacity) + color as f32 * opacity).round() as u8;
+        }
+    }
+    image
+}
+
+// Blue for a little heat through green and yellow to red for the most
+fn ramp(level: f32) -> [u8; 3] {
+    const STOPS: [[f32; 3]; 4] = [
+        [0.0, 0.0, 255.0],
+        [0.0, 255.0, 0.0],
+        [255.0, 255.0, 0.0],
+        [255.0, 0.0, 0.0],
+    ];
+    let position = level.clamp(0.0, 1.0) * (STOPS.len() - 1) as f32;
+    let index = (position as usize).min(STOPS.len() - 2);
+    let t = position - index as f32;
+    let (from, to) = (STOPS[index], STOPS[index + 1]);
+    [0, 1, 2].map(|channel| (from[channel] + (to[channel] - from[channel]) * t).round() as u8)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn click(x: i32, y: i32) -> Interaction {
+        Interaction {
+            x,
+            y,
+            kind: InteractionKind::Click,
+            action_type: "left_click".to_string(),
+            succeeded: true,
+            timestamp: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_render_marks_repeated_clicks_hottest() {
+        let bounds = ScreenBounds {
+            x: -1920,
+            y: 0,
+            width: 3840,
+            height: 1080,
+        };
+        let interactions = [
+            click(100, 100),
+            click(100, 100),
+            click(100, 100),
+            click(-1000, 500),
+        ];
+        let screen = RgbaImage::from_pixel(1920, 1080, Rgba([255, 255, 255, 255]));
+        let primary = ScreenBounds {
+            x: 0,
+            y: 0,
+            width: 1920,
+            height: 1080,
+        };
+        let image = render(bounds, &interactions, Some((&screen, primary)));
+
+        assert_eq!(image.dimensions(), (960, 270));
+        // The repeated click is red, the single one cooler
+        let hot = image.get_pixel(505, 25);
+        assert!(hot.0[0] > 200 && hot.0[2] < 100, "{:?}", hot);
+        let warm = image.get_pixel(230, 125);
+        assert!(warm.0[1] > 100 && warm.0[0] < 50, "{:?}", warm);
+        // Untouched parts show the screen, or the backdrop where there is no screenshot
+        assert_eq!(*image.get_pixel(900, 250), Rgba([255, 255, 255, 255]));
+        assert_eq!(*image.get_pixel(50, 250), BACKDROP);
+    }
+}
@@ -1,4 +1,6 @@
+use axum::Json;
 use enigo::Key;
+use serde::Serialize;
 use std::str::FromStr;
 
 #[derive(Debug)]
@@ -24,14 +26,7 @@ impl FromStr for KeyPress {
 
         // Handle modifier + key combination
         for &part in &parts[..parts.len() - 1] {
-            let modifier = match part.to_lowercase().as_str() {
-                "ctrl" | "control" => Key::Control,
-                "alt" => Key::Alt,
-                "shift" => Key::Shift,
-                "super" | "win" | "windows" | "command" => Key::Meta,
-                _ => return Err(format!("Unknown modifier: {}", part)),
-            };
-            modifiers.push(modifier);
+            modifiers.push(parse_modifier(part)?);
         }
 
         Ok(KeyPress {
@@ -41,64 +36,150 @@ impl FromStr for KeyPress {
     }
 }
 
-fn parse_single_key(key: &str) -> Result<Key, String> {
-    match key.to_lowercase().as_str() {
-        // Special keys
-        "esc" | "escape" => Ok(Key::Escape),
-        "return" | "enter" => Ok(Key::Return),
-        "tab" => Ok(Key::Tab),
-        "space" => Ok(Key::Space),
-        "backspace" => Ok(Key::Backspace),
-        "up" => Ok(Key::UpArrow),
-        "down" => Ok(Key::DownArrow),
-        "left" => Ok(Key::LeftArrow),
-        "right" => Ok(Key::RightArrow),
-        "delete" => Ok(Key::Delete),
-        "insert" => Ok(Key::Insert),
-        "home" => Ok(Key::Home),
-        "end" => Ok(Key::End),
-        "pageup" => Ok(Key::PageUp),
-        "pagedown" => Ok(Key::PageDown),
-        "printscreen" => Ok(Key::PrintScr),
-        "pause" => Ok(Key::Pause),
-        "numlock" => Ok(Key::Numlock),
-        "capslock" => Ok(Key::CapsLock),
-
-        // Modifiers
-        "ctrl" | "control" => Ok(Key::Control),
-        "alt" => Ok(Key::Alt),
-        "shift" => Ok(Key::Shift),
-        "super" | "win" | "windows" | "command" => Ok(Key::Meta),
-
-        // Function keys
-        "f1" => Ok(Key::F1),
-        "f2" => Ok(Key::F2),
-        "f3" => Ok(Key::F3),
-        "f4" => Ok(Key::F4),
-        "f5" => Ok(Key::F5),
-        "f6" => Ok(Key::F6),
-        "f7" => Ok(Key::F7),
-        "f8" => Ok(Key::F8),
-        "f9" => Ok(Key::F9),
-        "f10" => Ok(Key::F10),
-        "f11" => Ok(Key::F11),
-        "f12" => Ok(Key::F12),
-
-        // Numpad keys (doesn't appear enigo handles these so just mapping them to unicode numbers)
-        "kp_0" => Ok(Key::Unicode('0')),
-        "kp_1" => Ok(Key::Unicode('1')),
-        "kp_2" => Ok(Key::Unicode('2')),
-        "kp_3" => Ok(Key::Unicode('3')),
-        "kp_4" => Ok(Key::Unicode('4')),
-        "kp_5" => Ok(Key::Unicode('5')),
-        "kp_6" => Ok(Key::Unicode('6')),
-        "kp_7" => Ok(Key::Unicode('7')),
-        "kp_8" => Ok(Key::Unicode('8')),
-        "kp_9" => Ok(Key::Unicode('9')),
+/// A key the parser accepts by name
+pub struct NamedKey {
+    /// The names it goes by, ignoring case
+    pub names: &'static [&'static str],
+    /// The key on this platform, None where the input driver doesn't have it
+    pub key: Option<Key>,
+    /// Whether it can be held in a combination like "ctrl+a"
+    pub modifier: bool,
+}
+
+const fn key(names: &'static [&'static str], key: Key) -> NamedKey {
+    NamedKey {
+        names,
+        key: Some(key),
+        modifier: false,
+    }
+}
+
+const fn modifier(names: &'static [&'static str], key: Key) -> NamedKey {
+    NamedKey {
+        names,
+        key: Some(key),
+        modifier: true,
+    }
+}
+
+// Keys enigo doesn't have on macOS
+#[cfg(not(target_os = "macos"))]
+mod pc_keys {
+    use enigo::Key;
+
+    pub const INSERT: Option<Key> = Some(Key::Insert);
+    pub const PRINT_SCREEN: Option<Key> = Some(Key::PrintScr);
+    pub const PAUSE: Option<Key> = Some(Key::Pause);
+    pub const NUMLOCK: Option<Key> = Some(Key::Numlock);
+}
+
+#[cfg(target_os = "macos")]
+mod pc_keys {
+    use enigo::Key;
+
+    pub const INSERT: Option<Key> = None;
+    pub const PRINT_SCREEN: Option<Key> = None;
+    pub const PAUSE: Option<Key> = None;
+    pub const NUMLOCK: Option<Key> = None;
+}
+
+/// Every key known by name. Any other single character is typed as that character
+pub const NAMED_KEYS: &[NamedKey] = &[
+    // Special keys
+    key(&["esc", "escape"], Key::Escape),
+    key(&["return", "enter"], Key::Return),
+    key(&["tab"], Key::Tab),
+    key(&["space"], Key::Space),
+    key(&["backspace"], Key::Backspace),
+    key(&["up"], Key::UpArrow),
+    key(&["down"], Key::DownArrow),
+    key(&["left"], Key::LeftArrow),
+    key(&["right"], Key::RightArrow),
+    key(&["delete"], Key::Delete),
+    NamedKey {
+        names: &["insert"],
+        key: pc_keys::INSERT,
+        modifier: false,
+    },
+    key(&["home"], Key::Home),
+    key(&["end"], Key::End),
+    key(&["pageup"], Key::PageUp),
+    key(&["pagedown"], Key::PageDown),
+    NamedKey {
+        names: &["printscreen"],
+        key: pc_keys::PRINT_SCREEN,
+        modifier: false,
+    },
+    NamedKey {
+        names: &["pause"],
+        key: pc_keys::PAUSE,
+        modifier: false,
+    },
+    NamedKey {
+        names: &["numlock"],
+        key: pc_keys::NUMLOCK,
+        modifier: false,
+    },
+    key(&["capslock"], Key::CapsLock),
+    // Modifiers
+    modifier(&["ctrl", "control"], Key::Control),
+    modifier(&["alt"], Key::Alt),
+    modifier(&["shift"], Key::Shift),
+    modifier(&["super", "win", "windows", "command"], Key::Meta),
+    // Function keys
+    key(&["f1"], Key::F1),
+    key(&["f2"], Key::F2),
+    key(&["f3"], Key::F3),
+    key(&["f4"], Key::F4),
+    key(&["f5"], Key::F5),
+    key(&["f6"], Key::F6),
+    key(&["f7"], Key::F7),
+    key(&["f8"], Key::F8),
+    key(&["f9"], Key::F9),
+    key(&["f10"], Key::F10),
+    key(&["f11"], Key::F11),
+    key(&["f12"], Key::F12),
+    // Numpad keys (doesn't appear enigo handles these so just mapping them to unicode numbers)
+    key(&["kp_0"], Key::Unicode('0')),
+    key(&["kp_1"], Key::Unicode('1')),
+    key(&["kp_2"], Key::Unicode('2')),
+    key(&["kp_3"], Key::Unicode('3')),
+    key(&["kp_4"], Key::Unicode('4')),
+    key(&["kp_5"], Key::Unicode('5')),
+    key(&["kp_6"], Key::Unicode('6')),
+    key(&["kp_7"], Key::Unicode('7')),
+    key(&["kp_8"], Key::Unicode('8')),
+    key(&["kp_9"], Key::Unicode('9')),
+];
+
+fn named_key(name: &str) -> Option<&'static NamedKey> {
+    let name = name.to_lowercase();
+    NAMED_KEYS
+        .iter()
+        .find(|named| named.names.contains(&name.as_str()))
+}
+
+/// A modifier key by name, e.g. "ctrl" or "shift"
+pub fn parse_modifier(modifier: &str) -> Result<Key, String> {
+    match named_key(modifier) {
+        Some(NamedKey {
+            key: Some(key),
+            modifier: true,
+            ..
+        }) => Ok(*key),
+        _ => Err(format!("Unknown modifier: {}", modifier)),
+    }
+}
 
+fn parse_single_key(key: &str) -> Result<Key, String> {
+    match named_key(key) {
+        Some(NamedKey { key: Some(key), .. }) => Ok(*key),
+        Some(NamedKey { key: None, .. }) => {
+            Err(format!("Key {} isn't supported on this platform", key))
+        }
         // Default case for Unicode characters
-        _ => {
-            if key.len() == 1 {
+        None => {
+            if key.chars().count() == 1 {
                 Ok(Key::Unicode(key.chars().next().ok_or("Invalid key {")?))
             } else {
                 Err("Invalid key".to_string())
@@ -107,10 +188,48 @@ fn parse_single_key(key: &str) -> Result<Key, String> {
     }
 }
 
+#[derive(Debug, Serialize)]
+pub struct KeyName {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub modifier: bool,
+    pub supported: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct KeyList {
+    /// The OS the server runs on, which decides what is supported
+    pub platform: &'static str,
+    /// Joins modifiers and the key in a combination, e.g. "ctrl+shift+t"
+    pub separator: &'static str,
+    /// Any other single character is accepted as a key too
+    pub single_characters: bool,
+    pub keys: Vec<KeyName>,
+}
+
+/// List every key name `key_press` accepts, so clients can keep generated key strings valid
+pub async fn list_keys() -> Json<KeyList> {
+    Json(KeyList {
+        platform: std::env::consts::OS,
+        separator: "+",
+        single_characters: true,
+        keys: NAMED_KEYS
+            .iter()
+            .map(|named| KeyName {
+                name: named.names[0],
+                aliases: &named.names[1..],
+                modifier: named.modifier,
+                supported: named.key.is_some(),
+            })
+            .collect(),
+    })
+}
+
 // Tests
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn test_single_key() {
@@ -272,4 +391,56 @@ mod tests {
             };
         }
     }
+
+    #[tokio::test]
+    async fn test_listed_keys_all_parse() {
+        let Json(list) = list_keys().await;
+        assert_eq!(list.keys.len(), NAMED_KEYS.len());
+        let escape = list.keys.iter().find(|key| key.name == "esc").unwrap();
+        assert_eq!(escape.aliases, ["escape"]);
+
+        for listed in list.keys.iter().filter(|key| key.supported) {
+            for name in std::iter::once(&listed.name).chain(listed.aliases) {
+                assert!(KeyPress::from_str(name).is_ok(), "{} was rejected", name);
+                let combo = format!("{}+a", name);
+                assert_eq!(
+                    KeyPress::from_str(&combo).is_ok(),
+                    listed.modifier,
+                    "{}",
+                    combo
+                );
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn prop_arbitrary_strings_never_panic(input in "\\PC*") {
+            let _ = KeyPress::from_str(&input);
+        }
+
+        #[test]
+        fn prop_any_single_character_is_a_key(c in any::<char>().prop_filter("separator", |c| *c != '+')) {
+            let key = KeyPress::from_str(&c.to_string());
+            prop_assert!(key.is_ok(), "{:?} was rejected", c);
+        }
+
+        #[test]
+        fn prop_modifier_combinations_parse(
+            modifiers in prop::collection::vec(
+                prop::sample::select(vec!["ctrl", "Control", "ALT", "shift", "super", "win", "command"]),
+                0..4,
+            ),
+            key in prop::sample::select(vec!["a", "Z", "7", "return", "F12", "kp_5", "pageup", "é"]),
+        ) {
+            let mut combo = modifiers.join("+");
+            if !combo.is_empty() {
+                combo.push('+');
+            }
+            combo.push_str(key);
+            let parsed = KeyPress::from_str(&combo);
+            prop_assert!(parsed.is_ok(), "{} was rejected", combo);
+            prop_assert_eq!(parsed.unwrap().modifiers.len(), modifiers.len());
+        }
+    }
 }
@@ -0,0 +1,366 @@
+//! Template matching for the `locate_image` action, pyautogui's `locateOnScreen`.
+//!
+//! Screen and template are compared in grayscale with zero-mean normalized cross-correlation,
+//! so a match scores the same whatever the brightness and contrast of the screen. Large
+//! templates are first searched at a lower resolution, then refined at full resolution around
+//! the best candidates. They are never searched at full resolution over the whole screen,
+//! which would take minutes.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use image::{imageops, GrayImage, ImageReader};
+use std::io::Cursor;
+
+use crate::action_types::{ActionError, MAX_TEMPLATE_SIDE};
+
+/// Templates are shrunk until their shorter side is about this long for the coarse search
+const COARSE_SIDE: u32 = 12;
+/// How much lower than the least confidence a coarse candidate may score, shrinking blurs
+const COARSE_SLACK: f32 = 0.25;
+/// Coarse candidates refined at full resolution per requested match
+const CANDIDATES_PER_MATCH: usize = 8;
+/// Share of its contrast a shrunk template must keep for the coarse search to find it
+const MIN_COARSE_CONTRAST: f64 = 0.1;
+
+/// Where the template's top left corner matched, in screen pixels, and how well from 0 to 1
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Found {
+    pub x: u32,
+    pub y: u32,
+    pub confidence: f32,
+}
+
+fn invalid_template(e: &dyn std::fmt::Display) -> ActionError {
+    ActionError::InvalidInput(format!("Template is not a base64 image: {}", e))
+}
+
+/// Checks a base64 template is an image no larger than MAX_TEMPLATE_SIDE, from its header
+/// alone, so an oversized one is refused before it is queued
+pub fn check_template(template: &str) -> Result<(), ActionError> {
+    let bytes = BASE64
+        .decode(template.trim())
+        .map_err(|e| invalid_template(&e))?;
+    let (width, height) = ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(|e| invalid_template(&e))?
+        .into_dimensions()
+        .map_err(|e| invalid_template(&e))?;
+    if width > MAX_TEMPLATE_SIDE || height > MAX_TEMPLATE_SIDE {
+        return Err(ActionError::InvalidInput(format!(
+            "Template is {}x{}, its sides must be at most {}",
+            width, height, MAX_TEMPLATE_SIDE
+        )));
+    }
+    Ok(())
+}
+
+/// Decodes a base64 PNG, JPEG or other image the image crate reads
+pub fn decode_template(template: &str) -> Result<GrayImage, ActionError> {
+    let bytes = BASE64
+        .decode(template.trim())
+        .map_err(|e| invalid_template(&e))?;
+    let image = image::load_from_memory(&bytes).map_err(|e| invalid_template(&e))?;
+    Ok(image.to_luma8())
+}
+
+/// The best places the template matches the screen with at least `min_confidence`, the best
+/// first and none overlapping a better one by more than half
+pub fn locate(
+    screen: &GrayImage,
+    template: &GrayImage,
+    min_confidence: f32,
+    limit: usize,
+) -> Result<Vec<Found>, ActionError> {
+    if template.width() > screen.width() || template.height() > screen.height() {
+        return Err(ActionError::InvalidInput(format!(
+            "Template is {}x{}, larger than the {}x{} area searched",
+            template.width(),
+            template.height(),
+            screen.width(),
+            screen.height()
+        )));
+    }
+    let full = Correlation::new(screen, template).ok_or_else(|| {
+        ActionError::InvalidInput(
+            "Template is a single color, there is nothing to match".to_string(),
+        )
+    })?;
+
+    let shorter_side = template.width().min(template.height());
+    let mut factor = (shorter_side / COARSE_SIDE).max(1);
+    // Shrinking blurs away fine detail, a template that only has that is shrunk less
+    let detail = contrast(template);
+    while factor > 1 && contrast(&shrink(template, factor)) < detail * MIN_COARSE_CONTRAST {
+        factor /= 2;
+    }
+    // Searching a large template at full resolution would hold the worker for minutes, so
+    // one that is flat at every coarse level isn't searched for at all
+    if factor == 1 && shorter_side >= COARSE_SIDE * 2 {
+        return Ok(Vec::new());
+    }
+    let (small_screen, small_template);
+    let coarse = match factor {
+        1 => None,
+        _ => {
+            (small_screen, small_template) = (shrink(screen, factor), shrink(template, factor));
+            Correlation::new(&small_screen, &small_template)
+        }
+    };
+    let mut candidates = match coarse {
+        Some(coarse) => {
+            let mut peaks = peaks(coarse.all(), min_confidence - COARSE_SLACK);
+            peaks.truncate(limit * CANDIDATES_PER_MATCH);
+            peaks
+                .into_iter()
+                .map(|found| full.refine(found.x * factor, found.y * factor, factor))
+                .collect()
+        }
+        None => peaks(full.all(), min_confidence),
+    };
+    candidates.retain(|found| found.confidence >= min_confidence);
+    candidates.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+
+    let (width, height) = template.dimensions();
+    let mut matches: Vec<Found> = Vec::new();
+    for found in candidates {
+        let overlaps = matches.iter().any(|better| {
+            better.x.abs_diff(found.x) * 2 < width && better.y.abs_diff(found.y) * 2 < height
+        });
+        if !overlaps {
+            matches.push(found);
+            if matches.len() == limit {
+                break;
+            }
+        }
+    }
+    Ok(matches)
+}
+
+fn shrink(image: &GrayImage, factor: u32) -> GrayImage {
+    imageops::resize(
+        image,
+        (image.width() / factor).max(1),
+        (image.height() / factor).max(1),
+        imageops::FilterType::Triangle,
+    )
+}
+
+// Root mean square deviation of the pixels from their mean
+fn contrast(image: &GrayImage) -> f64 {
+    centered(image).map_or(0.0, |(_, norm)| {
+        norm / f64::from(image.width() * image.height()).sqrt()
+    })
+}
+
+// The template's pixels minus their mean, and their norm. None when it has no contrast
+fn centered(template: &GrayImage) -> Option<(Vec<f64>, f64)> {
+    let pixels: Vec<f64> = template.pixels().map(|p| f64::from(p.0[0])).collect();
+    let mean = pixels.iter().sum::<f64>() / pixels.len() as f64;
+    let centered: Vec<f64> = pixels.iter().map(|p| p - mean).collect();
+    let norm = centered.iter().map(|p| p * p).sum::<f64>().sqrt();
+    (norm >= 1e-6).then_some((centered, norm))
+}
+
+// Positions scoring at least `min` and no worse than any neighbour, the best first
+fn peaks(scores: Vec<Vec<f32>>, min: f32) -> Vec<Found> {
+    let score = |x: usize, y: usize| scores[y][x];
+    let mut peaks = Vec::new();
+    for (y, row) in scores.iter().enumerate() {
+        for (x, &confidence) in row.iter().enumerate() {
+            if confidence < min {
+                continue;
+            }
+            let higher_neighbour = (y.saturating_sub(1)..(y + 2).min(scores.len())).any(|ny| {
+                (x.saturating_sub(1)..(x + 2).min(row.len())).any(|nx| score(nx, ny) > confidence)
+            });
+            if !higher_neighbour {
+                peaks.push(Found {
+                    x: x as u32,
+                    y: y as u32,
+                    confidence,
+                });
+            }
+        }
+    }
+    peaks.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+    peaks
+}
+
+// Scores template positions on a screen. Sums over the screen area under the template come
+// from summed-area tables, only the product with the template is summed per position
+struct Correlation<'a> {
+    screen: &'a GrayImage,
+    // The template minus its mean, so the screen's mean cancels out of the product
+    template: Vec<f64>,
+    template_norm: f64,
+    width: u32,
+    height: u32,
+    sums: Vec<f64>,
+    squares: Vec<f64>,
+}
+
+impl<'a> Correlation<'a> {
+    // None when the template has no contrast
+    fn new(screen: &'a GrayImage, template: &GrayImage) -> Option<Self> {
+        let (template_pixels, template_norm) = centered(template)?;
+
+        let stride = screen.width() as usize + 1;
+        let mut sums = vec![0.0; stride * (screen.height() as usize + 1)];
+        let mut squares = sums.clone();
+        for (x, y, pixel) in screen.enumerate_pixels() {
+            let value = f64::from(pixel.0[0]);
+            let i = (y as usize + 1) * stride + x as usize + 1;
+            sums[i] = value + sums[i - 1] + sums[i - stride] - sums[i - stride - 1];
+            squares[i] =
+                value * value + squares[i - 1] + squares[i - stride] - squares[i - stride - 1];
+        }
+        Some(Self {
+            screen,
+            template: template_pixels,
+            template_norm,
+            width: template.width(),
+            height: template.height(),
+            sums,
+            squares,
+        })
+    }
+
+    // Sum over the screen area under the template at (x, y)
+    fn area(&self, table: &[f64], x: u32, y: u32) -> f64 {
+        let stride = self.screen.width() as usize + 1;
+        let (left, top) = (x as usize, y as usize);
+        let (right, bottom) = (left + self.width as usize, top + self.height as usize);
+        table[bottom * stride + right] - table[top * stride + right] - table[bottom * stride + left]
+            + table[top * stride + left]
+    }
+
+    fn score(&self, x: u32, y: u32) -> f32 {
+        let count = self.template.len() as f64;
+        let sum = self.area(&self.sums, x, y);
+        let variance = self.area(&self.squares, x, y) - sum * sum / count;
+        // A flat screen area matches nothing
+        if variance < 1e-6 {
+            return 0.0;
+        }
+        let mut product = 0.0;
+        let mut template = self.template.iter();
+        for row in y..y + self.height {
+            for column in x..x + self.width {
+                let value = f64::from(self.screen.get_pixel(column, row).0[0]);
+                product += value * template.next().copied().unwrap_or_default();
+            }
+        }
+        (product / (variance.sqrt() * self.template_norm)) as f32
+    }
+
+    // Scores of every position the template fits at, by row
+    fn all(&self) -> Vec<Vec<f32>> {
+        (0..=self.screen.height() - self.height)
+            .map(|y| {
+                (0..=self.screen.width() - self.width)
+                    .map(|x| self.score(x, y))
+                    .collect()
+            })
+            .collect()
+    }
+
+    // The best position at most `radius` away from (x, y)
+    fn refine(&self, x: u32, y: u32, radius: u32) -> Found {
+        let (max_x, max_y) = (
+            self.screen.width() - self.width,
+            self.screen.height() - self.height,
+        );
+        let mut best = Found {
+            x: x.min(max_x),
+            y: y.min(max_y),
+            confidence: f32::MIN,
+        };
+        for y in y.saturating_sub(radius)..=(y + radius).min(max_y) {
+            for x in x.saturating_sub(radius)..=(x + radius).min(max_x) {
+                let confidence = self.score(x, y);
+                if confidence > best.confidence {
+                    best = Found { x, y, confidence };
+                }
+            }
+        }
+        best
+    }
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    // A noisy background with two copies of a small pattern, one of them dimmer
+    fn screen_with_pattern(pattern: &GrayImage) -> GrayImage {
+        let mut screen = GrayImage::from_fn(240, 160, |x, y| Luma([((x * 7 + y * 13) % 50) as u8]));
+        for (x, y, pixel) in pattern.enumerate_pixels() {
+            screen.put_pixel(30 + x, 20 + y, *pixel);
+            screen.put_pixel(150 + x, 100 + y, Luma([pixel.0[0] / 2 + 40]));
+        }
+        screen
+    }
+
+    #[test]
+    fn test_locate_finds_every_copy() {
+        let pattern = GrayImage::from_fn(10, 8, |x, y| {
+            Luma([if (x + y) % 3 == 0 { 250 } else { 90 }])
+        });
+        let screen = screen_with_pattern(&pattern);
+
+        let found = locate(&screen, &pattern, 0.9, 5).unwrap();
+        assert_eq!(found.len(), 2);
+        assert_eq!((found[0].x, found[0].y), (30, 20));
+        assert!(found[0].confidence > 0.99);
+        // Brightness and contrast don't matter
+        assert_eq!((found[1].x, found[1].y), (150, 100));
+        assert!(found[1].confidence > 0.99);
+        assert_eq!(locate(&screen, &pattern, 0.9, 1).unwrap().len(), 1);
+
+        let flat = GrayImage::from_pixel(10, 8, Luma([128]));
+        assert!(matches!(
+            locate(&screen, &flat, 0.9, 1),
+            Err(ActionError::InvalidInput(_))
+        ));
+        assert!(locate(&pattern, &screen, 0.9, 1).is_err());
+    }
+
+    #[test]
+    fn test_locate_large_template_searches_coarse_first() {
+        let pattern = GrayImage::from_fn(40, 30, |x, y| {
+            Luma([if (x / 5 + y / 5) % 2 == 0 { 220 } else { 30 }])
+        });
+        let screen = screen_with_pattern(&pattern);
+
+        let found = locate(&screen, &pattern, 0.9, 3).unwrap();
+        let positions: Vec<(u32, u32)> = found.iter().map(|found| (found.x, found.y)).collect();
+        assert_eq!(positions, [(30, 20), (150, 100)]);
+    }
+
+    #[test]
+    fn test_locate_large_template_without_coarse_detail_finds_nothing() {
+        // Single pixel stripes average out to gray at every coarse level
+        let pattern = GrayImage::from_fn(40, 30, |x, _| Luma([if x % 2 == 0 { 220 } else { 30 }]));
+        let screen = screen_with_pattern(&pattern);
+
+        assert!(locate(&screen, &pattern, 0.9, 1).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_oversized_templates_are_refused() {
+        let encode = |side: u32| {
+            let mut png = Vec::new();
+            GrayImage::new(side, side)
+                .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+                .unwrap();
+            BASE64.encode(png)
+        };
+        assert!(check_template(&encode(MAX_TEMPLATE_SIDE)).is_ok());
+        assert!(matches!(
+            check_template(&encode(MAX_TEMPLATE_SIDE + 1)),
+            Err(ActionError::InvalidInput(_))
+        ));
+        assert!(check_template("not an image").is_err());
+    }
+}
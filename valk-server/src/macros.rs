@@ -0,0 +1,60 @@
+//! Macros, named lists of actions kept in the policy files of `VALK_POLICY_DIR` and run as a
+//! batch, so a deployment can ship its automation along with its rules.
+
+use axum::{
+    extract::{self, rejection::JsonRejection, Path},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::action_types::{Action, ActionRequest, OnTimeout};
+use crate::AppState;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct RunMacroBody {
+    /// Run the macro's actions count against
+    pub run_id: Option<String>,
+}
+
+/// `GET /v1/macros`, the macros the policy files define, by name
+pub async fn list_macros(
+    extract::State(state): extract::State<Arc<AppState>>,
+) -> Json<BTreeMap<String, Vec<Action>>> {
+    Json(state.action_queue.policy().macros())
+}
+
+/// `POST /v1/macros/{name}`, run a macro's actions as one batch
+pub async fn run_macro(
+    extract::State(state): extract::State<Arc<AppState>>,
+    Path(name): Path<String>,
+    // The body is optional, without one the macro runs outside of any run
+    body: Result<Json<RunMacroBody>, JsonRejection>,
+) -> Response {
+    let run_id = body.ok().and_then(|Json(body)| body.run_id);
+    let Some(actions) = state.action_queue.policy().macros().remove(&name) else {
+        return (StatusCode::NOT_FOUND, format!("No macro named {}", name)).into_response();
+    };
+    // The macro's name goes in the metadata, so monitors and run histories can tell its
+    // actions apart from the client's own
+    let mut metadata = serde_json::Map::new();
+    metadata.insert("macro".to_string(), json!(name));
+    let requests = actions
+        .into_iter()
+        .enumerate()
+        .map(|(index, action)| ActionRequest {
+            id: format!("{}-{}", name, index + 1),
+            action,
+            run_id: run_id.clone(),
+            task_id: None,
+            metadata: Some(metadata.clone()),
+            timeout_ms: None,
+            on_timeout: OnTimeout::Abort,
+        })
+        .collect();
+    crate::batch_response(state.action_queue.execute_batch(requests).await)
+}
@@ -1,97 +1,1489 @@
 use axum::{
-    extract::{self, Request},
-    http::StatusCode,
-    response::Response,
-    routing::{get, post},
+    extract::{self, rejection::JsonRejection, Request},
+    http::{StatusCode, Uri},
+    response::{IntoResponse, Response},
+    routing::{get, post, put},
     Json, Router,
 };
+use futures_util::{stream, StreamExt};
+use serde::Deserialize;
 use std::time::Duration;
 
 use std::sync::Arc;
 
-use tower_http::trace::{self, TraceLayer};
-use tracing::{info, Level, Span};
+use tower_http::trace::TraceLayer;
+use tracing::{error, info, warn, Span};
 
+mod accessibility;
 mod action_queue;
 mod action_types;
+mod agent;
+mod annotate;
+mod approvals;
+mod arming;
+mod authorization;
+mod bandwidth;
+mod calibration;
+mod capture;
+mod clipboard;
 mod config;
+mod cursor;
+mod display;
+mod dry_run;
+mod fair_queue;
+mod files;
+mod handover;
+mod heatmap;
 mod key_press;
+mod locate;
+mod macros;
+mod metrics;
 mod monitor;
+mod observation;
+mod observe;
+mod ocr;
+mod pacing;
+mod policy;
+mod processes;
+mod proxy;
+mod relay;
+mod runs;
+#[cfg(feature = "self-update")]
+mod self_update;
+mod simulation;
+mod startup;
+mod state;
 mod system_info;
+mod takeover;
+mod tool_schema;
+mod touch;
+mod trajectory;
+#[cfg(target_os = "linux")]
+mod uinput;
+mod version;
+#[cfg(target_os = "linux")]
+mod x11;
 
 use action_queue::{create_action_queue, SharedQueue};
 use action_types::{ActionError, ActionRequest, ActionResponse, ActionResponseStatus};
+use agent::{get_agent_run, run_agent, stop_agent_run};
+use approvals::{approve, list_approvals, reject};
+use calibration::{get_calibration, put_calibration};
 use config::Config;
+use cursor::cursor;
+use display::display_status;
+use macros::{list_macros, run_macro};
+use metrics::metrics;
 use monitor::monitor_websocket;
+use observe::{observe, observe_text};
+use relay::{list_peers, relay_action, relay_connect};
+use runs::{close_run, get_run, list_runs, open_run, run_baseline, run_heatmap, run_stats};
 use system_info::system_info;
+use takeover::takeover_websocket;
+use tool_schema::OpenAiToolCall;
+use trajectory::run_trajectory;
 
 async fn root() -> &'static str {
     "Valk is running"
 }
 
+#[derive(Debug, Default, Deserialize)]
+struct ActionParams {
+    /// Validate and describe the action without executing it
+    #[serde(default)]
+    dry_run: bool,
+    /// With dry_run, also return a screenshot with the planned interaction drawn on it
+    #[serde(default)]
+    preview_image: bool,
+}
+
 /// A single RCP style action request.
 async fn action(
     extract::State(state): extract::State<Arc<AppState>>,
-    Json(request): Json<ActionRequest>,
-) -> Result<Json<ActionResponse>, (StatusCode, Json<ActionResponse>)> {
-    // Convert application errors to appropriate HTTP status codes
+    extract::Query(params): extract::Query<ActionParams>,
+    request: Result<Json<ActionRequest>, JsonRejection>,
+) -> Response {
+    let Json(request) = match request {
+        Ok(request) => request,
+        Err(rejection) => return rejection_response(rejection),
+    };
+    if params.dry_run {
+        let preview = state
+            .action_queue
+            .dry_run(&request, params.preview_image)
+            .await;
+        let status_code = if preview.valid {
+            StatusCode::OK
+        } else {
+            StatusCode::UNPROCESSABLE_ENTITY
+        };
+        return (status_code, Json(preview)).into_response();
+    }
+
     let response = state.action_queue.execute_action(request).await;
+    (response_status(&response), Json(response)).into_response()
+}
 
-    match response.status {
-        ActionResponseStatus::Success => Ok(Json(response)),
-        ActionResponseStatus::Error => {
-            let status_code = match &response.error {
-                Some(ActionError::InvalidInput(_)) => StatusCode::UNPROCESSABLE_ENTITY,
-                Some(ActionError::Timeout) => StatusCode::REQUEST_TIMEOUT,
-                Some(ActionError::ExecutionFailed(_)) => StatusCode::INTERNAL_SERVER_ERROR,
-                Some(ActionError::ChannelError(_)) => StatusCode::INTERNAL_SERVER_ERROR,
-                None => StatusCode::INTERNAL_SERVER_ERROR,
-            };
-            Err((status_code, Json(response)))
+#[derive(Debug, Default, Deserialize)]
+struct ToolCallParams {
+    /// Run the action belongs to
+    run_id: Option<String>,
+}
+
+/// Run an OpenAI tool call of one of the tools from `/v1/tools/openai` as its action
+async fn openai_tool_call(
+    extract::State(state): extract::State<Arc<AppState>>,
+    extract::Query(params): extract::Query<ToolCallParams>,
+    call: Result<Json<OpenAiToolCall>, JsonRejection>,
+) -> Response {
+    let Json(call) = match call {
+        Ok(call) => call,
+        Err(rejection) => return rejection_response(rejection),
+    };
+    let request = match call.into_request(params.run_id) {
+        Ok(request) => request,
+        Err(error) => {
+            return (
+                error_status(Some(&error)),
+                Json(serde_json::json!({"status": "error", "error": error})),
+            )
+                .into_response()
+        }
+    };
+    let response = state.action_queue.execute_action(request).await;
+    (response_status(&response), Json(response)).into_response()
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct BatchParams {
+    /// Send each response as a line of NDJSON as soon as its action is done
+    #[serde(default)]
+    stream: bool,
+}
+
+/// An ordered list of action requests, run as one unit without other clients' actions in between
+async fn action_batch(
+    extract::State(state): extract::State<Arc<AppState>>,
+    extract::Query(params): extract::Query<BatchParams>,
+    requests: Result<Json<Vec<ActionRequest>>, JsonRejection>,
+) -> Response {
+    let Json(requests) = match requests {
+        Ok(requests) => requests,
+        Err(rejection) => return rejection_response(rejection),
+    };
+    if params.stream {
+        return stream_batch(state, requests).await;
+    }
+
+    batch_response(state.action_queue.execute_batch(requests).await)
+}
+
+// The responses of a batch with the status of the first failure, the same one it would get
+// on its own. Actions that didn't run because another was refused say nothing about the batch
+fn batch_response(result: Result<Vec<ActionResponse>, ActionError>) -> Response {
+    match result {
+        Ok(responses) => {
+            let status_code = responses
+                .iter()
+                .find(|response| {
+                    matches!(response.status, ActionResponseStatus::Error)
+                        && !matches!(response.error, Some(ActionError::NotRun(_)))
+                })
+                .map_or(StatusCode::OK, |response| {
+                    error_status(response.error.as_ref())
+                });
+            (status_code, Json(responses)).into_response()
         }
+        Err(error) => (
+            error_status(Some(&error)),
+            Json(serde_json::json!({"status": "error", "error": error})),
+        )
+            .into_response(),
     }
 }
 
+// A batch's responses as NDJSON, one line per action as it finishes. The status is sent
+// before the rest are done, so it only reflects whether the batch was taken, and closing the
+// connection skips the actions that haven't started
+async fn stream_batch(state: Arc<AppState>, requests: Vec<ActionRequest>) -> Response {
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let queue = state.action_queue.clone();
+    tokio::spawn(async move {
+        let result = queue
+            .execute_batch_each(requests, |response| tx.send(Ok(response)).is_ok())
+            .await;
+        if let Err(error) = result {
+            let _ = tx.send(Err(error));
+        }
+    });
+
+    let first = match rx.recv().await {
+        Some(Ok(response)) => response,
+        Some(Err(error)) => {
+            return (
+                error_status(Some(&error)),
+                Json(serde_json::json!({"status": "error", "error": error})),
+            )
+                .into_response()
+        }
+        None => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+    let rest = stream::unfold(rx, |mut rx| async move {
+        match rx.recv().await {
+            Some(Ok(response)) => Some((response, rx)),
+            _ => None,
+        }
+    });
+    let lines = stream::once(async { first }).chain(rest).map(|response| {
+        let mut line = serde_json::to_vec(&response).unwrap_or_default();
+        line.push(b'\n');
+        Ok::<_, std::convert::Infallible>(line)
+    });
+    (
+        [(axum::http::header::CONTENT_TYPE, "application/x-ndjson")],
+        axum::body::Body::from_stream(lines),
+    )
+        .into_response()
+}
+
+// Convert application errors to appropriate HTTP status codes
+fn response_status(response: &ActionResponse) -> StatusCode {
+    match response.status {
+        ActionResponseStatus::Success => StatusCode::OK,
+        ActionResponseStatus::Error => error_status(response.error.as_ref()),
+    }
+}
+
+fn error_status(error: Option<&ActionError>) -> StatusCode {
+    match error {
+        Some(ActionError::InvalidInput(_)) => StatusCode::UNPROCESSABLE_ENTITY,
+        Some(ActionError::Timeout) => StatusCode::REQUEST_TIMEOUT,
+        Some(ActionError::ExecutionFailed(_)) => StatusCode::INTERNAL_SERVER_ERROR,
+        Some(ActionError::ChannelError(_)) => StatusCode::INTERNAL_SERVER_ERROR,
+        Some(ActionError::PermissionDenied(_)) => StatusCode::FORBIDDEN,
+        Some(ActionError::BudgetExceeded(_)) => StatusCode::TOO_MANY_REQUESTS,
+        Some(ActionError::QueueFull(_)) => StatusCode::TOO_MANY_REQUESTS,
+        Some(ActionError::RateLimited(_)) => StatusCode::TOO_MANY_REQUESTS,
+        Some(ActionError::NotFound(_)) => StatusCode::NOT_FOUND,
+        Some(ActionError::DisplayUnavailable(_)) => StatusCode::SERVICE_UNAVAILABLE,
+        Some(ActionError::StaleTarget { .. }) => StatusCode::CONFLICT,
+        Some(ActionError::Restarting(_)) => StatusCode::SERVICE_UNAVAILABLE,
+        Some(ActionError::NotRun(_)) => StatusCode::FAILED_DEPENDENCY,
+        Some(ActionError::ServiceUnavailable(_)) => StatusCode::SERVICE_UNAVAILABLE,
+        Some(ActionError::Unsupported(_)) => StatusCode::NOT_IMPLEMENTED,
+        None => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+// Requests usually come straight from model output, so malformed ones get the same
+// error shape as any other invalid input rather than axum's plain text rejection
+fn rejection_response(rejection: JsonRejection) -> Response {
+    let error = ActionError::InvalidInput(rejection.body_text());
+    (
+        rejection.status(),
+        Json(serde_json::json!({"status": "error", "error": error})),
+    )
+        .into_response()
+}
+
 #[derive(Clone)]
 struct AppState {
     action_queue: SharedQueue,
 }
 
+/// Every route of the API, without the tracing layer
+fn router(state: Arc<AppState>) -> Router {
+    read_only_routes()
+        .route("/v1/action", post(action))
+        .route("/v1/arm", post(arming::arm))
+        .route("/v1/files/list", get(files::list_files))
+        .route("/v1/disarm", post(arming::disarm))
+        .route("/v1/actions/batch", post(action_batch))
+        .route("/v1/tools/openai/call", post(openai_tool_call))
+        .route("/v1/takeover", get(takeover_websocket))
+        .route("/v1/approvals/{id}/approve", post(approve))
+        .route("/v1/approvals/{id}/reject", post(reject))
+        .route("/v1/macros/{name}", post(run_macro))
+        .route("/v1/runs", post(open_run))
+        .route("/v1/runs/{id}/close", post(close_run))
+        .route("/v1/calibration", put(put_calibration))
+        .route("/v1/relay", get(list_peers))
+        .route("/v1/relay/connect", get(relay_connect))
+        .route("/v1/relay/{*route}", post(relay_action))
+        .route("/v1/agent/run", post(run_agent))
+        .route("/v1/agent/runs/{id}/stop", post(stop_agent_run))
+        .with_state(state)
+}
+
+/// The read-only mirror served on VALK_MIRROR_PORT, it has no route that can change anything
+fn mirror_router(state: Arc<AppState>) -> Router {
+    read_only_routes().with_state(state)
+}
+
+// Routes that only observe the screen, the queue and run history
+fn read_only_routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/", get(root))
+        .route("/v1/system/info", get(system_info))
+        .route("/v1/system/display", get(display_status))
+        .route("/v1/system/processes", get(processes::processes))
+        .route("/v1/arm", get(arming::arm_status))
+        .route("/v1/version", get(version::version))
+        .route("/v1/keys", get(key_press::list_keys))
+        .route("/v1/tool_schema", get(tool_schema::tool_schema))
+        .route("/v1/tools/openai", get(tool_schema::openai_tools))
+        .route("/v1/cursor", get(cursor))
+        .route("/v1/observe", get(observe))
+        .route("/v1/observe/text", get(observe_text))
+        .route(
+            "/v1/accessibility/tree",
+            get(accessibility::accessibility_tree),
+        )
+        .route("/v1/monitor", get(monitor_websocket))
+        .route("/metrics", get(metrics))
+        .route("/v1/approvals", get(list_approvals))
+        .route("/v1/macros", get(list_macros))
+        .route("/v1/runs", get(list_runs))
+        .route("/v1/runs/{id}", get(get_run))
+        .route("/v1/runs/{id}/stats", get(run_stats))
+        .route("/v1/runs/{id}/stats/heatmap", get(run_heatmap))
+        .route("/v1/runs/{id}/baseline", get(run_baseline))
+        .route("/v1/runs/{id}/trajectory", get(run_trajectory))
+        .route("/v1/calibration", get(get_calibration))
+        .route("/v1/agent/runs/{id}", get(get_agent_run))
+}
+
+// The takeover token may come in the query, as browsers can't set headers on WebSockets, so
+// that route is logged without it
+fn logged_uri(uri: &Uri) -> String {
+    match uri.query() {
+        Some(_) if uri.path() == "/v1/takeover" => format!("{}?[redacted]", uri.path()),
+        _ => uri.to_string(),
+    }
+}
+
+fn with_tracing(router: Router) -> Router {
+    router.layer(
+        TraceLayer::new_for_http()
+            .make_span_with(|request: &Request<_>| {
+                tracing::info_span!(
+                    "request",
+                    method = %request.method(),
+                    uri = %logged_uri(request.uri()),
+                    version = ?request.version(),
+                )
+            })
+            .on_request(|request: &Request<_>, _span: &Span| {
+                info!(
+                    "Request: {} {}",
+                    request.method(),
+                    logged_uri(request.uri())
+                );
+            })
+            .on_response(|response: &Response<_>, latency: Duration, _span: &Span| {
+                info!("Response: {} ({:?})", response.status(), latency);
+            }),
+    )
+}
+
 #[tokio::main]
 async fn main() {
-    let config = Config::new();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("self-update") {
+        std::process::exit(self_update(args[1..].to_vec()).await);
+    }
 
-    // Initialize tracing
+    // Initialize tracing, before the config so problems reading it are logged
     tracing_subscriber::fmt()
         .with_target(false)
         .with_level(true)
         .init();
 
-    let action_queue: SharedQueue = create_action_queue().await;
+    let config = Config::new();
+
+    // Held until the server exits, so a second server can't drive the same display
+    let state_dir = config
+        .state_dir
+        .clone()
+        .map_or_else(|| state::default_state_dir(&config), Into::into);
+    let state_dir = match state::StateDir::acquire(&state_dir) {
+        Ok(state_dir) => state_dir,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    info!("Using state dir {}", state_dir.path().display());
+    let config = Config {
+        state_dir: Some(state_dir.path().to_string_lossy().into_owned()),
+        ..config
+    };
+    // The same display the queue starts from, its watch reads DISPLAY once
+    let fingerprint =
+        calibration::fingerprint(&config, &Arc::new(display::DisplayWatch::default()));
+    let config = match calibration::load(state_dir.path(), &fingerprint) {
+        Ok(Some(calibration)) => {
+            info!("Applying saved calibration {}", fingerprint);
+            calibration.apply(config)
+        }
+        Ok(None) => config,
+        Err(e) => {
+            warn!("Ignoring saved calibration: {}", e);
+            config
+        }
+    };
+
+    let action_queue: SharedQueue = match create_action_queue(&config).await {
+        Ok(action_queue) => action_queue,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    match handover::resume_journal(state_dir.path(), action_queue.runs()) {
+        Ok(0) => {}
+        Ok(resumed) => info!("Resumed {} open runs from the journal", resumed),
+        Err(e) => warn!("Not resuming runs: {}", e),
+    }
+
+    // Run before listening, so clients find the machine in its provisioned state
+    if let Some(path) = &config.startup_actions {
+        match startup::load(std::path::Path::new(path)) {
+            Ok(actions) => {
+                info!("Running {} startup actions from {}", actions.len(), path);
+                startup::run(&action_queue, actions).await;
+            }
+            Err(e) => error!("Not running startup actions: {}", e),
+        }
+    }
+
+    // Startup actions are the operator's own, the interlock is for clients
+    if config.arm_token.is_some() {
+        action_queue.set_armed(false);
+        info!("Disarmed until POST /v1/arm, only read-only actions are accepted");
+    }
+
+    if config.approver_token.is_none() && !config.approval_actions.is_empty() {
+        warn!("VALK_APPROVER_TOKEN is unset, actions that need approval will time out");
+    }
+
+    if config.relay_upstream.is_some() {
+        tokio::spawn(relay::connect_upstream(action_queue.clone()));
+    }
 
     let state = Arc::new(AppState { action_queue });
 
-    let app = Router::new()
-        .route("/", get(root))
-        .route("/v1/system/info", get(system_info))
-        .route("/v1/action", post(action))
-        .route("/v1/monitor", get(monitor_websocket))
-        .with_state(state)
-        // Trace layer
-        .layer(
-            TraceLayer::new_for_http()
-                .make_span_with(trace::DefaultMakeSpan::new().level(Level::INFO))
-                .on_request(|request: &Request<_>, _span: &Span| {
-                    info!("Request: {} {}", request.method(), request.uri());
-                })
-                .on_response(|response: &Response<_>, latency: Duration, _span: &Span| {
-                    info!("Response: {} ({:?})", response.status(), latency);
-                }),
-        );
+    // The main port's socket comes first, then the mirror's
+    let mut inherited = handover::inherited_listeners().into_iter();
+    let bind = |port: u16, inherited: Option<std::net::TcpListener>| {
+        let address = format!("{}:{}", config.host, port);
+        async move {
+            match inherited {
+                Some(listener) => tokio::net::TcpListener::from_std(listener),
+                None => tokio::net::TcpListener::bind(address).await,
+            }
+        }
+    };
+    let main_listener = inherited.next();
+    if main_listener.is_some() {
+        info!("Serving on the socket passed by systemd");
+    }
+
+    if let Some(port) = config.mirror_port {
+        let mirror = with_tracing(mirror_router(state.clone()));
+        let listener = bind(port, inherited.next()).await.unwrap();
+        info!("Serving the read-only mirror on port {}", port);
+        tokio::spawn(async move { axum::serve(listener, mirror).await });
+    }
+
+    let queue = state.action_queue.clone();
+    let app = with_tracing(router(state));
 
     // run our app with hyper, listening globally on port 3000
-    let listener = tokio::net::TcpListener::bind(format!("{}:{}", config.host, config.port))
+    let listener = bind(config.port, main_listener).await.unwrap();
+    // On SIGTERM the queue pauses and requests already received are answered, queued
+    // actions with a restarting error for the client to send again
+    // The peer address tells arming requests made on the machine itself
+    let terminated = handover::terminated();
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+    )
+    .with_graceful_shutdown({
+        let queue = queue.clone();
+        async move {
+            terminated.await;
+            queue.pause();
+        }
+    })
+    .await
+    .unwrap();
+    queue.until_idle().await;
+    match handover::save_journal(state_dir.path(), queue.runs()) {
+        Ok(saved) => info!(
+            "Journaled {} open runs for the next process, exiting",
+            saved
+        ),
+        Err(e) => error!("Open runs are lost: {}", e),
+    }
+}
+
+#[cfg(feature = "self-update")]
+async fn self_update(args: Vec<String>) -> i32 {
+    match tokio::task::spawn_blocking(move || self_update::run(&args)).await {
+        Ok(Ok(())) => 0,
+        Ok(Err(e)) => {
+            eprintln!("{}", e);
+            1
+        }
+        Err(e) => {
+            eprintln!("Update failed: {}", e);
+            1
+        }
+    }
+}
+
+#[cfg(not(feature = "self-update"))]
+async fn self_update(_args: Vec<String>) -> i32 {
+    eprintln!("valk-server was built without the self-update feature");
+    2
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use action_queue::tests::{mock_capture, MockEnigo};
+    use action_queue::{ActionQueue, InputDriver};
+    use axum::body::Body;
+    use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+    use config::InputBackend;
+    use futures_util::{SinkExt, StreamExt};
+    use http_body_util::BodyExt;
+    use serde_json::{json, Value};
+    use tokio_tungstenite::tungstenite::{client::IntoClientRequest, Message};
+    use tower::ServiceExt;
+
+    /// The full app, routing and serialization included, backed by the mock input driver
+    async fn test_app() -> Router {
+        test_app_with_config(Config::default()).await
+    }
+
+    async fn test_app_with_config(config: Config) -> Router {
+        let input_driver: Box<dyn InputDriver> = Box::new(MockEnigo::new());
+        let action_queue =
+            Arc::new(ActionQueue::new(input_driver, config).with_capture(mock_capture()));
+        action_queue.start_processing().await;
+        router(Arc::new(AppState { action_queue }))
+    }
+
+    async fn send_raw(app: &Router, method: &str, uri: &str, body: String) -> (StatusCode, Value) {
+        let request = axum::http::Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(body))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    async fn send(app: &Router, method: &str, uri: &str, body: Value) -> (StatusCode, Value) {
+        send_raw(app, method, uri, body.to_string()).await
+    }
+
+    async fn post_action(app: &Router, action: Value) -> (StatusCode, Value) {
+        send(
+            app,
+            "POST",
+            "/v1/action",
+            json!({"id": "test", "action": action}),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_action_round_trip() {
+        let app = test_app().await;
+
+        let (status, body) = post_action(&app, json!({"type": "ping"})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "success");
+        assert_eq!(body["request_id"], "test");
+        assert_eq!(body["action"]["type"], "ping");
+        assert!(body["timing"]["queued_ms"].is_u64());
+
+        let (status, body) = post_action(
+            &app,
+            json!({"type": "mouse_move", "input": {"x": 30, "y": 40}}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "success");
+
+        let (status, body) = post_action(&app, json!({"type": "screenshot"})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body["data"]["image"].is_string());
+        assert_eq!(body["data"]["width"], 1920);
+        assert_eq!(body["data"]["height"], 1080);
+        assert!(body["data"]["captured_at"].is_string());
+
+        let (status, body) = send(&app, "GET", "/v1/system/info", Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["display_width"], 1920);
+
+        let (status, body) = send(&app, "GET", "/v1/version", Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+        assert!(body["target"].is_string());
+    }
+
+    #[tokio::test]
+    async fn test_tool_schema() {
+        let app = test_app().await;
+
+        let (status, body) = send(&app, "GET", "/v1/tool_schema", Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["screen"], json!({"width": 1920, "height": 1080}));
+        assert_eq!(body["limits"]["max_queue_depth"], 32);
+        assert_eq!(body["tools"][0]["name"], "left_click");
+        assert!(body["tools"][0]["input_schema"].is_object());
+
+        let uri = "/v1/tool_schema?format=openai";
+        let (status, body) = send(&app, "GET", uri, Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["tools"][0]["type"], "function");
+        assert_eq!(body["tools"][0]["function"]["name"], "left_click");
+
+        let (status, tools) = send(&app, "GET", "/v1/tools/openai", Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(tools, body["tools"]);
+    }
+
+    #[tokio::test]
+    async fn test_openai_tool_call() {
+        let app = test_app().await;
+        let call = |name: &str, arguments: &str| {
+            json!({
+                "id": "call_1",
+                "type": "function",
+                "function": {"name": name, "arguments": arguments},
+            })
+        };
+
+        let move_to = call("mouse_move", r#"{"x": 30, "y": 40}"#);
+        let (status, body) = send(&app, "POST", "/v1/tools/openai/call", move_to).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["request_id"], "call_1");
+        assert_eq!(body["status"], "success");
+
+        let (status, body) = send(
+            &app,
+            "POST",
+            "/v1/tools/openai/call",
+            call("cursor_position", ""),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"], json!({"x": 30, "y": 40}));
+
+        let (status, body) = send(
+            &app,
+            "POST",
+            "/v1/tools/openai/call",
+            call("mouse_move", r#"{"x": 30}"#),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["error"]["type"], "invalid_input");
+    }
+
+    #[tokio::test]
+    async fn test_batch_refused_past_its_first_action() {
+        let input_driver: Box<dyn InputDriver> = Box::new(MockEnigo::new());
+        let config = Config {
+            approval_actions: vec!["left_click".to_string()],
+            approver_token: Some("s3cret".to_string()),
+            ..Config::default()
+        };
+        let action_queue =
+            Arc::new(ActionQueue::new(input_driver, config).with_capture(mock_capture()));
+        action_queue.start_processing().await;
+        let app = router(Arc::new(AppState { action_queue }));
+
+        // The click would need an approval, but the batch is refused before anyone is asked
+        let batch = json!([
+            {"id": "click", "action": {"type": "left_click"}},
+            {"id": "type", "action": {"type": "type_text", "input": {"text": ""}}},
+        ]);
+        let (status, body) = tokio::time::timeout(
+            Duration::from_secs(5),
+            send(&app, "POST", "/v1/actions/batch", batch),
+        )
         .await
+        .expect("the batch waited for an approval");
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body[0]["error"]["type"], "not_run");
+        assert_eq!(body[1]["error"]["type"], "invalid_input");
+        let (_, body) = send(&app, "GET", "/v1/approvals", Value::Null).await;
+        assert_eq!(body, json!([]));
+    }
+
+    #[tokio::test]
+    async fn test_streamed_batch_sends_a_line_per_action() {
+        let app = test_app().await;
+
+        let batch = json!([
+            {"id": "first", "action": {"type": "ping"}},
+            {"id": "second", "action": {"type": "restore_cursor"}},
+            {"id": "third", "action": {"type": "ping"}},
+        ]);
+        let request = axum::http::Request::builder()
+            .method("POST")
+            .uri("/v1/actions/batch?stream=true")
+            .header("content-type", "application/json")
+            .body(Body::from(batch.to_string()))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "application/x-ndjson");
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let lines: Vec<Value> = String::from_utf8(body.to_vec())
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        // Nothing was saved to restore, so the last ping is skipped
+        let statuses: Vec<&str> = lines
+            .iter()
+            .map(|line| line["status"].as_str().unwrap())
+            .collect();
+        assert_eq!(statuses, ["success", "error", "error"]);
+        assert_eq!(lines[1]["request_id"], "second");
+
+        // A batch that is refused outright gets a plain error
+        let (status, body) = send(&app, "POST", "/v1/actions/batch?stream=true", json!([])).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["status"], "error");
+    }
+
+    #[tokio::test]
+    async fn test_system_info_lists_every_display() {
+        let screen = simulation::VirtualScreen::parse("1920x1080,1280x1024").unwrap();
+        let input_driver: Box<dyn InputDriver> = Box::new(MockEnigo::new());
+        let action_queue: SharedQueue = Arc::new(
+            ActionQueue::new(input_driver, Config::default()).with_capture(Arc::new(screen)),
+        );
+        let app = router(Arc::new(AppState { action_queue }));
+
+        let (status, body) = send(&app, "GET", "/v1/system/info", Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            (
+                body["display_width"].as_u64(),
+                body["display_height"].as_u64()
+            ),
+            (Some(1920), Some(1080))
+        );
+        assert_eq!(body["display_count"], 2);
+        assert_eq!(body["primary_display_id"], 0);
+        assert_eq!(
+            body["virtual_screen"],
+            json!({"x": 0, "y": 0, "width": 3200, "height": 1080})
+        );
+        assert_eq!(body["displays"][1]["x"], 1920);
+        assert_eq!(body["displays"][1]["is_primary"], false);
+    }
+
+    #[tokio::test]
+    async fn test_error_status_mapping() {
+        let app = test_app().await;
+
+        let (status, body) = send_raw(&app, "POST", "/v1/action", "{not json".to_string()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["type"], "invalid_input");
+
+        let (status, body) = post_action(&app, json!({"type": "teleport"})).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["error"]["type"], "invalid_input");
+
+        let (status, body) = post_action(
+            &app,
+            json!({"type": "key_press", "input": {"key": "ctrl+nope"}}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["status"], "error");
+        assert_eq!(body["error"]["type"], "invalid_input");
+
+        let (_, run) = send(
+            &app,
+            "POST",
+            "/v1/runs",
+            json!({"allowed_actions": ["ping"], "limits": {"max_actions": 1}}),
+        )
+        .await;
+        let run_id = run["run_id"].as_str().unwrap();
+        let in_run = |action: Value| json!({"id": "test", "run_id": run_id, "action": action});
+
+        let (status, _) = send(&app, "POST", "/v1/action", in_run(json!({"type": "ping"}))).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, body) =
+            send(&app, "POST", "/v1/action", in_run(json!({"type": "ping"}))).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(body["error"]["type"], "budget_exceeded");
+
+        // Actions outside the allowed set are refused before the budget is looked at
+        let (status, body) = send(
+            &app,
+            "POST",
+            "/v1/action",
+            in_run(json!({"type": "left_click"})),
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["error"]["type"], "permission_denied");
+
+        let (status, _) = send(&app, "GET", "/v1/runs/missing", Value::Null).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // Missing a service or platform support isn't the element's fault
+        let unavailable = ActionError::ServiceUnavailable("no bus".to_string());
+        assert_eq!(
+            error_status(Some(&unavailable)),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        let unsupported = ActionError::Unsupported("not here".to_string());
+        assert_eq!(
+            error_status(Some(&unsupported)),
+            StatusCode::NOT_IMPLEMENTED
+        );
+    }
+
+    #[tokio::test]
+    async fn test_calibration_is_saved() {
+        let dir =
+            std::env::temp_dir().join(format!("valk-main-calibration-{}", std::process::id()));
+        let app = test_app_with_config(Config {
+            input_backend: InputBackend::Simulated,
+            state_dir: Some(dir.to_string_lossy().into_owned()),
+            ..Config::default()
+        })
+        .await;
+
+        let (status, body) = send(&app, "GET", "/v1/calibration", Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["settings"], json!({}));
+
+        let settings = json!({"drag_path_mode": "absolute", "scroll_pixels_per_click": 120});
+        let (status, _) = send(&app, "PUT", "/v1/calibration", settings.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, body) = send(&app, "GET", "/v1/calibration", Value::Null).await;
+        assert_eq!(body["settings"], settings);
+
+        let (status, _) = send(
+            &app,
+            "PUT",
+            "/v1/calibration",
+            json!({"scroll_pixels_per_click": 0}),
+        )
+        .await;
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn test_run_baseline() {
+        let dir = std::env::temp_dir().join(format!("valk-main-baseline-{}", std::process::id()));
+        let app = test_app_with_config(Config {
+            state_dir: Some(dir.to_string_lossy().into_owned()),
+            ..Config::default()
+        })
+        .await;
+
+        let (_, run) = send(&app, "POST", "/v1/runs", json!({"agent_name": "tester"})).await;
+        let run_id = run["run_id"].as_str().unwrap();
+        assert_eq!(run["baseline"]["displays"][0]["width"], 1920);
+        assert!(run["baseline"]["windows"].is_array());
+
+        let (status, baseline) = send(
+            &app,
+            "GET",
+            &format!("/v1/runs/{}/baseline", run_id),
+            Value::Null,
+        )
+        .await;
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(status, StatusCode::OK);
+        let png = BASE64
+            .decode(baseline["screenshot"].as_str().unwrap())
+            .unwrap();
+        let image = image::load_from_memory(&png).unwrap();
+        assert_eq!((image.width(), image.height()), (1920, 1080));
+
+        let (status, _) = send(&app, "GET", "/v1/runs/missing/baseline", Value::Null).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_run_lifecycle() {
+        let app = test_app().await;
+
+        let (status, run) = send(&app, "POST", "/v1/runs", json!({"agent_name": "tester"})).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let run_id = run["run_id"].as_str().unwrap().to_string();
+
+        let request = json!({"id": "ping", "run_id": run_id, "task_id": "warmup", "action": {"type": "ping"}});
+        let (status, _) = send(&app, "POST", "/v1/action", request).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (_, run) = send(&app, "GET", &format!("/v1/runs/{}", run_id), Value::Null).await;
+        assert_eq!(run["metrics"]["actions"], 1);
+        assert_eq!(run["history"][0]["task_id"], "warmup");
+        let (_, stats) = send(
+            &app,
+            "GET",
+            &format!("/v1/runs/{}/stats", run_id),
+            Value::Null,
+        )
+        .await;
+        assert_eq!(stats["by_action_type"]["ping"]["success_rate"], 1.0);
+
+        let click = json!({"id": "click", "run_id": run_id, "action": {"type": "click", "input": {"x": 300, "y": 200}}});
+        let (status, _) = send(&app, "POST", "/v1/action", click).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, heatmap) = send(
+            &app,
+            "GET",
+            &format!("/v1/runs/{}/stats/heatmap", run_id),
+            Value::Null,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(heatmap["interactions"].as_array().unwrap().len(), 1);
+        assert_eq!(heatmap["interactions"][0]["kind"], "click");
+        assert_eq!(heatmap["interactions"][0]["x"], 300);
+        assert_eq!(heatmap["bounds"]["width"], 1920);
+        assert_eq!(
+            (heatmap["width"].as_u64(), heatmap["height"].as_u64()),
+            (Some(960), Some(540))
+        );
+        assert!(heatmap["image"].is_string());
+
+        let (status, trajectory) = send(
+            &app,
+            "GET",
+            &format!("/v1/runs/{}/trajectory?format=openai", run_id),
+            Value::Null,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(trajectory["messages"][1]["content"], "Done");
+
+        let (status, run) = send(
+            &app,
+            "POST",
+            &format!("/v1/runs/{}/close", run_id),
+            Value::Null,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(run["status"], "closed");
+        let request = json!({"id": "late", "run_id": run_id, "action": {"type": "ping"}});
+        let (status, _) = send(&app, "POST", "/v1/action", request).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    type MonitorSocket = tokio_tungstenite::WebSocketStream<
+        tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+    >;
+
+    async fn next_event(socket: &mut MonitorSocket) -> Value {
+        loop {
+            let message = tokio::time::timeout(Duration::from_secs(10), socket.next())
+                .await
+                .expect("no monitor event within 10s")
+                .unwrap()
+                .unwrap();
+            if let Message::Text(text) = message {
+                return serde_json::from_str(&text).unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_monitor_stream() {
+        let app = test_app().await;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = app.clone();
+        tokio::spawn(async move { axum::serve(listener, server).await });
+
+        let (mut socket, _) =
+            tokio_tungstenite::connect_async(format!("ws://{}/v1/monitor", address))
+                .await
+                .unwrap();
+
+        assert_eq!(next_event(&mut socket).await["event_type"], "hello");
+        assert_eq!(
+            next_event(&mut socket).await["event_type"],
+            "observation_state"
+        );
+
+        let request = json!({"id": "monitored", "action": {"type": "ping"}});
+        let (status, _) = send(&app, "POST", "/v1/action", request).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // Observation patches for the queue may be interleaved with the action events
+        let request_event = loop {
+            let event = next_event(&mut socket).await;
+            if event["event_type"] == "action_request" {
+                break event;
+            }
+        };
+        assert_eq!(request_event["data"]["id"], "monitored");
+        let response_event = loop {
+            let event = next_event(&mut socket).await;
+            if event["event_type"] == "action_response" {
+                break event;
+            }
+        };
+        assert_eq!(response_event["data"]["request_id"], "monitored");
+        assert_eq!(response_event["data"]["status"], "success");
+    }
+
+    #[tokio::test]
+    async fn test_mirror_cannot_change_anything() {
+        let input_driver: Box<dyn InputDriver> = Box::new(MockEnigo::new());
+        let action_queue = Arc::new(
+            ActionQueue::new(input_driver, Config::default()).with_capture(mock_capture()),
+        );
+        action_queue.start_processing().await;
+        let mirror = mirror_router(Arc::new(AppState { action_queue }));
+
+        let (status, body) = send(&mirror, "GET", "/v1/runs", Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.is_array());
+        let (status, _) = send(&mirror, "GET", "/v1/system/display", Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, body) = send(&mirror, "GET", "/v1/observe/text", Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body["windows"].is_array());
+        assert!(body.get("screenshot").is_none());
+
+        let ping = json!({"id": "test", "action": {"type": "ping"}});
+        let (status, _) = send(&mirror, "POST", "/v1/action", ping.clone()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = send(&mirror, "POST", "/v1/actions/batch", json!([ping])).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = send(&mirror, "POST", "/v1/runs", json!({})).await;
+        assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+        let (status, _) = send(&mirror, "PUT", "/v1/calibration", json!({})).await;
+        assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+        let (status, _) = send(&mirror, "GET", "/v1/takeover", Value::Null).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_observe_text_reads_the_screen() {
+        let word = |text: &str, x| action_types::TextSpan {
+            text: text.to_string(),
+            x,
+            y: 12,
+            width: 40,
+            height: 14,
+            confidence: 92.0,
+        };
+        let recognizer = ocr::FixedText(vec![vec![word("Inbox", 8), word("(3)", 52)], Vec::new()]);
+        let input_driver: Box<dyn InputDriver> = Box::new(MockEnigo::new());
+        let action_queue = Arc::new(
+            ActionQueue::new(input_driver, Config::default())
+                .with_capture(mock_capture())
+                .with_recognizer(Arc::new(recognizer)),
+        );
+        action_queue.start_processing().await;
+        let app = router(Arc::new(AppState { action_queue }));
+
+        let (status, body) = send(&app, "GET", "/v1/observe/text", Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["text_source"], "ocr");
+        assert_eq!(
+            body["text"],
+            json!([{"text": "Inbox (3)", "x": 8, "y": 12, "width": 84, "height": 14}])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_takeover_requires_token() {
+        let app = test_app_with_config(Config {
+            takeover_token: Some("s3cret".to_string()),
+            ..Config::default()
+        })
+        .await;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        for url in [
+            format!("ws://{}/v1/takeover", address),
+            format!("ws://{}/v1/takeover?token=wrong", address),
+        ] {
+            match tokio_tungstenite::connect_async(url).await {
+                Err(tokio_tungstenite::tungstenite::Error::Http(response)) => {
+                    assert_eq!(response.status(), StatusCode::UNAUTHORIZED)
+                }
+                other => panic!("expected a 401, got {:?}", other.map(|_| ())),
+            }
+        }
+
+        let (mut socket, _) =
+            tokio_tungstenite::connect_async(format!("ws://{}/v1/takeover?token=s3cret", address))
+                .await
+                .unwrap();
+        let request =
+            json!({"id": "human", "action": {"type": "mouse_move", "input": {"x": 5, "y": 6}}});
+        socket
+            .send(Message::Text(request.to_string().into()))
+            .await
+            .unwrap();
+        let response = next_event(&mut socket).await;
+        assert_eq!(response["request_id"], "human");
+        assert_eq!(response["status"], "success");
+    }
+
+    #[tokio::test]
+    async fn test_disarmed_server_only_observes_until_armed_locally() {
+        let input_driver: Box<dyn InputDriver> = Box::new(MockEnigo::new());
+        let config = Config {
+            arm_token: Some("s3cret".to_string()),
+            arm_local_only: true,
+            ..Config::default()
+        };
+        let action_queue =
+            Arc::new(ActionQueue::new(input_driver, config).with_capture(mock_capture()));
+        action_queue.start_processing().await;
+        action_queue.set_armed(false);
+        let app = router(Arc::new(AppState { action_queue }));
+        let click = json!({"type": "click", "input": {"x": 5, "y": 5}});
+
+        let (status, body) = post_action(&app, click.clone()).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["error"]["type"], "permission_denied");
+        let (status, _) = post_action(&app, json!({"type": "cursor_position"})).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let arm = |token: &str, peer: Option<&str>| {
+            let mut request = axum::http::Request::builder()
+                .method("POST")
+                .uri("/v1/arm")
+                .header("authorization", format!("Bearer {}", token))
+                .body(Body::empty())
+                .unwrap();
+            if let Some(peer) = peer {
+                request.extensions_mut().insert(extract::ConnectInfo(
+                    peer.parse::<std::net::SocketAddr>().unwrap(),
+                ));
+            }
+            let app = app.clone();
+            async move { app.oneshot(request).await.unwrap().status() }
+        };
+        assert_eq!(
+            arm("wrong", Some("127.0.0.1:40000")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            arm("s3cret", Some("10.0.0.7:40000")).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(arm("s3cret", Some("127.0.0.1:40000")).await, StatusCode::OK);
+
+        let (_, body) = send(&app, "GET", "/v1/arm", Value::Null).await;
+        assert_eq!(body["armed"], true);
+        let (status, _) = post_action(&app, click).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_approvals_are_decided_with_the_approver_token() {
+        let input_driver: Box<dyn InputDriver> = Box::new(MockEnigo::new());
+        let config = Config {
+            approval_actions: vec!["left_click".to_string()],
+            approver_token: Some("s3cret".to_string()),
+            ..Config::default()
+        };
+        let action_queue =
+            Arc::new(ActionQueue::new(input_driver, config).with_capture(mock_capture()));
+        action_queue.start_processing().await;
+        let app = router(Arc::new(AppState { action_queue }));
+
+        let pending = tokio::spawn({
+            let app = app.clone();
+            async move { post_action(&app, json!({"type": "left_click"})).await }
+        });
+        let approval_id = loop {
+            let (_, body) = send(&app, "GET", "/v1/approvals", Value::Null).await;
+            if let Some(approval_id) = body[0]["approval_id"].as_str() {
+                break approval_id.to_string();
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+
+        let approve = |token: Option<&str>| {
+            let mut request = axum::http::Request::builder()
+                .method("POST")
+                .uri(format!("/v1/approvals/{}/approve", approval_id));
+            if let Some(token) = token {
+                request = request.header("authorization", format!("Bearer {}", token));
+            }
+            let request = request.body(Body::empty()).unwrap();
+            let app = app.clone();
+            async move { app.oneshot(request).await.unwrap().status() }
+        };
+        // The agent can see what waits, but not approve it
+        assert_eq!(approve(None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(approve(Some("wrong")).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(approve(Some("s3cret")).await, StatusCode::NO_CONTENT);
+
+        let (status, body) = pending.await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "success");
+    }
+
+    #[test]
+    fn test_takeover_token_is_not_logged() {
+        let uri: Uri = "/v1/takeover?token=s3cret".parse().unwrap();
+        assert_eq!(logged_uri(&uri), "/v1/takeover?[redacted]");
+        let uri: Uri = "/v1/runs?limit=5".parse().unwrap();
+        assert_eq!(logged_uri(&uri), "/v1/runs?limit=5");
+    }
+
+    #[tokio::test]
+    async fn test_takeover_disconnect_releases_held_keys() {
+        let input_driver: Box<dyn InputDriver> = Box::new(MockEnigo::new());
+        let config = Config {
+            takeover_token: Some("s3cret".to_string()),
+            ..Config::default()
+        };
+        let action_queue: SharedQueue =
+            Arc::new(ActionQueue::new(input_driver, config).with_capture(mock_capture()));
+        action_queue.start_processing().await;
+        let (mut monitor, _) = action_queue.subscribe_monitor();
+        let app = router(Arc::new(AppState {
+            action_queue: action_queue.clone(),
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let (mut socket, _) =
+            tokio_tungstenite::connect_async(format!("ws://{}/v1/takeover?token=s3cret", address))
+                .await
+                .unwrap();
+        for key in ["ctrl", "shift"] {
+            let request = json!({"id": key, "action": {"type": "key_down", "input": {"key": key}}});
+            socket
+                .send(Message::Text(request.to_string().into()))
+                .await
+                .unwrap();
+            assert_eq!(next_event(&mut socket).await["status"], "success");
+        }
+        let request = json!({"id": "up", "action": {"type": "key_up", "input": {"key": "ctrl"}}});
+        socket
+            .send(Message::Text(request.to_string().into()))
+            .await
+            .unwrap();
+        next_event(&mut socket).await;
+        drop(socket);
+
+        let disconnected = loop {
+            let event = tokio::time::timeout(Duration::from_secs(10), monitor.recv())
+                .await
+                .expect("no disconnect event within 10s")
+                .unwrap();
+            let event = serde_json::to_value(&event).unwrap();
+            if event["event_type"] == "controller_disconnected" {
+                break event;
+            }
+        };
+        assert_eq!(disconnected["data"]["connection"], "takeover");
+        assert_eq!(disconnected["data"]["released_keys"], json!(["shift"]));
+    }
+
+    #[tokio::test]
+    async fn test_macros_from_policy_files_run_as_batches() {
+        let dir = std::env::temp_dir().join(format!("valk-macros-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("macros.json"),
+            json!({"macros": {"corner": [
+                {"type": "mouse_move", "input": {"x": 0, "y": 0}},
+                {"type": "left_click"}
+            ]}})
+            .to_string(),
+        )
         .unwrap();
-    axum::serve(listener, app).await.unwrap();
+        let app = test_app_with_config(Config {
+            policy_dir: Some(dir.to_string_lossy().to_string()),
+            ..Config::default()
+        })
+        .await;
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let (status, body) = send(&app, "GET", "/v1/macros", Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["corner"][1]["type"], "left_click");
+
+        let (status, body) = send(&app, "POST", "/v1/macros/corner", Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body[0]["request_id"], "corner-1");
+        assert_eq!(body[1]["request_id"], "corner-2");
+        assert_eq!(body[1]["status"], "success");
+        assert_eq!(body[1]["metadata"]["macro"], "corner");
+
+        let (status, _) = send(&app, "POST", "/v1/macros/missing", Value::Null).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_relay_forwards_to_connected_peer() {
+        let relay_config = |upstream: Option<String>| Config {
+            relay_token: Some("s3cret".to_string()),
+            relay_upstream: upstream,
+            relay_name: Some("desk-42".to_string()),
+            ..Config::default()
+        };
+        let bastion = test_app_with_config(relay_config(None)).await;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = bastion.clone();
+        tokio::spawn(async move { axum::serve(listener, server).await });
+
+        let input_driver: Box<dyn InputDriver> = Box::new(MockEnigo::new());
+        let inner: SharedQueue = Arc::new(
+            ActionQueue::new(
+                input_driver,
+                relay_config(Some(format!("ws://{}", address))),
+            )
+            .with_capture(mock_capture()),
+        );
+        inner.start_processing().await;
+        tokio::spawn(relay::connect_upstream(inner));
+
+        let relay = |method: &str, uri: &str, token: &str, body: Value| {
+            let request = axum::http::Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("content-type", "application/json")
+                .header("authorization", format!("Bearer {}", token))
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            let app = bastion.clone();
+            async move {
+                let response = app.oneshot(request).await.unwrap();
+                let status = response.status();
+                let body = response.into_body().collect().await.unwrap().to_bytes();
+                (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+            }
+        };
+        let mut peers = Value::Null;
+        for _ in 0..50 {
+            (_, peers) = relay("GET", "/v1/relay", "s3cret", Value::Null).await;
+            if peers.as_array().is_some_and(|peers| !peers.is_empty()) {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+        assert_eq!(peers[0]["name"], "desk-42");
+
+        // Another machine with the token can't take the name while desk-42 is connected
+        let mut hijack = format!("ws://{}/v1/relay/connect?name=desk-42", address)
+            .into_client_request()
+            .unwrap();
+        hijack
+            .headers_mut()
+            .insert("authorization", "Bearer s3cret".parse().unwrap());
+        match tokio_tungstenite::connect_async(hijack).await {
+            Err(tokio_tungstenite::tungstenite::Error::Http(response)) => {
+                assert_eq!(response.status(), StatusCode::CONFLICT)
+            }
+            other => panic!("Expected the name to be refused, got {:?}", other.err()),
+        }
+
+        let ping = json!({"id": "relayed", "action": {"type": "ping"}});
+        let (status, body) = relay("POST", "/v1/relay/desk-42", "s3cret", ping.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["request_id"], "relayed");
+        assert_eq!(body["status"], "success");
+
+        let invalid = json!({"id": "relayed", "action": {"type": "mouse_move", "input": {"x": 40000, "y": 0}}});
+        let (status, body) = relay("POST", "/v1/relay/desk-42", "s3cret", invalid).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["error"]["type"], "invalid_input");
+
+        let (status, body) =
+            relay("POST", "/v1/relay/desk-42/nobody", "s3cret", ping.clone()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"]["type"], "not_found");
+        let (status, _) = relay("POST", "/v1/relay/desk-42", "wrong", ping).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_agent_runs_model_steps() {
+        let (status, _) = send(
+            &test_app().await,
+            "POST",
+            "/v1/agent/run",
+            json!({"goal": "x"}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let replies = [
+            r#"{"thought": "The button is there", "action": {"type": "mouse_move", "input": {"x": 40, "y": 30}}}"#,
+            r#"{"thought": "Nothing to see", "action": {"type": "teleport"}}"#,
+        ];
+        let model = agent::ScriptedModel(std::sync::Mutex::new(
+            replies.iter().map(|reply| reply.to_string()).collect(),
+        ));
+        let input_driver: Box<dyn InputDriver> = Box::new(MockEnigo::new());
+        let action_queue: SharedQueue = Arc::new(
+            ActionQueue::new(input_driver, Config::default())
+                .with_capture(mock_capture())
+                .with_model_backend(Arc::new(model)),
+        );
+        action_queue.start_processing().await;
+        let app = router(Arc::new(AppState { action_queue }));
+
+        let (status, _) = send(&app, "POST", "/v1/agent/run", json!({"goal": " "})).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        let goal = json!({"goal": "Press the button"});
+        let (status, run) = send(&app, "POST", "/v1/agent/run", goal.clone()).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(run["status"], "running");
+        let (status, _) = send(&app, "POST", "/v1/agent/run", goal).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let uri = format!("/v1/agent/runs/{}", run["id"].as_str().unwrap());
+        let mut run = Value::Null;
+        for _ in 0..200 {
+            (_, run) = send(&app, "GET", &uri, Value::Null).await;
+            if run["status"] != "running" {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+        // The second reply isn't a valid action, which ends the run
+        assert_eq!(run["status"], "failed");
+        assert_eq!(run["steps"].as_array().unwrap().len(), 1);
+        assert_eq!(run["steps"][0]["thought"], "The button is there");
+        assert_eq!(run["steps"][0]["action"]["type"], "mouse_move");
+        assert_eq!(run["steps"][0]["result"], "success");
+        assert!(run["summary"]
+            .as_str()
+            .unwrap()
+            .contains("not a valid step"));
+    }
 }
@@ -0,0 +1,233 @@
+use axum::{extract, http::header};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::AppState;
+
+/// Server wide counters, exported on /metrics in the Prometheus text format
+#[derive(Default)]
+pub struct Metrics {
+    pub monitor_events_sent: AtomicU64,
+    /// Screen updates skipped by the drop_new_screenshots overflow strategy
+    pub monitor_screenshots_dropped: AtomicU64,
+    /// Events a monitor client missed because it fell behind the broadcast buffer
+    pub monitor_events_lagged: AtomicU64,
+    /// Error responses among the missed events that were sent to the client again
+    pub monitor_errors_resent: AtomicU64,
+    /// Error responses a client missed that were too old to send again
+    pub monitor_errors_lost: AtomicU64,
+    /// Screen updates skipped to stay within the monitor bandwidth budget
+    pub monitor_screenshots_throttled: AtomicU64,
+    /// Screen updates skipped because they couldn't be decoded to degrade them
+    pub monitor_screenshots_undecodable: AtomicU64,
+    /// Bytes written to monitor clients
+    pub monitor_bytes_sent: AtomicU64,
+    /// Time spent waiting for the input driver, held by the worker for a whole action
+    pub input_driver_lock_wait: Histogram,
+}
+
+/// Upper bounds of the lock wait buckets, in seconds
+const LOCK_WAIT_BUCKETS: [f64; 10] = [0.0001, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0];
+
+/// A Prometheus histogram of durations over LOCK_WAIT_BUCKETS
+#[derive(Default)]
+pub struct Histogram {
+    // Not cumulative, the +Inf bucket is last
+    buckets: [AtomicU64; LOCK_WAIT_BUCKETS.len() + 1],
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    pub fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let bucket = LOCK_WAIT_BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(LOCK_WAIT_BUCKETS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+impl Metrics {
+    pub fn increment(counter: &AtomicU64, amount: u64) {
+        counter.fetch_add(amount, Ordering::Relaxed);
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        write_counter(
+            &mut out,
+            "valk_monitor_events_sent_total",
+            "Events broadcast to monitor clients",
+            &[("", &self.monitor_events_sent)],
+        );
+        write_counter(
+            &mut out,
+            "valk_monitor_events_dropped_total",
+            "Monitor events that never reached a client",
+            &[
+                (
+                    "reason=\"screenshot_overflow\"",
+                    &self.monitor_screenshots_dropped,
+                ),
+                ("reason=\"client_lagged\"", &self.monitor_events_lagged),
+                ("reason=\"bandwidth\"", &self.monitor_screenshots_throttled),
+                (
+                    "reason=\"undecodable\"",
+                    &self.monitor_screenshots_undecodable,
+                ),
+            ],
+        );
+        write_counter(
+            &mut out,
+            "valk_monitor_error_responses_missed_total",
+            "Error responses monitor clients fell behind on, sent again or lost",
+            &[
+                ("outcome=\"resent\"", &self.monitor_errors_resent),
+                ("outcome=\"lost\"", &self.monitor_errors_lost),
+            ],
+        );
+        write_counter(
+            &mut out,
+            "valk_monitor_bytes_sent_total",
+            "Bytes written to monitor clients",
+            &[("", &self.monitor_bytes_sent)],
+        );
+        write_histogram(
+            &mut out,
+            "valk_lock_wait_seconds",
+            "Time spent waiting to acquire a lock",
+            &[("lock=\"input_driver\"", &self.input_driver_lock_wait)],
+        );
+        if let Some(bytes) = resident_memory_bytes() {
+            write_gauge(
+                &mut out,
+                "valk_process_resident_memory_bytes",
+                "Resident memory of the server",
+                bytes,
+            );
+        }
+        out
+    }
+}
+
+// Resident set size from /proc, so soak tests can watch for leaks
+#[cfg(target_os = "linux")]
+fn resident_memory_bytes() -> Option<u64> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    Some(pages * u64::try_from(page_size).ok()?)
+}
+
+// Without /proc the gauge is left out
+#[cfg(not(target_os = "linux"))]
+fn resident_memory_bytes() -> Option<u64> {
+    None
+}
+
+fn write_counter(out: &mut String, name: &str, help: &str, series: &[(&str, &AtomicU64)]) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+    for (labels, value) in series {
+        let value = value.load(Ordering::Relaxed);
+        if labels.is_empty() {
+            let _ = writeln!(out, "{} {}", name, value);
+        } else {
+            let _ = writeln!(out, "{}{{{}}} {}", name, labels, value);
+        }
+    }
+}
+
+fn write_gauge(out: &mut String, name: &str, help: &str, value: u64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} gauge", name);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+fn write_histogram(out: &mut String, name: &str, help: &str, series: &[(&str, &Histogram)]) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} histogram", name);
+    for (labels, histogram) in series {
+        let mut count = 0;
+        for (bucket, bound) in histogram.buckets.iter().zip(
+            LOCK_WAIT_BUCKETS
+                .iter()
+                .map(|bound| bound.to_string())
+                .chain(["+Inf".to_string()]),
+        ) {
+            count += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{},le=\"{}\"}} {}",
+                name, labels, bound, count
+            );
+        }
+        let sum = histogram.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, sum);
+        let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, count);
+    }
+}
+
+pub async fn metrics(
+    extract::State(state): extract::State<Arc<AppState>>,
+) -> ([(header::HeaderName, &'static str); 1], String) {
+    let mut out = state.action_queue.metrics().render();
+    write_gauge(
+        &mut out,
+        "valk_monitor_bandwidth_degradation",
+        "How far screen updates are cut back, 0 full, 1 frame rate, 2 scale, 3 quality",
+        state.action_queue.bandwidth().level() as u64,
+    );
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], out)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_prometheus_text() {
+        let metrics = Metrics::default();
+        Metrics::increment(&metrics.monitor_events_sent, 3);
+        Metrics::increment(&metrics.monitor_events_lagged, 2);
+
+        let text = metrics.render();
+        assert!(text.contains("# TYPE valk_monitor_events_sent_total counter"));
+        assert!(text.contains("valk_monitor_events_sent_total 3\n"));
+        assert!(text.contains("valk_monitor_events_dropped_total{reason=\"client_lagged\"} 2\n"));
+        assert!(
+            text.contains("valk_monitor_events_dropped_total{reason=\"screenshot_overflow\"} 0\n")
+        );
+        assert!(text.contains("# TYPE valk_process_resident_memory_bytes gauge"));
+    }
+
+    #[test]
+    fn test_render_lock_wait_histogram() {
+        let metrics = Metrics::default();
+        metrics
+            .input_driver_lock_wait
+            .observe(Duration::from_micros(50));
+        metrics
+            .input_driver_lock_wait
+            .observe(Duration::from_millis(200));
+        metrics
+            .input_driver_lock_wait
+            .observe(Duration::from_secs(60));
+
+        let text = metrics.render();
+        assert!(text.contains("# TYPE valk_lock_wait_seconds histogram"));
+        let series = "valk_lock_wait_seconds_bucket{lock=\"input_driver\"";
+        assert!(text.contains(&format!("{},le=\"0.0001\"}} 1\n", series)));
+        assert!(text.contains(&format!("{},le=\"0.1\"}} 1\n", series)));
+        assert!(text.contains(&format!("{},le=\"0.5\"}} 2\n", series)));
+        assert!(text.contains(&format!("{},le=\"+Inf\"}} 3\n", series)));
+        assert!(text.contains("valk_lock_wait_seconds_sum{lock=\"input_driver\"} 60.20005\n"));
+        assert!(text.contains("valk_lock_wait_seconds_count{lock=\"input_driver\"} 3\n"));
+    }
+}
@@ -8,10 +8,14 @@ use axum::{
 use chrono::{DateTime, Utc};
 
 use crate::action_queue::SharedQueue;
+use crate::action_types::ActionResponseStatus;
+use crate::metrics::Metrics;
 use crate::AppState;
 
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use uuid::Uuid;
 // Configuration for the monitor connection
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MonitorConfig {
@@ -20,16 +24,62 @@ pub struct MonitorConfig {
     pub always_send_cursor_updates: bool,
 }
 
+/// Bumped whenever an event is removed or an existing field changes meaning,
+/// so recorders can refuse streams they don't understand instead of mis-parsing them
+pub const MONITOR_SCHEMA_VERSION: u32 = 1;
+
+/// Every `event_type` the server may emit
+pub const MONITOR_EVENT_TYPES: &[&str] = &[
+    "hello",
+    "action_request",
+    "action_response",
+    "screen_update",
+    "cursor_update",
+    "approval_required",
+    "approval_resolved",
+    "driver_recovery",
+    "display_status",
+    "observation_state",
+    "observation_patch",
+    "agent_step",
+    "agent_finished",
+    "action_progress",
+    "controller_disconnected",
+];
+
 #[derive(Clone, Serialize)]
 pub struct MonitorEvent {
     pub event_id: String,
+    pub schema_version: u32,
     #[serde(flatten)]
     pub payload: MonitorEventPayload,
+    /// Error responses broadcast up to and including this event, so a client that fell
+    /// behind can tell which ones it missed
+    #[serde(skip)]
+    pub errors_sent: u64,
+}
+
+impl MonitorEvent {
+    pub fn new(payload: MonitorEventPayload) -> Self {
+        Self {
+            event_id: Uuid::new_v4().to_string(),
+            schema_version: MONITOR_SCHEMA_VERSION,
+            payload,
+            errors_sent: 0,
+        }
+    }
 }
 
 #[derive(Clone, Serialize)]
 #[serde(tag = "event_type", content = "data")]
 pub enum MonitorEventPayload {
+    /// First message on every connection, describing the stream that follows
+    #[serde(rename = "hello")]
+    Hello {
+        schema_version: u32,
+        server_version: String,
+        event_types: Vec<String>,
+    },
     #[serde(rename = "action_request")]
     ActionRequest(crate::action_types::ActionRequest),
     #[serde(rename = "action_response")]
@@ -38,18 +88,94 @@ pub enum MonitorEventPayload {
     ScreenUpdate {
         action_id: String, // ID of the action that triggered this screenshot
         image: String,     // Base64 encoded image
+        image_format: crate::action_types::ScreenshotFormat, // As captured, jpeg when degraded
         screen_size: (u32, u32),
         timestamp: DateTime<Utc>,
     },
     #[serde(rename = "cursor_update")]
     CursorUpdate {
         action_id: String, // ID of the action that triggered this cursor update
-        x: u32,
-        y: u32,
+        x: i32,
+        y: i32,
+        timestamp: DateTime<Utc>,
+    },
+    #[serde(rename = "approval_required")]
+    ApprovalRequired(crate::approvals::PendingApproval),
+    #[serde(rename = "approval_resolved")]
+    ApprovalResolved { approval_id: String, approved: bool },
+    #[serde(rename = "driver_recovery")]
+    DriverRecovery {
+        failures: u32, // Consecutive failures that triggered the recovery, 0 after a display reconnect
+        recovered: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+        timestamp: DateTime<Utc>,
+    },
+    #[serde(rename = "display_status")]
+    DisplayStatus(crate::display::DisplayStatus),
+    /// Full observation state, sent once when a client connects
+    #[serde(rename = "observation_state")]
+    ObservationState {
+        version: u64,
+        state: serde_json::Value,
+    },
+    /// Changes since the previous state or patch sent to the client, as RFC 6902 JSON-Patch
+    /// operations. Changes in quick succession arrive as one patch, skipping versions
+    #[serde(rename = "observation_patch")]
+    ObservationPatch {
+        version: u64,
+        patch: Vec<crate::observation::PatchOp>,
+    },
+    /// A step of an agent run, after its action ran
+    #[serde(rename = "agent_step")]
+    AgentStep {
+        agent_run_id: String,
+        step: crate::agent::AgentStep,
+    },
+    #[serde(rename = "agent_finished")]
+    AgentFinished(crate::agent::AgentRun),
+    /// A step of a long action or batch is starting
+    #[serde(rename = "action_progress")]
+    ActionProgress {
+        action_id: String, // For a batch, the id of the action starting
+        #[serde(skip_serializing_if = "Option::is_none")]
+        batch_id: Option<String>, // Set for the steps of a batch, the same for all of them
+        step: u32,         // Counted from 1
+        total_steps: u32,
+        percent: u8,     // Share of the steps already done
+        current: String, // What the step does, an action type in a batch
+        timestamp: DateTime<Utc>,
+    },
+    /// A connection that was sending input closed, with the keys and buttons released on its
+    /// behalf
+    #[serde(rename = "controller_disconnected")]
+    ControllerDisconnected {
+        connection: String, // Which channel, "takeover" or "relay"
+        released_keys: Vec<String>,
+        released_buttons: Vec<String>, // Left down by an action cut off midway
         timestamp: DateTime<Utc>,
     },
 }
 
+impl MonitorEventPayload {
+    /// An action_response for a failed action, which monitor clients are never left without
+    pub fn is_error_response(&self) -> bool {
+        matches!(
+            self,
+            MonitorEventPayload::ActionResponse(response)
+                if matches!(response.status, ActionResponseStatus::Error)
+        )
+    }
+
+    pub fn hello() -> Self {
+        MonitorEventPayload::Hello {
+            schema_version: MONITOR_SCHEMA_VERSION,
+            server_version: env!("CARGO_PKG_VERSION").to_string(),
+            event_types: MONITOR_EVENT_TYPES.iter().map(|t| t.to_string()).collect(),
+        }
+    }
+}
+
 impl Default for MonitorConfig {
     fn default() -> Self {
         Self {
@@ -66,9 +192,62 @@ pub async fn monitor_websocket(
     ws.on_upgrade(move |socket| handle_socket(socket, state.action_queue.clone()))
 }
 
+// Send an event to the client, false once it has disconnected
+async fn send_event(socket: &mut WebSocket, queue: &SharedQueue, event: &MonitorEvent) -> bool {
+    let Ok(msg) = serde_json::to_string(event) else {
+        return true;
+    };
+    let bytes = msg.len();
+    if socket
+        .send(Message::Text(Utf8Bytes::from(msg)))
+        .await
+        .is_err()
+    {
+        return false;
+    }
+    queue.record_monitor_bytes(bytes);
+    true
+}
+
+// The patch from the observation state the client has, `sent`, to `current`, which then
+// becomes what the client has
+fn next_patch(
+    sent: &mut (u64, serde_json::Value),
+    current: (u64, serde_json::Value),
+) -> Option<MonitorEventPayload> {
+    if current.0 <= sent.0 {
+        return None;
+    }
+    let mut patch = Vec::new();
+    crate::observation::diff(&sent.1, &current.1, "", &mut patch);
+    *sent = current;
+    Some(MonitorEventPayload::ObservationPatch {
+        version: sent.0,
+        patch,
+    })
+}
+
 async fn handle_socket(mut socket: WebSocket, queue: SharedQueue) {
     // Subscribe to events from the action queue
-    let mut action_rx = queue.subscribe_monitor();
+    let (mut action_rx, mut errors_seen) = queue.subscribe_monitor();
+    let mut lagged = false;
+    let mut observation_rx = queue.subscribe_observation();
+
+    // Greet the client so it can check it understands this stream, then give it
+    // the observation state that later patches apply to
+    let mut observed = observation_rx.borrow_and_update().snapshot();
+    let greeting = [
+        MonitorEventPayload::hello(),
+        MonitorEventPayload::ObservationState {
+            version: observed.0,
+            state: observed.1.clone(),
+        },
+    ];
+    for payload in greeting {
+        if !send_event(&mut socket, &queue, &MonitorEvent::new(payload)).await {
+            return;
+        }
+    }
 
     loop {
         tokio::select! {
@@ -91,16 +270,94 @@ async fn handle_socket(mut socket: WebSocket, queue: SharedQueue) {
                 }
             },
 
+            // Observation changes, however many happened since the last one sent
+            changed = observation_rx.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                let current = observation_rx.borrow_and_update().snapshot();
+                if let Some(payload) = next_patch(&mut observed, current) {
+                    if !send_event(&mut socket, &queue, &MonitorEvent::new(payload)).await {
+                        break;
+                    }
+                }
+            },
+
             // Handle action events
             action_event = action_rx.recv() => {
-                if let Ok(event) = action_event {
-                    if let Ok(msg) = serde_json::to_string(&event) {
-                        if socket.send(Message::Text(Utf8Bytes::from(msg))).await.is_err() {
+                match action_event {
+                    Ok(event) => {
+                        // Error responses are never dropped, the ones lost while the client
+                        // lagged go out before the first event it gets afterwards
+                        if std::mem::take(&mut lagged) {
+                            let through =
+                                event.errors_sent - u64::from(event.payload.is_error_response());
+                            for missed in queue.missed_monitor_errors(errors_seen, through) {
+                                if !send_event(&mut socket, &queue, &missed).await {
+                                    return;
+                                }
+                            }
+                        }
+                        errors_seen = event.errors_sent;
+                        if !send_event(&mut socket, &queue, &event).await {
                             break; // Client disconnected
                         }
                     }
+                    Err(RecvError::Lagged(missed)) => {
+                        // The client fell behind and the oldest events were overwritten
+                        Metrics::increment(&queue.metrics().monitor_events_lagged, missed);
+                        lagged = true;
+                    }
+                    Err(RecvError::Closed) => break,
                 }
             },
         }
     }
 }
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_events_are_versioned_and_listed() {
+        let payloads = vec![
+            MonitorEventPayload::hello(),
+            MonitorEventPayload::ApprovalResolved {
+                approval_id: "approval".to_string(),
+                approved: true,
+            },
+            MonitorEventPayload::CursorUpdate {
+                action_id: "action".to_string(),
+                x: 1,
+                y: 2,
+                timestamp: Utc::now(),
+            },
+        ];
+
+        for payload in payloads {
+            let value = serde_json::to_value(MonitorEvent::new(payload)).unwrap();
+            assert_eq!(value["schema_version"], MONITOR_SCHEMA_VERSION);
+            let event_type = value["event_type"].as_str().unwrap();
+            assert!(MONITOR_EVENT_TYPES.contains(&event_type));
+        }
+    }
+
+    #[test]
+    fn test_patches_cover_every_change_since_the_last() {
+        let mut sent = (4, serde_json::json!({"cursor": [1, 2], "busy": false}));
+        assert!(next_patch(&mut sent, (4, serde_json::json!({"cursor": [1, 2]}))).is_none());
+
+        // Two changes made before the client was woken arrive together
+        let current = serde_json::json!({"cursor": [3, 4], "busy": true});
+        let Some(MonitorEventPayload::ObservationPatch { version, patch }) =
+            next_patch(&mut sent, (6, current.clone()))
+        else {
+            panic!("expected a patch");
+        };
+        assert_eq!(version, 6);
+        assert_eq!(patch.len(), 2);
+        assert_eq!(sent, (6, current));
+    }
+}
@@ -0,0 +1,170 @@
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::observe::WindowInfo;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct QueueStatus {
+    pub pending: usize,
+    pub busy: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current_action: Option<String>,
+    /// A human has taken over, agent actions are held until they hand control back
+    pub takeover: bool,
+}
+
+/// What the server currently knows about the screen and queue, mirrored to monitor
+/// clients as JSON-Patch deltas so dashboards stay in sync without polling
+#[derive(Debug, Default, Serialize)]
+pub struct ObservationState {
+    pub cursor: Option<(i32, i32)>,
+    pub focused_window: Option<WindowInfo>,
+    pub queue: QueueStatus,
+    pub last_screenshot_id: Option<String>,
+}
+
+/// A single RFC 6902 operation
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum PatchOp {
+    Add { path: String, value: Value },
+    Remove { path: String },
+    Replace { path: String, value: Value },
+}
+
+/// Holds the state alongside its last serialized form, so updates can be turned into patches
+pub struct ObservationTracker {
+    state: ObservationState,
+    value: Value,
+    version: u64,
+}
+
+impl Default for ObservationTracker {
+    fn default() -> Self {
+        let state = ObservationState::default();
+        Self {
+            value: serde_json::to_value(&state).unwrap_or(Value::Null),
+            state,
+            version: 0,
+        }
+    }
+}
+
+impl ObservationTracker {
+    /// Apply a change to the state, returning the new version and the patch if anything changed
+    pub fn update(
+        &mut self,
+        change: impl FnOnce(&mut ObservationState),
+    ) -> Option<(u64, Vec<PatchOp>)> {
+        change(&mut self.state);
+        let value = serde_json::to_value(&self.state).ok()?;
+        let mut patch = Vec::new();
+        diff(&self.value, &value, "", &mut patch);
+        self.value = value;
+        if patch.is_empty() {
+            return None;
+        }
+        self.version += 1;
+        Some((self.version, patch))
+    }
+
+    /// The full state, sent to clients when they connect
+    pub fn snapshot(&self) -> (u64, Value) {
+        (self.version, self.value.clone())
+    }
+}
+
+// Escape a key for use in a JSON pointer
+fn pointer_token(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+/// Collect the operations turning `old` into `new`, objects are compared key by key
+/// and anything else (including arrays) is replaced whole
+pub fn diff(old: &Value, new: &Value, path: &str, patch: &mut Vec<PatchOp>) {
+    match (old, new) {
+        (Value::Object(old), Value::Object(new)) => diff_objects(old, new, path, patch),
+        _ if old != new => patch.push(PatchOp::Replace {
+            path: path.to_string(),
+            value: new.clone(),
+        }),
+        _ => {}
+    }
+}
+
+fn diff_objects(
+    old: &Map<String, Value>,
+    new: &Map<String, Value>,
+    path: &str,
+    patch: &mut Vec<PatchOp>,
+) {
+    for key in old.keys().filter(|key| !new.contains_key(*key)) {
+        patch.push(PatchOp::Remove {
+            path: format!("{}/{}", path, pointer_token(key)),
+        });
+    }
+    for (key, value) in new {
+        let child = format!("{}/{}", path, pointer_token(key));
+        match old.get(key) {
+            Some(old_value) => diff(old_value, value, &child, patch),
+            None => patch.push(PatchOp::Add {
+                path: child,
+                value: value.clone(),
+            }),
+        }
+    }
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_diff_objects() {
+        let old = json!({"cursor": [1, 2], "queue": {"pending": 0, "busy": false}, "gone": 1});
+        let new = json!({"cursor": [1, 3], "queue": {"pending": 2, "busy": false}, "a/b": "x"});
+        let mut patch = Vec::new();
+        diff(&old, &new, "", &mut patch);
+
+        assert_eq!(
+            patch,
+            vec![
+                PatchOp::Remove {
+                    path: "/gone".to_string()
+                },
+                PatchOp::Add {
+                    path: "/a~1b".to_string(),
+                    value: json!("x")
+                },
+                PatchOp::Replace {
+                    path: "/cursor".to_string(),
+                    value: json!([1, 3])
+                },
+                PatchOp::Replace {
+                    path: "/queue/pending".to_string(),
+                    value: json!(2)
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tracker_versions_only_real_changes() {
+        let mut tracker = ObservationTracker::default();
+        let (version, patch) = tracker.update(|state| state.cursor = Some((5, 6))).unwrap();
+        assert_eq!(version, 1);
+        assert!(patch.contains(&PatchOp::Replace {
+            path: "/cursor".to_string(),
+            value: json!([5, 6])
+        }));
+
+        assert!(tracker
+            .update(|state| state.cursor = Some((5, 6)))
+            .is_none());
+        let (version, state) = tracker.snapshot();
+        assert_eq!(version, 1);
+        assert_eq!(state["cursor"], json!([5, 6]));
+    }
+}
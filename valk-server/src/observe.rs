@@ -0,0 +1,393 @@
+use axum::{extract, http::StatusCode, Json};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::accessibility;
+use crate::action_types::ActionError;
+use crate::capture::{encode_png, fit_within, DisplayInfo, ScreenCapture};
+use crate::cursor::CursorSnapshot;
+use crate::AppState;
+
+/// Width scaled screenshots are reduced to, enough for an agent to orient itself
+const SCALED_SCREENSHOT_WIDTH: u32 = 640;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScreenshotMode {
+    #[default]
+    None,
+    Scaled,
+    Full,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ObserveParams {
+    #[serde(default)]
+    pub screenshot: ScreenshotMode,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WindowInfo {
+    pub id: u32,
+    pub app_name: String,
+    pub title: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ObservedScreenshot {
+    /// Base64 encoded PNG
+    pub image: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Everything an agent usually wants before deciding its next step, in one round-trip
+#[derive(Debug, Serialize)]
+pub struct Observation {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<CursorSnapshot>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub focused_window: Option<WindowInfo>,
+    pub displays: Vec<DisplayInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub screenshot: Option<ObservedScreenshot>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// A window as text, the part of it a text-only agent can use
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WindowTitle {
+    pub app_name: String,
+    pub title: String,
+}
+
+/// A top-level window, as listed by the `list_windows` action
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ListedWindow {
+    pub id: u32,
+    pub pid: u32,
+    pub app_name: String,
+    pub title: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    /// Position in the stacking order, 0 is in front of all other windows
+    pub z_order: u32,
+    pub is_focused: bool,
+    pub is_minimized: bool,
+    pub is_maximized: bool,
+}
+
+/// The observation without any image, for agents that work from text alone
+#[derive(Debug, Serialize)]
+pub struct TextObservation {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<CursorSnapshot>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub focused_window: Option<WindowTitle>,
+    /// Every window that isn't minimized and has a title
+    pub windows: Vec<WindowTitle>,
+    /// What the screen shows, lines in reading order or elements parents first
+    pub text: Vec<ScreenText>,
+    /// Where `text` came from, unset when neither source could be read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text_source: Option<TextSource>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// A piece of text on screen and where it is, in input coordinates
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ScreenText {
+    pub text: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TextSource {
+    /// Lines recognized on the primary monitor
+    Ocr,
+    /// Names of the accessibility elements shown on any monitor
+    Accessibility,
+}
+
+pub fn focused_window(display: Option<&str>) -> Option<WindowInfo> {
+    let windows = top_level_windows(display).ok()?;
+    let window = windows.into_iter().find(|w| w.is_focused)?;
+    Some(WindowInfo {
+        id: window.id,
+        app_name: window.app_name,
+        title: window.title,
+        x: window.x,
+        y: window.y,
+        width: window.width,
+        height: window.height,
+    })
+}
+
+pub fn visible_windows(display: Option<&str>) -> Vec<WindowTitle> {
+    let Ok(windows) = top_level_windows(display) else {
+        return Vec::new();
+    };
+    windows
+        .into_iter()
+        .filter(|window| !window.is_minimized && !window.title.is_empty())
+        .map(|window| WindowTitle {
+            app_name: window.app_name,
+            title: window.title,
+        })
+        .collect()
+}
+
+/// Every top-level window of the X display, or of whatever xcap finds without one, the
+/// frontmost first
+pub fn top_level_windows(display: Option<&str>) -> Result<Vec<ListedWindow>, ActionError> {
+    #[cfg(target_os = "linux")]
+    if let Some(display) = display {
+        return crate::x11::windows(display)
+            .map_err(|e| ActionError::ExecutionFailed(format!("Failed to list windows: {}", e)));
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = display;
+    let mut windows = xcap::Window::all()
+        .map_err(|e| ActionError::ExecutionFailed(format!("Failed to list windows: {}", e)))?;
+    // xcap counts up from the bottom of the stack
+    windows.sort_by_key(|window| std::cmp::Reverse(window.z()));
+    Ok(windows
+        .iter()
+        .enumerate()
+        .map(|(z_order, window)| ListedWindow {
+            id: window.id(),
+            pid: window.pid(),
+            app_name: window.app_name().to_string(),
+            title: window.title().to_string(),
+            x: window.x(),
+            y: window.y(),
+            width: window.width(),
+            height: window.height(),
+            z_order: z_order as u32,
+            is_focused: window.is_focused(),
+            is_minimized: window.is_minimized(),
+            is_maximized: window.is_maximized(),
+        })
+        .collect())
+}
+
+/// What the window manager is asked to do with a window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowCommand {
+    /// Raise it and give it input focus
+    Activate,
+    /// Close it as its close button would, so the app may still ask to save
+    Close,
+    Minimize,
+}
+
+impl WindowCommand {
+    pub fn verb(self) -> &'static str {
+        match self {
+            WindowCommand::Activate => "focus",
+            WindowCommand::Close => "close",
+            WindowCommand::Minimize => "minimize",
+        }
+    }
+}
+
+/// Send a command for a window to the window manager, through the EWMH and ICCCM messages
+#[cfg(target_os = "linux")]
+pub fn command_window(
+    display: Option<&str>,
+    id: u32,
+    command: WindowCommand,
+) -> Result<(), ActionError> {
+    use xcb::{x, XidNew};
+
+    // ICCCM state of a minimized window
+    const ICONIC_STATE: u32 = 3;
+    // Source 2 says a pager asked, which window managers don't treat as focus stealing
+    const SOURCE_PAGER: u32 = 2;
+
+    let failed = |e: String| {
+        ActionError::ExecutionFailed(format!("Failed to {} window: {}", command.verb(), e))
+    };
+    let (conn, screen) = xcb::Connection::connect(display).map_err(|e| failed(e.to_string()))?;
+    let root = conn
+        .get_setup()
+        .roots()
+        .nth(screen as usize)
+        .ok_or_else(|| failed("no root window".to_string()))?
+        .root();
+    let (message, data) = match command {
+        WindowCommand::Activate => (
+            "_NET_ACTIVE_WINDOW",
+            [SOURCE_PAGER, x::CURRENT_TIME, 0, 0, 0],
+        ),
+        WindowCommand::Close => (
+            "_NET_CLOSE_WINDOW",
+            [x::CURRENT_TIME, SOURCE_PAGER, 0, 0, 0],
+        ),
+        WindowCommand::Minimize => ("WM_CHANGE_STATE", [ICONIC_STATE, 0, 0, 0, 0]),
+    };
+    let cookie = conn.send_request(&x::InternAtom {
+        only_if_exists: false,
+        name: message.as_bytes(),
+    });
+    let message = conn
+        .wait_for_reply(cookie)
+        .map_err(|e| failed(e.to_string()))?
+        .atom();
+    // Ids come from list_windows, which got them from the X server
+    let window = unsafe { x::Window::new(id) };
+
+    let event = x::ClientMessageEvent::new(window, message, x::ClientMessageData::Data32(data));
+    conn.send_and_check_request(&x::SendEvent {
+        propagate: false,
+        destination: x::SendEventDest::Window(root),
+        event_mask: x::EventMask::SUBSTRUCTURE_NOTIFY | x::EventMask::SUBSTRUCTURE_REDIRECT,
+        event: &event,
+    })
+    .map_err(|e| failed(e.to_string()))?;
+    if command == WindowCommand::Activate {
+        // Without a window manager nobody answers the request, so raise the window directly too
+        conn.send_and_check_request(&x::ConfigureWindow {
+            window,
+            value_list: &[x::ConfigWindow::StackMode(x::StackMode::Above)],
+        })
+        .map_err(|e| failed(e.to_string()))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn command_window(
+    _display: Option<&str>,
+    _id: u32,
+    command: WindowCommand,
+) -> Result<(), ActionError> {
+    Err(ActionError::ExecutionFailed(format!(
+        "Can't {} windows, window commands are only supported on Linux",
+        command.verb()
+    )))
+}
+
+fn screenshot(
+    capture: &dyn ScreenCapture,
+    mode: ScreenshotMode,
+) -> Result<Option<ObservedScreenshot>, String> {
+    let image = match mode {
+        ScreenshotMode::None => return Ok(None),
+        ScreenshotMode::Scaled => fit_within(
+            capture.capture().map_err(|e| format!("{:?}", e))?,
+            Some(SCALED_SCREENSHOT_WIDTH),
+            None,
+        ),
+        ScreenshotMode::Full => capture.capture().map_err(|e| format!("{:?}", e))?,
+    };
+    Ok(Some(ObservedScreenshot {
+        image: encode_png(&image).map_err(|e| format!("{:?}", e))?,
+        width: image.width(),
+        height: image.height(),
+    }))
+}
+
+/// Cursor, focused window, display geometry and an optional screenshot in one response
+pub async fn observe(
+    extract::State(state): extract::State<Arc<AppState>>,
+    extract::Query(params): extract::Query<ObserveParams>,
+) -> Result<Json<Observation>, (StatusCode, String)> {
+    let displays = state.action_queue.capture().displays().map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to get displays: {:?}", e),
+        )
+    })?;
+    let screenshot = screenshot(state.action_queue.capture(), params.screenshot)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+
+    Ok(Json(Observation {
+        cursor: state.action_queue.cursor_snapshot(),
+        focused_window: state.action_queue.capture().focused_window(),
+        displays,
+        screenshot,
+        timestamp: Utc::now(),
+    }))
+}
+
+// Text is recognized when the build can, the accessibility tree names what is shown otherwise
+async fn screen_text(state: &AppState) -> (Vec<ScreenText>, Option<TextSource>) {
+    if let Ok(spans) = state.action_queue.screen_text().await {
+        let text = spans
+            .into_iter()
+            .map(|span| ScreenText {
+                text: span.text,
+                x: span.x,
+                y: span.y,
+                width: span.width,
+                height: span.height,
+            })
+            .collect();
+        return (text, Some(TextSource::Ocr));
+    }
+    match accessibility::shown_text().await {
+        Ok(elements) => {
+            let text = elements
+                .into_iter()
+                .map(|element| ScreenText {
+                    text: element.name,
+                    x: element.bounds.x,
+                    y: element.bounds.y,
+                    width: element.bounds.width,
+                    height: element.bounds.height,
+                })
+                .collect();
+            (text, Some(TextSource::Accessibility))
+        }
+        Err(_) => (Vec::new(), None),
+    }
+}
+
+/// Cursor, focused window, window titles and the text on screen, without display geometry
+/// or images
+pub async fn observe_text(
+    extract::State(state): extract::State<Arc<AppState>>,
+) -> Json<TextObservation> {
+    let (text, text_source) = screen_text(&state).await;
+    Json(TextObservation {
+        cursor: state.action_queue.cursor_snapshot(),
+        focused_window: state
+            .action_queue
+            .capture()
+            .focused_window()
+            .map(|window| WindowTitle {
+                app_name: window.app_name,
+                title: window.title,
+            }),
+        windows: state.action_queue.capture().windows(),
+        text,
+        text_source,
+        timestamp: Utc::now(),
+    })
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_screenshot_mode_defaults_to_none() {
+        let params: ObserveParams = serde_json::from_str("{}").unwrap();
+        assert_eq!(params.screenshot, ScreenshotMode::None);
+        let params: ObserveParams = serde_json::from_str(r#"{"screenshot":"scaled"}"#).unwrap();
+        assert_eq!(params.screenshot, ScreenshotMode::Scaled);
+    }
+}
@@ -0,0 +1,288 @@
+//! Text recognition for the `ocr` action, injected into the queue like screen capture.
+//!
+//! Tesseract does the recognizing when the server is built with the `ocr` feature, which needs
+//! the tesseract and leptonica libraries. Without it, recognizing text fails.
+
+use image::RgbaImage;
+
+use crate::action_types::{ActionError, MatchMode, TextLevel, TextSpan};
+
+/// Least similarity of a fuzzy match, enough for "Submit" recognized as "Subrnit"
+const FUZZY_THRESHOLD: f32 = 0.7;
+
+pub trait TextRecognizer: Send + Sync + 'static {
+    /// The lines of words in an image, with bounds in image pixels
+    fn recognize(&self, image: &RgbaImage) -> Result<Vec<Vec<TextSpan>>, ActionError>;
+}
+
+/// Recognized lines as spans of the requested level, a line spans its words
+pub fn spans(lines: Vec<Vec<TextSpan>>, level: TextLevel) -> Vec<TextSpan> {
+    match level {
+        TextLevel::Word => lines.into_iter().flatten().collect(),
+        TextLevel::Line => lines
+            .iter()
+            .filter(|words| !words.is_empty())
+            .map(|words| join(words))
+            .collect(),
+    }
+}
+
+// Consecutive words as one span around all of them
+fn join(words: &[TextSpan]) -> TextSpan {
+    let left = words.iter().map(|word| word.x).min().unwrap_or_default();
+    let top = words.iter().map(|word| word.y).min().unwrap_or_default();
+    let right = words.iter().map(|word| word.x + word.width as i32).max();
+    let bottom = words.iter().map(|word| word.y + word.height as i32).max();
+    TextSpan {
+        text: words
+            .iter()
+            .map(|word| word.text.as_str())
+            .collect::<Vec<_>>()
+            .join(" "),
+        x: left,
+        y: top,
+        width: (right.unwrap_or_default() - left) as u32,
+        height: (bottom.unwrap_or_default() - top) as u32,
+        confidence: words.iter().map(|word| word.confidence).sum::<f32>() / words.len() as f32,
+    }
+}
+
+/// The words that match the query best and how well, from 0 to 1.
+/// Runs of as many words as the query has are compared, so the match is as tight as the query
+pub fn best_match(
+    lines: &[Vec<TextSpan>],
+    query: &str,
+    mode: MatchMode,
+) -> Option<(TextSpan, f32)> {
+    let query = normalize(query);
+    let length = query.split(' ').count();
+    lines
+        .iter()
+        .filter(|words| !words.is_empty())
+        .flat_map(|words| words.windows(length.min(words.len())))
+        .filter_map(|run| {
+            let candidate = join(run);
+            let text = normalize(&candidate.text);
+            let score = match mode {
+                MatchMode::Exact => (text == query).then_some(1.0),
+                // Tighter matches are better, "Save" in "Save" beats "Save" in "Saved"
+                MatchMode::Contains => text
+                    .contains(&query)
+                    .then(|| query.chars().count() as f32 / text.chars().count() as f32),
+                MatchMode::Fuzzy => {
+                    Some(similarity(&text, &query)).filter(|s| *s >= FUZZY_THRESHOLD)
+                }
+            }?;
+            Some((candidate, score))
+        })
+        .max_by(|(a, a_score), (b, b_score)| {
+            a_score
+                .total_cmp(b_score)
+                .then(a.confidence.total_cmp(&b.confidence))
+        })
+}
+
+// Case and spacing differ between how a label is written and how it is recognized
+fn normalize(text: &str) -> String {
+    text.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+// 1 minus the edit distance relative to the longer text
+fn similarity(a: &str, b: &str) -> f32 {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.iter().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    1.0 - previous[b.len()] as f32 / longest as f32
+}
+
+/// The recognizer the server was built with
+pub fn platform_recognizer() -> Box<dyn TextRecognizer> {
+    #[cfg(feature = "ocr")]
+    {
+        Box::new(Tesseract)
+    }
+    #[cfg(not(feature = "ocr"))]
+    {
+        Box::new(Unavailable)
+    }
+}
+
+/// Recognizes English text with the system's tesseract
+#[cfg(feature = "ocr")]
+pub struct Tesseract;
+
+#[cfg(feature = "ocr")]
+impl TextRecognizer for Tesseract {
+    fn recognize(&self, image: &RgbaImage) -> Result<Vec<Vec<TextSpan>>, ActionError> {
+        let tsv = Self::tsv(image)
+            .map_err(|e| ActionError::ExecutionFailed(format!("Text recognition failed: {}", e)))?;
+        Ok(parse_tsv(&tsv))
+    }
+}
+
+#[cfg(feature = "ocr")]
+impl Tesseract {
+    fn tsv(image: &RgbaImage) -> Result<String, tesseract::TesseractError> {
+        let (width, height) = (image.width() as i32, image.height() as i32);
+        let mut api = tesseract::Tesseract::new(None, Some("eng"))?
+            .set_frame(image.as_raw(), width, height, 4, width * 4)?
+            .recognize()?;
+        Ok(api.get_tsv_text(0)?)
+    }
+}
+
+/// Stands in for tesseract when the server was built without it
+#[cfg(not(feature = "ocr"))]
+pub struct Unavailable;
+
+#[cfg(not(feature = "ocr"))]
+impl TextRecognizer for Unavailable {
+    fn recognize(&self, _image: &RgbaImage) -> Result<Vec<Vec<TextSpan>>, ActionError> {
+        Err(ActionError::ExecutionFailed(
+            "valk-server was built without the ocr feature".to_string(),
+        ))
+    }
+}
+
+// Tesseract's TSV has a row per page, block, paragraph, line and word. Only word rows (level 5)
+// have text, their block, paragraph and line numbers say which line they are on
+#[cfg(any(feature = "ocr", test))]
+fn parse_tsv(tsv: &str) -> Vec<Vec<TextSpan>> {
+    let mut lines: Vec<((u32, u32, u32), Vec<TextSpan>)> = Vec::new();
+    for row in tsv.lines() {
+        let fields: Vec<&str> = row.split('\t').collect();
+        let [level, _page, block, paragraph, line, _word, left, top, width, height, confidence, text] =
+            fields[..]
+        else {
+            continue;
+        };
+        let number = |field: &str| field.parse::<u32>().ok();
+        let (Some(5), Some(block), Some(paragraph), Some(line)) = (
+            number(level),
+            number(block),
+            number(paragraph),
+            number(line),
+        ) else {
+            continue;
+        };
+        let (Ok(x), Ok(y), Some(width), Some(height), Ok(confidence)) = (
+            left.parse(),
+            top.parse(),
+            number(width),
+            number(height),
+            confidence.parse::<f32>(),
+        ) else {
+            continue;
+        };
+        // Empty words are layout guesses, their confidence is -1
+        if text.trim().is_empty() || confidence < 0.0 {
+            continue;
+        }
+        let word = TextSpan {
+            text: text.trim().to_string(),
+            x,
+            y,
+            width,
+            height,
+            confidence,
+        };
+        let key = (block, paragraph, line);
+        match lines.last_mut() {
+            Some((last, words)) if *last == key => words.push(word),
+            _ => lines.push((key, vec![word])),
+        }
+    }
+    lines.into_iter().map(|(_, words)| words).collect()
+}
+
+/// Recognizes the same text in every image, for tests
+#[cfg(test)]
+pub struct FixedText(pub Vec<Vec<TextSpan>>);
+
+#[cfg(test)]
+impl TextRecognizer for FixedText {
+    fn recognize(&self, _image: &RgbaImage) -> Result<Vec<Vec<TextSpan>>, ActionError> {
+        Ok(self.0.clone())
+    }
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tsv_groups_words_into_lines() {
+        let tsv = [
+            "1\t1\t0\t0\t0\t0\t0\t0\t800\t600\t-1\t",
+            "4\t1\t1\t1\t1\t0\t10\t20\t120\t16\t-1\t",
+            "5\t1\t1\t1\t1\t1\t10\t20\t50\t16\t96.5\tSave",
+            "5\t1\t1\t1\t1\t2\t66\t20\t64\t16\t91\tchanges",
+            "5\t1\t1\t1\t1\t3\t140\t20\t0\t0\t-1\t ",
+            "5\t1\t2\t1\t1\t1\t300\t400\t60\t18\t88\tCancel",
+            "not a row",
+        ]
+        .join("\n");
+
+        let lines = parse_tsv(&tsv);
+        assert_eq!(lines.len(), 2);
+        let words: Vec<&str> = lines[0].iter().map(|word| word.text.as_str()).collect();
+        assert_eq!(words, ["Save", "changes"]);
+        assert_eq!(
+            (lines[0][1].x, lines[0][1].y, lines[0][1].width),
+            (66, 20, 64)
+        );
+        assert_eq!(lines[1][0].text, "Cancel");
+        assert_eq!(lines[1][0].confidence, 88.0);
+
+        let line = &spans(lines.clone(), TextLevel::Line)[0];
+        assert_eq!(line.text, "Save changes");
+        assert_eq!((line.x, line.y, line.width, line.height), (10, 20, 120, 16));
+        assert_eq!(line.confidence, 93.75);
+        assert_eq!(spans(lines, TextLevel::Word).len(), 3);
+    }
+
+    #[test]
+    fn test_best_match_prefers_tight_matches() {
+        let word = |text: &str, x| TextSpan {
+            text: text.to_string(),
+            x,
+            y: 0,
+            width: 40,
+            height: 10,
+            confidence: 90.0,
+        };
+        let lines = vec![
+            vec![word("Saved", 0), word("drafts", 50)],
+            vec![word("Cancel", 0), word("Save", 50), word("changes", 100)],
+            vec![word("Subrnit", 0)],
+        ];
+
+        let (found, score) = best_match(&lines, "save", MatchMode::Contains).unwrap();
+        assert_eq!((found.text.as_str(), found.x, score), ("Save", 50, 1.0));
+        let (found, _) = best_match(&lines, "SAVE  changes", MatchMode::Exact).unwrap();
+        assert_eq!(
+            (found.text.as_str(), found.x, found.width),
+            ("Save changes", 50, 90)
+        );
+        assert!(best_match(&lines, "Submit", MatchMode::Exact).is_none());
+        let (found, score) = best_match(&lines, "Submit", MatchMode::Fuzzy).unwrap();
+        assert_eq!(found.text, "Subrnit");
+        assert!((FUZZY_THRESHOLD..1.0).contains(&score));
+        assert!(best_match(&lines, "Delete", MatchMode::Fuzzy).is_none());
+    }
+}
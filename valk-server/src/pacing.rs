@@ -0,0 +1,123 @@
+//! Per action type pacing rules, so operators can slow down aggressive agents.
+//!
+//! Each rule allows at most `max` actions of one type to start within any `per_ms` window.
+//! The pacer is asked before every agent action, which either waits for the next free slot
+//! or is refused, depending on the configured mode. Units wait in the dispatcher, so the
+//! worker doesn't hold the input driver meanwhile, only later actions of a batch wait in it.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::PacingRule;
+
+pub struct Pacer {
+    rules: Vec<PacingRule>,
+    // Start times of recent actions, per action type with a rule
+    started: Mutex<HashMap<String, VecDeque<Instant>>>,
+    // Every action waiting for its slot holds up the actions queued behind it too, so callers
+    // don't count this against their timeout
+    held_ms: AtomicU64,
+}
+
+impl Pacer {
+    pub fn new(rules: Vec<PacingRule>) -> Self {
+        Self {
+            rules,
+            started: Mutex::new(HashMap::new()),
+            held_ms: AtomicU64::new(0),
+        }
+    }
+
+    /// Note that an action is being held back for `wait`
+    pub fn hold(&self, wait: Duration) {
+        self.held_ms
+            .fetch_add(wait.as_millis() as u64, Ordering::SeqCst);
+    }
+
+    /// How long actions have been held back so far, in total
+    pub fn held(&self) -> Duration {
+        Duration::from_millis(self.held_ms.load(Ordering::SeqCst))
+    }
+
+    /// How long an action of this type has to wait before it may start, and the rule holding it
+    pub fn wait(&self, action_type: &str) -> Option<(Duration, &PacingRule)> {
+        self.wait_at(action_type, Instant::now())
+    }
+
+    /// Note that an action of this type started
+    pub fn record(&self, action_type: &str) {
+        self.record_at(action_type, Instant::now());
+    }
+
+    fn wait_at(&self, action_type: &str, now: Instant) -> Option<(Duration, &PacingRule)> {
+        let started = self.started.lock().unwrap();
+        let history = started.get(action_type)?;
+        self.rules
+            .iter()
+            .filter(|rule| rule.action_type == action_type)
+            .filter_map(|rule| {
+                // The window is full while the max-th most recent start is still inside it
+                let oldest = history.iter().rev().nth(rule.max as usize - 1)?;
+                let free_at = *oldest + Duration::from_millis(rule.per_ms);
+                (free_at > now).then(|| (free_at - now, rule))
+            })
+            .max_by_key(|(wait, _)| *wait)
+    }
+
+    fn record_at(&self, action_type: &str, now: Instant) {
+        // Enough history for the strictest rule on this type
+        let Some(keep) = self
+            .rules
+            .iter()
+            .filter(|rule| rule.action_type == action_type)
+            .map(|rule| rule.max as usize)
+            .max()
+        else {
+            return;
+        };
+        let mut started = self.started.lock().unwrap();
+        let history = started.entry(action_type.to_string()).or_default();
+        history.push_back(now);
+        while history.len() > keep {
+            history.pop_front();
+        }
+    }
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pacer_spaces_actions_per_rule() {
+        let pacer = Pacer::new(vec![
+            "screenshot=2/1000".parse().unwrap(),
+            "click=200".parse().unwrap(),
+        ]);
+        let start = Instant::now();
+
+        assert!(pacer.wait_at("screenshot", start).is_none());
+        pacer.record_at("screenshot", start);
+        pacer.record_at("screenshot", start + Duration::from_millis(100));
+        let (wait, rule) = pacer
+            .wait_at("screenshot", start + Duration::from_millis(400))
+            .unwrap();
+        assert_eq!(wait, Duration::from_millis(600));
+        assert_eq!(rule.to_string(), "2 screenshot actions per 1000ms");
+        assert!(pacer
+            .wait_at("screenshot", start + Duration::from_millis(1000))
+            .is_none());
+
+        // Types without a rule, and types with history of their own, don't interfere
+        pacer.record_at("mouse_move", start);
+        assert!(pacer.wait_at("mouse_move", start).is_none());
+        pacer.record_at("click", start);
+        let (wait, _) = pacer
+            .wait_at("click", start + Duration::from_millis(50))
+            .unwrap();
+        assert_eq!(wait, Duration::from_millis(150));
+    }
+}
@@ -0,0 +1,446 @@
+//! Policy rules, redaction zones and macros loaded from a watched directory, so deployments
+//! can manage them as files.
+//!
+//! Every `*.json` file in `VALK_POLICY_DIR` holds rules like
+//! `{"approval_actions": ["type_text"], "denied_actions": ["paste_text"]}`, screen areas to
+//! black out of every capture and named lists of actions, and the contents of all files add
+//! up. The directory is polled and reloaded when a file is added, removed or changed. If any
+//! file fails to parse, the previous contents stay in effect. If there are none because the
+//! first load already failed, every action is denied and nothing is captured until a load
+//! succeeds, rather than running without the rules.
+
+use image::{Rgba, RgbaImage};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tracing::{error, info};
+
+use crate::action_queue::MAX_BATCH_ACTIONS;
+use crate::action_types::{Action, ActionError, Region};
+use crate::capture::{DisplayInfo, ScreenCapture};
+use crate::observe::{ListedWindow, WindowCommand, WindowInfo, WindowTitle};
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    /// Action types held until approved, on top of VALK_APPROVAL_ACTIONS
+    #[serde(default)]
+    pub approval_actions: Vec<String>,
+    /// Action types refused outright
+    #[serde(default)]
+    pub denied_actions: Vec<String>,
+    /// Screen areas painted black in every capture, in screen coordinates
+    #[serde(default)]
+    pub redaction_zones: Vec<Region>,
+    /// Named lists of actions, run as a batch by POST /v1/macros/{name}
+    #[serde(default)]
+    pub macros: BTreeMap<String, Vec<Action>>,
+}
+
+impl Policy {
+    fn extend(&mut self, other: Policy) -> Result<(), String> {
+        self.approval_actions.extend(other.approval_actions);
+        self.denied_actions.extend(other.denied_actions);
+        self.redaction_zones.extend(other.redaction_zones);
+        for (name, actions) in other.macros {
+            if self.macros.insert(name.clone(), actions).is_some() {
+                return Err(format!("Macro {} is defined more than once", name));
+            }
+        }
+        Ok(())
+    }
+
+    // Macros are checked as they load, so a broken one is reported by the reload instead of
+    // each time it runs
+    fn validate(&self) -> Result<(), String> {
+        for (name, actions) in &self.macros {
+            if !valid_macro_name(name) {
+                return Err(format!(
+                    "Macro name {:?} must be up to 64 letters, digits, '-', '_' or '.'",
+                    name
+                ));
+            }
+            if !(1..=MAX_BATCH_ACTIONS).contains(&actions.len()) {
+                return Err(format!(
+                    "Macro {} needs between 1 and {} actions, has {}",
+                    name,
+                    MAX_BATCH_ACTIONS,
+                    actions.len()
+                ));
+            }
+            for (index, action) in actions.iter().enumerate() {
+                action
+                    .validate()
+                    .map_err(|e| format!("Action {} of macro {}: {:?}", index + 1, name, e))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Macro names are part of the path they are run by
+fn valid_macro_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+// What the directory looked like when it was last loaded, to notice changes
+type Signature = Vec<(PathBuf, Option<SystemTime>, u64)>;
+
+pub struct PolicyWatch {
+    dir: Option<PathBuf>,
+    // No policy while the directory has never loaded, which denies everything
+    state: Mutex<(Signature, Option<Policy>)>,
+}
+
+impl PolicyWatch {
+    /// Loads the directory right away. A directory that can't be listed has no files and so
+    /// no rules, but a file that fails to load leaves the watch without a policy
+    pub fn new(dir: Option<PathBuf>) -> Self {
+        let Some(path) = &dir else {
+            return Self {
+                dir,
+                state: Mutex::new((Vec::new(), Some(Policy::default()))),
+            };
+        };
+        let signature = signature(path);
+        let policy = match load_dir(path) {
+            Ok(policy) => {
+                info!("Loaded policy from {} files", signature.len());
+                Some(policy)
+            }
+            Err(e) => {
+                error!(
+                    "No valid policy, every action is denied until one loads: {}",
+                    e
+                );
+                None
+            }
+        };
+        Self {
+            dir,
+            state: Mutex::new((signature, policy)),
+        }
+    }
+
+    pub fn is_watching(&self) -> bool {
+        self.dir.is_some()
+    }
+
+    /// Reload if the directory changed since the last load, returning the outcome if it did
+    pub fn refresh(&self) -> Option<Result<usize, String>> {
+        let dir = self.dir.as_ref()?;
+        let signature = signature(dir);
+        let mut state = self.state.lock().unwrap();
+        if signature == state.0 {
+            return None;
+        }
+        // Remember the failed signature too, so a broken file is reported once
+        state.0 = signature;
+        let files = state.0.len();
+        Some(load_dir(dir).map(|policy| {
+            state.1 = Some(policy);
+            files
+        }))
+    }
+
+    pub fn requires_approval(&self, action_type: &str) -> bool {
+        let state = self.state.lock().unwrap();
+        state
+            .1
+            .as_ref()
+            .is_some_and(|policy| policy.approval_actions.iter().any(|t| t == action_type))
+    }
+
+    /// Refuses action types the policy denies, and every action while there is no policy
+    pub fn check(&self, action_type: &str) -> Result<(), ActionError> {
+        let state = self.state.lock().unwrap();
+        let Some(policy) = &state.1 else {
+            return Err(no_policy());
+        };
+        if policy.denied_actions.iter().any(|t| t == action_type) {
+            return Err(ActionError::PermissionDenied(format!(
+                "Policy denies {} actions",
+                action_type
+            )));
+        }
+        Ok(())
+    }
+
+    /// Screen areas to black out of captures, None while there is no policy
+    pub fn redaction_zones(&self) -> Option<Vec<Region>> {
+        let state = self.state.lock().unwrap();
+        state
+            .1
+            .as_ref()
+            .map(|policy| policy.redaction_zones.clone())
+    }
+
+    pub fn macros(&self) -> BTreeMap<String, Vec<Action>> {
+        let state = self.state.lock().unwrap();
+        state
+            .1
+            .as_ref()
+            .map(|policy| policy.macros.clone())
+            .unwrap_or_default()
+    }
+}
+
+fn no_policy() -> ActionError {
+    ActionError::PermissionDenied(
+        "No valid policy has loaded from VALK_POLICY_DIR, nothing is allowed until one does"
+            .to_string(),
+    )
+}
+
+/// Capture with the policy's redaction zones painted black, so nothing that reads the screen,
+/// screenshots, OCR and monitors included, sees what they cover
+pub struct RedactedCapture {
+    inner: Arc<dyn ScreenCapture>,
+    policy: Arc<PolicyWatch>,
+}
+
+impl RedactedCapture {
+    pub fn new(inner: Arc<dyn ScreenCapture>, policy: Arc<PolicyWatch>) -> Self {
+        Self { inner, policy }
+    }
+
+    fn redact(
+        &self,
+        mut image: RgbaImage,
+        display: impl FnOnce(Vec<DisplayInfo>) -> Option<DisplayInfo>,
+    ) -> Result<RgbaImage, ActionError> {
+        let zones = self.policy.redaction_zones().ok_or_else(no_policy)?;
+        if zones.is_empty() {
+            return Ok(image);
+        }
+        let display = display(self.inner.displays()?).ok_or_else(|| {
+            ActionError::ExecutionFailed("Captured monitor is not listed".to_string())
+        })?;
+        // Monitors with a scale factor capture more pixels than they have coordinates
+        let scale = image.width() as f64 / display.width.max(1) as f64;
+        let to_image = |value: i64, origin: i32, limit: u32| {
+            (((value - origin as i64) as f64 * scale).round() as i64).clamp(0, limit as i64) as u32
+        };
+        for zone in zones {
+            let left = to_image(zone.x as i64, display.x, image.width());
+            let top = to_image(zone.y as i64, display.y, image.height());
+            let right = to_image(zone.x as i64 + zone.width as i64, display.x, image.width());
+            let bottom = to_image(
+                zone.y as i64 + zone.height as i64,
+                display.y,
+                image.height(),
+            );
+            for y in top..bottom {
+                for x in left..right {
+                    image.put_pixel(x, y, Rgba([0, 0, 0, 255]));
+                }
+            }
+        }
+        Ok(image)
+    }
+}
+
+impl ScreenCapture for RedactedCapture {
+    fn capture(&self) -> Result<RgbaImage, ActionError> {
+        let image = self.inner.capture()?;
+        self.redact(image, |displays| {
+            displays.into_iter().find(|display| display.is_primary)
+        })
+    }
+
+    fn capture_display(&self, id: u32) -> Result<RgbaImage, ActionError> {
+        let image = self.inner.capture_display(id)?;
+        self.redact(image, |displays| {
+            displays.into_iter().find(|display| display.id == id)
+        })
+    }
+
+    fn screen_size(&self) -> Result<(u32, u32), ActionError> {
+        self.inner.screen_size()
+    }
+
+    fn displays(&self) -> Result<Vec<DisplayInfo>, ActionError> {
+        self.inner.displays()
+    }
+
+    fn focused_window(&self) -> Option<WindowInfo> {
+        self.inner.focused_window()
+    }
+
+    fn windows(&self) -> Vec<WindowTitle> {
+        self.inner.windows()
+    }
+
+    fn top_level_windows(&self) -> Result<Vec<ListedWindow>, ActionError> {
+        self.inner.top_level_windows()
+    }
+
+    fn command_window(&self, id: u32, command: WindowCommand) -> Result<(), ActionError> {
+        self.inner.command_window(id, command)
+    }
+}
+
+fn policy_files(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "json")
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+fn signature(dir: &Path) -> Signature {
+    policy_files(dir)
+        .into_iter()
+        .map(|path| {
+            let metadata = fs::metadata(&path).ok();
+            let modified = metadata.as_ref().and_then(|m| m.modified().ok());
+            let len = metadata.map_or(0, |m| m.len());
+            (path, modified, len)
+        })
+        .collect()
+}
+
+fn load_dir(dir: &Path) -> Result<Policy, String> {
+    let mut policy = Policy::default();
+    for path in policy_files(dir) {
+        let json = fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let mut file: Policy = serde_json::from_str(&json)
+            .map_err(|e| format!("Invalid policy {}: {}", path.display(), e))?;
+        for action_type in file
+            .approval_actions
+            .iter_mut()
+            .chain(file.denied_actions.iter_mut())
+        {
+            *action_type = action_type.trim().to_lowercase();
+        }
+        policy
+            .extend(file)
+            .map_err(|e| format!("Invalid policy {}: {}", path.display(), e))?;
+    }
+    policy.validate()?;
+    Ok(policy)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::ImageCapture;
+
+    #[test]
+    fn test_policy_reloads_on_change() {
+        let dir = std::env::temp_dir().join(format!("valk-policy-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("base.json"),
+            r#"{"denied_actions": ["paste_text"]}"#,
+        )
+        .unwrap();
+        fs::write(dir.join("notes.txt"), "not a policy").unwrap();
+
+        let watch = PolicyWatch::new(Some(dir.clone()));
+        assert!(watch.check("paste_text").is_err());
+        assert!(watch.check("type_text").is_ok());
+        assert!(!watch.requires_approval("type_text"));
+        assert!(watch.refresh().is_none());
+
+        fs::write(
+            dir.join("review.json"),
+            r#"{"approval_actions": ["Type_Text"]}"#,
+        )
+        .unwrap();
+        assert_eq!(watch.refresh(), Some(Ok(2)));
+        assert!(watch.requires_approval("type_text"));
+        assert!(watch.check("paste_text").is_err());
+
+        // A broken file keeps the rules that were loaded last
+        fs::write(dir.join("review.json"), r#"{"approval": ["type_text"]}"#).unwrap();
+        let reloaded = watch.refresh();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(reloaded, Some(Err(_))));
+        assert!(watch.requires_approval("type_text"));
+    }
+
+    #[test]
+    fn test_broken_first_load_denies_everything() {
+        let dir = std::env::temp_dir().join(format!("valk-policy-broken-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("base.json"), r#"{"denied": ["paste_text"]}"#).unwrap();
+
+        let watch = Arc::new(PolicyWatch::new(Some(dir.clone())));
+        assert!(watch.check("ping").is_err());
+        let capture = RedactedCapture::new(
+            Arc::new(ImageCapture::new(RgbaImage::new(8, 8))),
+            watch.clone(),
+        );
+        assert!(capture.capture().is_err());
+
+        fs::write(
+            dir.join("base.json"),
+            r#"{"denied_actions": ["paste_text"]}"#,
+        )
+        .unwrap();
+        let reloaded = watch.refresh();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(reloaded, Some(Ok(1)));
+        assert!(watch.check("ping").is_ok());
+        assert!(capture.capture().is_ok());
+    }
+
+    #[test]
+    fn test_redaction_zones_and_macros_load() {
+        let dir = std::env::temp_dir().join(format!("valk-policy-extras-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("kiosk.json"),
+            r#"{
+                "redaction_zones": [{"x": 2, "y": 1, "width": 3, "height": 2}],
+                "macros": {"reset": [{"type": "mouse_move", "input": {"x": 0, "y": 0}}]}
+            }"#,
+        )
+        .unwrap();
+
+        let watch = Arc::new(PolicyWatch::new(Some(dir.clone())));
+        assert_eq!(watch.macros()["reset"].len(), 1);
+        let white = RgbaImage::from_pixel(8, 8, Rgba([255, 255, 255, 255]));
+        let capture = RedactedCapture::new(Arc::new(ImageCapture::new(white)), watch.clone());
+        let image = capture.capture().unwrap();
+        assert_eq!(*image.get_pixel(2, 1), Rgba([0, 0, 0, 255]));
+        assert_eq!(*image.get_pixel(4, 2), Rgba([0, 0, 0, 255]));
+        assert_eq!(*image.get_pixel(5, 2), Rgba([255, 255, 255, 255]));
+        assert_eq!(*image.get_pixel(2, 3), Rgba([255, 255, 255, 255]));
+
+        // A macro defined twice, or with an action that can't run, fails the load
+        fs::write(
+            dir.join("more.json"),
+            r#"{"macros": {"reset": [{"type": "left_click"}]}}"#,
+        )
+        .unwrap();
+        let duplicate = watch.refresh();
+        fs::write(
+            dir.join("more.json"),
+            r#"{"macros": {"far": [{"type": "mouse_move", "input": {"x": 40000, "y": 0}}]}}"#,
+        )
+        .unwrap();
+        let invalid = watch.refresh();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(duplicate, Some(Err(e)) if e.contains("more than once")));
+        assert!(matches!(invalid, Some(Err(e)) if e.contains("far")));
+        assert_eq!(watch.macros().len(), 1);
+    }
+}
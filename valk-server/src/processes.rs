@@ -0,0 +1,85 @@
+//! Processes running on the machine, so orchestrators can check that an app they started is
+//! up, or spot one that hangs.
+
+use axum::{extract, Json};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sysinfo::{ProcessRefreshKind, ProcessesToUpdate, System, MINIMUM_CPU_UPDATE_INTERVAL};
+
+#[derive(Debug, Serialize)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent_pid: Option<u32>,
+    pub name: String,
+    /// Share of one core over the sampling interval, above 100 for processes using several
+    pub cpu_percent: f32,
+    /// Resident memory
+    pub memory_bytes: u64,
+    pub started_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ProcessQuery {
+    /// Only processes whose name contains this, ignoring case
+    pub name: Option<String>,
+}
+
+/// Every process, threads left out, ordered by pid. CPU usage is measured over a short
+/// interval, so this takes a moment
+pub async fn list_processes(name: Option<&str>) -> Vec<ProcessInfo> {
+    let refresh = ProcessRefreshKind::nothing().with_cpu().with_memory();
+    let mut system = System::new();
+    system.refresh_processes_specifics(ProcessesToUpdate::All, true, refresh);
+    tokio::time::sleep(MINIMUM_CPU_UPDATE_INTERVAL).await;
+    system.refresh_processes_specifics(ProcessesToUpdate::All, true, refresh);
+
+    let name = name.map(str::to_lowercase);
+    let mut processes: Vec<ProcessInfo> = system
+        .processes()
+        .values()
+        .filter(|process| process.thread_kind().is_none())
+        .map(|process| ProcessInfo {
+            pid: process.pid().as_u32(),
+            parent_pid: process.parent().map(|pid| pid.as_u32()),
+            name: process.name().to_string_lossy().into_owned(),
+            cpu_percent: process.cpu_usage(),
+            memory_bytes: process.memory(),
+            started_at: DateTime::from_timestamp(process.start_time() as i64, 0),
+        })
+        .filter(|process| {
+            name.as_ref()
+                .is_none_or(|name| process.name.to_lowercase().contains(name))
+        })
+        .collect();
+    processes.sort_by_key(|process| process.pid);
+    processes
+}
+
+/// `GET /v1/system/processes`, optionally `?name=firefox`
+pub async fn processes(
+    extract::Query(query): extract::Query<ProcessQuery>,
+) -> Json<Vec<ProcessInfo>> {
+    Json(list_processes(query.name.as_deref()).await)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_lists_this_process() {
+        let pid = std::process::id();
+        let all = list_processes(None).await;
+        let this = all
+            .iter()
+            .find(|process| process.pid == pid)
+            .expect("this process is listed");
+        assert!(this.memory_bytes > 0);
+
+        let matching = list_processes(Some(&this.name.to_uppercase())).await;
+        assert!(matching.iter().any(|process| process.pid == pid));
+        assert!(matching.len() < all.len());
+    }
+}
@@ -0,0 +1,626 @@
+//! Forwarding actions between valk instances, for jump-host setups where only a bastion is
+//! reachable from the network.
+//!
+//! Inner machines set `VALK_RELAY_UPSTREAM` and keep a WebSocket open to the bastion's
+//! `/v1/relay/connect`, so they never accept connections themselves. Requests POSTed to the
+//! bastion's `/v1/relay/{route}` travel down that socket, where `route` names the peers to go
+//! through, e.g. `lab-gateway/desk-42` when the inner machine is itself a bastion. Every hop
+//! checks `VALK_RELAY_TOKEN`, and the last one runs the action like any other request.
+//!
+//! Every peer presents the same token, so a name can't be taken over while its peer is still
+//! connected. The bastion pings its peers and drops those that stop answering, which frees
+//! the name of a peer whose connection died without a close.
+
+use axum::{
+    extract::{
+        self,
+        rejection::JsonRejection,
+        ws::{Message, Utf8Bytes, WebSocket, WebSocketUpgrade},
+        Query,
+    },
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinSet;
+use tokio::time::{interval, sleep, timeout, Duration, Instant};
+use tokio_tungstenite::tungstenite::{self, client::IntoClientRequest};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::action_queue::SharedQueue;
+use crate::action_types::{ActionError, ActionRequest};
+use crate::proxy;
+use crate::takeover::{tokens_match, HeldInput};
+use crate::AppState;
+
+/// Waiting for a reconnect starts here and doubles up to `RECONNECT_MAX`
+const RECONNECT_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_MAX: Duration = Duration::from_secs(60);
+/// Added to the approval timeout, so a relayed action held for approval can still be answered
+const RELAY_MARGIN: Duration = Duration::from_secs(30);
+/// How often the bastion pings its peers
+const PEER_PING_INTERVAL: Duration = Duration::from_secs(15);
+/// A connection nothing arrived on for this long is taken for dead, on either side
+const PEER_IDLE_TIMEOUT: Duration = Duration::from_secs(45);
+
+/// Messages on a relay socket, as JSON text
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum RelayMessage {
+    /// Bastion to peer: run the request, or pass it on along the rest of the route
+    Action {
+        call_id: u64,
+        #[serde(default)]
+        route: Vec<String>,
+        request: Box<ActionRequest>,
+    },
+    /// Peer to bastion: the HTTP status and body the request ended with
+    Response {
+        call_id: u64,
+        status: u16,
+        body: Value,
+    },
+}
+
+struct RelayCall {
+    route: Vec<String>,
+    request: ActionRequest,
+    reply: oneshot::Sender<(StatusCode, Value)>,
+}
+
+struct Peer {
+    /// Tells a peer's connection from the one that replaced it
+    session: Uuid,
+    calls: mpsc::Sender<RelayCall>,
+    connected_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerInfo {
+    pub name: String,
+    pub connected_at: DateTime<Utc>,
+}
+
+/// The peers connected to this instance, by name
+#[derive(Default)]
+pub struct RelayRegistry {
+    peers: Mutex<HashMap<String, Peer>>,
+}
+
+impl RelayRegistry {
+    pub fn peers(&self) -> Vec<PeerInfo> {
+        let peers = self.peers.lock().unwrap();
+        let mut peers: Vec<PeerInfo> = peers
+            .iter()
+            .filter(|(_, peer)| !peer.calls.is_closed())
+            .map(|(name, peer)| PeerInfo {
+                name: name.clone(),
+                connected_at: peer.connected_at,
+            })
+            .collect();
+        peers.sort_by(|a, b| a.name.cmp(&b.name));
+        peers
+    }
+
+    // Takes the name for a new connection, unless a peer is still connected under it. Every
+    // peer holds the same token, so replacing it would let any of them take another's actions
+    fn register(&self, name: &str, calls: mpsc::Sender<RelayCall>) -> Option<Uuid> {
+        let mut peers = self.peers.lock().unwrap();
+        if peers.get(name).is_some_and(|peer| !peer.calls.is_closed()) {
+            return None;
+        }
+        let session = Uuid::new_v4();
+        let peer = Peer {
+            session,
+            calls,
+            connected_at: Utc::now(),
+        };
+        peers.insert(name.to_string(), peer);
+        Some(session)
+    }
+
+    fn unregister(&self, name: &str, session: Uuid) {
+        let mut peers = self.peers.lock().unwrap();
+        if peers.get(name).is_some_and(|peer| peer.session == session) {
+            peers.remove(name);
+        }
+    }
+
+    /// Sends a request along a route of peer names, answering with the status and body the
+    /// last peer gave it
+    pub async fn forward(
+        &self,
+        route: &[String],
+        request: ActionRequest,
+        wait: Duration,
+    ) -> (StatusCode, Value) {
+        let Some((name, rest)) = route.split_first() else {
+            return error_reply(ActionError::InvalidInput(
+                "Relay route is empty".to_string(),
+            ));
+        };
+        let calls = self
+            .peers
+            .lock()
+            .unwrap()
+            .get(name)
+            .map(|peer| peer.calls.clone());
+        let Some(calls) = calls else {
+            return error_reply(ActionError::NotFound(format!(
+                "No relay peer named {} is connected",
+                name
+            )));
+        };
+        let (reply, answer) = oneshot::channel();
+        let call = RelayCall {
+            route: rest.to_vec(),
+            request,
+            reply,
+        };
+        if calls.send(call).await.is_err() {
+            return error_reply(disconnected(name));
+        }
+        match timeout(wait, answer).await {
+            Ok(Ok(answer)) => answer,
+            Ok(Err(_)) => error_reply(disconnected(name)),
+            Err(_) => error_reply(ActionError::Timeout),
+        }
+    }
+}
+
+fn disconnected(name: &str) -> ActionError {
+    ActionError::ExecutionFailed(format!("Relay peer {} disconnected", name))
+}
+
+fn error_reply(error: ActionError) -> (StatusCode, Value) {
+    (
+        crate::error_status(Some(&error)),
+        serde_json::json!({"status": "error", "error": error}),
+    )
+}
+
+// How long to wait for a relayed action, approvals on the far side may hold it
+fn relay_wait(queue: &SharedQueue) -> Duration {
+    Duration::from_secs(queue.config().approval_timeout_secs) + RELAY_MARGIN
+}
+
+// Peer names end up in route paths, so they can't contain slashes or other surprises
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+// Every relay endpoint needs VALK_RELAY_TOKEN, and is off without it
+fn check_token(state: &AppState, headers: &HeaderMap) -> Result<(), (StatusCode, &'static str)> {
+    let Some(expected) = state.action_queue.config().relay_token.as_deref() else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            "Relaying is disabled, set VALK_RELAY_TOKEN to enable it",
+        ));
+    };
+    let presented = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if !presented.is_some_and(|token| tokens_match(token, expected)) {
+        return Err((StatusCode::UNAUTHORIZED, "Invalid relay token"));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConnectParams {
+    name: String,
+}
+
+/// `GET /v1/relay/connect?name=...`, the WebSocket an inner machine keeps open to take
+/// relayed actions
+pub async fn relay_connect(
+    ws: WebSocketUpgrade,
+    extract::State(state): extract::State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(params): Query<ConnectParams>,
+) -> Response {
+    if let Err(refusal) = check_token(&state, &headers) {
+        return refusal.into_response();
+    }
+    if !valid_name(&params.name) {
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Peer names are up to 64 letters, digits, '-', '_' or '.'",
+        )
+            .into_response();
+    }
+    // Registered before the upgrade, so two connections can't both take the name. Should the
+    // upgrade fail, the dropped receiver frees the name again
+    let (calls_tx, calls) = mpsc::channel(32);
+    let Some(session) = state.action_queue.relay().register(&params.name, calls_tx) else {
+        return (
+            StatusCode::CONFLICT,
+            format!("A relay peer named {} is already connected", params.name),
+        )
+            .into_response();
+    };
+    let queue = state.action_queue.clone();
+    ws.on_upgrade(move |socket| handle_peer(socket, queue, params.name, session, calls))
+}
+
+/// `GET /v1/relay`, the peers connected to this instance
+pub async fn list_peers(
+    extract::State(state): extract::State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(refusal) = check_token(&state, &headers) {
+        return refusal.into_response();
+    }
+    Json(state.action_queue.relay().peers()).into_response()
+}
+
+/// `POST /v1/relay/{*route}`, run an action request on the peer at the end of the route
+pub async fn relay_action(
+    extract::State(state): extract::State<Arc<AppState>>,
+    extract::Path(route): extract::Path<String>,
+    headers: HeaderMap,
+    request: Result<Json<ActionRequest>, JsonRejection>,
+) -> Response {
+    if let Err(refusal) = check_token(&state, &headers) {
+        return refusal.into_response();
+    }
+    let Json(request) = match request {
+        Ok(request) => request,
+        Err(rejection) => return crate::rejection_response(rejection),
+    };
+    let route: Vec<String> = route
+        .split('/')
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect();
+    let queue = &state.action_queue;
+    let (status, body) = queue
+        .relay()
+        .forward(&route, request, relay_wait(queue))
+        .await;
+    (status, Json(body)).into_response()
+}
+
+// Bastion side of a peer's socket, calls go out as they come and answers are matched by id
+async fn handle_peer(
+    mut socket: WebSocket,
+    queue: SharedQueue,
+    name: String,
+    session: Uuid,
+    mut calls: mpsc::Receiver<RelayCall>,
+) {
+    info!("Relay peer {} connected", name);
+    let mut pending: HashMap<u64, oneshot::Sender<(StatusCode, Value)>> = HashMap::new();
+    let mut next_call = 0;
+    let mut ping = interval(PEER_PING_INTERVAL);
+    let mut last_seen = Instant::now();
+
+    loop {
+        tokio::select! {
+            _ = ping.tick() => {
+                if last_seen.elapsed() > PEER_IDLE_TIMEOUT {
+                    warn!("Relay peer {} stopped answering, dropping it", name);
+                    break;
+                }
+                if socket.send(Message::Ping(Default::default())).await.is_err() {
+                    break;
+                }
+            }
+            Some(call) = calls.recv() => {
+                next_call += 1;
+                let message = RelayMessage::Action {
+                    call_id: next_call,
+                    route: call.route,
+                    request: Box::new(call.request),
+                };
+                let Ok(text) = serde_json::to_string(&message) else {
+                    continue;
+                };
+                if socket.send(Message::Text(Utf8Bytes::from(text))).await.is_err() {
+                    break;
+                }
+                // Callers that timed out won't be answered, so their entries go
+                pending.retain(|_, reply| !reply.is_closed());
+                pending.insert(next_call, call.reply);
+            }
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    last_seen = Instant::now();
+                    match serde_json::from_str(&text) {
+                        Ok(RelayMessage::Response { call_id, status, body }) => {
+                            if let Some(reply) = pending.remove(&call_id) {
+                                let status = StatusCode::from_u16(status)
+                                    .unwrap_or(StatusCode::BAD_GATEWAY);
+                                let _ = reply.send((status, body));
+                            }
+                        }
+                        _ => warn!("Ignoring unexpected message from relay peer {}", name),
+                    }
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => last_seen = Instant::now(),
+            },
+        }
+    }
+
+    // Dropping the pending replies tells their callers the peer disconnected
+    queue.relay().unregister(&name, session);
+    let _ = socket.send(Message::Close(None)).await;
+    info!("Relay peer {} disconnected", name);
+}
+
+/// Keeps a connection to the bastion in VALK_RELAY_UPSTREAM open, reconnecting with backoff
+pub async fn connect_upstream(queue: SharedQueue) {
+    let Some(upstream) = queue.config().relay_upstream.clone() else {
+        return;
+    };
+    let mut delay = RECONNECT_MIN;
+    loop {
+        match serve_upstream(&queue, &upstream).await {
+            Ok(()) => {
+                info!("Relay connection to {} closed", upstream);
+                delay = RECONNECT_MIN;
+            }
+            Err(e) => warn!("Relay connection to {} failed: {}", upstream, e),
+        }
+        sleep(delay).await;
+        delay = (delay * 2).min(RECONNECT_MAX);
+    }
+}
+
+// Peer side of the socket. Once it drops, for whatever reason, the bastion's clients can't
+// follow up on what they sent, so actions still in flight are abandoned and keys they left
+// down released
+async fn serve_upstream(queue: &SharedQueue, upstream: &str) -> Result<(), String> {
+    let config = queue.config();
+    let name = config.relay_name.clone().unwrap_or_else(hostname);
+    let url = format!(
+        "{}/v1/relay/connect?name={}",
+        upstream.trim_end_matches('/'),
+        name
+    );
+    let mut request = url.into_client_request().map_err(|e| e.to_string())?;
+    if let Some(token) = &config.relay_token {
+        let value = format!("Bearer {}", token)
+            .parse()
+            .map_err(|_| "VALK_RELAY_TOKEN is not a valid header value".to_string())?;
+        request.headers_mut().insert(header::AUTHORIZATION, value);
+    }
+    let (host, port) = upstream_address(request.uri())?;
+    let stream = proxy::connect(config, &host, port)
+        .await
+        .map_err(|e| e.to_string())?;
+    let (mut socket, _) = tokio_tungstenite::client_async_tls(request, stream)
+        .await
+        .map_err(|e| e.to_string())?;
+    info!("Connected to relay {} as {}", upstream, name);
+
+    let held = Arc::new(Mutex::new(HeldInput::default()));
+    let result = serve_relayed(queue, upstream, &mut socket, &held).await;
+    let _ = socket.close(None).await;
+    let held = std::mem::take(&mut *held.lock().unwrap());
+    held.release(queue, "relay").await;
+    result
+}
+
+// Every relayed action runs in its own task so slow ones don't hold up the rest. Returning
+// aborts the ones still running
+async fn serve_relayed(
+    queue: &SharedQueue,
+    upstream: &str,
+    socket: &mut WebSocketStream<MaybeTlsStream<TcpStream>>,
+    held: &Arc<Mutex<HeldInput>>,
+) -> Result<(), String> {
+    let mut calls = JoinSet::new();
+    let (replies_tx, mut replies) = mpsc::channel::<RelayMessage>(32);
+    // The bastion pings, so silence means the connection is gone
+    let idle = sleep(PEER_IDLE_TIMEOUT);
+    tokio::pin!(idle);
+    loop {
+        tokio::select! {
+            _ = &mut idle => return Err("Nothing heard from the relay, reconnecting".to_string()),
+            message = socket.next() => {
+                idle.as_mut().reset(Instant::now() + PEER_IDLE_TIMEOUT);
+                match message {
+                    Some(Ok(tungstenite::Message::Text(text))) => match serde_json::from_str(&text) {
+                        Ok(RelayMessage::Action { call_id, route, request }) => {
+                            let queue = queue.clone();
+                            let replies = replies_tx.clone();
+                            let held = held.clone();
+                            calls.spawn(async move {
+                                let (status, body) =
+                                    run_relayed(&queue, &route, *request, &held).await;
+                                let reply = RelayMessage::Response {
+                                    call_id,
+                                    status: status.as_u16(),
+                                    body,
+                                };
+                                let _ = replies.send(reply).await;
+                            });
+                        }
+                        _ => warn!("Ignoring unexpected message from relay {}", upstream),
+                    },
+                    Some(Ok(tungstenite::Message::Close(_))) | None => return Ok(()),
+                    Some(Ok(_)) => {}
+                    Some(Err(e)) => return Err(e.to_string()),
+                }
+            }
+            Some(_) = calls.join_next(), if !calls.is_empty() => {}
+            Some(reply) = replies.recv() => {
+                let text = serde_json::to_string(&reply).map_err(|e| e.to_string())?;
+                socket
+                    .send(tungstenite::Message::Text(text.into()))
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+    }
+}
+
+// Where to connect for an upstream URL. The token and every relayed action cross the link,
+// so plain ws:// is only allowed to a bastion on this machine
+fn upstream_address(uri: &tungstenite::http::Uri) -> Result<(String, u16), String> {
+    let host = uri.host().unwrap_or_default();
+    let loopback = host == "localhost"
+        || host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+            .is_ok_and(|ip| ip.is_loopback());
+    let default_port = match uri.scheme_str() {
+        Some("wss") => 443,
+        Some("ws") if loopback => 80,
+        Some("ws") => {
+            return Err(format!(
+                "Refusing to relay over plain ws:// to {}, use wss://",
+                host
+            ))
+        }
+        scheme => return Err(format!("Unsupported relay scheme {:?}", scheme)),
+    };
+    Ok((host.to_string(), uri.port_u16().unwrap_or(default_port)))
+}
+
+// Runs a relayed request here at the end of its route, or passes it on to the next peer
+async fn run_relayed(
+    queue: &SharedQueue,
+    route: &[String],
+    request: ActionRequest,
+    held: &Mutex<HeldInput>,
+) -> (StatusCode, Value) {
+    if !route.is_empty() {
+        return queue
+            .relay()
+            .forward(route, request, relay_wait(queue))
+            .await;
+    }
+    let response = queue.execute_action(request).await;
+    held.lock().unwrap().track(&response);
+    (
+        crate::response_status(&response),
+        serde_json::to_value(&response).unwrap_or_default(),
+    )
+}
+
+fn hostname() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|name| name.trim().to_string())
+        .ok()
+        .filter(|name| valid_name(name))
+        .unwrap_or_else(|| "valk".to_string())
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action_queue::tests::{mock_capture, MockEnigo};
+    use crate::action_queue::{ActionQueue, InputDriver};
+    use crate::config::Config;
+
+    #[test]
+    fn test_valid_name() {
+        assert!(valid_name("desk-42.lab_1"));
+        assert!(!valid_name(""));
+        assert!(!valid_name("a/b"));
+        assert!(!valid_name("desk 42"));
+        assert!(!valid_name(&"a".repeat(65)));
+    }
+
+    #[test]
+    fn test_upstream_address() {
+        let address = |url: &str| upstream_address(&url.parse().unwrap());
+        assert_eq!(
+            address("wss://bastion/v1"),
+            Ok(("bastion".to_string(), 443))
+        );
+        assert_eq!(
+            address("wss://bastion:8255/v1"),
+            Ok(("bastion".to_string(), 8255))
+        );
+        assert_eq!(
+            address("ws://localhost:8255/v1"),
+            Ok(("localhost".to_string(), 8255))
+        );
+        assert!(address("ws://127.0.0.1/v1").is_ok());
+        assert!(address("ws://[::1]/v1").is_ok());
+        assert!(address("ws://bastion:8255/v1").is_err());
+        assert!(address("http://bastion/v1").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_dropped_upstream_releases_held_keys() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let input_driver: Box<dyn InputDriver> = Box::new(MockEnigo::new());
+        let config = Config {
+            relay_upstream: Some(format!("ws://{}", address)),
+            relay_name: Some("desk-42".to_string()),
+            ..Config::default()
+        };
+        let queue: SharedQueue =
+            Arc::new(ActionQueue::new(input_driver, config).with_capture(mock_capture()));
+        queue.start_processing().await;
+        let (mut monitor, _) = queue.subscribe_monitor();
+        let peer = tokio::spawn({
+            let queue = queue.clone();
+            async move { serve_upstream(&queue, &format!("ws://{}", address)).await }
+        });
+
+        // A bastion that has the peer hold ctrl down, then goes away
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut bastion = tokio_tungstenite::accept_async(stream).await.unwrap();
+        let call = RelayMessage::Action {
+            call_id: 1,
+            route: Vec::new(),
+            request: Box::new(
+                serde_json::from_value(serde_json::json!(
+                    {"id": "hold", "action": {"type": "key_down", "input": {"key": "ctrl"}}}
+                ))
+                .unwrap(),
+            ),
+        };
+        let text = serde_json::to_string(&call).unwrap();
+        bastion
+            .send(tungstenite::Message::Text(text.into()))
+            .await
+            .unwrap();
+        let reply = bastion.next().await.unwrap().unwrap();
+        assert!(reply.to_text().unwrap().contains("success"));
+        drop(bastion);
+
+        assert!(timeout(Duration::from_secs(10), peer).await.is_ok());
+        let disconnected = loop {
+            let event = monitor.try_recv().expect("no disconnect event");
+            let event = serde_json::to_value(&event).unwrap();
+            if event["event_type"] == "controller_disconnected" {
+                break event;
+            }
+        };
+        assert_eq!(disconnected["data"]["connection"], "relay");
+        assert_eq!(
+            disconnected["data"]["released_keys"],
+            serde_json::json!(["ctrl"])
+        );
+        assert_eq!(
+            disconnected["data"]["released_buttons"],
+            serde_json::json!([])
+        );
+    }
+}
@@ -0,0 +1,1162 @@
+use axum::{
+    extract::{self, Path},
+    http::StatusCode,
+    Json,
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use chrono::{DateTime, Utc};
+use image::{ImageFormat, RgbaImage};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs;
+use std::num::NonZeroU32;
+use std::path::{Path as FsPath, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::action_types::{
+    ActionError, ActionOutput, ActionRequest, ActionResponse, ActionResponseStatus,
+    ScreenshotFormat, MAX_COORDINATE,
+};
+use crate::capture::{encode_png, virtual_screen, DisplayInfo, ScreenBounds, ScreenCapture};
+use crate::heatmap::{self, Interaction};
+use crate::observe::WindowTitle;
+use crate::AppState;
+
+/// Oldest history entries are dropped past this so long runs don't grow without bound
+const MAX_RUN_HISTORY: usize = 1000;
+/// Clicks and typing kept per run for its heatmap, the oldest are dropped past this
+const MAX_RUN_INTERACTIONS: usize = 10_000;
+/// Closed runs kept for `GET /v1/runs`, the ones closed longest ago are forgotten first
+const MAX_CLOSED_RUNS: usize = 100;
+/// Name of the baseline screenshot in a run's recordings
+const BASELINE_SCREENSHOT: &str = "baseline.png";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    Open,
+    Closed,
+}
+
+/// Counters for a set of actions, kept for the whole run and for each action type
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ActionCounts {
+    pub actions: u64,
+    pub errors: u64,
+    pub queued_ms: u64,
+    pub execution_ms: u64,
+}
+
+impl ActionCounts {
+    fn add(&mut self, response: &ActionResponse) {
+        self.actions += 1;
+        if let ActionResponseStatus::Error = response.status {
+            self.errors += 1;
+        }
+        if let Some(timing) = response.timing {
+            self.queued_ms += timing.queued_ms;
+            self.execution_ms += timing.execution_ms;
+        }
+    }
+
+    fn error_rate(&self) -> f64 {
+        if self.actions == 0 {
+            0.0
+        } else {
+            self.errors as f64 / self.actions as f64
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RunMetrics {
+    #[serde(flatten)]
+    pub totals: ActionCounts,
+    /// Size of the base64 encoded screenshots returned to the client
+    pub screenshot_bytes: u64,
+    pub by_action_type: BTreeMap<String, ActionCounts>,
+}
+
+/// Counters for a set of actions along with their success and error rates
+#[derive(Debug, Clone, Serialize)]
+pub struct ActionStats {
+    #[serde(flatten)]
+    pub counts: ActionCounts,
+    pub success_rate: f64,
+    pub error_rate: f64,
+}
+
+impl From<&ActionCounts> for ActionStats {
+    fn from(counts: &ActionCounts) -> Self {
+        let error_rate = counts.error_rate();
+        ActionStats {
+            counts: counts.clone(),
+            success_rate: if counts.actions == 0 {
+                0.0
+            } else {
+                1.0 - error_rate
+            },
+            error_rate,
+        }
+    }
+}
+
+/// Summary of where a run spent its time and which action types failed
+#[derive(Debug, Clone, Serialize)]
+pub struct RunStats {
+    pub run_id: String,
+    #[serde(flatten)]
+    pub totals: ActionStats,
+    pub screenshot_bytes: u64,
+    pub by_action_type: BTreeMap<String, ActionStats>,
+}
+
+/// Limits a run declares up front, actions past any of them are rejected
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RunLimits {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_actions: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_screenshots: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_wall_time_secs: Option<u64>,
+}
+
+/// A finished action as recorded in the run history. Screenshots are too large to keep in
+/// memory, they are saved to the recordings dir instead when the server has one
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunHistoryEntry {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub task_id: Option<String>,
+    #[serde(flatten)]
+    pub response: ActionResponse,
+    #[serde(skip)]
+    pub screenshot: Option<PathBuf>,
+}
+
+/// What the machine looked like when a run opened, so a failed run can be looked into later
+/// without the machine. The screenshot is saved with the run's recordings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunBaseline {
+    pub os_type: String,
+    pub os_version: String,
+    pub displays: Vec<DisplayInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub focused_window: Option<WindowTitle>,
+    pub windows: Vec<WindowTitle>,
+    pub captured_at: DateTime<Utc>,
+    #[serde(skip)]
+    pub screenshot: Option<PathBuf>,
+}
+
+impl RunBaseline {
+    pub fn capture(capture: &dyn ScreenCapture) -> Self {
+        let os_info = os_info::get();
+        Self {
+            os_type: os_info.os_type().to_string(),
+            os_version: os_info.version().to_string(),
+            displays: capture.displays().unwrap_or_default(),
+            focused_window: capture.focused_window().map(|window| WindowTitle {
+                app_name: window.app_name,
+                title: window.title,
+            }),
+            windows: capture.windows(),
+            captured_at: Utc::now(),
+            screenshot: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BaselineResponse {
+    #[serde(flatten)]
+    pub baseline: RunBaseline,
+    /// Base64 encoded PNG of the primary monitor, when the server keeps recordings
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub screenshot: Option<String>,
+}
+
+/// A group of actions performed by one agent working towards one goal
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Run {
+    pub run_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub agent_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub goal: Option<String>,
+    pub metadata: HashMap<String, serde_json::Value>,
+    pub limits: RunLimits,
+    /// Action types the run may perform, any type when unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_actions: Option<Vec<String>>,
+    /// Size the client's model sees screenshots at, coordinates are in its pixels when set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model_resolution: Option<ModelResolution>,
+    pub status: RunStatus,
+    pub opened_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub closed_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub baseline: Option<RunBaseline>,
+    pub metrics: RunMetrics,
+    pub history: VecDeque<RunHistoryEntry>,
+    /// Where the run clicked and typed, served by the heatmap endpoint
+    #[serde(skip)]
+    pub interactions: VecDeque<Interaction>,
+    /// What the run has spent of its limits, including admitted actions that haven't finished
+    #[serde(skip)]
+    budget: RunBudget,
+}
+
+// Actions count against a run's limits from the moment they are admitted, so neither a batch
+// nor concurrent requests get past a limit before their actions finish. Rejected and failed
+// actions give their reservation back
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct RunBudget {
+    actions: u64,
+    screenshots: u64,
+    // Admitted requests that haven't been recorded yet, by request id
+    #[serde(skip)]
+    reserved: HashMap<String, u32>,
+}
+
+impl RunBudget {
+    fn reserve(&mut self, request: &ActionRequest) {
+        self.actions += 1;
+        if request.action.action_type() == "screenshot" {
+            self.screenshots += 1;
+        }
+        *self.reserved.entry(request.id.clone()).or_default() += 1;
+    }
+
+    // Settle the reservation of a finished request, which is kept only if the action succeeded
+    fn settle(&mut self, request: &ActionRequest, response: &ActionResponse) {
+        if self.unreserve(request) && matches!(response.status, ActionResponseStatus::Error) {
+            self.give_back(request);
+        }
+    }
+
+    // Give back the reservation of a request that will never be recorded
+    fn release(&mut self, request: &ActionRequest) {
+        if self.unreserve(request) {
+            self.give_back(request);
+        }
+    }
+
+    // Take one reservation of the request off the books, false if it had none
+    fn unreserve(&mut self, request: &ActionRequest) -> bool {
+        let Some(count) = self.reserved.get_mut(&request.id) else {
+            return false;
+        };
+        *count -= 1;
+        if *count == 0 {
+            self.reserved.remove(&request.id);
+        }
+        true
+    }
+
+    fn give_back(&mut self, request: &ActionRequest) {
+        self.actions = self.actions.saturating_sub(1);
+        if request.action.action_type() == "screenshot" {
+            self.screenshots = self.screenshots.saturating_sub(1);
+        }
+    }
+}
+
+impl Run {
+    fn allows(&self, action_type: &str) -> bool {
+        self.allowed_actions
+            .as_ref()
+            .is_none_or(|allowed| allowed.iter().any(|name| name == action_type))
+    }
+
+    // The first limit the run has reached, if any
+    fn exceeded_limit(&self) -> Option<String> {
+        let limits = &self.limits;
+        if let Some(max) = limits.max_actions.filter(|max| self.budget.actions >= *max) {
+            return Some(format!("Run has reached its limit of {} actions", max));
+        }
+        if let Some(max) = limits
+            .max_screenshots
+            .filter(|max| self.budget.screenshots >= *max)
+        {
+            return Some(format!("Run has reached its limit of {} screenshots", max));
+        }
+        let elapsed = (Utc::now() - self.opened_at).num_seconds().max(0) as u64;
+        if let Some(max) = limits.max_wall_time_secs.filter(|max| elapsed >= *max) {
+            return Some(format!("Run has exceeded its wall time of {}s", max));
+        }
+        None
+    }
+
+    pub fn stats(&self) -> RunStats {
+        RunStats {
+            run_id: self.run_id.clone(),
+            totals: (&self.metrics.totals).into(),
+            screenshot_bytes: self.metrics.screenshot_bytes,
+            by_action_type: self
+                .metrics
+                .by_action_type
+                .iter()
+                .map(|(action_type, counts)| (action_type.clone(), counts.into()))
+                .collect(),
+        }
+    }
+
+    /// The run without its history, used when listing runs
+    pub fn summary(&self) -> Run {
+        Run {
+            history: VecDeque::new(),
+            interactions: VecDeque::new(),
+            ..self.clone()
+        }
+    }
+}
+
+/// An open run as the journal keeps it across a restart, with the parts the API leaves out
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JournaledRun {
+    #[serde(flatten)]
+    run: Run,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    baseline_screenshot: Option<PathBuf>,
+    /// Saved screenshot of each history entry, in the same order
+    screenshots: Vec<Option<PathBuf>>,
+    interactions: VecDeque<Interaction>,
+    budget: RunBudget,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct OpenRunBody {
+    pub agent_name: Option<String>,
+    pub goal: Option<String>,
+    #[serde(default)]
+    pub metadata: HashMap<String, serde_json::Value>,
+    #[serde(default)]
+    pub limits: RunLimits,
+    pub allowed_actions: Option<Vec<String>>,
+    pub model_resolution: Option<ModelResolution>,
+}
+
+/// Resolution a model views screenshots at, e.g. 1280x800 for a model trained on that size
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModelResolution {
+    pub width: NonZeroU32,
+    pub height: NonZeroU32,
+}
+
+impl ModelResolution {
+    /// Model pixels per screen pixel for a screen of this size. The aspect ratio is kept, so
+    /// a screen of a different shape fits within the resolution, and a screen smaller than it
+    /// isn't scaled up
+    pub fn scale_from(&self, width: u32, height: u32) -> f64 {
+        f64::min(
+            self.width.get() as f64 / width.max(1) as f64,
+            self.height.get() as f64 / height.max(1) as f64,
+        )
+        .min(1.0)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.width.get() > MAX_COORDINATE || self.height.get() > MAX_COORDINATE {
+            return Err(format!(
+                "Model resolution {}x{} is too large, must be at most {} pixels each way",
+                self.width, self.height, MAX_COORDINATE
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct RunRegistry {
+    runs: Mutex<HashMap<String, Run>>,
+    // Screenshots of each run are saved in a subdirectory named after it
+    recordings: Option<PathBuf>,
+}
+
+impl RunRegistry {
+    /// A registry that saves the screenshots returned to each run under `dir`
+    pub fn with_recordings(dir: PathBuf) -> Self {
+        Self {
+            runs: Mutex::default(),
+            recordings: Some(dir),
+        }
+    }
+
+    pub fn open(&self, body: OpenRunBody) -> Run {
+        let run = Run {
+            run_id: Uuid::new_v4().to_string(),
+            agent_name: body.agent_name,
+            goal: body.goal,
+            metadata: body.metadata,
+            limits: body.limits,
+            allowed_actions: body.allowed_actions,
+            model_resolution: body.model_resolution,
+            status: RunStatus::Open,
+            opened_at: Utc::now(),
+            closed_at: None,
+            baseline: None,
+            metrics: RunMetrics::default(),
+            history: VecDeque::new(),
+            interactions: VecDeque::new(),
+            budget: RunBudget::default(),
+        };
+        self.runs
+            .lock()
+            .unwrap()
+            .insert(run.run_id.clone(), run.clone());
+        run
+    }
+
+    /// Run summaries, oldest first
+    pub fn list(&self) -> Vec<Run> {
+        let mut runs: Vec<Run> = self
+            .runs
+            .lock()
+            .unwrap()
+            .values()
+            .map(Run::summary)
+            .collect();
+        runs.sort_by_key(|run| run.opened_at);
+        runs
+    }
+
+    pub fn get(&self, run_id: &str) -> Option<Run> {
+        self.runs.lock().unwrap().get(run_id).cloned()
+    }
+
+    /// Keep the state of the machine as the run started, with the screen at the time if the
+    /// registry has somewhere to save it
+    pub fn set_baseline(
+        &self,
+        run_id: &str,
+        mut baseline: RunBaseline,
+        screenshot: Option<RgbaImage>,
+    ) -> Option<Run> {
+        if let (Some(dir), Some(image)) = (&self.recordings, screenshot) {
+            let path = dir.join(run_id).join(BASELINE_SCREENSHOT);
+            match save_image(&path, &image) {
+                Ok(()) => baseline.screenshot = Some(path),
+                Err(e) => warn!("Failed to save screenshot {}: {}", path.display(), e),
+            }
+        }
+        let mut runs = self.runs.lock().unwrap();
+        let run = runs.get_mut(run_id)?;
+        run.baseline = Some(baseline);
+        Some(run.clone())
+    }
+
+    /// Close a run so it stops accepting actions, closing an already closed run is a no-op
+    pub fn close(&self, run_id: &str) -> Option<Run> {
+        let mut runs = self.runs.lock().unwrap();
+        let run = runs.get_mut(run_id)?;
+        if run.status == RunStatus::Open {
+            run.status = RunStatus::Closed;
+            run.closed_at = Some(Utc::now());
+        }
+        let summary = run.summary();
+        forget_closed(&mut runs);
+        Some(summary)
+    }
+
+    pub fn model_resolution(&self, run_id: &str) -> Option<ModelResolution> {
+        self.runs
+            .lock()
+            .unwrap()
+            .get(run_id)
+            .and_then(|run| run.model_resolution)
+    }
+
+    /// Checks that the run a request refers to exists, is still open, allows the action
+    /// and has budget left, reserving the action's share of the budget until it is recorded
+    pub fn check(&self, request: &ActionRequest) -> Result<(), ActionError> {
+        let Some(run_id) = &request.run_id else {
+            return Ok(());
+        };
+        match self.runs.lock().unwrap().get_mut(run_id) {
+            Some(run) if run.status == RunStatus::Open => {
+                let action_type = request.action.action_type();
+                if !run.allows(action_type) {
+                    return Err(ActionError::PermissionDenied(format!(
+                        "Run {} is not allowed to perform {} actions",
+                        run_id, action_type
+                    )));
+                }
+                if let Some(message) = run.exceeded_limit() {
+                    return Err(ActionError::BudgetExceeded(message));
+                }
+                // Reserved under the same lock as the check, so the next request sees it
+                run.budget.reserve(request);
+                Ok(())
+            }
+            Some(_) => Err(ActionError::InvalidInput(format!(
+                "Run {} is closed",
+                run_id
+            ))),
+            None => Err(ActionError::InvalidInput(format!(
+                "Run {} not found",
+                run_id
+            ))),
+        }
+    }
+
+    /// Give back the budget `check` reserved for a request that will never be recorded, e.g.
+    /// because its client went away while it waited
+    pub fn release(&self, request: &ActionRequest) {
+        let Some(run_id) = &request.run_id else {
+            return;
+        };
+        if let Some(run) = self.runs.lock().unwrap().get_mut(run_id) {
+            run.budget.release(request);
+        }
+    }
+
+    /// Add a finished action to the history and metrics of its run
+    pub fn record(&self, request: &ActionRequest, response: &ActionResponse) {
+        let Some(run_id) = &request.run_id else {
+            return;
+        };
+        let (image, format) = match &response.data {
+            // An unchanged screenshot has no image, it is kept in the history as it is
+            Some(ActionOutput::Screenshot {
+                image,
+                format,
+                unchanged: false,
+                ..
+            }) => (Some(image), *format),
+            _ => (None, ScreenshotFormat::Png),
+        };
+
+        let mut runs = self.runs.lock().unwrap();
+        let Some(run) = runs.get_mut(run_id) else {
+            return;
+        };
+        // Only ids the server made go into the path, the request's own id is the client's and
+        // may repeat or hold a path
+        let screenshot = self
+            .recordings
+            .as_ref()
+            .filter(|_| image.is_some())
+            .map(|dir| {
+                dir.join(&run.run_id)
+                    .join(format!("{}.{}", response.id, format.extension()))
+            });
+
+        run.budget.settle(request, response);
+        run.metrics.totals.add(response);
+        run.metrics
+            .by_action_type
+            .entry(request.action.action_type().to_string())
+            .or_default()
+            .add(response);
+        match &response.data {
+            Some(ActionOutput::Screenshot { image, .. }) => {
+                run.metrics.screenshot_bytes += image.len() as u64;
+            }
+            Some(ActionOutput::Burst { frames }) => {
+                run.metrics.screenshot_bytes += frames
+                    .iter()
+                    .map(|frame| frame.image.len() as u64)
+                    .sum::<u64>();
+            }
+            _ => {}
+        }
+
+        let expired = if run.history.len() >= MAX_RUN_HISTORY {
+            run.history.pop_front().and_then(|entry| entry.screenshot)
+        } else {
+            None
+        };
+        // Images are too large to keep in memory, a single screenshot is saved to disk instead
+        let mut kept = response.without_data();
+        if image.is_none() && !matches!(response.data, Some(ActionOutput::Burst { .. })) {
+            kept.data = response.data.clone().map(ActionOutput::without_debug_image);
+        }
+        run.history.push_back(RunHistoryEntry {
+            task_id: request.task_id.clone(),
+            response: kept,
+            screenshot: screenshot.clone(),
+        });
+        drop(runs);
+
+        if let Some(path) = expired {
+            let _ = fs::remove_file(path);
+        }
+        if let (Some(path), Some(image)) = (screenshot, image) {
+            if let Err(e) = save_screenshot(&path, image) {
+                warn!("Failed to save screenshot {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    /// The open runs with everything needed to carry on with them in the next server process.
+    /// Closed runs are left behind
+    pub fn journal(&self) -> Vec<JournaledRun> {
+        let runs = self.runs.lock().unwrap();
+        let mut journal: Vec<JournaledRun> = runs
+            .values()
+            .filter(|run| run.status == RunStatus::Open)
+            .map(|run| JournaledRun {
+                run: run.clone(),
+                baseline_screenshot: run
+                    .baseline
+                    .as_ref()
+                    .and_then(|baseline| baseline.screenshot.clone()),
+                screenshots: run
+                    .history
+                    .iter()
+                    .map(|entry| entry.screenshot.clone())
+                    .collect(),
+                interactions: run.interactions.clone(),
+                budget: run.budget.clone(),
+            })
+            .collect();
+        journal.sort_by_key(|journaled| journaled.run.opened_at);
+        journal
+    }
+
+    /// Carry on with runs journaled by the previous server process
+    pub fn resume(&self, journal: Vec<JournaledRun>) {
+        let mut runs = self.runs.lock().unwrap();
+        for journaled in journal {
+            let mut run = journaled.run;
+            if let Some(baseline) = &mut run.baseline {
+                baseline.screenshot = journaled.baseline_screenshot;
+            }
+            for (entry, screenshot) in run.history.iter_mut().zip(journaled.screenshots) {
+                entry.screenshot = screenshot;
+            }
+            run.interactions = journaled.interactions;
+            run.budget = journaled.budget;
+            runs.insert(run.run_id.clone(), run);
+        }
+        forget_closed(&mut runs);
+    }
+
+    /// What a run's heatmap is drawn from, its baseline and interactions, without copying
+    /// the rest of the run
+    pub fn heatmap_source(&self, run_id: &str) -> Option<(Option<RunBaseline>, Vec<Interaction>)> {
+        let runs = self.runs.lock().unwrap();
+        let run = runs.get(run_id)?;
+        Some((
+            run.baseline.clone(),
+            run.interactions.iter().cloned().collect(),
+        ))
+    }
+
+    /// Add a click or typing position to the heatmap of its run
+    pub fn record_interaction(&self, run_id: &str, interaction: Interaction) {
+        let mut runs = self.runs.lock().unwrap();
+        let Some(run) = runs.get_mut(run_id) else {
+            return;
+        };
+        if run.interactions.len() >= MAX_RUN_INTERACTIONS {
+            run.interactions.pop_front();
+        }
+        run.interactions.push_back(interaction);
+    }
+}
+
+// Forget the runs closed longest ago past MAX_CLOSED_RUNS. Their recordings stay on disk
+fn forget_closed(runs: &mut HashMap<String, Run>) {
+    let mut closed: Vec<(DateTime<Utc>, String)> = runs
+        .values()
+        .filter_map(|run| Some((run.closed_at?, run.run_id.clone())))
+        .collect();
+    if closed.len() <= MAX_CLOSED_RUNS {
+        return;
+    }
+    closed.sort();
+    for (_, run_id) in &closed[..closed.len() - MAX_CLOSED_RUNS] {
+        runs.remove(run_id);
+    }
+}
+
+fn save_image(path: &FsPath, image: &RgbaImage) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    image
+        .save_with_format(path, ImageFormat::Png)
+        .map_err(|e| e.to_string())
+}
+
+fn save_screenshot(path: &FsPath, image: &str) -> Result<(), String> {
+    let bytes = BASE64.decode(image).map_err(|e| e.to_string())?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    fs::write(path, bytes).map_err(|e| e.to_string())
+}
+
+pub async fn open_run(
+    extract::State(state): extract::State<Arc<AppState>>,
+    Json(body): Json<OpenRunBody>,
+) -> Result<(StatusCode, Json<Run>), (StatusCode, String)> {
+    if let Some(model_resolution) = &body.model_resolution {
+        model_resolution
+            .validate()
+            .map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e))?;
+    }
+    let runs = state.action_queue.runs();
+    let run = runs.open(body);
+    let capture = state.action_queue.capture();
+    let baseline = RunBaseline::capture(capture);
+    let run = runs
+        .set_baseline(&run.run_id, baseline, capture.capture().ok())
+        .unwrap_or(run);
+    Ok((StatusCode::CREATED, Json(run)))
+}
+
+/// `GET /v1/runs/{id}/baseline`, the machine as the run started, with its screenshot
+pub async fn run_baseline(
+    extract::State(state): extract::State<Arc<AppState>>,
+    Path(run_id): Path<String>,
+) -> Result<Json<BaselineResponse>, StatusCode> {
+    let baseline = state
+        .action_queue
+        .runs()
+        .get(&run_id)
+        .and_then(|run| run.baseline)
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let screenshot = baseline
+        .screenshot
+        .as_ref()
+        .and_then(|path| fs::read(path).ok())
+        .map(|bytes| BASE64.encode(bytes));
+    Ok(Json(BaselineResponse {
+        baseline,
+        screenshot,
+    }))
+}
+
+pub async fn list_runs(extract::State(state): extract::State<Arc<AppState>>) -> Json<Vec<Run>> {
+    Json(state.action_queue.runs().list())
+}
+
+pub async fn get_run(
+    extract::State(state): extract::State<Arc<AppState>>,
+    Path(run_id): Path<String>,
+) -> Result<Json<Run>, StatusCode> {
+    state
+        .action_queue
+        .runs()
+        .get(&run_id)
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+pub async fn run_stats(
+    extract::State(state): extract::State<Arc<AppState>>,
+    Path(run_id): Path<String>,
+) -> Result<Json<RunStats>, StatusCode> {
+    state
+        .action_queue
+        .runs()
+        .get(&run_id)
+        .map(|run| Json(run.stats()))
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+#[derive(Debug, Serialize)]
+pub struct HeatmapResponse {
+    pub run_id: String,
+    /// The part of the desktop the image covers, in input coordinates
+    pub bounds: ScreenBounds,
+    pub interactions: Vec<Interaction>,
+    /// Base64 encoded PNG, drawn over the baseline screenshot when the run has one
+    pub image: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// `GET /v1/runs/{id}/stats/heatmap`, where the run clicked and typed, as points and an image
+pub async fn run_heatmap(
+    extract::State(state): extract::State<Arc<AppState>>,
+    Path(run_id): Path<String>,
+) -> Result<Json<HeatmapResponse>, (StatusCode, String)> {
+    let (baseline, interactions) = state
+        .action_queue
+        .runs()
+        .heatmap_source(&run_id)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("Run {} not found", run_id)))?;
+    // The desktop as the run started, or as it is now for runs without a baseline
+    let displays = match &baseline {
+        Some(baseline) if !baseline.displays.is_empty() => baseline.displays.clone(),
+        _ => state.action_queue.capture().displays().unwrap_or_default(),
+    };
+    let bounds = virtual_screen(&displays);
+    let screenshot = baseline.and_then(|baseline| baseline.screenshot);
+    let primary = displays
+        .iter()
+        .find(|display| display.is_primary)
+        .map(|display| ScreenBounds {
+            x: display.x,
+            y: display.y,
+            width: display.width,
+            height: display.height,
+        });
+    // Decoding the screenshot, drawing and encoding take a while for a large desktop
+    tokio::task::spawn_blocking(move || {
+        let screenshot = screenshot
+            .and_then(|path| image::open(path).ok())
+            .map(|image| image.to_rgba8());
+        let image = heatmap::render(bounds, &interactions, screenshot.as_ref().zip(primary));
+        let encoded = encode_png(&image).map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to encode heatmap: {:?}", e),
+            )
+        })?;
+        Ok(Json(HeatmapResponse {
+            run_id,
+            bounds,
+            interactions,
+            image: encoded,
+            width: image.width(),
+            height: image.height(),
+        }))
+    })
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+}
+
+pub async fn close_run(
+    extract::State(state): extract::State<Arc<AppState>>,
+    Path(run_id): Path<String>,
+) -> Result<Json<Run>, StatusCode> {
+    state
+        .action_queue
+        .runs()
+        .close(&run_id)
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action_types::{Action, ActionTiming};
+
+    fn request(run_id: &str, task_id: Option<&str>) -> ActionRequest {
+        ActionRequest {
+            run_id: Some(run_id.to_string()),
+            task_id: task_id.map(str::to_string),
+            ..ActionRequest::new(
+                "action".to_string(),
+                Action::Screenshot {
+                    input: Default::default(),
+                },
+            )
+        }
+    }
+
+    #[test]
+    fn test_record_groups_history_and_metrics() {
+        let registry = RunRegistry::default();
+        let run = registry.open(OpenRunBody {
+            agent_name: Some("agent".to_string()),
+            ..OpenRunBody::default()
+        });
+
+        let ok = request(&run.run_id, Some("login"));
+        let response = ActionResponse::success(
+            ok.id.clone(),
+            ok.action.clone(),
+            ActionOutput::Screenshot {
+                image: "large".to_string(),
+                format: Default::default(),
+                width: 1920,
+                height: 1080,
+                monitor_id: 0,
+                x: 0,
+                y: 0,
+                scale_factor: 1.0,
+                image_scale: 1.0,
+                captured_at: Utc::now(),
+                hash: String::new(),
+                unchanged: false,
+            },
+        );
+        registry.record(&ok, &response);
+        let failed = request(&run.run_id, None);
+        let response = ActionResponse::error(
+            failed.id.clone(),
+            failed.action.clone(),
+            ActionError::Timeout,
+        );
+        registry.record(&failed, &response);
+
+        let run = registry.get(&run.run_id).unwrap();
+        assert_eq!(run.metrics.totals.actions, 2);
+        assert_eq!(run.metrics.totals.errors, 1);
+        assert_eq!(run.metrics.by_action_type["screenshot"].actions, 2);
+        assert_eq!(run.metrics.screenshot_bytes, 5);
+        assert_eq!(run.history.len(), 2);
+        assert_eq!(run.history[0].task_id.as_deref(), Some("login"));
+        assert!(run.history[0].response.data.is_none());
+        assert!(registry.list()[0].history.is_empty());
+    }
+
+    #[test]
+    fn test_recorded_screenshots_are_named_by_the_server() {
+        let dir = std::env::temp_dir().join(format!("valk-runs-{}", std::process::id()));
+        let registry = RunRegistry::with_recordings(dir.clone());
+        let run = registry.open(OpenRunBody::default());
+        let screenshot = || ActionOutput::Screenshot {
+            image: BASE64.encode("image"),
+            format: Default::default(),
+            width: 1920,
+            height: 1080,
+            monitor_id: 0,
+            x: 0,
+            y: 0,
+            scale_factor: 1.0,
+            image_scale: 1.0,
+            captured_at: Utc::now(),
+            hash: String::new(),
+            unchanged: false,
+        };
+
+        let mut escaping = request(&run.run_id, None);
+        escaping.id = "../../escaped".to_string();
+        for _ in 0..2 {
+            let response =
+                ActionResponse::success(escaping.id.clone(), escaping.action.clone(), screenshot());
+            registry.record(&escaping, &response);
+        }
+
+        let run = registry.get(&run.run_id).unwrap();
+        let paths: Vec<_> = run
+            .history
+            .iter()
+            .map(|entry| entry.screenshot.clone().unwrap())
+            .collect();
+        assert_ne!(paths[0], paths[1]);
+        for path in &paths {
+            assert_eq!(path.parent(), Some(dir.join(&run.run_id).as_path()));
+            assert!(path.exists());
+        }
+        assert_eq!(run.history[0].response.request_id, "../../escaped");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_journal_carries_open_runs_over() {
+        let dir = std::env::temp_dir().join(format!("valk-runs-journal-{}", std::process::id()));
+        let registry = RunRegistry::with_recordings(dir.clone());
+        let run = registry.open(OpenRunBody {
+            limits: RunLimits {
+                max_actions: Some(2),
+                ..RunLimits::default()
+            },
+            ..OpenRunBody::default()
+        });
+        let closed = registry.open(OpenRunBody::default());
+        registry.close(&closed.run_id);
+
+        let screenshot = request(&run.run_id, None);
+        assert!(registry.check(&screenshot).is_ok());
+        let response = ActionResponse::success(
+            screenshot.id.clone(),
+            screenshot.action.clone(),
+            ActionOutput::Screenshot {
+                image: BASE64.encode("image"),
+                format: Default::default(),
+                width: 1920,
+                height: 1080,
+                monitor_id: 0,
+                x: 0,
+                y: 0,
+                scale_factor: 1.0,
+                image_scale: 1.0,
+                captured_at: Utc::now(),
+                hash: String::new(),
+                unchanged: false,
+            },
+        );
+        registry.record(&screenshot, &response);
+        let click = ActionRequest {
+            run_id: Some(run.run_id.clone()),
+            ..ActionRequest::new("click".to_string(), Action::LeftClick)
+        };
+        let response = ActionResponse::error(
+            click.id.clone(),
+            click.action.clone(),
+            ActionError::StaleTarget {
+                message: "Moved".to_string(),
+                crop: "crop".to_string(),
+            },
+        );
+        registry.record(&click, &response);
+
+        let json = serde_json::to_string(&registry.journal()).unwrap();
+        let resumed = RunRegistry::with_recordings(dir.clone());
+        resumed.resume(serde_json::from_str(&json).unwrap());
+
+        assert!(resumed.get(&closed.run_id).is_none());
+        let run = resumed.get(&run.run_id).unwrap();
+        assert_eq!(run.status, RunStatus::Open);
+        assert_eq!(run.metrics.totals.actions, 2);
+        assert!(run.history[0].screenshot.as_ref().unwrap().exists());
+        assert!(matches!(
+            &run.history[1].response.error,
+            Some(ActionError::StaleTarget { crop, .. }) if crop == "crop"
+        ));
+        // The failed click gave its reservation back, the screenshot still counts
+        assert!(resumed.check(&screenshot).is_ok());
+        assert!(matches!(
+            resumed.check(&screenshot),
+            Err(ActionError::BudgetExceeded(_))
+        ));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_stats_rates_and_timing() {
+        let registry = RunRegistry::default();
+        let run = registry.open(OpenRunBody::default());
+        let request = request(&run.run_id, None);
+        for (queued_ms, failed) in [(10, false), (30, false), (20, true), (40, false)] {
+            let mut response = if failed {
+                ActionResponse::error(
+                    request.id.clone(),
+                    request.action.clone(),
+                    ActionError::Timeout,
+                )
+            } else {
+                ActionResponse::success(
+                    request.id.clone(),
+                    request.action.clone(),
+                    ActionOutput::NoData,
+                )
+            };
+            response.timing = Some(ActionTiming {
+                queued_ms,
+                execution_ms: 5,
+            });
+            registry.record(&request, &response);
+        }
+
+        let stats = registry.get(&run.run_id).unwrap().stats();
+        assert_eq!(stats.totals.counts.queued_ms, 100);
+        assert_eq!(stats.totals.counts.execution_ms, 20);
+        assert_eq!(stats.totals.error_rate, 0.25);
+        assert_eq!(stats.by_action_type["screenshot"].success_rate, 0.75);
+    }
+
+    #[test]
+    fn test_check_enforces_limits() {
+        let registry = RunRegistry::default();
+        let run = registry.open(OpenRunBody {
+            limits: RunLimits {
+                max_screenshots: Some(1),
+                ..RunLimits::default()
+            },
+            ..OpenRunBody::default()
+        });
+        let screenshot = request(&run.run_id, None);
+        assert!(registry.check(&screenshot).is_ok());
+        let response = ActionResponse::success(
+            screenshot.id.clone(),
+            screenshot.action.clone(),
+            ActionOutput::NoData,
+        );
+        registry.record(&screenshot, &response);
+        assert!(matches!(
+            registry.check(&screenshot),
+            Err(ActionError::BudgetExceeded(_))
+        ));
+
+        let expired = registry.open(OpenRunBody {
+            limits: RunLimits {
+                max_wall_time_secs: Some(0),
+                ..RunLimits::default()
+            },
+            ..OpenRunBody::default()
+        });
+        assert!(matches!(
+            registry.check(&request(&expired.run_id, None)),
+            Err(ActionError::BudgetExceeded(_))
+        ));
+    }
+
+    #[test]
+    fn test_check_enforces_allowed_actions() {
+        let registry = RunRegistry::default();
+        let run = registry.open(OpenRunBody {
+            allowed_actions: Some(vec![
+                "screenshot".to_string(),
+                "cursor_position".to_string(),
+            ]),
+            ..OpenRunBody::default()
+        });
+
+        assert!(registry.check(&request(&run.run_id, None)).is_ok());
+        let click = ActionRequest {
+            run_id: Some(run.run_id.clone()),
+            ..ActionRequest::new("click".to_string(), Action::LeftClick)
+        };
+        assert!(matches!(
+            registry.check(&click),
+            Err(ActionError::PermissionDenied(_))
+        ));
+    }
+
+    #[test]
+    fn test_check_rejects_unknown_and_closed_runs() {
+        let registry = RunRegistry::default();
+        let run = registry.open(OpenRunBody::default());
+
+        assert!(registry.check(&request(&run.run_id, None)).is_ok());
+        assert!(registry
+            .check(&ActionRequest::new("no_run".to_string(), Action::LeftClick))
+            .is_ok());
+        assert!(matches!(
+            registry.check(&request("missing", None)),
+            Err(ActionError::InvalidInput(_))
+        ));
+
+        let closed = registry.close(&run.run_id).unwrap();
+        assert_eq!(closed.status, RunStatus::Closed);
+        assert!(matches!(
+            registry.check(&request(&run.run_id, None)),
+            Err(ActionError::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn test_closed_runs_are_forgotten_oldest_first() {
+        let registry = RunRegistry::default();
+        let open = registry.open(OpenRunBody::default());
+        let first = registry.open(OpenRunBody::default());
+        registry.close(&first.run_id);
+        for _ in 0..MAX_CLOSED_RUNS {
+            let run = registry.open(OpenRunBody::default());
+            registry.close(&run.run_id);
+        }
+
+        assert!(registry.get(&first.run_id).is_none());
+        assert!(registry.get(&open.run_id).is_some());
+        assert_eq!(registry.list().len(), MAX_CLOSED_RUNS + 1);
+    }
+
+    #[test]
+    fn test_model_resolution_fits_without_scaling_up() {
+        let resolution = |width, height| ModelResolution {
+            width: NonZeroU32::new(width).unwrap(),
+            height: NonZeroU32::new(height).unwrap(),
+        };
+        assert_eq!(resolution(960, 600).scale_from(1920, 1080), 0.5);
+        assert_eq!(resolution(3840, 2160).scale_from(1920, 1080), 1.0);
+
+        assert!(resolution(MAX_COORDINATE, MAX_COORDINATE)
+            .validate()
+            .is_ok());
+        assert!(resolution(4_000_000_000, 800).validate().is_err());
+    }
+}
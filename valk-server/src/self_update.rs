@@ -0,0 +1,272 @@
+//! `valk-server self-update`, replaces the running binary with the latest signed release.
+//!
+//! The release manifest is a JSON document like
+//!
+//! ```json
+//! {
+//!   "version": "0.2.0",
+//!   "targets": {
+//!     "x86_64-linux": {
+//!       "url": "https://…/valk-server",
+//!       "sha256": "<hex>",
+//!       "signature": "<base64>"
+//!     }
+//!   }
+//! }
+//! ```
+//!
+//! where `signature` is the ed25519 signature of `valk-server <version> <target> <sha256>`,
+//! the version and target as in the manifest and the hex SHA-256 of the binary. Signing the
+//! version along with the binary keeps an older signed release from being passed off as a
+//! newer one. Only releases signed by the key baked in at build time
+//! (VALK_UPDATE_PUBLIC_KEY, base64) are installed.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use ed25519_dalek::{Signature, VerifyingKey};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read};
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use crate::config::Config;
+use crate::proxy;
+use crate::version::{target, VERSION};
+
+// Anything larger than this is not a valk-server binary
+const MAX_BINARY_SIZE: u64 = 256 * 1024 * 1024;
+
+const USAGE: &str = "usage: valk-server self-update [--manifest-url URL] [--check] [--force]";
+
+#[derive(Debug, Deserialize)]
+struct Manifest {
+    version: String,
+    targets: HashMap<String, Release>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Release {
+    url: String,
+    sha256: String,
+    signature: String,
+}
+
+/// Run the subcommand with the arguments that follow `self-update`
+pub fn run(args: &[String]) -> Result<(), String> {
+    let mut manifest_url = std::env::var("VALK_UPDATE_MANIFEST_URL").ok();
+    let mut check_only = false;
+    let mut force = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--manifest-url" => manifest_url = args.next().cloned(),
+            "--check" => check_only = true,
+            "--force" => force = true,
+            _ => return Err(format!("Unknown option: {}\n{}", arg, USAGE)),
+        }
+    }
+    let manifest_url = manifest_url.ok_or_else(|| {
+        format!(
+            "No manifest URL, pass --manifest-url or set VALK_UPDATE_MANIFEST_URL\n{}",
+            USAGE
+        )
+    })?;
+    let key = public_key()?;
+
+    let config = Config::new();
+    let manifest = proxy::agent(&config, &manifest_url)?
+        .build()
+        .get(&manifest_url)
+        .call()
+        .map_err(|e| e.to_string())
+        .and_then(|response| response.into_string().map_err(|e| e.to_string()))
+        .map_err(|e| format!("Failed to fetch {}: {}", manifest_url, e))?;
+    let manifest: Manifest =
+        serde_json::from_str(&manifest).map_err(|e| format!("Invalid manifest: {}", e))?;
+    let release = manifest
+        .targets
+        .get(&target())
+        .ok_or_else(|| format!("Release {} has no build for {}", manifest.version, target()))?;
+    // The version is only trusted once the signature says it belongs to this release
+    verify(&key, &manifest.version, &target(), release)?;
+    if !force && !is_newer(&manifest.version, VERSION) {
+        println!("valk-server {} is up to date", VERSION);
+        return Ok(());
+    }
+    if check_only {
+        println!(
+            "valk-server {} is available (running {})",
+            manifest.version, VERSION
+        );
+        return Ok(());
+    }
+
+    let mut binary = Vec::new();
+    proxy::agent(&config, &release.url)?
+        .build()
+        .get(&release.url)
+        .call()
+        .map_err(|e| format!("Failed to download {}: {}", release.url, e))?
+        .into_reader()
+        .take(MAX_BINARY_SIZE)
+        .read_to_end(&mut binary)
+        .map_err(|e| format!("Failed to download {}: {}", release.url, e))?;
+    if sha256_hex(&binary) != release.sha256.to_ascii_lowercase() {
+        return Err(format!(
+            "{} does not match the signed checksum, refusing to install it",
+            release.url
+        ));
+    }
+    install(&binary)?;
+    println!(
+        "Updated valk-server {} -> {}, restart the server to use it",
+        VERSION, manifest.version
+    );
+    Ok(())
+}
+
+fn public_key() -> Result<VerifyingKey, String> {
+    let encoded = option_env!("VALK_UPDATE_PUBLIC_KEY")
+        .ok_or("This build has no update signing key, set VALK_UPDATE_PUBLIC_KEY when building")?;
+    let bytes: [u8; 32] = BASE64
+        .decode(encoded)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or("Invalid update signing key")?;
+    VerifyingKey::from_bytes(&bytes).map_err(|e| format!("Invalid update signing key: {}", e))
+}
+
+// What a release's signature covers
+fn signed_payload(version: &str, target: &str, sha256: &str) -> String {
+    format!(
+        "valk-server {} {} {}",
+        version,
+        target,
+        sha256.to_ascii_lowercase()
+    )
+}
+
+fn verify(
+    key: &VerifyingKey,
+    version: &str,
+    target: &str,
+    release: &Release,
+) -> Result<(), String> {
+    let signature = BASE64
+        .decode(&release.signature)
+        .ok()
+        .and_then(|bytes| Signature::from_slice(&bytes).ok())
+        .ok_or("Invalid release signature")?;
+    let payload = signed_payload(version, target, &release.sha256);
+    key.verify_strict(payload.as_bytes(), &signature)
+        .map_err(|_| "Release signature does not match, refusing to install it".to_string())
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+// Write next to the current binary, then rename over it, so a failed update never
+// leaves a half written binary behind
+fn install(binary: &[u8]) -> Result<(), String> {
+    let current =
+        std::env::current_exe().map_err(|e| format!("Failed to find the running binary: {}", e))?;
+    let staged = current.with_extension("update");
+    fs::write(&staged, binary)
+        .and_then(|_| make_executable(&staged))
+        .and_then(|_| replace(&staged, &current))
+        .map_err(|e| {
+            let _ = fs::remove_file(&staged);
+            format!("Failed to install {}: {}", current.display(), e)
+        })
+}
+
+#[cfg(unix)]
+fn make_executable(path: &Path) -> io::Result<()> {
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))
+}
+
+// Without Unix permissions any file can be run
+#[cfg(not(unix))]
+fn make_executable(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+// The running binary keeps its inode, so the new one can be renamed straight over it
+#[cfg(unix)]
+fn replace(staged: &Path, current: &Path) -> io::Result<()> {
+    fs::rename(staged, current)
+}
+
+// Elsewhere a running binary can't be replaced, only moved out of the way first. It is
+// left behind as .old and cleared by the next update
+#[cfg(not(unix))]
+fn replace(staged: &Path, current: &Path) -> io::Result<()> {
+    let old = current.with_extension("old");
+    let _ = fs::remove_file(&old);
+    fs::rename(current, &old)?;
+    fs::rename(staged, current).inspect_err(|_| {
+        let _ = fs::rename(&old, current);
+    })
+}
+
+// Compare dotted numeric versions, ignoring pre-release suffixes
+fn is_newer(candidate: &str, current: &str) -> bool {
+    let parse = |version: &str| -> Vec<u64> {
+        version
+            .trim_start_matches('v')
+            .split(['-', '+'])
+            .next()
+            .unwrap_or("")
+            .split('.')
+            .map(|part| part.parse().unwrap_or(0))
+            .collect()
+    };
+    parse(candidate) > parse(current)
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    #[test]
+    fn test_is_newer() {
+        assert!(is_newer("0.2.0", "0.1.0"));
+        assert!(is_newer("v0.10.0", "0.9.3"));
+        assert!(!is_newer("0.1.0", "0.1.0"));
+        assert!(!is_newer("0.1.0-rc1", "0.1.0"));
+        assert!(!is_newer("0.0.9", "0.1.0"));
+    }
+
+    #[test]
+    fn test_verify_signature() {
+        let signing_key = SigningKey::from_bytes(&[7; 32]);
+        let key = signing_key.verifying_key();
+        let sha256 = sha256_hex(b"valk-server release");
+        let payload = signed_payload("0.2.0", "x86_64-linux", &sha256);
+        let release = |sha256: &str, signature: &str| Release {
+            url: "https://example.com/valk-server".to_string(),
+            sha256: sha256.to_string(),
+            signature: signature.to_string(),
+        };
+        let signature = BASE64.encode(signing_key.sign(payload.as_bytes()).to_bytes());
+        let signed = release(&sha256, &signature);
+
+        assert!(verify(&key, "0.2.0", "x86_64-linux", &signed).is_ok());
+        // An older release served under a newer version
+        assert!(verify(&key, "0.3.0", "x86_64-linux", &signed).is_err());
+        assert!(verify(&key, "0.2.0", "aarch64-linux", &signed).is_err());
+        let tampered = release(&sha256_hex(b"tampered release"), &signature);
+        assert!(verify(&key, "0.2.0", "x86_64-linux", &tampered).is_err());
+        let unsigned = release(&sha256, "not a signature");
+        assert!(verify(&key, "0.2.0", "x86_64-linux", &unsigned).is_err());
+    }
+}
@@ -0,0 +1,309 @@
+use enigo::{Axis, Button, Coordinate, Direction, InputResult, Key, Keyboard, Mouse};
+use image::{imageops, RgbaImage};
+
+use crate::action_types::ActionError;
+use crate::capture::{DisplayInfo, ScreenCapture};
+
+/// One monitor of a virtual desktop, in desktop coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VirtualMonitor {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// A desktop of one or more monitors laid out left to right, with an image of what is
+/// on it. Used to run the server without a display server, e.g. to develop agents
+/// against a known screen.
+pub struct VirtualScreen {
+    monitors: Vec<VirtualMonitor>,
+    image: RgbaImage,
+}
+
+impl VirtualScreen {
+    /// Monitors of the given sizes, top aligned, the first one is primary
+    pub fn new(sizes: &[(u32, u32)]) -> Self {
+        let mut x = 0;
+        let monitors: Vec<VirtualMonitor> = sizes
+            .iter()
+            .map(|&(width, height)| {
+                let monitor = VirtualMonitor {
+                    x,
+                    y: 0,
+                    width,
+                    height,
+                };
+                x += width as i32;
+                monitor
+            })
+            .collect();
+        let (width, height) = desktop_size(&monitors);
+        Self {
+            monitors,
+            image: RgbaImage::new(width, height),
+        }
+    }
+
+    /// Parse a layout like `1920x1080,1280x1024`
+    pub fn parse(layout: &str) -> Result<Self, String> {
+        let sizes = layout
+            .split(',')
+            .map(|size| {
+                let (width, height) = size
+                    .trim()
+                    .split_once('x')
+                    .ok_or_else(|| format!("Invalid monitor size: {}", size))?;
+                match (width.parse(), height.parse()) {
+                    (Ok(width), Ok(height)) if width > 0 && height > 0 => Ok((width, height)),
+                    _ => Err(format!("Invalid monitor size: {}", size)),
+                }
+            })
+            .collect::<Result<Vec<(u32, u32)>, String>>()?;
+        Ok(Self::new(&sizes))
+    }
+
+    /// Show the given image across the whole desktop, scaling it to fit
+    pub fn with_image(mut self, image: RgbaImage) -> Self {
+        let (width, height) = self.image.dimensions();
+        self.image = if image.dimensions() == (width, height) {
+            image
+        } else {
+            imageops::resize(&image, width, height, imageops::FilterType::Triangle)
+        };
+        self
+    }
+
+    /// Size of the box enclosing every monitor
+    pub fn desktop_size(&self) -> (u32, u32) {
+        desktop_size(&self.monitors)
+    }
+}
+
+fn desktop_size(monitors: &[VirtualMonitor]) -> (u32, u32) {
+    let width = monitors
+        .iter()
+        .map(|m| m.x + m.width as i32)
+        .max()
+        .unwrap_or(0);
+    let height = monitors
+        .iter()
+        .map(|m| m.y + m.height as i32)
+        .max()
+        .unwrap_or(0);
+    (width.max(1) as u32, height.max(1) as u32)
+}
+
+impl ScreenCapture for VirtualScreen {
+    // Like a real capture, only the primary monitor is returned
+    fn capture(&self) -> Result<RgbaImage, ActionError> {
+        if self.monitors.is_empty() {
+            return Err(ActionError::ExecutionFailed(
+                "Virtual screen has no monitors".to_string(),
+            ));
+        }
+        self.capture_display(0)
+    }
+
+    // Monitor ids are their index in the layout
+    fn capture_display(&self, id: u32) -> Result<RgbaImage, ActionError> {
+        let monitor = self
+            .monitors
+            .get(id as usize)
+            .ok_or_else(|| ActionError::ExecutionFailed(format!("No monitor with id {}", id)))?;
+        Ok(imageops::crop_imm(
+            &self.image,
+            monitor.x as u32,
+            monitor.y as u32,
+            monitor.width,
+            monitor.height,
+        )
+        .to_image())
+    }
+
+    fn screen_size(&self) -> Result<(u32, u32), ActionError> {
+        self.monitors
+            .first()
+            .map(|primary| (primary.width, primary.height))
+            .ok_or_else(|| {
+                ActionError::ExecutionFailed("Virtual screen has no monitors".to_string())
+            })
+    }
+
+    fn displays(&self) -> Result<Vec<DisplayInfo>, ActionError> {
+        Ok(self
+            .monitors
+            .iter()
+            .enumerate()
+            .map(|(index, monitor)| DisplayInfo {
+                id: index as u32,
+                name: format!("virtual-{}", index),
+                x: monitor.x,
+                y: monitor.y,
+                width: monitor.width,
+                height: monitor.height,
+                scale_factor: 1.0,
+                is_primary: index == 0,
+            })
+            .collect())
+    }
+}
+
+/// Input driver for a virtual screen, it tracks the pointer and accepts every other event
+pub struct SimulatedInput {
+    desktop_size: (u32, u32),
+    primary_size: (u32, u32),
+    location: (i32, i32),
+}
+
+impl SimulatedInput {
+    pub fn new(screen: &VirtualScreen) -> Self {
+        Self {
+            desktop_size: screen.desktop_size(),
+            primary_size: screen.screen_size().unwrap_or((0, 0)),
+            location: (0, 0),
+        }
+    }
+}
+
+impl Keyboard for SimulatedInput {
+    fn fast_text(&mut self, _text: &str) -> InputResult<Option<()>> {
+        Ok(Some(()))
+    }
+
+    fn key(&mut self, _key: Key, _direction: Direction) -> InputResult<()> {
+        Ok(())
+    }
+
+    fn raw(&mut self, _keycode: u16, _direction: Direction) -> InputResult<()> {
+        Ok(())
+    }
+}
+
+impl Mouse for SimulatedInput {
+    fn button(&mut self, _button: Button, _direction: Direction) -> InputResult<()> {
+        Ok(())
+    }
+
+    // Like a real pointer, it can't leave the desktop
+    fn move_mouse(&mut self, x: i32, y: i32, coordinate: Coordinate) -> InputResult<()> {
+        let (x, y) = match coordinate {
+            Coordinate::Abs => (x, y),
+            Coordinate::Rel => (self.location.0 + x, self.location.1 + y),
+        };
+        self.location = (
+            x.clamp(0, self.desktop_size.0.saturating_sub(1) as i32),
+            y.clamp(0, self.desktop_size.1.saturating_sub(1) as i32),
+        );
+        Ok(())
+    }
+
+    fn scroll(&mut self, _length: i32, _axis: Axis) -> InputResult<()> {
+        Ok(())
+    }
+
+    fn main_display(&self) -> InputResult<(i32, i32)> {
+        Ok((self.primary_size.0 as i32, self.primary_size.1 as i32))
+    }
+
+    fn location(&self) -> InputResult<(i32, i32)> {
+        Ok(self.location)
+    }
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action_queue::ActionQueue;
+    use crate::action_types::{
+        Action, ActionOutput, ActionRequest, ActionResponseStatus, MouseMoveInput,
+    };
+    use crate::config::Config;
+    use image::Rgba;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_parse_layout() {
+        let screen = VirtualScreen::parse("1920x1080, 1280x1024").unwrap();
+        assert_eq!(screen.desktop_size(), (3200, 1080));
+        assert_eq!(screen.screen_size().unwrap(), (1920, 1080));
+        let displays = screen.displays().unwrap();
+        assert_eq!(displays.len(), 2);
+        assert_eq!(
+            (displays[1].x, displays[1].width, displays[1].height),
+            (1920, 1280, 1024)
+        );
+        assert!(displays[0].is_primary && !displays[1].is_primary);
+
+        assert!(VirtualScreen::parse("1920").is_err());
+        assert!(VirtualScreen::parse("0x1080").is_err());
+    }
+
+    #[test]
+    fn test_capture_crops_primary_monitor() {
+        let mut image = RgbaImage::new(30, 10);
+        image.put_pixel(5, 5, Rgba([255, 0, 0, 255]));
+        image.put_pixel(25, 5, Rgba([0, 255, 0, 255]));
+        let screen = VirtualScreen::parse("20x10,10x10")
+            .unwrap()
+            .with_image(image);
+
+        let capture = screen.capture().unwrap();
+        assert_eq!(capture.dimensions(), (20, 10));
+        assert_eq!(capture.get_pixel(5, 5), &Rgba([255, 0, 0, 255]));
+
+        // Images of another size are scaled to the desktop
+        let screen = VirtualScreen::parse("20x10")
+            .unwrap()
+            .with_image(RgbaImage::new(4, 2));
+        assert_eq!(screen.capture().unwrap().dimensions(), (20, 10));
+    }
+
+    #[tokio::test]
+    async fn test_queue_runs_on_virtual_screen() {
+        let screen = VirtualScreen::parse("1920x1080,1280x1024").unwrap();
+        let queue = Arc::new(
+            ActionQueue::new(SimulatedInput::new(&screen), Config::default())
+                .with_capture(Arc::new(screen)),
+        );
+        queue.start_processing().await;
+
+        let response = queue
+            .execute_action(ActionRequest::new(
+                "test_simulated_move".to_string(),
+                Action::MouseMove {
+                    input: MouseMoveInput {
+                        x: 2500,
+                        y: 300,
+                        monitor: None,
+                        motion: None,
+                        duration_ms: None,
+                    },
+                },
+            ))
+            .await;
+        assert!(matches!(response.status, ActionResponseStatus::Success));
+        let response = queue
+            .execute_action(ActionRequest::new(
+                "test_simulated_cursor".to_string(),
+                Action::CursorPosition,
+            ))
+            .await;
+        assert!(matches!(
+            response.data,
+            Some(ActionOutput::CursorPosition { x: 2500, y: 300 })
+        ));
+    }
+
+    #[test]
+    fn test_pointer_stays_on_desktop() {
+        let screen = VirtualScreen::parse("1920x1080,1280x1024").unwrap();
+        let mut input = SimulatedInput::new(&screen);
+        input.move_mouse(3000, 500, Coordinate::Abs).unwrap();
+        assert_eq!(input.location().unwrap(), (3000, 500));
+        input.move_mouse(5000, 5000, Coordinate::Rel).unwrap();
+        assert_eq!(input.location().unwrap(), (3199, 1079));
+        assert_eq!(input.main_display().unwrap(), (1920, 1080));
+    }
+}
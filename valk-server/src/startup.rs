@@ -0,0 +1,135 @@
+//! Actions run once when the server starts, to put provisioned kiosk and test machines
+//! into a known state before any client connects.
+
+use std::fs;
+use std::path::Path;
+
+use tracing::{info, warn};
+
+use crate::action_queue::{ActionQueue, InputDriver};
+use crate::action_types::{Action, ActionRequest, ActionResponse, ActionResponseStatus, OnTimeout};
+
+/// Read the startup actions from a JSON file holding a list of actions
+pub fn load(path: &Path) -> Result<Vec<Action>, String> {
+    let json = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let actions: Vec<Action> = serde_json::from_str(&json)
+        .map_err(|e| format!("Invalid startup actions {}: {}", path.display(), e))?;
+    for (index, action) in actions.iter().enumerate() {
+        action.validate().map_err(|e| {
+            format!(
+                "Invalid startup action {} in {}: {:?}",
+                index + 1,
+                path.display(),
+                e
+            )
+        })?;
+    }
+    Ok(actions)
+}
+
+/// Run the actions through the queue in order, logging each result. Later actions build on
+/// the ones before, so they are skipped once one fails. They run before the server listens,
+/// so none of them waits for an approval nobody could give
+pub async fn run<T: InputDriver>(
+    queue: &ActionQueue<T>,
+    actions: Vec<Action>,
+) -> Vec<ActionResponse> {
+    let total = actions.len();
+    let mut responses = Vec::with_capacity(total);
+    for (index, action) in actions.into_iter().enumerate() {
+        let request = ActionRequest {
+            id: format!("startup-{}", index + 1),
+            action,
+            run_id: None,
+            task_id: None,
+            metadata: serde_json::json!({"source": "startup"})
+                .as_object()
+                .cloned(),
+            timeout_ms: None,
+            on_timeout: OnTimeout::Abort,
+        };
+        let response = queue.execute_configured(request).await;
+        let action_type = response.action.action_type();
+        match (&response.status, &response.error) {
+            (ActionResponseStatus::Success, _) => {
+                info!(
+                    "Startup action {} of {} ({}) done",
+                    index + 1,
+                    total,
+                    action_type
+                )
+            }
+            (_, error) => {
+                warn!(
+                    "Startup action {} of {} ({}) failed, skipping the rest: {:?}",
+                    index + 1,
+                    total,
+                    action_type,
+                    error
+                );
+                responses.push(response);
+                break;
+            }
+        }
+        responses.push(response);
+    }
+    responses
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action_queue::tests::{
+        create_test_action_queue, create_test_action_queue_with_config,
+    };
+    use crate::config::Config;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_startup_actions_run_until_one_fails() {
+        let path = std::env::temp_dir().join(format!("valk-startup-{}.json", std::process::id()));
+        fs::write(
+            &path,
+            r#"[
+                {"type": "mouse_move", "input": {"x": 0, "y": 0}},
+                {"type": "restore_cursor"},
+                {"type": "left_click"}
+            ]"#,
+        )
+        .unwrap();
+        let actions = load(&path);
+        fs::write(&path, r#"[{"type": "tap", "input": {"x": 99999, "y": 0}}]"#).unwrap();
+        let invalid = load(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(invalid.is_err());
+
+        let queue = create_test_action_queue().await;
+        let responses = run(&queue, actions.unwrap()).await;
+        // Nothing was saved to restore, so the click never runs
+        assert_eq!(responses.len(), 2);
+        assert!(matches!(responses[0].status, ActionResponseStatus::Success));
+        assert_eq!(responses[1].request_id, "startup-2");
+        assert!(responses[1].error.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_startup_actions_skip_approval() {
+        let queue = create_test_action_queue_with_config(Config {
+            approval_actions: vec!["mouse_move".to_string()],
+            approval_timeout_secs: 60,
+            ..Config::default()
+        })
+        .await;
+
+        let actions = vec![Action::MouseMove {
+            input: serde_json::from_value(serde_json::json!({"x": 3, "y": 4})).unwrap(),
+        }];
+        let responses = tokio::time::timeout(Duration::from_secs(5), run(&queue, actions))
+            .await
+            .expect("the startup action waited for an approval");
+        assert!(matches!(responses[0].status, ActionResponseStatus::Success));
+        assert!(queue.approvals().list().is_empty());
+    }
+}
@@ -0,0 +1,179 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use crate::config::{Config, InputBackend};
+
+const PID_FILE: &str = "valk.pid";
+
+/// Subdirectories for state that outlives a single request
+const SUBDIRECTORIES: &[&str] = &["recordings", "journals", "macros", "calibration"];
+
+/// Scratch space for files being written, anything left here is from a crashed server
+pub const TMP_DIR: &str = "tmp";
+
+/// A state directory owned by this server for as long as the value lives.
+///
+/// Ownership is an exclusive lock on the PID file (`flock` on Unix), so it is released by
+/// the kernel even if the server crashes, and a PID file left behind by a crash never
+/// blocks the next start.
+pub struct StateDir {
+    path: PathBuf,
+    // Holds the lock
+    pid_file: File,
+}
+
+impl StateDir {
+    pub fn acquire(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref().to_path_buf();
+        fs::create_dir_all(&path)
+            .map_err(|e| format!("Failed to create state dir {}: {}", path.display(), e))?;
+
+        let pid_path = path.join(PID_FILE);
+        let mut pid_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&pid_path)
+            .map_err(|e| format!("Failed to open {}: {}", pid_path.display(), e))?;
+
+        if !try_lock(&pid_file) {
+            let mut owner = String::new();
+            let _ = pid_file.read_to_string(&mut owner);
+            return Err(format!(
+                "State dir {} is in use by another valk-server (pid {}), set VALK_STATE_DIR to run a second instance",
+                path.display(),
+                owner.trim()
+            ));
+        }
+
+        pid_file
+            .set_len(0)
+            .and_then(|_| pid_file.seek(SeekFrom::Start(0)))
+            .and_then(|_| writeln!(pid_file, "{}", std::process::id()))
+            .map_err(|e| format!("Failed to write {}: {}", pid_path.display(), e))?;
+
+        let tmp = path.join(TMP_DIR);
+        if tmp.exists() {
+            fs::remove_dir_all(&tmp)
+                .map_err(|e| format!("Failed to clean {}: {}", tmp.display(), e))?;
+        }
+        for subdirectory in SUBDIRECTORIES.iter().chain([&TMP_DIR]) {
+            let subdirectory = path.join(subdirectory);
+            fs::create_dir_all(&subdirectory)
+                .map_err(|e| format!("Failed to create {}: {}", subdirectory.display(), e))?;
+        }
+
+        Ok(Self { path, pid_file })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for StateDir {
+    // The lock goes with the file descriptor, the PID file only tells people who holds it
+    fn drop(&mut self) {
+        let _ = self.pid_file.set_len(0);
+    }
+}
+
+// Take the lock without waiting, false while another process holds it
+#[cfg(unix)]
+fn try_lock(file: &File) -> bool {
+    unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) == 0 }
+}
+
+#[cfg(not(unix))]
+fn try_lock(file: &File) -> bool {
+    file.try_lock().is_ok()
+}
+
+/// One state dir per display, so servers driving different displays can run side by side
+pub fn default_state_dir(config: &Config) -> PathBuf {
+    let display = if config.input_backend == InputBackend::Simulated {
+        format!("simulated-{}", config.port)
+    } else {
+        std::env::var("WAYLAND_DISPLAY")
+            .or_else(|_| std::env::var("DISPLAY"))
+            .unwrap_or_else(|_| "default".to_string())
+    };
+    let display: String = display
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let base = std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir);
+    base.join(format!("valk-{}", display))
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("valk-state-{}-{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_second_instance_is_refused() {
+        let path = temp_path("lock");
+        let state = StateDir::acquire(&path).unwrap();
+        assert_eq!(
+            fs::read_to_string(path.join(PID_FILE)).unwrap().trim(),
+            std::process::id().to_string()
+        );
+        for subdirectory in SUBDIRECTORIES {
+            assert!(path.join(subdirectory).is_dir());
+        }
+
+        let error = StateDir::acquire(&path).err().unwrap();
+        assert!(error.contains(&format!("pid {}", std::process::id())));
+
+        // Released on drop, even though the PID file stays
+        drop(state);
+        let state = StateDir::acquire(&path).unwrap();
+        assert_eq!(state.path(), path);
+        drop(state);
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn test_stale_state_is_cleaned() {
+        let path = temp_path("stale");
+        fs::create_dir_all(path.join(TMP_DIR)).unwrap();
+        fs::create_dir_all(path.join("recordings")).unwrap();
+        fs::write(path.join(TMP_DIR).join("partial.json"), "{").unwrap();
+        fs::write(path.join("recordings").join("kept.json"), "{}").unwrap();
+        // Left behind by a server that crashed
+        fs::write(path.join(PID_FILE), "999999\n").unwrap();
+
+        let state = StateDir::acquire(&path).unwrap();
+        assert!(!path.join(TMP_DIR).join("partial.json").exists());
+        assert!(path.join("recordings").join("kept.json").exists());
+        drop(state);
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn test_default_state_dir_per_display() {
+        let config = Config {
+            input_backend: InputBackend::Simulated,
+            port: 9123,
+            ..Config::default()
+        };
+        assert!(default_state_dir(&config).ends_with("valk-simulated-9123"));
+    }
+}
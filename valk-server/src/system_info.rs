@@ -1,37 +1,53 @@
 use axum::http::StatusCode;
-use axum::Json;
+use axum::{extract, Json};
 use serde::Serialize;
+use std::sync::Arc;
+
+use crate::capture::{virtual_screen, DisplayInfo, ScreenBounds};
+use crate::AppState;
 
 #[derive(Debug, Serialize)]
 pub struct ComputerInfo {
     os_type: String,
     os_version: String,
+    /// Size of the primary monitor
     display_width: u32,
     display_height: u32,
+    display_count: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    primary_display_id: Option<u32>,
+    /// The box enclosing every monitor, legal input coordinates lie within it
+    virtual_screen: ScreenBounds,
+    displays: Vec<DisplayInfo>,
 }
 
 /// Get information about the computer system
-pub async fn system_info() -> Result<Json<ComputerInfo>, (StatusCode, String)> {
-    let monitor = xcap::Monitor::all()
-        .map_err(|e| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Failed to get display info: {}", e),
-            )
-        })?
-        .first()
-        .cloned()
-        .ok_or((
+pub async fn system_info(
+    extract::State(state): extract::State<Arc<AppState>>,
+) -> Result<Json<ComputerInfo>, (StatusCode, String)> {
+    let capture = state.action_queue.capture();
+    let failed = |e| {
+        (
             StatusCode::INTERNAL_SERVER_ERROR,
-            "No monitor found".to_string(),
-        ))?;
+            format!("Failed to get display info: {:?}", e),
+        )
+    };
+    let (display_width, display_height) = capture.screen_size().map_err(failed)?;
+    let displays = capture.displays().map_err(failed)?;
 
     let os_info = os_info::get();
 
     Ok(Json(ComputerInfo {
         os_type: os_info.os_type().to_string(),
         os_version: os_info.version().to_string(),
-        display_width: monitor.width(),
-        display_height: monitor.height(),
+        display_width,
+        display_height,
+        display_count: displays.len(),
+        primary_display_id: displays
+            .iter()
+            .find(|display| display.is_primary)
+            .map(|display| display.id),
+        virtual_screen: virtual_screen(&displays),
+        displays,
     }))
 }
@@ -0,0 +1,266 @@
+use axum::{
+    extract::{
+        self,
+        ws::{Message, Utf8Bytes, WebSocket, WebSocketUpgrade},
+        Query,
+    },
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+use serde::Deserialize;
+use std::sync::Arc;
+use tokio::time::{interval, Duration, Instant};
+use tracing::warn;
+
+use crate::action_queue::SharedQueue;
+use crate::action_types::{
+    Action, ActionError, ActionRequest, ActionResponse, ActionResponseStatus, KeyPressInput,
+    OnTimeout,
+};
+use crate::monitor::MonitorEventPayload;
+use crate::AppState;
+
+/// How often the server pings a takeover connection
+const PING_INTERVAL: Duration = Duration::from_secs(15);
+/// A connection nothing arrived on for this long is taken for dead, and gives input back
+const IDLE_TIMEOUT: Duration = Duration::from_secs(45);
+
+#[derive(Debug, Default, Deserialize)]
+pub struct TakeoverParams {
+    /// Browsers can't set headers on WebSocket connections, so the token may come here instead
+    token: Option<String>,
+}
+
+/// `/v1/takeover`, a WebSocket that hands input to a human observer for as long as it is open.
+///
+/// Every text message is an action request like the body of `POST /v1/action`, run ahead of
+/// the agents' queued actions and answered with its response. Agent actions are held until
+/// the connection closes.
+pub async fn takeover_websocket(
+    ws: WebSocketUpgrade,
+    extract::State(state): extract::State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(params): Query<TakeoverParams>,
+) -> Response {
+    let Some(expected) = state.action_queue.config().takeover_token.as_deref() else {
+        return (
+            StatusCode::NOT_FOUND,
+            "Takeover is disabled, set VALK_TAKEOVER_TOKEN to enable it",
+        )
+            .into_response();
+    };
+    let presented = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or(params.token.as_deref());
+    if !presented.is_some_and(|token| tokens_match(token, expected)) {
+        return (StatusCode::UNAUTHORIZED, "Invalid takeover token").into_response();
+    }
+
+    let queue = state.action_queue.clone();
+    ws.on_upgrade(move |socket| handle_socket(socket, queue))
+}
+
+// Compare without returning early, so the time taken doesn't leak how much of the token matched
+pub fn tokens_match(presented: &str, expected: &str) -> bool {
+    presented.len() == expected.len()
+        && presented
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+// Agents are held back for as long as this lives. Dropped without `end`, because the task
+// running the connection was cancelled, it still releases the keys and hands input back
+struct TakeoverSession {
+    queue: SharedQueue,
+    held: HeldInput,
+    ended: bool,
+}
+
+impl TakeoverSession {
+    fn start(queue: SharedQueue) -> Self {
+        queue.start_takeover();
+        Self {
+            queue,
+            held: HeldInput::default(),
+            ended: false,
+        }
+    }
+
+    async fn end(mut self) {
+        self.held.clone().release(&self.queue, "takeover").await;
+        self.queue.end_takeover();
+        self.ended = true;
+    }
+}
+
+impl Drop for TakeoverSession {
+    fn drop(&mut self) {
+        if self.ended {
+            return;
+        }
+        let queue = self.queue.clone();
+        let held = std::mem::take(&mut self.held);
+        // Releasing keys runs actions, which can't be awaited here
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                runtime.spawn(async move {
+                    held.release(&queue, "takeover").await;
+                    queue.end_takeover();
+                });
+            }
+            Err(_) => queue.end_takeover(),
+        }
+    }
+}
+
+async fn handle_socket(mut socket: WebSocket, queue: SharedQueue) {
+    let mut session = TakeoverSession::start(queue.clone());
+    // Browsers answer pings on their own, so a connection that stops answering is gone, e.g.
+    // a laptop that went to sleep, and mustn't hold agents back for good
+    let mut ping = interval(PING_INTERVAL);
+    let mut last_seen = Instant::now();
+
+    loop {
+        let text = tokio::select! {
+            _ = ping.tick() => {
+                if last_seen.elapsed() > IDLE_TIMEOUT {
+                    warn!("Takeover connection stopped answering, giving input back");
+                    break;
+                }
+                if socket.send(Message::Ping(Default::default())).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => text,
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {
+                    last_seen = Instant::now();
+                    continue;
+                }
+            },
+        };
+        last_seen = Instant::now();
+        let reply = match serde_json::from_str::<ActionRequest>(&text) {
+            Ok(request) => {
+                let response = queue.execute_takeover(request).await;
+                session.held.track(&response);
+                serde_json::to_string(&response)
+            }
+            Err(e) => serde_json::to_string(&serde_json::json!({
+                "status": "error",
+                "error": ActionError::InvalidInput(e.to_string()),
+            })),
+        };
+        if let Ok(reply) = reply {
+            if socket
+                .send(Message::Text(Utf8Bytes::from(reply)))
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+    }
+
+    session.end().await;
+}
+
+/// Keys a connection sending input pressed with key_down and hasn't released yet. A
+/// connection that drops mid-sequence mustn't leave them stuck down for whoever comes next
+#[derive(Debug, Clone, Default)]
+pub struct HeldInput {
+    keys: Vec<String>,
+}
+
+impl HeldInput {
+    /// Follow what an action the connection sent pressed or released
+    pub fn track(&mut self, response: &ActionResponse) {
+        if let ActionResponseStatus::Success = response.status {
+            match &response.action {
+                Action::KeyDown { input } => self.keys.push(input.key.clone()),
+                Action::KeyUp { input } => self.keys.retain(|key| *key != input.key),
+                _ => {}
+            }
+        }
+    }
+
+    /// Release the keys, last pressed first, along with any mouse button an action cut off
+    /// midway left down, and tell monitors the `connection` went away
+    pub async fn release(self, queue: &SharedQueue, connection: &str) {
+        for (index, key) in self.keys.iter().rev().enumerate() {
+            let request = ActionRequest {
+                id: format!("{}-release-{}", connection, index + 1),
+                action: Action::KeyUp {
+                    input: KeyPressInput { key: key.clone() },
+                },
+                run_id: None,
+                task_id: None,
+                metadata: None,
+                timeout_ms: None,
+                on_timeout: OnTimeout::Abort,
+            };
+            // Ahead of queued agent actions, which mustn't run with the keys still down
+            queue.execute_takeover(request).await;
+        }
+        let released_buttons = queue.release_buttons().await;
+        queue.send_monitor_event(MonitorEventPayload::ControllerDisconnected {
+            connection: connection.to_string(),
+            released_keys: self.keys,
+            released_buttons,
+            timestamp: Utc::now(),
+        });
+    }
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action_queue::tests::{mock_capture, MockEnigo};
+    use crate::action_queue::{ActionQueue, InputDriver};
+    use crate::config::Config;
+
+    #[test]
+    fn test_tokens_match() {
+        assert!(tokens_match("s3cret", "s3cret"));
+        assert!(!tokens_match("s3cres", "s3cret"));
+        assert!(!tokens_match("s3cre", "s3cret"));
+        assert!(!tokens_match("", "s3cret"));
+    }
+
+    #[tokio::test]
+    async fn test_dropped_session_gives_input_back() {
+        let input_driver: Box<dyn InputDriver> = Box::new(MockEnigo::new());
+        let queue: SharedQueue = Arc::new(
+            ActionQueue::new(input_driver, Config::default()).with_capture(mock_capture()),
+        );
+        queue.start_processing().await;
+        let (mut monitor, _) = queue.subscribe_monitor();
+
+        let mut session = TakeoverSession::start(queue.clone());
+        session.held.keys.push("ctrl".to_string());
+        assert_eq!(queue.observation_snapshot().1["queue"]["takeover"], true);
+        // As when the task running the connection is cancelled
+        drop(session);
+
+        loop {
+            let event = tokio::time::timeout(Duration::from_secs(10), monitor.recv())
+                .await
+                .expect("no disconnect event within 10s")
+                .unwrap();
+            let event = serde_json::to_value(&event).unwrap();
+            if event["event_type"] == "controller_disconnected" {
+                assert_eq!(event["data"]["released_keys"], serde_json::json!(["ctrl"]));
+                break;
+            }
+        }
+        assert_eq!(queue.observation_snapshot().1["queue"]["takeover"], false);
+    }
+}
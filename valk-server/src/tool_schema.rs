@@ -0,0 +1,288 @@
+//! Tool definitions for every action, generated from the `Action` type itself, so agents can
+//! hand valk's actions to a model as function-calling tools without writing schemas by hand.
+//! A call of tool `name` with arguments `args` is the action `{"type": name, "input": args}`,
+//! which `POST /v1/tools/openai/call` runs for OpenAI tool calls.
+
+use axum::{
+    extract::{self, Query},
+    Json,
+};
+use schemars::generate::SchemaSettings;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+use crate::action_queue::{ACTION_TIMEOUT, MAX_BATCH_ACTIONS};
+use crate::action_types::{
+    Action, ActionError, ActionRequest, OnTimeout, MAX_COORDINATE, MAX_GESTURE_DURATION_MS,
+    MAX_REQUEST_TIMEOUT_MS,
+};
+use crate::config::{Config, PacingMode, PacingRule};
+use crate::trajectory::{TrajectoryFormat, TrajectoryQuery};
+use crate::AppState;
+
+const COORDINATES: &str = "Coordinates are pixels from the top-left corner of the desktop, \
+    as spanned by all monitors. Pointer actions and screenshots accept a `monitor` to use \
+    coordinates relative to that monitor instead.";
+
+/// A tool per action, taking the action's input as its arguments
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ActionTool {
+    pub name: String,
+    pub description: String,
+    pub parameters: Value,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Screen {
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Limits {
+    pub max_coordinate: u32,
+    pub max_gesture_duration_ms: u64,
+    pub max_batch_actions: usize,
+    /// Actions a run may have waiting in the queue
+    pub max_queue_depth: usize,
+    pub action_timeout_ms: u64,
+    pub max_request_timeout_ms: u64,
+    pub pacing_rules: Vec<PacingRule>,
+    pub pacing_mode: PacingMode,
+}
+
+impl Limits {
+    fn from_config(config: &Config) -> Self {
+        Self {
+            max_coordinate: MAX_COORDINATE,
+            max_gesture_duration_ms: MAX_GESTURE_DURATION_MS,
+            max_batch_actions: MAX_BATCH_ACTIONS,
+            max_queue_depth: config.max_queue_depth,
+            action_timeout_ms: ACTION_TIMEOUT.as_millis() as u64,
+            max_request_timeout_ms: MAX_REQUEST_TIMEOUT_MS,
+            pacing_rules: config.pacing_rules.clone(),
+            pacing_mode: config.pacing_mode,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ToolSchema {
+    /// The primary monitor, unset if there is no display to ask
+    pub screen: Option<Screen>,
+    pub coordinates: &'static str,
+    pub limits: Limits,
+    pub tools: Vec<Value>,
+}
+
+/// Every action as a tool, in the order `Action` declares them. Each variant's doc comment
+/// becomes the description, its input type the parameters
+pub fn action_tools() -> Vec<ActionTool> {
+    let settings = SchemaSettings::draft2020_12().with(|settings| {
+        settings.inline_subschemas = true;
+    });
+    let schema = settings.into_generator().into_root_schema_for::<Action>();
+    let variants = schema
+        .get("oneOf")
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default();
+    variants
+        .iter()
+        .filter_map(|variant| {
+            let properties = variant.get("properties")?;
+            let name = properties.pointer("/type/const")?.as_str()?.to_string();
+            let parameters = properties
+                .get("input")
+                .cloned()
+                .unwrap_or_else(|| json!({"type": "object", "properties": {}}));
+            let description = variant
+                .get("description")
+                .and_then(Value::as_str)
+                .map(|doc| doc.replace('\n', " "))
+                .unwrap_or_else(|| name.replace('_', " "));
+            Some(ActionTool {
+                name,
+                description,
+                parameters,
+            })
+        })
+        .collect()
+}
+
+impl ActionTool {
+    pub fn to_format(&self, format: TrajectoryFormat) -> Value {
+        match format {
+            TrajectoryFormat::Anthropic => json!({
+                "name": self.name,
+                "description": self.description,
+                "input_schema": self.parameters,
+            }),
+            TrajectoryFormat::OpenAi => json!({
+                "type": "function",
+                "function": {
+                    "name": self.name,
+                    "description": self.description,
+                    "parameters": self.parameters,
+                },
+            }),
+        }
+    }
+}
+
+/// A tool call as chat completions return it in `tool_calls`
+#[derive(Debug, Deserialize)]
+pub struct OpenAiToolCall {
+    pub id: String,
+    pub function: OpenAiFunctionCall,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OpenAiFunctionCall {
+    pub name: String,
+    /// The arguments as a string of JSON, empty for tools without parameters
+    #[serde(default)]
+    pub arguments: String,
+}
+
+impl OpenAiToolCall {
+    /// The call as a request for its action, with the call's id
+    pub fn into_request(self, run_id: Option<String>) -> Result<ActionRequest, ActionError> {
+        let arguments = match self.function.arguments.trim() {
+            "" => json!({}),
+            arguments => serde_json::from_str(arguments).map_err(|e| {
+                ActionError::InvalidInput(format!("Arguments are not valid JSON: {}", e))
+            })?,
+        };
+        let Value::Object(arguments) = arguments else {
+            return Err(ActionError::InvalidInput(
+                "Arguments must be a JSON object".to_string(),
+            ));
+        };
+        let mut action = json!({"type": self.function.name});
+        // Tools without parameters are actions without input
+        if !arguments.is_empty() {
+            action["input"] = Value::Object(arguments);
+        }
+        let action = serde_json::from_value(action).map_err(|e| {
+            ActionError::InvalidInput(format!("Invalid {} call: {}", self.function.name, e))
+        })?;
+        Ok(ActionRequest {
+            id: self.id,
+            action,
+            run_id,
+            task_id: None,
+            metadata: None,
+            timeout_ms: None,
+            on_timeout: OnTimeout::Abort,
+        })
+    }
+}
+
+/// `GET /v1/tools/openai`, the tools alone, ready to pass as `tools` to chat completions
+pub async fn openai_tools() -> Json<Vec<Value>> {
+    Json(
+        action_tools()
+            .iter()
+            .map(|tool| tool.to_format(TrajectoryFormat::OpenAi))
+            .collect(),
+    )
+}
+
+/// `GET /v1/tool_schema`, `?format=anthropic` (default) or `?format=openai`
+pub async fn tool_schema(
+    extract::State(state): extract::State<Arc<AppState>>,
+    Query(query): Query<TrajectoryQuery>,
+) -> Json<ToolSchema> {
+    let queue = &state.action_queue;
+    let screen = queue.capture().displays().ok().and_then(|displays| {
+        displays
+            .into_iter()
+            .find(|display| display.is_primary)
+            .map(|display| Screen {
+                width: display.width,
+                height: display.height,
+            })
+    });
+    Json(ToolSchema {
+        screen,
+        coordinates: COORDINATES,
+        limits: Limits::from_config(queue.config()),
+        tools: action_tools()
+            .iter()
+            .map(|tool| tool.to_format(query.format))
+            .collect(),
+    })
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_action_is_a_tool() {
+        let tools = action_tools();
+        let names: Vec<&str> = tools.iter().map(|tool| tool.name.as_str()).collect();
+        assert_eq!(names.first(), Some(&"left_click"));
+        assert!(names.contains(&"kinetic_scroll"));
+
+        let click = tools.iter().find(|tool| tool.name == "click").unwrap();
+        assert_eq!(
+            click.description,
+            "Move to a point and click there, as one queue item"
+        );
+        assert_eq!(click.parameters["required"], json!(["x", "y"]));
+        assert!(click.parameters["properties"]["button"].is_object());
+
+        let openai = click.to_format(TrajectoryFormat::OpenAi);
+        assert_eq!(openai["function"]["name"], "click");
+        let anthropic = click.to_format(TrajectoryFormat::Anthropic);
+        assert_eq!(anthropic["input_schema"], click.parameters);
+
+        // Actions without input take no arguments
+        let ping = tools.iter().find(|tool| tool.name == "ping").unwrap();
+        assert_eq!(ping.parameters, json!({"type": "object", "properties": {}}));
+        let parsed: Action = serde_json::from_value(json!({"type": ping.name})).unwrap();
+        assert_eq!(parsed.action_type(), "ping");
+    }
+
+    #[test]
+    fn test_openai_tool_calls_become_actions() {
+        let call = |name: &str, arguments: &str| OpenAiToolCall {
+            id: "call_1".to_string(),
+            function: OpenAiFunctionCall {
+                name: name.to_string(),
+                arguments: arguments.to_string(),
+            },
+        };
+
+        let request = call("click", r#"{"x": 10, "y": 20, "button": "right"}"#)
+            .into_request(Some("run-1".to_string()))
+            .unwrap();
+        assert_eq!(request.id, "call_1");
+        assert_eq!(request.run_id.as_deref(), Some("run-1"));
+        let Action::Click { input } = request.action else {
+            panic!("expected a click, got {:?}", request.action);
+        };
+        assert_eq!((input.x, input.y), (10, 20));
+
+        for arguments in ["", "{}"] {
+            let request = call("left_click", arguments).into_request(None).unwrap();
+            assert!(matches!(request.action, Action::LeftClick));
+        }
+        let request = call("screenshot", "{}").into_request(None).unwrap();
+        assert!(matches!(request.action, Action::Screenshot { .. }));
+
+        for (name, arguments) in [
+            ("click", "{\"x\": 10"),
+            ("click", "[10, 20]"),
+            ("click", "{\"x\": 10}"),
+            ("launch_missiles", "{}"),
+        ] {
+            let result = call(name, arguments).into_request(None);
+            assert!(matches!(result, Err(ActionError::InvalidInput(_))));
+        }
+    }
+}
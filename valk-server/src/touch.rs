@@ -0,0 +1,180 @@
+use serde::{Deserialize, Serialize};
+
+/// The kind of contact being simulated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TouchTool {
+    Finger,
+    Pen,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TouchPhase {
+    Down,
+    Move,
+    Up,
+}
+
+/// Injects touch and pen contacts as a real touch/pen device rather than mouse emulation
+pub trait TouchDriver: Send + 'static {
+    /// Pressure is normalized to 0.0..=1.0 and ignored by tools that don't support it
+    fn contact(
+        &mut self,
+        tool: TouchTool,
+        phase: TouchPhase,
+        x: i32,
+        y: i32,
+        pressure: f32,
+    ) -> Result<(), String>;
+}
+
+/// Returns the touch driver for the current platform, if touch injection is supported
+pub fn platform_touch_driver(screen_size: (u32, u32)) -> Option<Box<dyn TouchDriver>> {
+    #[cfg(target_os = "linux")]
+    {
+        Some(Box::new(linux::UinputTouchDriver::new(screen_size)))
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = screen_size;
+        None
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::{TouchDriver, TouchPhase, TouchTool};
+    use crate::uinput::*;
+
+    const PRESSURE_MAX: i32 = 1024;
+
+    /// Virtual touchscreen and pen tablet backed by uinput
+    /// Devices are created lazily so servers without /dev/uinput access still start
+    pub struct UinputTouchDriver {
+        screen_size: (u32, u32),
+        touchscreen: Option<UinputDevice>,
+        pen: Option<UinputDevice>,
+        tracking_id: i32,
+    }
+
+    impl UinputTouchDriver {
+        pub fn new(screen_size: (u32, u32)) -> Self {
+            UinputTouchDriver {
+                screen_size,
+                touchscreen: None,
+                pen: None,
+                tracking_id: 0,
+            }
+        }
+
+        fn axes(&self) -> (i32, i32) {
+            (
+                self.screen_size.0.saturating_sub(1) as i32,
+                self.screen_size.1.saturating_sub(1) as i32,
+            )
+        }
+
+        fn touchscreen(&mut self) -> Result<&mut UinputDevice, String> {
+            if self.touchscreen.is_none() {
+                let (max_x, max_y) = self.axes();
+                self.touchscreen = Some(UinputDevice::create(&DeviceSpec {
+                    name: "valk virtual touchscreen".to_string(),
+                    keys: vec![BTN_TOUCH],
+                    abs_axes: vec![
+                        (ABS_X, 0, max_x),
+                        (ABS_Y, 0, max_y),
+                        (ABS_MT_SLOT, 0, 0),
+                        (ABS_MT_TRACKING_ID, 0, i16::MAX as i32),
+                        (ABS_MT_POSITION_X, 0, max_x),
+                        (ABS_MT_POSITION_Y, 0, max_y),
+                    ],
+                    properties: vec![INPUT_PROP_DIRECT],
+                    ..DeviceSpec::default()
+                })?);
+            }
+            Ok(self.touchscreen.as_mut().unwrap())
+        }
+
+        fn pen(&mut self) -> Result<&mut UinputDevice, String> {
+            if self.pen.is_none() {
+                let (max_x, max_y) = self.axes();
+                self.pen = Some(UinputDevice::create(&DeviceSpec {
+                    name: "valk virtual pen".to_string(),
+                    keys: vec![BTN_TOOL_PEN, BTN_TOUCH],
+                    abs_axes: vec![
+                        (ABS_X, 0, max_x),
+                        (ABS_Y, 0, max_y),
+                        (ABS_PRESSURE, 0, PRESSURE_MAX),
+                    ],
+                    properties: vec![INPUT_PROP_DIRECT],
+                    ..DeviceSpec::default()
+                })?);
+            }
+            Ok(self.pen.as_mut().unwrap())
+        }
+    }
+
+    impl TouchDriver for UinputTouchDriver {
+        fn contact(
+            &mut self,
+            tool: TouchTool,
+            phase: TouchPhase,
+            x: i32,
+            y: i32,
+            pressure: f32,
+        ) -> Result<(), String> {
+            match tool {
+                TouchTool::Finger => {
+                    if phase == TouchPhase::Down {
+                        self.tracking_id = (self.tracking_id + 1) % i16::MAX as i32;
+                    }
+                    let tracking_id = self.tracking_id;
+                    let device = self.touchscreen()?;
+                    device.emit(EV_ABS, ABS_MT_SLOT, 0)?;
+                    match phase {
+                        TouchPhase::Down | TouchPhase::Move => {
+                            if phase == TouchPhase::Down {
+                                device.emit(EV_ABS, ABS_MT_TRACKING_ID, tracking_id)?;
+                            }
+                            device.emit(EV_ABS, ABS_MT_POSITION_X, x)?;
+                            device.emit(EV_ABS, ABS_MT_POSITION_Y, y)?;
+                            if phase == TouchPhase::Down {
+                                device.emit(EV_KEY, BTN_TOUCH, 1)?;
+                            }
+                            device.emit(EV_ABS, ABS_X, x)?;
+                            device.emit(EV_ABS, ABS_Y, y)?;
+                        }
+                        TouchPhase::Up => {
+                            device.emit(EV_ABS, ABS_MT_TRACKING_ID, -1)?;
+                            device.emit(EV_KEY, BTN_TOUCH, 0)?;
+                        }
+                    }
+                    device.sync()
+                }
+                TouchTool::Pen => {
+                    let pressure = (pressure.clamp(0.0, 1.0) * PRESSURE_MAX as f32) as i32;
+                    let device = self.pen()?;
+                    match phase {
+                        TouchPhase::Down | TouchPhase::Move => {
+                            if phase == TouchPhase::Down {
+                                device.emit(EV_KEY, BTN_TOOL_PEN, 1)?;
+                            }
+                            device.emit(EV_ABS, ABS_X, x)?;
+                            device.emit(EV_ABS, ABS_Y, y)?;
+                            device.emit(EV_ABS, ABS_PRESSURE, pressure)?;
+                            if phase == TouchPhase::Down {
+                                device.emit(EV_KEY, BTN_TOUCH, 1)?;
+                            }
+                        }
+                        TouchPhase::Up => {
+                            device.emit(EV_ABS, ABS_PRESSURE, 0)?;
+                            device.emit(EV_KEY, BTN_TOUCH, 0)?;
+                            device.emit(EV_KEY, BTN_TOOL_PEN, 0)?;
+                        }
+                    }
+                    device.sync()
+                }
+            }
+        }
+    }
+}
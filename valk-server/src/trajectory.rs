@@ -0,0 +1,389 @@
+//! Export of a recorded run as a computer-use trajectory, in the message and tool call
+//! shape used by fine-tuning datasets, so every deployment can collect training data.
+//!
+//! Each action becomes a call of a single `computer` tool and its result is the tool
+//! result, with the screenshot attached for screenshot actions.
+
+use axum::{
+    extract::{self, Path, Query},
+    http::StatusCode,
+    Json,
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::sync::Arc;
+
+use crate::action_types::{Action, ActionOutput, ActionResponseStatus, ScreenshotFormat};
+use crate::runs::{Run, RunHistoryEntry};
+use crate::AppState;
+
+const TOOL_NAME: &str = "computer";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum TrajectoryFormat {
+    /// Messages API, tool_use and tool_result content blocks
+    #[default]
+    #[serde(rename = "anthropic")]
+    Anthropic,
+    /// Chat completions, tool_calls and tool messages
+    #[serde(rename = "openai")]
+    OpenAi,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct TrajectoryQuery {
+    #[serde(default)]
+    pub format: TrajectoryFormat,
+}
+
+// What an action returned, as the model would have seen it
+struct ToolResult {
+    text: String,
+    // Base64 image and its media type
+    image: Option<(String, &'static str)>,
+    is_error: bool,
+}
+
+/// The run as `{"messages": [...]}`, starting with its goal as the user's request
+pub fn export(run: &Run, format: TrajectoryFormat) -> Value {
+    let mut messages = Vec::new();
+    if let Some(goal) = &run.goal {
+        messages.push(match format {
+            TrajectoryFormat::Anthropic => {
+                json!({"role": "user", "content": [{"type": "text", "text": goal}]})
+            }
+            TrajectoryFormat::OpenAi => json!({"role": "user", "content": goal}),
+        });
+    }
+
+    for entry in &run.history {
+        let call_id = format!("call_{}", entry.response.id.simple());
+        let input = tool_input(&entry.response.action);
+        let result = tool_result(entry);
+        match format {
+            TrajectoryFormat::Anthropic => {
+                messages.push(json!({
+                    "role": "assistant",
+                    "content": [{"type": "tool_use", "id": call_id, "name": TOOL_NAME, "input": input}],
+                }));
+                let content = match &result.image {
+                    Some((image, media_type)) => json!([{
+                        "type": "image",
+                        "source": {"type": "base64", "media_type": media_type, "data": image},
+                    }]),
+                    None => json!([{"type": "text", "text": result.text}]),
+                };
+                messages.push(json!({
+                    "role": "user",
+                    "content": [{
+                        "type": "tool_result",
+                        "tool_use_id": call_id,
+                        "content": content,
+                        "is_error": result.is_error,
+                    }],
+                }));
+            }
+            TrajectoryFormat::OpenAi => {
+                messages.push(json!({
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [{
+                        "id": call_id,
+                        "type": "function",
+                        "function": {"name": TOOL_NAME, "arguments": input.to_string()},
+                    }],
+                }));
+                messages
+                    .push(json!({"role": "tool", "tool_call_id": call_id, "content": result.text}));
+                // Tool messages can only hold text, so the screenshot follows as a user message
+                if let Some((image, media_type)) = &result.image {
+                    messages.push(json!({
+                        "role": "user",
+                        "content": [{
+                            "type": "image_url",
+                            "image_url": {"url": format!("data:{};base64,{}", media_type, image)},
+                        }],
+                    }));
+                }
+            }
+        }
+    }
+    json!({ "messages": messages })
+}
+
+// The action in the computer tool's terms, `{"action": "key", "text": "ctrl+s"}` etc.
+// Actions the tool doesn't have keep their own name and inputs
+fn tool_input(action: &Action) -> Value {
+    let name = match action.action_type() {
+        "type_text" => "type",
+        "key_press" => "key",
+        other => other,
+    };
+    let mut input = Map::new();
+    input.insert("action".to_string(), json!(name));
+    if let Ok(Value::Object(mut action)) = serde_json::to_value(action) {
+        if let Some(Value::Object(fields)) = action.remove("input") {
+            input.extend(fields);
+        }
+    }
+    if name == "key" {
+        if let Some(key) = input.remove("key") {
+            input.insert("text".to_string(), key);
+        }
+    }
+    if let (Some(x), Some(y)) = (input.get("x"), input.get("y")) {
+        let coordinate = json!([x, y]);
+        input.remove("x");
+        input.remove("y");
+        input.insert("coordinate".to_string(), coordinate);
+    }
+    Value::Object(input)
+}
+
+fn tool_result(entry: &RunHistoryEntry) -> ToolResult {
+    let response = &entry.response;
+    if let ActionResponseStatus::Error = response.status {
+        let error = serde_json::to_value(&response.error).unwrap_or_default();
+        return ToolResult {
+            text: format!(
+                "Error ({}): {}",
+                error["type"].as_str().unwrap_or("unknown"),
+                error["message"].as_str().unwrap_or_default()
+            ),
+            image: None,
+            is_error: true,
+        };
+    }
+
+    // Recordings are named after the format they were taken in
+    let image = entry.screenshot.as_ref().and_then(|path| {
+        let format = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(ScreenshotFormat::from_extension)
+            .unwrap_or_default();
+        let bytes = std::fs::read(path).ok()?;
+        Some((BASE64.encode(bytes), format.media_type()))
+    });
+    let text = match (&response.action, &response.data) {
+        (_, Some(ActionOutput::CursorPosition { x, y })) => format!("Cursor is at ({}, {})", x, y),
+        (Action::Burst { input }, _) => format!("Captured {} frames, not recorded", input.count),
+        (_, Some(ActionOutput::TextMatch { span, center, .. })) => {
+            format!("Found \"{}\" at ({}, {})", span.text, center.x, center.y)
+        }
+        (_, Some(ActionOutput::Element { element, center })) => format!(
+            "Clicked {} \"{}\" at ({}, {})",
+            element.role, element.name, center.x, center.y
+        ),
+        (_, Some(ActionOutput::ImageMatches { matches })) => {
+            let centers: Vec<String> = matches
+                .iter()
+                .map(|found| format!("({}, {})", found.center.x, found.center.y))
+                .collect();
+            format!("Found the image at {}", centers.join(", "))
+        }
+        (
+            Action::FocusWindow { .. } | Action::CloseWindow { .. } | Action::MinimizeWindow { .. },
+            Some(ActionOutput::Windows { windows }),
+        ) => {
+            let done = match response.action {
+                Action::CloseWindow { .. } => "Closed",
+                Action::MinimizeWindow { .. } => "Minimized",
+                _ => "Focused",
+            };
+            windows
+                .first()
+                .map(|window| format!("{} {} \"{}\"", done, window.app_name, window.title))
+                .unwrap_or_default()
+        }
+        (_, Some(ActionOutput::Windows { windows })) => windows
+            .iter()
+            .map(|window| {
+                format!(
+                    "{} \"{}\" at ({}, {}), {}x{}",
+                    window.app_name, window.title, window.x, window.y, window.width, window.height
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        (_, Some(ActionOutput::Text { spans, .. })) => spans
+            .iter()
+            .map(|span| span.text.as_str())
+            .collect::<Vec<_>>()
+            .join("\n"),
+        (
+            _,
+            Some(ActionOutput::Screenshot {
+                unchanged: true, ..
+            }),
+        ) => "Screenshot unchanged since the previous one".to_string(),
+        (Action::Screenshot { .. }, _) if image.is_some() => "Screenshot taken".to_string(),
+        (Action::Screenshot { .. }, _) => "Screenshot taken, but it was not recorded".to_string(),
+        _ => "Done".to_string(),
+    };
+    ToolResult {
+        text,
+        image,
+        is_error: false,
+    }
+}
+
+pub async fn run_trajectory(
+    extract::State(state): extract::State<Arc<AppState>>,
+    Path(run_id): Path<String>,
+    Query(query): Query<TrajectoryQuery>,
+) -> Result<Json<Value>, StatusCode> {
+    let run = state
+        .action_queue
+        .runs()
+        .get(&run_id)
+        .ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(export(&run, query.format)))
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action_types::{
+        ActionError, ActionRequest, ActionResponse, KeyPressInput, MouseMoveInput,
+    };
+    use crate::runs::{OpenRunBody, RunRegistry};
+    use chrono::Utc;
+
+    // A run with a screenshot, a move and a failed key press
+    fn recorded_run(name: &str) -> (Run, std::path::PathBuf) {
+        let dir =
+            std::env::temp_dir().join(format!("valk-trajectory-{}-{}", name, std::process::id()));
+        let registry = RunRegistry::with_recordings(dir.clone());
+        let run = registry.open(OpenRunBody {
+            goal: Some("Save the document".to_string()),
+            ..OpenRunBody::default()
+        });
+        let request = |action| ActionRequest {
+            run_id: Some(run.run_id.clone()),
+            ..ActionRequest::new("step".to_string(), action)
+        };
+
+        let screenshot = request(Action::Screenshot {
+            input: Default::default(),
+        });
+        let output = ActionOutput::Screenshot {
+            image: BASE64.encode(b"png bytes"),
+            format: Default::default(),
+            width: 2,
+            height: 2,
+            monitor_id: 0,
+            x: 0,
+            y: 0,
+            scale_factor: 1.0,
+            image_scale: 1.0,
+            captured_at: Utc::now(),
+            hash: String::new(),
+            unchanged: false,
+        };
+        registry.record(
+            &screenshot,
+            &ActionResponse::success(
+                "step".to_string(),
+                Action::Screenshot {
+                    input: Default::default(),
+                },
+                output,
+            ),
+        );
+        let moved = request(Action::MouseMove {
+            input: MouseMoveInput {
+                x: 10,
+                y: 20,
+                monitor: None,
+                motion: None,
+                duration_ms: None,
+            },
+        });
+        registry.record(
+            &moved,
+            &ActionResponse::success(
+                "step".to_string(),
+                moved.action.clone(),
+                ActionOutput::NoData,
+            ),
+        );
+        let key = request(Action::KeyPress {
+            input: KeyPressInput {
+                key: "ctrl+s".to_string(),
+            },
+        });
+        registry.record(
+            &key,
+            &ActionResponse::error("step".to_string(), key.action.clone(), ActionError::Timeout),
+        );
+        (registry.get(&run.run_id).unwrap(), dir)
+    }
+
+    #[test]
+    fn test_export_anthropic() {
+        let (run, dir) = recorded_run("anthropic");
+        let messages = export(&run, TrajectoryFormat::Anthropic)["messages"].clone();
+        std::fs::remove_dir_all(dir).unwrap();
+
+        assert_eq!(messages.as_array().unwrap().len(), 7);
+        assert_eq!(messages[0]["content"][0]["text"], "Save the document");
+
+        let tool_use = &messages[1]["content"][0];
+        assert_eq!(tool_use["name"], TOOL_NAME);
+        assert_eq!(tool_use["input"], json!({"action": "screenshot"}));
+        let result = &messages[2]["content"][0];
+        assert_eq!(result["tool_use_id"], tool_use["id"]);
+        assert_eq!(
+            result["content"][0]["source"]["data"],
+            BASE64.encode(b"png bytes")
+        );
+
+        assert_eq!(
+            messages[3]["content"][0]["input"],
+            json!({"action": "mouse_move", "coordinate": [10, 20]})
+        );
+        assert_eq!(
+            messages[5]["content"][0]["input"],
+            json!({"action": "key", "text": "ctrl+s"})
+        );
+        assert_eq!(messages[6]["content"][0]["is_error"], true);
+    }
+
+    #[test]
+    fn test_export_openai() {
+        let (run, dir) = recorded_run("openai");
+        let messages = export(&run, TrajectoryFormat::OpenAi)["messages"].clone();
+        std::fs::remove_dir_all(dir).unwrap();
+
+        let roles: Vec<&str> = messages
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|message| message["role"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            roles,
+            [
+                "user",
+                "assistant",
+                "tool",
+                "user",
+                "assistant",
+                "tool",
+                "assistant",
+                "tool"
+            ]
+        );
+        let call = &messages[1]["tool_calls"][0];
+        assert_eq!(messages[2]["tool_call_id"], call["id"]);
+        assert_eq!(call["function"]["arguments"], r#"{"action":"screenshot"}"#);
+        assert!(messages[3]["content"][0]["image_url"]["url"]
+            .as_str()
+            .unwrap()
+            .starts_with("data:image/png;base64,"));
+        assert_eq!(messages[7]["content"], "Error (timeout): Action timed out");
+    }
+}
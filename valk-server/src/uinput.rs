@@ -0,0 +1,499 @@
+// Minimal Linux uinput plumbing for creating virtual input devices.
+// Events written to a uinput device look like real hardware to the kernel,
+// so anything reading evdev (libinput, games, kiosk shells) sees them.
+
+use enigo::{Axis, Button, Coordinate, Direction, InputError, InputResult, Key, Keyboard, Mouse};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+
+// Event types
+pub const EV_SYN: u16 = 0x00;
+pub const EV_KEY: u16 = 0x01;
+pub const EV_REL: u16 = 0x02;
+pub const EV_ABS: u16 = 0x03;
+
+// Event codes
+pub const SYN_REPORT: u16 = 0x00;
+pub const BTN_LEFT: u16 = 0x110;
+pub const BTN_RIGHT: u16 = 0x111;
+pub const BTN_MIDDLE: u16 = 0x112;
+pub const BTN_SIDE: u16 = 0x113;
+pub const BTN_EXTRA: u16 = 0x114;
+pub const BTN_TOOL_PEN: u16 = 0x140;
+pub const BTN_TOUCH: u16 = 0x14a;
+pub const ABS_X: u16 = 0x00;
+pub const ABS_Y: u16 = 0x01;
+pub const ABS_PRESSURE: u16 = 0x18;
+pub const ABS_MT_SLOT: u16 = 0x2f;
+pub const ABS_MT_POSITION_X: u16 = 0x35;
+pub const ABS_MT_POSITION_Y: u16 = 0x36;
+pub const ABS_MT_TRACKING_ID: u16 = 0x39;
+pub const REL_HWHEEL: u16 = 0x06;
+pub const REL_WHEEL: u16 = 0x08;
+
+// Device properties
+pub const INPUT_PROP_DIRECT: u16 = 0x01;
+
+const BUS_VIRTUAL: u16 = 0x06;
+const UINPUT_PATH: &str = "/dev/uinput";
+const UINPUT_MAX_NAME_SIZE: usize = 80;
+
+// ioctl request numbers from linux/uinput.h
+const fn iow(nr: u64, size: usize) -> u64 {
+    (1 << 30) | ((size as u64) << 16) | ((b'U' as u64) << 8) | nr
+}
+const fn io(nr: u64) -> u64 {
+    ((b'U' as u64) << 8) | nr
+}
+const UI_DEV_CREATE: u64 = io(1);
+const UI_DEV_DESTROY: u64 = io(2);
+const UI_DEV_SETUP: u64 = iow(3, std::mem::size_of::<UinputSetup>());
+const UI_ABS_SETUP: u64 = iow(4, std::mem::size_of::<UinputAbsSetup>());
+const UI_SET_EVBIT: u64 = iow(100, std::mem::size_of::<libc::c_int>());
+const UI_SET_KEYBIT: u64 = iow(101, std::mem::size_of::<libc::c_int>());
+const UI_SET_RELBIT: u64 = iow(102, std::mem::size_of::<libc::c_int>());
+const UI_SET_ABSBIT: u64 = iow(103, std::mem::size_of::<libc::c_int>());
+const UI_SET_PROPBIT: u64 = iow(110, std::mem::size_of::<libc::c_int>());
+
+#[repr(C)]
+struct InputEvent {
+    time: libc::timeval,
+    type_: u16,
+    code: u16,
+    value: i32,
+}
+
+#[repr(C)]
+struct InputId {
+    bustype: u16,
+    vendor: u16,
+    product: u16,
+    version: u16,
+}
+
+#[repr(C)]
+struct UinputSetup {
+    id: InputId,
+    name: [u8; UINPUT_MAX_NAME_SIZE],
+    ff_effects_max: u32,
+}
+
+#[repr(C)]
+struct InputAbsinfo {
+    value: i32,
+    minimum: i32,
+    maximum: i32,
+    fuzz: i32,
+    flat: i32,
+    resolution: i32,
+}
+
+#[repr(C)]
+struct UinputAbsSetup {
+    code: u16,
+    absinfo: InputAbsinfo,
+}
+
+/// Describes the capabilities of a virtual device before it is created
+#[derive(Debug, Default)]
+pub struct DeviceSpec {
+    pub name: String,
+    pub keys: Vec<u16>,
+    /// Absolute axes as (code, minimum, maximum)
+    pub abs_axes: Vec<(u16, i32, i32)>,
+    pub rel_axes: Vec<u16>,
+    pub properties: Vec<u16>,
+}
+
+/// A created uinput device, destroyed when dropped
+pub struct UinputDevice {
+    file: File,
+}
+
+fn ioctl_int(file: &File, request: u64, value: libc::c_int) -> Result<(), String> {
+    // SAFETY: the request numbers used here all take an int argument by value
+    let result = unsafe { libc::ioctl(file.as_raw_fd(), request as _, value) };
+    if result < 0 {
+        return Err(format!(
+            "uinput ioctl failed: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    Ok(())
+}
+
+fn ioctl_ptr<T>(file: &File, request: u64, value: &T) -> Result<(), String> {
+    // SAFETY: the request numbers encode size_of::<T>() and T is #[repr(C)]
+    let result = unsafe { libc::ioctl(file.as_raw_fd(), request as _, value as *const T) };
+    if result < 0 {
+        return Err(format!(
+            "uinput ioctl failed: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    Ok(())
+}
+
+impl UinputDevice {
+    pub fn create(spec: &DeviceSpec) -> Result<Self, String> {
+        let file = OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(UINPUT_PATH)
+            .map_err(|e| format!("Failed to open {}: {}", UINPUT_PATH, e))?;
+
+        if !spec.keys.is_empty() {
+            ioctl_int(&file, UI_SET_EVBIT, EV_KEY as libc::c_int)?;
+            for key in &spec.keys {
+                ioctl_int(&file, UI_SET_KEYBIT, *key as libc::c_int)?;
+            }
+        }
+
+        if !spec.abs_axes.is_empty() {
+            ioctl_int(&file, UI_SET_EVBIT, EV_ABS as libc::c_int)?;
+            for (code, minimum, maximum) in &spec.abs_axes {
+                ioctl_int(&file, UI_SET_ABSBIT, *code as libc::c_int)?;
+                let abs_setup = UinputAbsSetup {
+                    code: *code,
+                    absinfo: InputAbsinfo {
+                        value: 0,
+                        minimum: *minimum,
+                        maximum: *maximum,
+                        fuzz: 0,
+                        flat: 0,
+                        resolution: 0,
+                    },
+                };
+                ioctl_ptr(&file, UI_ABS_SETUP, &abs_setup)?;
+            }
+        }
+
+        if !spec.rel_axes.is_empty() {
+            ioctl_int(&file, UI_SET_EVBIT, EV_REL as libc::c_int)?;
+            for code in &spec.rel_axes {
+                ioctl_int(&file, UI_SET_RELBIT, *code as libc::c_int)?;
+            }
+        }
+
+        for property in &spec.properties {
+            ioctl_int(&file, UI_SET_PROPBIT, *property as libc::c_int)?;
+        }
+
+        let mut name = [0u8; UINPUT_MAX_NAME_SIZE];
+        let name_bytes = spec.name.as_bytes();
+        let name_len = name_bytes.len().min(UINPUT_MAX_NAME_SIZE - 1);
+        name[..name_len].copy_from_slice(&name_bytes[..name_len]);
+
+        let setup = UinputSetup {
+            id: InputId {
+                bustype: BUS_VIRTUAL,
+                vendor: 0x1209, // pid.codes open source vendor id
+                product: 0x7a1c,
+                version: 1,
+            },
+            name,
+            ff_effects_max: 0,
+        };
+        ioctl_ptr(&file, UI_DEV_SETUP, &setup)?;
+        // UI_DEV_CREATE takes no argument
+        ioctl_int(&file, UI_DEV_CREATE, 0)?;
+
+        Ok(UinputDevice { file })
+    }
+
+    /// Write a single event, callers are responsible for sending SYN_REPORT
+    pub fn emit(&mut self, type_: u16, code: u16, value: i32) -> Result<(), String> {
+        let event = InputEvent {
+            time: libc::timeval {
+                tv_sec: 0,
+                tv_usec: 0,
+            },
+            type_,
+            code,
+            value,
+        };
+        // SAFETY: InputEvent is #[repr(C)] plain data matching struct input_event
+        let bytes = unsafe {
+            std::slice::from_raw_parts(
+                &event as *const InputEvent as *const u8,
+                std::mem::size_of::<InputEvent>(),
+            )
+        };
+        self.file
+            .write_all(bytes)
+            .map_err(|e| format!("Failed to write input event: {}", e))
+    }
+
+    pub fn sync(&mut self) -> Result<(), String> {
+        self.emit(EV_SYN, SYN_REPORT, 0)
+    }
+}
+
+impl Drop for UinputDevice {
+    fn drop(&mut self) {
+        let _ = ioctl_int(&self.file, UI_DEV_DESTROY, 0);
+    }
+}
+
+const KEY_LEFTSHIFT: u16 = 42;
+// Every key code from KEY_ESC up to KEY_MICMUTE is registered on the virtual keyboard
+const KEY_CODE_RANGE: std::ops::RangeInclusive<u16> = 1..=248;
+
+/// Linux key code for a named key, or None if it has no evdev equivalent
+fn key_code(key: Key) -> Option<u16> {
+    let code = match key {
+        Key::Escape => 1,
+        Key::Backspace => 14,
+        Key::Tab => 15,
+        Key::Return => 28,
+        Key::Control | Key::LControl => 29,
+        Key::Shift | Key::LShift => KEY_LEFTSHIFT,
+        Key::RShift => 54,
+        Key::Alt => 56,
+        Key::Space => 57,
+        Key::CapsLock => 58,
+        Key::F1 => 59,
+        Key::F2 => 60,
+        Key::F3 => 61,
+        Key::F4 => 62,
+        Key::F5 => 63,
+        Key::F6 => 64,
+        Key::F7 => 65,
+        Key::F8 => 66,
+        Key::F9 => 67,
+        Key::F10 => 68,
+        Key::Numlock => 69,
+        Key::F11 => 87,
+        Key::F12 => 88,
+        Key::RControl => 97,
+        Key::PrintScr => 99,
+        Key::Home => 102,
+        Key::UpArrow => 103,
+        Key::PageUp => 104,
+        Key::LeftArrow => 105,
+        Key::RightArrow => 106,
+        Key::End => 107,
+        Key::DownArrow => 108,
+        Key::PageDown => 109,
+        Key::Insert => 110,
+        Key::Delete => 111,
+        Key::Pause => 119,
+        Key::Meta => 125,
+        Key::Unicode(c) => return char_key_code(c).map(|(code, _)| code),
+        _ => return None,
+    };
+    Some(code)
+}
+
+/// Key code and whether shift is needed to produce the character on a US layout
+fn char_key_code(c: char) -> Option<(u16, bool)> {
+    const ROWS: [(&str, u16); 4] = [
+        ("1234567890-=", 2),
+        ("qwertyuiop[]", 16),
+        ("asdfghjkl;'`", 30),
+        ("\\zxcvbnm,./", 43),
+    ];
+    const SHIFTED_ROWS: [(&str, u16); 4] = [
+        ("!@#$%^&*()_+", 2),
+        ("QWERTYUIOP{}", 16),
+        ("ASDFGHJKL:\"~", 30),
+        ("|ZXCVBNM<>?", 43),
+    ];
+
+    match c {
+        ' ' => return Some((57, false)),
+        '\n' => return Some((28, false)),
+        '\t' => return Some((15, false)),
+        _ => {}
+    }
+
+    for (rows, shift) in [(ROWS, false), (SHIFTED_ROWS, true)] {
+        for (row, first_code) in rows {
+            if let Some(offset) = row.chars().position(|r| r == c) {
+                return Some((first_code + offset as u16, shift));
+            }
+        }
+    }
+    None
+}
+
+/// Keyboard and pointer driver emitting kernel-level events through uinput
+///
+/// Applications that ignore synthetic X11/XTest events (games, anti-cheat, some RDP clients)
+/// can't tell these apart from physical devices. Text entry assumes a US keyboard layout
+/// and the cursor location is tracked internally since uinput devices can't be queried.
+pub struct UinputDriver {
+    keyboard: UinputDevice,
+    pointer: UinputDevice,
+    screen_size: (u32, u32),
+    location: (i32, i32),
+}
+
+impl UinputDriver {
+    pub fn new(screen_size: (u32, u32)) -> Result<Self, String> {
+        let keyboard = UinputDevice::create(&DeviceSpec {
+            name: "valk virtual keyboard".to_string(),
+            keys: KEY_CODE_RANGE.collect(),
+            ..DeviceSpec::default()
+        })?;
+
+        let max_x = screen_size.0.saturating_sub(1) as i32;
+        let max_y = screen_size.1.saturating_sub(1) as i32;
+        let pointer = UinputDevice::create(&DeviceSpec {
+            name: "valk virtual pointer".to_string(),
+            keys: vec![BTN_LEFT, BTN_RIGHT, BTN_MIDDLE, BTN_SIDE, BTN_EXTRA],
+            abs_axes: vec![(ABS_X, 0, max_x), (ABS_Y, 0, max_y)],
+            rel_axes: vec![REL_WHEEL, REL_HWHEEL],
+            ..DeviceSpec::default()
+        })?;
+
+        Ok(UinputDriver {
+            keyboard,
+            pointer,
+            screen_size,
+            location: (max_x / 2, max_y / 2),
+        })
+    }
+
+    fn press_release(
+        device: &mut UinputDevice,
+        code: u16,
+        direction: Direction,
+    ) -> Result<(), String> {
+        if matches!(direction, Direction::Press | Direction::Click) {
+            device.emit(EV_KEY, code, 1)?;
+            device.sync()?;
+        }
+        if matches!(direction, Direction::Release | Direction::Click) {
+            device.emit(EV_KEY, code, 0)?;
+            device.sync()?;
+        }
+        Ok(())
+    }
+}
+
+fn simulate_error(e: String) -> InputError {
+    tracing::warn!("uinput error: {}", e);
+    InputError::Simulate("failed to write to uinput device")
+}
+
+impl Keyboard for UinputDriver {
+    fn fast_text(&mut self, _text: &str) -> InputResult<Option<()>> {
+        // No fast path, text is entered key by key
+        Ok(None)
+    }
+
+    fn key(&mut self, key: Key, direction: Direction) -> InputResult<()> {
+        let (code, needs_shift) = match key {
+            Key::Unicode(c) => char_key_code(c).ok_or_else(|| {
+                InputError::Mapping(format!("No uinput key code for character {:?}", c))
+            })?,
+            _ => (
+                key_code(key).ok_or_else(|| {
+                    InputError::Mapping(format!("No uinput key code for key {:?}", key))
+                })?,
+                false,
+            ),
+        };
+
+        if needs_shift && matches!(direction, Direction::Press | Direction::Click) {
+            Self::press_release(&mut self.keyboard, KEY_LEFTSHIFT, Direction::Press)
+                .map_err(simulate_error)?;
+        }
+        Self::press_release(&mut self.keyboard, code, direction).map_err(simulate_error)?;
+        if needs_shift && matches!(direction, Direction::Release | Direction::Click) {
+            Self::press_release(&mut self.keyboard, KEY_LEFTSHIFT, Direction::Release)
+                .map_err(simulate_error)?;
+        }
+        Ok(())
+    }
+
+    fn raw(&mut self, keycode: u16, direction: Direction) -> InputResult<()> {
+        Self::press_release(&mut self.keyboard, keycode, direction).map_err(simulate_error)
+    }
+}
+
+impl Mouse for UinputDriver {
+    fn button(&mut self, button: Button, direction: Direction) -> InputResult<()> {
+        let code = match button {
+            Button::Left => BTN_LEFT,
+            Button::Right => BTN_RIGHT,
+            Button::Middle => BTN_MIDDLE,
+            Button::Back => BTN_SIDE,
+            Button::Forward => BTN_EXTRA,
+            Button::ScrollUp => return self.scroll(-1, Axis::Vertical),
+            Button::ScrollDown => return self.scroll(1, Axis::Vertical),
+            Button::ScrollLeft => return self.scroll(-1, Axis::Horizontal),
+            Button::ScrollRight => return self.scroll(1, Axis::Horizontal),
+        };
+        Self::press_release(&mut self.pointer, code, direction).map_err(simulate_error)
+    }
+
+    fn move_mouse(&mut self, x: i32, y: i32, coordinate: Coordinate) -> InputResult<()> {
+        let (x, y) = match coordinate {
+            Coordinate::Abs => (x, y),
+            Coordinate::Rel => (self.location.0 + x, self.location.1 + y),
+        };
+        let x = x.clamp(0, self.screen_size.0.saturating_sub(1) as i32);
+        let y = y.clamp(0, self.screen_size.1.saturating_sub(1) as i32);
+
+        self.pointer
+            .emit(EV_ABS, ABS_X, x)
+            .map_err(simulate_error)?;
+        self.pointer
+            .emit(EV_ABS, ABS_Y, y)
+            .map_err(simulate_error)?;
+        self.pointer.sync().map_err(simulate_error)?;
+        self.location = (x, y);
+        Ok(())
+    }
+
+    fn scroll(&mut self, length: i32, axis: Axis) -> InputResult<()> {
+        // Enigo uses positive lengths for down/right, evdev wheels use positive for up/right
+        let (code, value) = match axis {
+            Axis::Vertical => (REL_WHEEL, -length),
+            Axis::Horizontal => (REL_HWHEEL, length),
+        };
+        self.pointer
+            .emit(EV_REL, code, value)
+            .map_err(simulate_error)?;
+        self.pointer.sync().map_err(simulate_error)
+    }
+
+    fn main_display(&self) -> InputResult<(i32, i32)> {
+        Ok((self.screen_size.0 as i32, self.screen_size.1 as i32))
+    }
+
+    fn location(&self) -> InputResult<(i32, i32)> {
+        Ok(self.location)
+    }
+}
+
+// Tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_char_key_codes() {
+        assert_eq!(char_key_code('a'), Some((30, false)));
+        assert_eq!(char_key_code('A'), Some((30, true)));
+        assert_eq!(char_key_code('1'), Some((2, false)));
+        assert_eq!(char_key_code('!'), Some((2, true)));
+        assert_eq!(char_key_code('q'), Some((16, false)));
+        assert_eq!(char_key_code('m'), Some((50, false)));
+        assert_eq!(char_key_code('?'), Some((53, true)));
+        assert_eq!(char_key_code(' '), Some((57, false)));
+        assert_eq!(char_key_code('é'), None);
+    }
+
+    #[test]
+    fn test_named_key_codes() {
+        assert_eq!(key_code(Key::Return), Some(28));
+        assert_eq!(key_code(Key::Control), Some(29));
+        assert_eq!(key_code(Key::F12), Some(88));
+        assert_eq!(key_code(Key::Unicode('z')), Some(44));
+    }
+}
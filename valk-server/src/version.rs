@@ -0,0 +1,31 @@
+use axum::Json;
+use serde::Serialize;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[derive(Debug, Serialize)]
+pub struct VersionInfo {
+    version: &'static str,
+    /// Commit the binary was built from, if VALK_GIT_COMMIT was set at build time
+    git_commit: Option<&'static str>,
+    target: String,
+    debug_build: bool,
+    /// Whether `valk-server self-update` is available
+    self_update: bool,
+}
+
+/// `{arch}-{os}` of this build, e.g. "x86_64-linux", which picks the release to update to
+pub fn target() -> String {
+    format!("{}-{}", std::env::consts::ARCH, std::env::consts::OS)
+}
+
+/// Report the version and build of this server
+pub async fn version() -> Json<VersionInfo> {
+    Json(VersionInfo {
+        version: VERSION,
+        git_commit: option_env!("VALK_GIT_COMMIT"),
+        target: target(),
+        debug_build: cfg!(debug_assertions),
+        self_update: cfg!(feature = "self-update"),
+    })
+}
@@ -0,0 +1,256 @@
+// X11 through a display named by the caller rather than DISPLAY, so the display the server
+// drives lives in its state and moving to another one never touches the process environment.
+// Monitors are listed and captured the way xcap does it, so ids and geometry match.
+
+use image::RgbaImage;
+use xcb::{randr, x, Connection, Xid};
+
+use crate::capture::DisplayInfo;
+use crate::observe::ListedWindow;
+
+/// Connect to `display`, returning the root window of its default screen
+pub fn connect(display: &str) -> Result<(Connection, x::Window), String> {
+    let (conn, screen) = Connection::connect(Some(display))
+        .map_err(|e| format!("Failed to connect to {}: {}", display, e))?;
+    let root = conn
+        .get_setup()
+        .roots()
+        .nth(screen as usize)
+        .ok_or_else(|| format!("{} has no screen {}", display, screen))?
+        .root();
+    Ok((conn, root))
+}
+
+// Xft.dpi over 96, positions and sizes are reported divided by it
+fn scale_factor(conn: &Connection, root: x::Window) -> f32 {
+    let cookie = conn.send_request(&x::GetProperty {
+        delete: false,
+        window: root,
+        property: x::ATOM_RESOURCE_MANAGER,
+        r#type: x::ATOM_STRING,
+        long_offset: 0,
+        long_length: 60,
+    });
+    conn.wait_for_reply(cookie)
+        .ok()
+        .and_then(|reply| {
+            let resources = String::from_utf8_lossy(reply.value()).into_owned();
+            resources
+                .lines()
+                .find_map(|line| line.strip_prefix("Xft.dpi:\t"))
+                .and_then(|dpi| dpi.trim().parse::<f32>().ok())
+        })
+        .map_or(1.0, |dpi| dpi / 96.0)
+}
+
+/// The active monitors of `display`, each identified by its first output
+pub fn monitors(display: &str) -> Result<Vec<DisplayInfo>, String> {
+    let (conn, root) = connect(display)?;
+    let scale = scale_factor(&conn, root);
+    let cookie = conn.send_request(&randr::GetMonitors {
+        window: root,
+        get_active: true,
+    });
+    let reply = conn.wait_for_reply(cookie).map_err(|e| e.to_string())?;
+
+    let mut displays = Vec::new();
+    for monitor in reply.monitors() {
+        let Some(output) = monitor.outputs().first() else {
+            continue;
+        };
+        let cookie = conn.send_request(&randr::GetOutputInfo {
+            output: *output,
+            config_timestamp: x::CURRENT_TIME,
+        });
+        let Ok(info) = conn.wait_for_reply(cookie) else {
+            continue;
+        };
+        displays.push(DisplayInfo {
+            id: output.resource_id(),
+            name: String::from_utf8_lossy(info.name()).into_owned(),
+            x: (monitor.x() as f32 / scale) as i32,
+            y: (monitor.y() as f32 / scale) as i32,
+            width: (monitor.width() as f32 / scale) as u32,
+            height: (monitor.height() as f32 / scale) as u32,
+            scale_factor: scale,
+            is_primary: monitor.primary(),
+        });
+    }
+    Ok(displays)
+}
+
+/// Capture a monitor of `display`, as listed by `monitors`
+pub fn capture(display: &str, monitor: &DisplayInfo) -> Result<RgbaImage, String> {
+    let (conn, root) = connect(display)?;
+    let scale = monitor.scale_factor;
+    let width = (monitor.width as f32 * scale) as u32;
+    let height = (monitor.height as f32 * scale) as u32;
+    let cookie = conn.send_request(&x::GetImage {
+        format: x::ImageFormat::ZPixmap,
+        drawable: x::Drawable::Window(root),
+        x: (monitor.x as f32 * scale) as i16,
+        y: (monitor.y as f32 * scale) as i16,
+        width: width as u16,
+        height: height as u16,
+        plane_mask: u32::MAX,
+    });
+    let reply = conn.wait_for_reply(cookie).map_err(|e| e.to_string())?;
+
+    let setup = conn.get_setup();
+    let depth = reply.depth();
+    let bytes_per_pixel = setup
+        .pixmap_formats()
+        .iter()
+        .find(|format| format.depth() == depth)
+        .map(|format| format.bits_per_pixel() as usize / 8)
+        .ok_or_else(|| format!("No pixmap format for depth {}", depth))?;
+    if depth != 24 && depth != 32 || bytes_per_pixel < 3 {
+        return Err(format!("Unsupported depth {}", depth));
+    }
+    // Pixels are BGRX in memory on little-endian servers, XRGB on big-endian ones
+    let little_endian = setup.image_byte_order() == x::ImageOrder::LsbFirst;
+    let rgba = reply
+        .data()
+        .chunks_exact(bytes_per_pixel)
+        .take((width * height) as usize)
+        .flat_map(|pixel| {
+            if little_endian {
+                [pixel[2], pixel[1], pixel[0], 255]
+            } else {
+                let rgb = &pixel[bytes_per_pixel - 3..];
+                [rgb[0], rgb[1], rgb[2], 255]
+            }
+        })
+        .collect();
+    RgbaImage::from_raw(width, height, rgba).ok_or_else(|| format!("Short image from {}", display))
+}
+
+fn atom(conn: &Connection, name: &str) -> Result<x::Atom, String> {
+    let cookie = conn.send_request(&x::InternAtom {
+        only_if_exists: true,
+        name: name.as_bytes(),
+    });
+    let atom = conn
+        .wait_for_reply(cookie)
+        .map_err(|e| e.to_string())?
+        .atom();
+    if atom.is_none() {
+        return Err(format!("{} not supported", name));
+    }
+    Ok(atom)
+}
+
+fn property(
+    conn: &Connection,
+    window: x::Window,
+    property: x::Atom,
+    r#type: x::Atom,
+    long_length: u32,
+) -> Result<x::GetPropertyReply, String> {
+    let cookie = conn.send_request(&x::GetProperty {
+        delete: false,
+        window,
+        property,
+        r#type,
+        long_offset: 0,
+        long_length,
+    });
+    conn.wait_for_reply(cookie).map_err(|e| e.to_string())
+}
+
+// The EWMH atoms a window's listing needs
+struct WindowAtoms {
+    pid: x::Atom,
+    state: x::Atom,
+    hidden: x::Atom,
+    maximized_vert: x::Atom,
+    maximized_horz: x::Atom,
+}
+
+/// Every top-level window the window manager lists on `display`, the frontmost first
+pub fn windows(display: &str) -> Result<Vec<ListedWindow>, String> {
+    let (conn, root) = connect(display)?;
+    let client_list = atom(&conn, "_NET_CLIENT_LIST_STACKING")?;
+    let atoms = WindowAtoms {
+        pid: atom(&conn, "_NET_WM_PID")?,
+        state: atom(&conn, "_NET_WM_STATE")?,
+        hidden: atom(&conn, "_NET_WM_STATE_HIDDEN")?,
+        maximized_vert: atom(&conn, "_NET_WM_STATE_MAXIMIZED_VERT")?,
+        maximized_horz: atom(&conn, "_NET_WM_STATE_MAXIMIZED_HORZ")?,
+    };
+    let active = atom(&conn, "_NET_ACTIVE_WINDOW")
+        .and_then(|active| property(&conn, root, active, x::ATOM_NONE, 1))
+        .ok()
+        .and_then(|reply| reply.value::<u32>().first().copied());
+
+    let clients = property(&conn, root, client_list, x::ATOM_NONE, 1024)?;
+    // The stacking order lists the bottom window first
+    let listed = clients
+        .value::<x::Window>()
+        .iter()
+        .rev()
+        .filter_map(|window| listed_window(&conn, *window, &atoms).ok())
+        .enumerate()
+        .map(|(z_order, mut window)| {
+            window.z_order = z_order as u32;
+            window.is_focused = active == Some(window.id);
+            window
+        })
+        .collect();
+    Ok(listed)
+}
+
+fn listed_window(
+    conn: &Connection,
+    window: x::Window,
+    atoms: &WindowAtoms,
+) -> Result<ListedWindow, String> {
+    let pid = property(conn, window, atoms.pid, x::ATOM_CARDINAL, 1)?
+        .value::<u32>()
+        .first()
+        .copied()
+        .ok_or_else(|| "Window has no pid".to_string())?;
+    let title = property(conn, window, x::ATOM_WM_NAME, x::ATOM_STRING, 1024)?;
+    let title = String::from_utf8_lossy(title.value()).into_owned();
+    let class = property(conn, window, x::ATOM_WM_CLASS, x::ATOM_STRING, 1024)?;
+    // WM_CLASS is the instance then the class, each nul terminated
+    let app_name = String::from_utf8_lossy(class.value())
+        .split('\0')
+        .find(|name| !name.is_empty())
+        .unwrap_or_default()
+        .to_string();
+
+    let cookie = conn.send_request(&x::GetGeometry {
+        drawable: x::Drawable::Window(window),
+    });
+    let geometry = conn.wait_for_reply(cookie).map_err(|e| e.to_string())?;
+    let cookie = conn.send_request(&x::TranslateCoordinates {
+        src_window: window,
+        dst_window: geometry.root(),
+        src_x: geometry.x(),
+        src_y: geometry.y(),
+    });
+    let position = conn.wait_for_reply(cookie).map_err(|e| e.to_string())?;
+
+    let state = property(conn, window, atoms.state, x::ATOM_ATOM, 12)?;
+    let state = state.value::<x::Atom>();
+    let is_minimized = state.contains(&atoms.hidden);
+    let is_maximized = !is_minimized
+        && state.contains(&atoms.maximized_vert)
+        && state.contains(&atoms.maximized_horz);
+
+    Ok(ListedWindow {
+        id: window.resource_id(),
+        pid,
+        app_name,
+        title,
+        x: (position.dst_x() - geometry.x()) as i32,
+        y: (position.dst_y() - geometry.y()) as i32,
+        width: geometry.width() as u32,
+        height: geometry.height() as u32,
+        z_order: 0,
+        is_focused: false,
+        is_minimized,
+        is_maximized,
+    })
+}